@@ -1,12 +1,15 @@
 use coord_2d::Size;
+use image::{Rgba, RgbaImage};
 use wfc::WaveCellRef;
 use wfc_image::ImagePatterns;
 
+#[cfg(feature = "window")]
 pub struct WindowPixels {
     _window: winit::window::Window,
     pixels: pixels::Pixels,
 }
 
+#[cfg(feature = "window")]
 impl WindowPixels {
     pub fn new(grid_size: Size, pixel_size: Size) -> Self {
         let event_loop = winit::event_loop::EventLoop::new();
@@ -52,3 +55,64 @@ impl WindowPixels {
         let _ = self.pixels.render();
     }
 }
+
+/// Receives the frames drawn by `HeadlessPixels`, e.g. to pass them to a video encoder
+pub trait FrameSink {
+    fn frame(&mut self, frame: RgbaImage);
+}
+
+impl FrameSink for Vec<RgbaImage> {
+    fn frame(&mut self, frame: RgbaImage) {
+        self.push(frame);
+    }
+}
+
+impl<S: FrameSink> FrameSink for &mut S {
+    fn frame(&mut self, frame: RgbaImage) {
+        (**self).frame(frame);
+    }
+}
+
+/// Like `WindowPixels`, but rather than being shown in a window each frame is passed to a
+/// `FrameSink`, so animations can be produced without a display (e.g. in CI). Frames have one
+/// pixel per cell. By default frames are collected into a `Vec`.
+pub struct HeadlessPixels<S: FrameSink = Vec<RgbaImage>> {
+    grid_size: Size,
+    sink: S,
+}
+
+impl HeadlessPixels {
+    pub fn new(grid_size: Size) -> Self {
+        Self::with_sink(grid_size, Vec::new())
+    }
+}
+
+impl<S: FrameSink> HeadlessPixels<S> {
+    pub fn with_sink(grid_size: Size, sink: S) -> Self {
+        Self { grid_size, sink }
+    }
+
+    pub fn draw<'a>(
+        &mut self,
+        cells: impl Iterator<Item = WaveCellRef<'a>>,
+        image_patterns: &ImagePatterns,
+    ) {
+        let mut frame = RgbaImage::from_pixel(
+            self.grid_size.width(),
+            self.grid_size.height(),
+            Rgba([0, 0, 0, 0]),
+        );
+        for (cell, pixel) in cells.zip(frame.pixels_mut()) {
+            *pixel = image_patterns.weighted_average_colour(&cell);
+        }
+        self.sink.frame(frame);
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+}
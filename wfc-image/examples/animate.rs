@@ -3,8 +3,6 @@ use coord_2d::Coord;
 use rand::{Rng, SeedableRng};
 use rand_xorshift::XorShiftRng;
 use std::num::NonZeroU32;
-use std::thread;
-use std::time::Duration;
 use wfc::wrap::*;
 use wfc::*;
 use wfc_image::{ImagePatterns, Size};
@@ -47,7 +45,7 @@ fn main() {
         anchor_bottom,
         width,
         height,
-        delay,
+        fps,
         pattern_size,
         all_orientations,
     ) = meap::all! {
@@ -58,7 +56,7 @@ fn main() {
         flag('b').name("anchor-bottom").desc("anchor bottom"),
         opt_opt::<u32, _>("INT", 'x').name("width").desc("width").with_default(48),
         opt_opt::<u32, _>("INT", 'y').name("height").desc("height").with_default(48),
-        opt_opt::<u64, _>("MS", 'd').name("delay").desc("delay between steps"),
+        opt_opt::<u32, _>("INT", 'r').name("fps").desc("steps per second (0 for unlimited)").with_default(0),
         opt_opt::<u32, _>("INT", 'p').name("pattern-size").desc("size of patterns in pixels").with_default(3),
         flag('a').name("all-orientations").desc("all orientations"),
     }
@@ -106,8 +104,7 @@ fn main() {
     let global_stats = image_patterns.global_stats();
     let mut wave = Wave::new(grid_size);
     let mut context = Context::new();
-    let delay = delay.map(Duration::from_millis);
-    'generate: loop {
+    loop {
         let forbid = Forbid {
             bottom_left_corner_id,
             wrapped_top_left_corner_id,
@@ -120,24 +117,17 @@ fn main() {
             forbid,
             &mut rng,
         );
-        'inner: loop {
-            window_pixels.draw(run.wave_cell_ref_iter(), &image_patterns);
-            if let Some(delay) = delay {
-                thread::sleep(delay);
-            }
-            match run.step(&mut rng) {
-                Ok(observe) => match observe {
-                    Observe::Complete => {
-                        if forever {
-                            continue 'generate;
-                        } else {
-                            break 'generate;
-                        }
-                    }
-                    Observe::Incomplete => (),
-                },
-                Err(PropagateError::Contradiction) => break 'inner,
-            }
+        let result = image_patterns.collapse_animated(
+            &mut run,
+            fps,
+            |wave, _| {
+                window_pixels
+                    .draw(wave.wave_cell_ref_iter(&global_stats), &image_patterns)
+            },
+            &mut rng,
+        );
+        if result.is_ok() && !forever {
+            break;
         }
     }
 }
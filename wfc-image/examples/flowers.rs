@@ -67,7 +67,7 @@ fn main() {
     let wave = {
         let global_stats = image_patterns.global_stats();
         let mut wave = Wave::new(grid_size);
-        'generate: loop {
+        loop {
             let mut context = Context::new();
             let sprout_coord = Coord::new(
                 (rng.gen::<u32>() % grid_size.width()) as i32,
@@ -86,19 +86,24 @@ fn main() {
                 forbid,
                 &mut rng,
             );
-            'inner: loop {
-                match run.step(&mut rng) {
-                    Ok(observe) => {
-                        if let Some(window_pixels) = window_pixels.as_mut() {
-                            window_pixels.draw(run.wave_cell_ref_iter(), &image_patterns);
-                        }
-                        match observe {
-                            Observe::Complete => break 'generate,
-                            Observe::Incomplete => (),
-                        }
-                    }
-                    Err(PropagateError::Contradiction) => break 'inner,
-                }
+            let result = match window_pixels.as_mut() {
+                Some(window_pixels) => image_patterns
+                    .collapse_animated(
+                        &mut run,
+                        0,
+                        |wave, _| {
+                            window_pixels.draw(
+                                wave.wave_cell_ref_iter(&global_stats),
+                                &image_patterns,
+                            )
+                        },
+                        &mut rng,
+                    )
+                    .map(|_| ()),
+                None => run.collapse(&mut rng),
+            };
+            if result.is_ok() {
+                break;
             }
         }
         let end_time = ::std::time::Instant::now();
@@ -0,0 +1,34 @@
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use std::num::NonZeroU32;
+use wfc::retry::NumTimes;
+use wfc::RunOwn;
+use wfc_image::*;
+
+fn main() {
+    let args = ::std::env::args().collect::<Vec<_>>();
+    if args.len() != 5 {
+        println!(
+            "usage: {} SPRITE_SHEET_PATH EXAMPLE_PATH TILE_SIZE OUTPUT_PATH",
+            args[0]
+        );
+        ::std::process::exit(1);
+    }
+    let sheet = image::open(&args[1]).unwrap();
+    let example = image::open(&args[2]).unwrap();
+    let tile_size = args[3]
+        .parse::<NonZeroU32>()
+        .expect("tile size must be a positive integer");
+    let output_path = &args[4];
+    let mut tile_set = ImageTileSet::from_sprite_sheet(&sheet, tile_size, tile_size);
+    tile_set
+        .learn_from_example(&example)
+        .expect("example contains a tile which isn't in the sprite sheet");
+    let global_stats = tile_set.global_stats();
+    let mut rng = XorShiftRng::from_entropy();
+    let wave = RunOwn::new_wrap(Size::new(24, 24), &global_stats, WrapXY, &mut rng)
+        .collapse_retrying(NumTimes(10), &mut rng)
+        .expect("Too many contradictions");
+    let output_image = tile_set.image_from_wave(&wave);
+    output_image.save(output_path).expect("Failed to save");
+}
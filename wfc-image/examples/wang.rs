@@ -0,0 +1,29 @@
+use image::Rgba;
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use wfc::retry::NumTimes;
+use wfc::RunOwn;
+use wfc_image::*;
+
+fn main() {
+    let args = ::std::env::args().collect::<Vec<_>>();
+    if args.len() != 3 || !["corner", "edge"].contains(&args[1].as_str()) {
+        println!("usage: {} (corner|edge) OUTPUT_PATH", args[0]);
+        ::std::process::exit(1);
+    }
+    let model = if args[1] == "corner" {
+        WangModel::Corner
+    } else {
+        WangModel::Edge
+    };
+    let output_path = &args[2];
+    let palette = [Rgba([40, 90, 200, 255]), Rgba([80, 160, 60, 255])];
+    let tile_set = WangTileSet::complete(model, Size::new(8, 8), &palette);
+    let global_stats = tile_set.global_stats();
+    let mut rng = XorShiftRng::from_entropy();
+    let wave = RunOwn::new_wrap(Size::new(32, 32), &global_stats, WrapXY, &mut rng)
+        .collapse_retrying(NumTimes(10), &mut rng)
+        .expect("Too many contradictions");
+    let output_image = tile_set.image_from_wave(&wave);
+    output_image.save(output_path).expect("Failed to save");
+}
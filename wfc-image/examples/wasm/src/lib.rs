@@ -0,0 +1,38 @@
+//! Runs wfc in the browser, drawing the output to a canvas. The seed is chosen by the page,
+//! so no source of randomness is needed on the rust side. Build with
+//! `wasm-pack build --target web`, then serve this directory and open `index.html`.
+
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use std::num::NonZeroU32;
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+use wfc::RunOwn;
+use wfc_image::*;
+
+const INPUT: &[u8] = include_bytes!("../../rooms.png");
+
+/// Fills `canvas` with an image generated from the example input, one pixel per cell
+#[wasm_bindgen]
+pub fn generate(canvas: HtmlCanvasElement, seed: u32) -> Result<(), JsValue> {
+    let input = image::load_from_memory(INPUT)
+        .map_err(|error| JsValue::from_str(&error.to_string()))?;
+    let image_patterns =
+        ImagePatterns::new(&input, NonZeroU32::new(3).unwrap(), &orientation::ALL);
+    let global_stats = image_patterns.global_stats();
+    let output_size = Size::new(canvas.width(), canvas.height());
+    let mut rng = XorShiftRng::seed_from_u64(seed as u64);
+    let mut run = RunOwn::new_wrap(output_size, &global_stats, WrapXY, &mut rng);
+    let mut attempts = 10;
+    while run.collapse(&mut rng).is_err() {
+        attempts -= 1;
+        if attempts == 0 {
+            return Err(JsValue::from_str("too many contradictions"));
+        }
+    }
+    let context = canvas
+        .get_context("2d")?
+        .ok_or_else(|| JsValue::from_str("no 2d context"))?
+        .dyn_into::<CanvasRenderingContext2d>()?;
+    image_patterns.draw_to_canvas(run.wave_cell_ref_iter(), output_size, &context)
+}
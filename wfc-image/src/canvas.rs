@@ -0,0 +1,28 @@
+use crate::ImagePatterns;
+use coord_2d::Size;
+use wasm_bindgen::{Clamped, JsValue};
+use web_sys::{CanvasRenderingContext2d, ImageData};
+use wfc::WaveCellRef;
+
+impl ImagePatterns {
+    /// Draws the weighted average colour of each cell to `context`, one pixel per cell with
+    /// the top-left cell at (0, 0). `cells` must be in row-major order, as produced by
+    /// `wave_cell_ref_iter`, and `size` is the size of the wave. Requires the `wasm` feature.
+    pub fn draw_to_canvas<'a>(
+        &self,
+        cells: impl Iterator<Item = WaveCellRef<'a>>,
+        size: Size,
+        context: &CanvasRenderingContext2d,
+    ) -> Result<(), JsValue> {
+        let mut data = Vec::with_capacity(size.count() * 4);
+        for cell in cells {
+            data.extend_from_slice(&self.weighted_average_colour(&cell).0);
+        }
+        let image_data = ImageData::new_with_u8_clamped_array_and_sh(
+            Clamped(&data),
+            size.width(),
+            size.height(),
+        )?;
+        context.put_image_data(&image_data, 0., 0.)
+    }
+}
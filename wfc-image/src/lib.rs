@@ -1,10 +1,16 @@
 pub use coord_2d::{Coord, Size};
+use direction::CardinalDirection;
 use grid_2d::Grid;
 use image::{DynamicImage, Rgba, RgbaImage};
-use rand::{Rng, SeedableRng};
+use rand::Rng;
+use std::collections::HashSet;
+use std::io;
 use std::num::NonZeroU32;
+use std::thread;
+use std::time::{Duration, Instant};
 use wfc::orientation::OrientationTable;
 pub use wfc::orientation::{self, Orientation};
+pub use wfc::overlapping::{BorderOccurrences, InputWrap, PatternIdMap, StitchError};
 use wfc::overlapping::{OverlappingPatterns, Pattern};
 use wfc::retry as wfc_retry;
 pub use wfc::wrap;
@@ -12,59 +18,548 @@ pub use wfc::ForbidNothing;
 use wfc::*;
 pub use wrap::WrapXY;
 
+#[cfg(feature = "wasm")]
+mod canvas;
+mod luma;
+mod palette;
+mod pixel;
+mod tile_set;
+mod wang;
+pub use luma::LumaPatterns;
+pub use palette::PaletteSwap;
+pub use pixel::ImagePixel;
+pub use tile_set::{ImageTileSet, UnknownTile};
+pub use wang::{WangModel, WangTileSet};
+
 pub mod retry {
-    #[cfg(feature = "parallel")]
-    pub use super::wfc_retry::ParNumTimes;
     pub use super::wfc_retry::RetryOwn as Retry;
-    pub use super::wfc_retry::{Forever, NumTimes};
+    pub use super::wfc_retry::{
+        Forever, ForeverCancellable, NoRetryObserver, NumTimes, NumTimesReseed,
+        NumTimesWithReport, PartialRestart, RetryObserver, RetryReport, SeedScan,
+    };
+    #[cfg(feature = "parallel")]
+    pub use super::wfc_retry::{ParNumTimes, ParNumTimesInPool, ParNumTimesWithRng};
+
+    pub use super::wfc_retry::{CollapseFrontend, RetryOutput};
 
     pub trait ImageRetry: Retry {
         type ImageReturn;
         #[doc(hidden)]
-        fn image_return(
+        fn image_return<C: CollapseFrontend<super::DynamicImage>>(
             r: Self::Return,
-            image_patterns: &super::ImagePatterns,
+            frontend: &C,
         ) -> Self::ImageReturn;
     }
+
+    impl<RT: RetryOutput<super::DynamicImage>> ImageRetry for RT {
+        type ImageReturn = RT::Output;
+        fn image_return<C: CollapseFrontend<super::DynamicImage>>(
+            r: Self::Return,
+            frontend: &C,
+        ) -> Self::ImageReturn {
+            frontend.output_from_retry::<RT>(r)
+        }
+    }
 }
 
-pub struct ImagePatterns {
-    overlapping_patterns: OverlappingPatterns<Rgba<u8>>,
-    empty_colour: Rgba<u8>,
+/// Patterns learnt from an image. The pixel type defaults to 8-bit RGBA. Construct with
+/// `new_pixel` and friends to use `Rgba<u16>` or `Rgba<f32>`, keeping the precision of 16-bit
+/// or floating point images, such as heightmaps and HDR textures, in which case outputs are of
+/// the matching `DynamicImage` variant.
+pub struct ImagePatterns<P: ImagePixel = Rgba<u8>> {
+    overlapping_patterns: OverlappingPatterns<P::Key>,
+    empty_colour: P,
 }
 
-impl ImagePatterns {
-    pub fn new(
+impl<P: ImagePixel> ImagePatterns<P> {
+    /// Like `ImagePatterns::new`, but the image is converted to the pixel type `P`, e.g.
+    /// `ImagePatterns::<Rgba<u16>>::new_pixel`
+    pub fn new_pixel(
         image: &DynamicImage,
         pattern_size: NonZeroU32,
         orientations: &[Orientation],
     ) -> Self {
-        let rgba_image = image.to_rgba8();
-        let size = Size::new(rgba_image.width(), rgba_image.height());
-        let grid = Grid::new_fn(size, |Coord { x, y }| {
-            *rgba_image.get_pixel(x as u32, y as u32)
-        });
-        let overlapping_patterns =
-            OverlappingPatterns::new(grid, pattern_size, orientations);
+        Self::new_pixel_input_wrap(image, pattern_size, orientations, InputWrap::Tile)
+    }
+
+    /// Like `ImagePatterns::new_input_wrap`, but the image is converted to the pixel type `P`
+    pub fn new_pixel_input_wrap(
+        image: &DynamicImage,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+        input_wrap: InputWrap,
+    ) -> Self {
+        let grid = P::grid_from_image(image);
+        Self::from_grid(grid, pattern_size, orientations, input_wrap)
+    }
+
+    /// Like `ImagePatterns::new_masked`, but the image is converted to the pixel type `P`
+    pub fn new_pixel_masked(
+        image: &DynamicImage,
+        mask: &DynamicImage,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+        input_wrap: InputWrap,
+    ) -> Self {
+        let grid = Grid::new_grid_map(P::grid_from_image(image), P::to_key);
+        let mask = mask.to_luma8();
+        let mask =
+            Grid::new_fn(Size::new(mask.width(), mask.height()), |Coord { x, y }| {
+                mask.get_pixel(x as u32, y as u32).0[0] > 127
+            });
+        let overlapping_patterns = OverlappingPatterns::new_masked(
+            grid,
+            mask,
+            pattern_size,
+            orientations,
+            input_wrap,
+        );
+        Self {
+            overlapping_patterns,
+            empty_colour: P::transparent(),
+        }
+    }
+
+    fn from_grid(
+        grid: Grid<P>,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+        input_wrap: InputWrap,
+    ) -> Self {
+        let overlapping_patterns = OverlappingPatterns::new_input_wrap(
+            Grid::new_grid_map(grid, P::to_key),
+            pattern_size,
+            orientations,
+            input_wrap,
+        );
         Self {
             overlapping_patterns,
-            empty_colour: Rgba([0, 0, 0, 0]),
+            empty_colour: P::transparent(),
         }
     }
 
-    pub fn set_empty_colour(&mut self, empty_colour: Rgba<u8>) {
+    pub fn set_empty_colour(&mut self, empty_colour: P) {
         self.empty_colour = empty_colour;
     }
 
+    /// Learn from another example image. See `OverlappingPatterns::add_example`.
+    pub fn add_example(&mut self, image: &DynamicImage) {
+        let grid = Grid::new_grid_map(P::grid_from_image(image), P::to_key);
+        self.overlapping_patterns.add_example(grid);
+    }
+
+    /// Scale the weights of patterns according to their orientation. See
+    /// `OverlappingPatterns::set_orientation_weights`.
+    pub fn set_orientation_weights(&mut self, weights: OrientationTable<u32>) {
+        self.overlapping_patterns.set_orientation_weights(weights);
+    }
+
+    /// Count occurrences of patterns across the edges of the image according to
+    /// `border_occurrences`, along the axes on which `wrap` doesn't wrap the output. See
+    /// `OverlappingPatterns::set_output_wrap`.
+    pub fn set_output_wrap<W: Wrap>(
+        &mut self,
+        wrap: W,
+        border_occurrences: BorderOccurrences,
+    ) {
+        self.overlapping_patterns
+            .set_output_wrap(wrap, border_occurrences);
+    }
+
+    /// Removes patterns which occur fewer than `count_threshold` times in the image. See
+    /// `OverlappingPatterns::prune_below`.
+    pub fn prune_below(&mut self, count_threshold: u32) {
+        self.overlapping_patterns.prune_below(count_threshold);
+    }
+
+    /// Translates ids of patterns in `self` to ids of patterns with the same pixels in `to`
+    pub fn pattern_id_map(&self, to: &ImagePatterns<P>) -> PatternIdMap {
+        PatternIdMap::new(&self.overlapping_patterns, &to.overlapping_patterns)
+    }
+
     pub fn image_from_wave(&self, wave: &Wave) -> DynamicImage {
+        let grid =
+            Grid::new_grid_map_ref(wave.grid(), |cell| match cell.chosen_pattern_id() {
+                Ok(pattern_id) => P::from_key(
+                    self.overlapping_patterns.pattern_top_left_value(pattern_id),
+                ),
+                Err(_) => self.empty_colour,
+            });
+        P::image_from_grid(&grid)
+    }
+
+    /// Like `image_from_wave`, but the output is repeated `repeat_x` times horizontally and
+    /// `repeat_y` times vertically, so that seams between copies can be checked by eye for
+    /// waves collapsed with a wrapping `Wrap` such as `WrapXY`. Panics if the width or height
+    /// of the output doesn't fit in a `u32`.
+    pub fn image_from_wave_tiled(
+        &self,
+        wave: &Wave,
+        repeat_x: NonZeroU32,
+        repeat_y: NonZeroU32,
+    ) -> DynamicImage {
+        let wave_size = wave.grid().size();
+        let size = Size::new(
+            wave_size
+                .width()
+                .checked_mul(repeat_x.get())
+                .expect("tiled image is too wide"),
+            wave_size
+                .height()
+                .checked_mul(repeat_y.get())
+                .expect("tiled image is too tall"),
+        );
+        let grid = Grid::new_fn(size, |coord| {
+            match wave.grid().get_tiled(coord).chosen_pattern_id() {
+                Ok(pattern_id) => P::from_key(
+                    self.overlapping_patterns.pattern_top_left_value(pattern_id),
+                ),
+                Err(_) => self.empty_colour,
+            }
+        });
+        P::image_from_grid(&grid)
+    }
+
+    /// A single image of `first` with `second` placed next to it in `direction`, overlapping
+    /// it by `overlap` cells. Where the waves overlap, `second` is drawn. If `second` was
+    /// collapsed with `wfc::constraint::Stamp::from_wave_edge` the overlapping cells agree,
+    /// so there's no seam. Parts of the image covered by neither wave (when their sizes
+    /// differ) are filled with the empty colour.
+    pub fn image_from_waves_joined(
+        &self,
+        first: &Wave,
+        second: &Wave,
+        direction: CardinalDirection,
+        overlap: u32,
+    ) -> DynamicImage {
+        let first_size = first.grid().size();
+        let second_size = second.grid().size();
+        let overlap = overlap as i32;
+        let offset = match direction {
+            CardinalDirection::North => {
+                Coord::new(0, overlap - second_size.height() as i32)
+            }
+            CardinalDirection::East => Coord::new(first_size.width() as i32 - overlap, 0),
+            CardinalDirection::South => {
+                Coord::new(0, first_size.height() as i32 - overlap)
+            }
+            CardinalDirection::West => {
+                Coord::new(overlap - second_size.width() as i32, 0)
+            }
+        };
+        let top_left = Coord::new(offset.x.min(0), offset.y.min(0));
+        let bottom_right = Coord::new(
+            (first_size.width() as i32).max(offset.x + second_size.width() as i32),
+            (first_size.height() as i32).max(offset.y + second_size.height() as i32),
+        );
+        let size = Size::new(
+            (bottom_right.x - top_left.x) as u32,
+            (bottom_right.y - top_left.y) as u32,
+        );
+        let grid = Grid::new_fn(size, |coord| {
+            let coord = coord + top_left;
+            let cell = second
+                .grid()
+                .get(coord - offset)
+                .or_else(|| first.grid().get(coord));
+            match cell.map(|cell| cell.chosen_pattern_id()) {
+                Some(Ok(pattern_id)) => P::from_key(
+                    self.overlapping_patterns.pattern_top_left_value(pattern_id),
+                ),
+                _ => self.empty_colour,
+            }
+        });
+        P::image_from_grid(&grid)
+    }
+
+    /// Like `image_from_wave`, but with the colours of the patterns replaced according to
+    /// `palette_swap`, so the same patterns can produce differently themed outputs. The empty
+    /// colour isn't replaced.
+    pub fn image_from_wave_with_palette(
+        &self,
+        wave: &Wave,
+        palette_swap: &PaletteSwap<P>,
+    ) -> DynamicImage {
+        let grid =
+            Grid::new_grid_map_ref(wave.grid(), |cell| match cell.chosen_pattern_id() {
+                Ok(pattern_id) => palette_swap.swap_key(
+                    self.overlapping_patterns.pattern_top_left_value(pattern_id),
+                ),
+                Err(_) => self.empty_colour,
+            });
+        P::image_from_grid(&grid)
+    }
+
+    /// Draws the whole of each cell's pattern, so a wave collapsed with `WrapNone` produces an
+    /// image `pattern_size - 1` pixels larger than the wave in each dimension, rather than
+    /// cutting off the patterns along the right and bottom edges. See
+    /// `OverlappingPatterns::stitch_wave`.
+    pub fn image_from_wave_stitched(
+        &self,
+        wave: &Wave,
+    ) -> Result<DynamicImage, StitchError> {
+        let grid = self.overlapping_patterns.stitch_wave(wave)?;
+        Ok(P::image_from_grid(&Grid::new_grid_map_ref(
+            &grid,
+            P::from_key,
+        )))
+    }
+
+    /// Like `image_from_wave`, but each pixel is the average of the colours given to it by
+    /// every collapsed cell whose pattern covers it, rather than just the top-left pixel of its
+    /// own cell's pattern. This smooths out seams where overlapping patterns disagree. `wrap`
+    /// should match the wrapping the wave was collapsed with. Pixels not covered by any
+    /// collapsed cell are filled with the empty colour. Integer channels are rounded to the
+    /// nearest value.
+    pub fn image_from_wave_blended<W: Wrap>(&self, wave: &Wave, wrap: W) -> DynamicImage {
+        let _ = wrap;
+        let size = wave.grid().size();
+        let pattern_size = self.overlapping_patterns.pattern_size();
+        let mut sums = Grid::new_copy(size, ([0f64; 4], 0u32));
+        for (coord, cell) in wave.grid().enumerate() {
+            if let Ok(pattern_id) = cell.chosen_pattern_id() {
+                let values = self.overlapping_patterns.pattern_values(pattern_id);
+                for (offset, key) in pattern_size.coord_iter_row_major().zip(values) {
+                    if let Some(coord) = W::normalize_coord(coord + offset, size) {
+                        let (sum, count) = sums.get_checked_mut(coord);
+                        let channels = P::from_key(key).to_channels();
+                        for (acc, channel) in sum.iter_mut().zip(channels.iter()) {
+                            *acc += *channel;
+                        }
+                        *count += 1;
+                    }
+                }
+            }
+        }
+        let grid = Grid::new_grid_map(sums, |(sum, count)| {
+            if count == 0 {
+                self.empty_colour
+            } else {
+                P::from_channels(sum.map(|channel| channel / count as f64))
+            }
+        });
+        P::image_from_grid(&grid)
+    }
+
+    /// The pattern chosen for each cell of the wave, or `None` for cells which aren't
+    /// collapsed
+    pub fn pattern_id_grid(&self, wave: &Wave) -> Grid<Option<PatternId>> {
+        Grid::new_grid_map_ref(wave.grid(), |cell| cell.chosen_pattern_id().ok())
+    }
+
+    /// Writes the pattern chosen for each cell of the wave as CSV with the columns `x`, `y`,
+    /// `pattern_id` and `orientation`, for use as semantic data alongside the rendered image.
+    /// The last two columns are empty for cells which aren't collapsed.
+    pub fn write_id_grid_csv<W: io::Write>(
+        &self,
+        wave: &Wave,
+        mut writer: W,
+    ) -> io::Result<()> {
+        writeln!(writer, "x,y,pattern_id,orientation")?;
+        for (Coord { x, y }, pattern_id) in self.pattern_id_grid(wave).enumerate() {
+            match pattern_id {
+                Some(pattern_id) => writeln!(
+                    writer,
+                    "{},{},{},{:?}",
+                    x,
+                    y,
+                    pattern_id,
+                    self.pattern(*pattern_id).orientation()
+                )?,
+                None => writeln!(writer, "{},{},,", x, y)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn id_grid(&self) -> Grid<OrientationTable<PatternId>> {
+        self.overlapping_patterns.id_grid()
+    }
+
+    pub fn id_grid_original_orientation(&self) -> Grid<PatternId> {
+        self.overlapping_patterns.id_grid_original_orientation()
+    }
+
+    pub fn pattern(&self, pattern_id: PatternId) -> &Pattern {
+        self.overlapping_patterns.pattern(pattern_id)
+    }
+
+    pub fn pattern_mut(&mut self, pattern_id: PatternId) -> &mut Pattern {
+        self.overlapping_patterns.pattern_mut(pattern_id)
+    }
+
+    pub fn global_stats(&self) -> GlobalStats {
+        P::global_stats(&self.overlapping_patterns)
+    }
+
+    pub fn adjacency_frequencies(&self) -> AdjacencyFrequencies {
+        self.overlapping_patterns.adjacency_frequencies()
+    }
+
+    /// See `OverlappingPatterns::edge_patterns`
+    pub fn edge_patterns(&self) -> wfc::constraint::EdgePatterns {
+        self.overlapping_patterns.edge_patterns()
+    }
+
+    pub fn collapse_wave_retrying<W, F, RT, R>(
+        &self,
+        output_size: Size,
+        wrap: W,
+        forbid: F,
+        retry: RT,
+        rng: &mut R,
+    ) -> RT::Return
+    where
+        W: Wrap,
+        F: ForbidPattern + Send + Sync + Clone,
+        RT: retry::Retry,
+        R: Rng + Send + Sync + Clone,
+    {
+        let global_stats = self.global_stats();
+        let run = RunOwn::new_wrap_forbid(output_size, &global_stats, wrap, forbid, rng);
+        run.collapse_retrying(retry, rng)
+    }
+
+    /// Collapses `run` one step per frame at up to `fps` frames per second (or as fast as
+    /// possible if `fps` is 0), calling `draw` with the wave and the coordinates of the cells
+    /// which changed since it was last called. `draw` is first called before any steps are
+    /// taken, with every coordinate. Returns the final image, or an error on contradiction,
+    /// in which case the run must be reset before collapsing again.
+    pub fn collapse_animated<W, F, D, R>(
+        &self,
+        run: &mut RunBorrow<W, F>,
+        fps: u32,
+        mut draw: D,
+        rng: &mut R,
+    ) -> Result<DynamicImage, PropagateError>
+    where
+        W: Wrap,
+        F: ForbidPattern,
+        D: FnMut(&Wave, &[Coord]),
+        R: Rng,
+    {
+        let frame_duration = if fps == 0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs(1) / fps
+        };
+        let all_coords = run.wave().size().coord_iter_row_major().collect::<Vec<_>>();
+        draw(run.wave(), &all_coords);
+        let mut changed = ChangedCoords::default();
+        loop {
+            let frame_start = Instant::now();
+            let observe = run.step_with_listener(rng, &mut changed)?;
+            draw(run.wave(), &changed.coords);
+            changed.clear();
+            if let Observe::Complete = observe {
+                return Ok(self.image_from_wave(run.wave()));
+            }
+            if let Some(remaining) = frame_duration.checked_sub(frame_start.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+    }
+}
+
+impl ImagePatterns {
+    pub fn new(
+        image: &DynamicImage,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+    ) -> Self {
+        Self::new_pixel(image, pattern_size, orientations)
+    }
+
+    /// Like `new`, but choosing how patterns are sampled near the edges of the image. Use
+    /// `InputWrap::Clamp` or `InputWrap::IgnoreBorders` for images which don't tile seamlessly.
+    pub fn new_input_wrap(
+        image: &DynamicImage,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+        input_wrap: InputWrap,
+    ) -> Self {
+        Self::new_pixel_input_wrap(image, pattern_size, orientations, input_wrap)
+    }
+
+    /// Like `new_input_wrap`, but patterns are only learnt from the parts of `image` which
+    /// are light in `mask` (brighter than mid-grey), so that e.g. watermarks or irrelevant
+    /// corners of a sample image can be left out. Panics if `mask` isn't the same size as
+    /// `image`.
+    pub fn new_masked(
+        image: &DynamicImage,
+        mask: &DynamicImage,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+        input_wrap: InputWrap,
+    ) -> Self {
+        Self::new_pixel_masked(image, mask, pattern_size, orientations, input_wrap)
+    }
+
+    /// Like `new`, but takes an already-decoded RGBA image, avoiding a conversion
+    pub fn from_image_buffer(
+        rgba_image: &RgbaImage,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+    ) -> Self {
+        let grid = pixel::grid_from_image_buffer(rgba_image);
+        Self::from_grid(grid, pattern_size, orientations, InputWrap::Tile)
+    }
+
+    /// Takes raw pixel data, 4 bytes per pixel in RGBA order, row by row. Returns `None` if
+    /// the length of `rgba` doesn't match the given dimensions.
+    pub fn from_rgba_buffer(
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+    ) -> Option<Self> {
+        let size = Size::new(width, height);
+        if rgba.len() != size.count() * 4 {
+            return None;
+        }
+        let pixels = rgba
+            .chunks_exact(4)
+            .map(|pixel| Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]));
+        let grid = Grid::new_iterator(size, pixels);
+        Some(Self::from_grid(
+            grid,
+            pattern_size,
+            orientations,
+            InputWrap::Tile,
+        ))
+    }
+
+    /// Gives the patterns ids which depend only on their pixels. See
+    /// `OverlappingPatterns::sort_ids_by_content`.
+    pub fn sort_ids_by_content(&mut self) {
+        self.overlapping_patterns
+            .sort_ids_by_content_key(|colour| colour.0);
+    }
+
+    /// Draws each cell of a wave in a shade of grey according to its entropy, from black for
+    /// decided cells to white for the cells with the highest entropy in the wave. Cells with no
+    /// weighted patterns remaining are filled with the empty colour. Useful for seeing where
+    /// generation is making progress.
+    pub fn entropy_image(&self, wave: &Wave) -> DynamicImage {
         let size = wave.grid().size();
+        let max_entropy = wave
+            .grid()
+            .iter()
+            .filter_map(|cell| cell.entropy())
+            .fold(0.0, f32::max);
         let mut rgba_image = RgbaImage::new(size.width(), size.height());
         wave.grid().enumerate().for_each(|(Coord { x, y }, cell)| {
-            let colour = match cell.chosen_pattern_id() {
-                Ok(pattern_id) => {
-                    *self.overlapping_patterns.pattern_top_left_value(pattern_id)
+            let colour = match cell.entropy() {
+                Some(entropy) => {
+                    let shade = if max_entropy > 0.0 {
+                        (255.0 * entropy / max_entropy).round() as u8
+                    } else {
+                        0
+                    };
+                    Rgba([shade, shade, shade, 255])
                 }
-                Err(_) => self.empty_colour,
+                None => self.empty_colour,
             };
             rgba_image.put_pixel(x as u32, y as u32, colour);
         });
@@ -85,11 +580,12 @@ impl ImagePatterns {
                     .map(|(pattern_id, weight)| {
                         let &Rgba([r, g, b, a]) =
                             self.overlapping_patterns.pattern_top_left_value(pattern_id);
+                        let weight = weight as u64;
                         (
-                            r as u32 * weight,
-                            g as u32 * weight,
-                            b as u32 * weight,
-                            a as u32 * weight,
+                            r as u64 * weight,
+                            g as u64 * weight,
+                            b as u64 * weight,
+                            a as u64 * weight,
                         )
                     })
                     .fold(
@@ -98,7 +594,7 @@ impl ImagePatterns {
                             (acc_r + r, acc_g + g, acc_b + b, acc_a + a)
                         },
                     );
-                let total_weight = cell.sum_compatible_pattern_weight();
+                let total_weight = cell.sum_compatible_pattern_weight() as u64;
                 Rgba([
                     (r / total_weight) as u8,
                     (g / total_weight) as u8,
@@ -109,88 +605,165 @@ impl ImagePatterns {
         }
     }
 
-    pub fn grid(&self) -> &Grid<Rgba<u8>> {
-        self.overlapping_patterns.grid()
+    /// Like `weighted_average_colour`, but the colour channels are converted from sRGB to
+    /// linear light before averaging, and the result is returned in linear light with each
+    /// channel between 0 and 1. This is appropriate for renderers which handle gamma themselves.
+    pub fn weighted_average_colour_f32<'a>(
+        &self,
+        cell: &'a WaveCellRef<'a>,
+    ) -> Rgba<f32> {
+        use wfc::EnumerateCompatiblePatternWeights::*;
+        let linear = |Rgba([r, g, b, a]): Rgba<u8>| {
+            Rgba([
+                srgb_to_linear(r),
+                srgb_to_linear(g),
+                srgb_to_linear(b),
+                a as f32 / 255.,
+            ])
+        };
+        match cell.enumerate_compatible_pattern_weights() {
+            MultipleCompatiblePatternsWithoutWeights | NoCompatiblePattern => {
+                linear(self.empty_colour)
+            }
+            SingleCompatiblePatternWithoutWeight(pattern_id) => {
+                linear(*self.overlapping_patterns.pattern_top_left_value(pattern_id))
+            }
+            CompatiblePatternsWithWeights(iter) => {
+                let mut sum = [0f64; 4];
+                for (pattern_id, weight) in iter {
+                    let Rgba(colour) = linear(
+                        *self.overlapping_patterns.pattern_top_left_value(pattern_id),
+                    );
+                    for (acc, channel) in sum.iter_mut().zip(colour.iter()) {
+                        *acc += *channel as f64 * weight as f64;
+                    }
+                }
+                let total_weight = cell.sum_compatible_pattern_weight() as f64;
+                Rgba(sum.map(|channel| (channel / total_weight) as f32))
+            }
+        }
     }
 
-    pub fn id_grid(&self) -> Grid<OrientationTable<PatternId>> {
-        self.overlapping_patterns.id_grid()
+    pub fn grid(&self) -> &Grid<Rgba<u8>> {
+        self.overlapping_patterns.grid()
     }
+}
 
-    pub fn id_grid_original_orientation(&self) -> Grid<PatternId> {
-        self.overlapping_patterns.id_grid_original_orientation()
-    }
+// Collects the coordinates of cells which changed during a step, each once
+#[derive(Default)]
+struct ChangedCoords {
+    coords: Vec<Coord>,
+    seen: HashSet<Coord>,
+}
 
-    pub fn pattern(&self, pattern_id: PatternId) -> &Pattern {
-        self.overlapping_patterns.pattern(pattern_id)
+impl ChangedCoords {
+    fn clear(&mut self) {
+        self.coords.clear();
+        self.seen.clear();
     }
 
-    pub fn pattern_mut(&mut self, pattern_id: PatternId) -> &mut Pattern {
-        self.overlapping_patterns.pattern_mut(pattern_id)
+    fn insert(&mut self, coord: Coord) {
+        if self.seen.insert(coord) {
+            self.coords.push(coord);
+        }
     }
+}
 
-    pub fn global_stats(&self) -> GlobalStats {
-        self.overlapping_patterns.global_stats()
+impl DebugListener for ChangedCoords {
+    fn on_observe(&mut self, coord: Coord, _pattern_id: PatternId) {
+        self.insert(coord);
     }
 
-    pub fn collapse_wave_retrying<W, F, RT, R>(
-        &self,
-        output_size: Size,
-        wrap: W,
-        forbid: F,
-        retry: RT,
-        rng: &mut R,
-    ) -> RT::Return
-    where
-        W: Wrap,
-        F: ForbidPattern + Send + Sync + Clone,
-        RT: retry::Retry,
-        R: Rng + Send + Sync + Clone,
-    {
-        let global_stats = self.global_stats();
-        let run = RunOwn::new_wrap_forbid(output_size, &global_stats, wrap, forbid, rng);
-        run.collapse_retrying(retry, rng)
+    fn on_remove_pattern(&mut self, coord: Coord, _pattern_id: PatternId) {
+        self.insert(coord);
     }
 }
 
-impl retry::ImageRetry for retry::Forever {
-    type ImageReturn = DynamicImage;
-    fn image_return(
-        r: Self::Return,
-        image_patterns: &ImagePatterns,
-    ) -> Self::ImageReturn {
-        image_patterns.image_from_wave(&r)
+impl<P: ImagePixel> retry::CollapseFrontend<DynamicImage> for ImagePatterns<P> {
+    fn output_from_wave(&self, wave: &Wave) -> DynamicImage {
+        self.image_from_wave(wave)
     }
 }
 
-impl retry::ImageRetry for retry::NumTimes {
-    type ImageReturn = Result<DynamicImage, PropagateError>;
-    fn image_return(
-        r: Self::Return,
-        image_patterns: &ImagePatterns,
-    ) -> Self::ImageReturn {
-        match r {
-            Ok(r) => Ok(image_patterns.image_from_wave(&r)),
-            Err(e) => Err(e),
-        }
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
     }
 }
 
-#[cfg(feature = "parallel")]
-impl retry::ImageRetry for retry::ParNumTimes {
-    type ImageReturn = Result<DynamicImage, PropagateError>;
-    fn image_return(
-        r: Self::Return,
-        image_patterns: &ImagePatterns,
-    ) -> Self::ImageReturn {
-        match r {
-            Ok(r) => Ok(image_patterns.image_from_wave(&r)),
-            Err(e) => Err(e),
+/// The variant of the returned `DynamicImage` depends on the input's. Single-channel
+/// (`ImageLuma8`) inputs are handled by `LumaPatterns` and produce an `ImageLuma8`. 16-bit and
+/// floating point inputs are converted to `Rgba<u16>` and `Rgba<f32>` respectively, keeping
+/// their precision, and produce an `ImageRgba16` or `ImageRgba32F`. All other inputs are
+/// converted to 8-bit RGBA and produce an `ImageRgba8`. Call `to_rgba8` on the output if an
+/// 8-bit RGBA image is needed regardless of the input.
+pub fn generate_image_with_rng<W, F, IR, R>(
+    image: &DynamicImage,
+    pattern_size: NonZeroU32,
+    output_size: Size,
+    orientations: &[Orientation],
+    wrap: W,
+    forbid: F,
+    retry: IR,
+    rng: &mut R,
+) -> IR::ImageReturn
+where
+    W: Wrap,
+    F: ForbidPattern + Send + Sync + Clone,
+    IR: retry::ImageRetry,
+    R: Rng + Send + Sync + Clone,
+{
+    if let DynamicImage::ImageLuma8(luma_image) = image {
+        let luma_patterns = LumaPatterns::new(luma_image, pattern_size, orientations);
+        return IR::image_return(
+            luma_patterns.collapse_wave_retrying(output_size, wrap, forbid, retry, rng),
+            &luma_patterns,
+        );
+    }
+    match image {
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => generate_image_pixel::<Rgba<u16>, _, _, _, _>(
+            image,
+            pattern_size,
+            output_size,
+            orientations,
+            wrap,
+            forbid,
+            retry,
+            rng,
+        ),
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            generate_image_pixel::<Rgba<f32>, _, _, _, _>(
+                image,
+                pattern_size,
+                output_size,
+                orientations,
+                wrap,
+                forbid,
+                retry,
+                rng,
+            )
         }
+        _ => generate_image_pixel::<Rgba<u8>, _, _, _, _>(
+            image,
+            pattern_size,
+            output_size,
+            orientations,
+            wrap,
+            forbid,
+            retry,
+            rng,
+        ),
     }
 }
 
-pub fn generate_image_with_rng<W, F, IR, R>(
+#[allow(clippy::too_many_arguments)]
+fn generate_image_pixel<P, W, F, IR, R>(
     image: &DynamicImage,
     pattern_size: NonZeroU32,
     output_size: Size,
@@ -201,18 +774,23 @@ pub fn generate_image_with_rng<W, F, IR, R>(
     rng: &mut R,
 ) -> IR::ImageReturn
 where
+    P: ImagePixel,
     W: Wrap,
     F: ForbidPattern + Send + Sync + Clone,
     IR: retry::ImageRetry,
     R: Rng + Send + Sync + Clone,
 {
-    let image_patterns = ImagePatterns::new(image, pattern_size, orientations);
+    let image_patterns = ImagePatterns::<P>::new_pixel(image, pattern_size, orientations);
     IR::image_return(
         image_patterns.collapse_wave_retrying(output_size, wrap, forbid, retry, rng),
         &image_patterns,
     )
 }
 
+/// Like `generate_image_with_rng`, using an rng seeded from the operating system's source of
+/// randomness. Requires the `entropy` feature, which is enabled by default. On
+/// `wasm32-unknown-unknown`, this additionally requires the `js` feature.
+#[cfg(feature = "entropy")]
 pub fn generate_image<W, F, IR>(
     image: &DynamicImage,
     pattern_size: NonZeroU32,
@@ -227,6 +805,7 @@ where
     F: ForbidPattern + Send + Sync + Clone,
     IR: retry::ImageRetry,
 {
+    use rand::SeedableRng;
     generate_image_with_rng(
         image,
         pattern_size,
@@ -238,3 +817,135 @@ where
         &mut rand::rngs::StdRng::from_entropy(),
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+
+    fn collapsed_wave(image_patterns: &ImagePatterns) -> Wave {
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        image_patterns
+            .collapse_wave_retrying(
+                Size::new(3, 2),
+                WrapXY,
+                ForbidNothing,
+                retry::NumTimes(10),
+                &mut rng,
+            )
+            .unwrap()
+    }
+
+    fn two_colour_patterns() -> ImagePatterns {
+        let input = RgbaImage::from_fn(2, 2, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        ImagePatterns::new(
+            &DynamicImage::ImageRgba8(input),
+            NonZeroU32::new(1).unwrap(),
+            &[Orientation::Original],
+        )
+    }
+
+    #[test]
+    fn image_from_wave_tiled() {
+        let image_patterns = two_colour_patterns();
+        let wave = collapsed_wave(&image_patterns);
+        let image = image_patterns.image_from_wave(&wave).to_rgba8();
+        let tiled = image_patterns
+            .image_from_wave_tiled(
+                &wave,
+                NonZeroU32::new(2).unwrap(),
+                NonZeroU32::new(3).unwrap(),
+            )
+            .to_rgba8();
+        assert_eq!((tiled.width(), tiled.height()), (6, 6));
+        for (x, y, pixel) in tiled.enumerate_pixels() {
+            assert_eq!(pixel, image.get_pixel(x % 3, y % 2));
+        }
+    }
+
+    #[test]
+    fn image_from_wave_blended() {
+        let input =
+            RgbaImage::from_fn(3, 3, |x, y| Rgba([x as u8 * 50, y as u8 * 50, 0, 255]));
+        let image_patterns = ImagePatterns::new(
+            &DynamicImage::ImageRgba8(input),
+            NonZeroU32::new(2).unwrap(),
+            &[Orientation::Original],
+        );
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let wave = image_patterns
+            .collapse_wave_retrying(
+                Size::new(6, 6),
+                WrapXY,
+                ForbidNothing,
+                retry::NumTimes(10),
+                &mut rng,
+            )
+            .unwrap();
+        // overlapping patterns of a collapsed wave always agree, so blending changes nothing
+        assert_eq!(
+            image_patterns
+                .image_from_wave_blended(&wave, WrapXY)
+                .to_rgba8(),
+            image_patterns.image_from_wave(&wave).to_rgba8()
+        );
+        // observe only the bottom-right cell, whose pattern overhangs the edges of the wave
+        let global_stats = image_patterns.global_stats();
+        let size = Size::new(3, 3);
+        let mut wave = Wave::new(size);
+        wave.set_priority(Grid::new_fn(size, |coord| {
+            if coord == Coord::new(2, 2) {
+                -1000.0
+            } else {
+                0.0
+            }
+        }));
+        let mut context = Context::new();
+        let mut run = RunBorrow::new(&mut context, &mut wave, &global_stats, &mut rng);
+        run.observe_only(&mut rng).unwrap();
+        assert!(wave
+            .grid()
+            .get_checked(Coord::new(2, 2))
+            .chosen_pattern_id()
+            .is_ok());
+        let top_left = *image_patterns
+            .image_from_wave(&wave)
+            .to_rgba8()
+            .get_pixel(2, 2);
+        let empty = Rgba([0, 0, 0, 0]);
+        // without wrapping, the rest of the pattern falls outside the output
+        let blended = image_patterns
+            .image_from_wave_blended(&wave, wrap::WrapNone)
+            .to_rgba8();
+        for (x, y, &pixel) in blended.enumerate_pixels() {
+            let expected = if (x, y) == (2, 2) { top_left } else { empty };
+            assert_eq!(pixel, expected);
+        }
+        // with wrapping, it covers the opposite edges
+        let blended = image_patterns
+            .image_from_wave_blended(&wave, WrapXY)
+            .to_rgba8();
+        for (x, y, &pixel) in blended.enumerate_pixels() {
+            let covered = (x == 0 || x == 2) && (y == 0 || y == 2);
+            assert_eq!(pixel == empty, !covered);
+        }
+        let Rgba([r, g, _, _]) = top_left;
+        assert_eq!(
+            *blended.get_pixel(0, 0),
+            Rgba([(r + 50) % 150, (g + 50) % 150, 0, 255])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "tiled image is too wide")]
+    fn image_from_wave_tiled_overflow() {
+        let image_patterns = two_colour_patterns();
+        let wave = collapsed_wave(&image_patterns);
+        image_patterns.image_from_wave_tiled(
+            &wave,
+            NonZeroU32::new(u32::MAX).unwrap(),
+            NonZeroU32::new(1).unwrap(),
+        );
+    }
+}
@@ -0,0 +1,138 @@
+use crate::retry;
+use coord_2d::{Coord, Size};
+use grid_2d::Grid;
+use image::{DynamicImage, GrayImage, Luma};
+use rand::Rng;
+use std::num::NonZeroU32;
+use wfc::orientation::Orientation;
+use wfc::overlapping::{OverlappingPatterns, Pattern};
+use wfc::retry::CollapseFrontend;
+use wfc::*;
+
+/// Like `ImagePatterns`, but for single-channel images such as heightmaps. Patterns are
+/// made of `u8` values rather than RGBA pixels, which uses less memory and hashes faster,
+/// and outputs are luma images.
+pub struct LumaPatterns {
+    overlapping_patterns: OverlappingPatterns<u8>,
+    empty_value: u8,
+}
+
+impl LumaPatterns {
+    pub fn new(
+        luma_image: &GrayImage,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+    ) -> Self {
+        let size = Size::new(luma_image.width(), luma_image.height());
+        let grid = Grid::new_fn(size, |Coord { x, y }| {
+            luma_image.get_pixel(x as u32, y as u32).0[0]
+        });
+        let overlapping_patterns =
+            OverlappingPatterns::new(grid, pattern_size, orientations);
+        Self {
+            overlapping_patterns,
+            empty_value: 0,
+        }
+    }
+
+    pub fn set_empty_value(&mut self, empty_value: u8) {
+        self.empty_value = empty_value;
+    }
+
+    pub fn image_from_wave(&self, wave: &Wave) -> DynamicImage {
+        let size = wave.grid().size();
+        let mut luma_image = GrayImage::new(size.width(), size.height());
+        wave.grid().enumerate().for_each(|(Coord { x, y }, cell)| {
+            let value = match cell.chosen_pattern_id() {
+                Ok(pattern_id) => {
+                    *self.overlapping_patterns.pattern_top_left_value(pattern_id)
+                }
+                Err(_) => self.empty_value,
+            };
+            luma_image.put_pixel(x as u32, y as u32, Luma([value]));
+        });
+        DynamicImage::ImageLuma8(luma_image)
+    }
+
+    pub fn grid(&self) -> &Grid<u8> {
+        self.overlapping_patterns.grid()
+    }
+
+    pub fn id_grid_original_orientation(&self) -> Grid<PatternId> {
+        self.overlapping_patterns.id_grid_original_orientation()
+    }
+
+    pub fn pattern(&self, pattern_id: PatternId) -> &Pattern {
+        self.overlapping_patterns.pattern(pattern_id)
+    }
+
+    pub fn pattern_mut(&mut self, pattern_id: PatternId) -> &mut Pattern {
+        self.overlapping_patterns.pattern_mut(pattern_id)
+    }
+
+    pub fn global_stats(&self) -> GlobalStats {
+        self.overlapping_patterns.global_stats()
+    }
+
+    pub fn collapse_wave_retrying<W, F, RT, R>(
+        &self,
+        output_size: Size,
+        wrap: W,
+        forbid: F,
+        retry: RT,
+        rng: &mut R,
+    ) -> RT::Return
+    where
+        W: Wrap,
+        F: ForbidPattern + Send + Sync + Clone,
+        RT: retry::Retry,
+        R: Rng + Send + Sync + Clone,
+    {
+        let global_stats = self.global_stats();
+        let run = RunOwn::new_wrap_forbid(output_size, &global_stats, wrap, forbid, rng);
+        run.collapse_retrying(retry, rng)
+    }
+}
+
+impl CollapseFrontend<DynamicImage> for LumaPatterns {
+    fn output_from_wave(&self, wave: &Wave) -> DynamicImage {
+        self.image_from_wave(wave)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::retry::NumTimes;
+    use crate::{generate_image_with_rng, Orientation, WrapXY};
+    use coord_2d::Size;
+    use image::{DynamicImage, GrayImage, Luma};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+    use std::num::NonZeroU32;
+    use wfc::ForbidNothing;
+
+    #[test]
+    fn generate_luma_image() {
+        let input = GrayImage::from_fn(2, 1, |x, _| Luma([x as u8 * 100 + 7]));
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let output = generate_image_with_rng(
+            &DynamicImage::ImageLuma8(input),
+            NonZeroU32::new(1).unwrap(),
+            Size::new(4, 4),
+            &[Orientation::Original],
+            WrapXY,
+            ForbidNothing,
+            NumTimes(10),
+            &mut rng,
+        )
+        .unwrap();
+        let output = match output {
+            DynamicImage::ImageLuma8(output) => output,
+            _ => panic!("expected a luma image"),
+        };
+        assert_eq!((output.width(), output.height()), (4, 4));
+        assert!(output
+            .pixels()
+            .all(|&Luma([value])| value == 7 || value == 107));
+    }
+}
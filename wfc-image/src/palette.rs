@@ -0,0 +1,105 @@
+use crate::ImagePixel;
+use image::Rgba;
+use std::collections::HashMap;
+
+/// Replacement colours for some of the colours of the input, so that one set of patterns can
+/// be rendered in several colour themes. Colours without a replacement are drawn unchanged.
+#[derive(Debug, Clone)]
+pub struct PaletteSwap<P: ImagePixel = Rgba<u8>> {
+    replacements: HashMap<P::Key, P>,
+}
+
+impl<P: ImagePixel> Default for PaletteSwap<P> {
+    fn default() -> Self {
+        Self {
+            replacements: HashMap::new(),
+        }
+    }
+}
+
+impl<P: ImagePixel> PaletteSwap<P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Draw `from` as `to`, replacing any previous replacement for `from`
+    pub fn insert(&mut self, from: P, to: P) {
+        self.replacements.insert(from.to_key(), to);
+    }
+
+    pub fn remove(&mut self, from: P) {
+        self.replacements.remove(&from.to_key());
+    }
+
+    /// The colour `colour` is drawn as
+    pub fn swap(&self, colour: P) -> P {
+        self.swap_key(&colour.to_key())
+    }
+
+    pub(crate) fn swap_key(&self, key: &P::Key) -> P {
+        self.replacements
+            .get(key)
+            .copied()
+            .unwrap_or_else(|| P::from_key(key))
+    }
+}
+
+impl<P: ImagePixel> FromIterator<(P, P)> for PaletteSwap<P> {
+    fn from_iter<I: IntoIterator<Item = (P, P)>>(iter: I) -> Self {
+        let mut palette_swap = Self::new();
+        for (from, to) in iter {
+            palette_swap.insert(from, to);
+        }
+        palette_swap
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::retry::NumTimes;
+    use crate::{ImagePatterns, Orientation, Size, WrapXY};
+    use image::{DynamicImage, RgbaImage};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+    use std::num::NonZeroU32;
+    use wfc::ForbidNothing;
+
+    #[test]
+    fn swap_one_colour() {
+        let red = Rgba([255, 0, 0, 255]);
+        let green = Rgba([0, 255, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        let palette_swap = [(red, blue)].into_iter().collect::<PaletteSwap>();
+        assert_eq!(palette_swap.swap(red), blue);
+        assert_eq!(palette_swap.swap(green), green);
+        assert_eq!(palette_swap.swap(blue), blue);
+        let input = RgbaImage::from_fn(2, 1, |x, _| if x == 0 { red } else { green });
+        let image_patterns = ImagePatterns::new(
+            &DynamicImage::ImageRgba8(input),
+            NonZeroU32::new(1).unwrap(),
+            &[Orientation::Original],
+        );
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let wave = image_patterns
+            .collapse_wave_retrying(
+                Size::new(4, 4),
+                WrapXY,
+                ForbidNothing,
+                NumTimes(10),
+                &mut rng,
+            )
+            .unwrap();
+        let original = image_patterns.image_from_wave(&wave).to_rgba8();
+        let swapped = image_patterns
+            .image_from_wave_with_palette(&wave, &palette_swap)
+            .to_rgba8();
+        for (&original, &swapped) in original.pixels().zip(swapped.pixels()) {
+            if original == red {
+                assert_eq!(swapped, blue);
+            } else {
+                assert_eq!(swapped, original);
+            }
+        }
+    }
+}
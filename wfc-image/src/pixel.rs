@@ -0,0 +1,224 @@
+use coord_2d::{Coord, Size};
+use grid_2d::Grid;
+use image::{DynamicImage, ImageBuffer, Rgba};
+use std::hash::Hash;
+use wfc::overlapping::OverlappingPatterns;
+use wfc::GlobalStats;
+
+/// A pixel type which `ImagePatterns` can learn patterns from. Implemented for 8-bit,
+/// 16-bit and floating point RGBA pixels, so heightmaps and HDR textures keep their full
+/// precision.
+pub trait ImagePixel: Copy {
+    /// What pixels are compared and hashed as when finding patterns. Floating point pixels
+    /// are compared by their bits, as `f32` is neither `Eq` nor `Hash`.
+    type Key: Eq + Clone + Hash;
+
+    fn to_key(self) -> Self::Key;
+    fn from_key(key: &Self::Key) -> Self;
+
+    /// The colour of cells which aren't collapsed, until changed with `set_empty_colour`
+    fn transparent() -> Self;
+
+    /// Converts an image of any format to this pixel type
+    fn grid_from_image(image: &DynamicImage) -> Grid<Self>;
+
+    /// An image of the `DynamicImage` variant matching this pixel type
+    fn image_from_grid(grid: &Grid<Self>) -> DynamicImage;
+
+    fn to_channels(self) -> [f64; 4];
+
+    /// Integer channels are rounded to the nearest value and clamped to their range
+    fn from_channels(channels: [f64; 4]) -> Self;
+
+    #[doc(hidden)]
+    fn global_stats(
+        overlapping_patterns: &OverlappingPatterns<Self::Key>,
+    ) -> GlobalStats {
+        overlapping_patterns.global_stats()
+    }
+}
+
+impl ImagePixel for Rgba<u8> {
+    type Key = Self;
+
+    fn to_key(self) -> Self {
+        self
+    }
+
+    fn from_key(key: &Self) -> Self {
+        *key
+    }
+
+    fn transparent() -> Self {
+        Rgba([0, 0, 0, 0])
+    }
+
+    fn grid_from_image(image: &DynamicImage) -> Grid<Self> {
+        grid_from_image_buffer(&image.to_rgba8())
+    }
+
+    fn image_from_grid(grid: &Grid<Self>) -> DynamicImage {
+        DynamicImage::ImageRgba8(image_buffer_from_grid(grid))
+    }
+
+    fn to_channels(self) -> [f64; 4] {
+        self.0.map(|channel| channel as f64)
+    }
+
+    fn from_channels(channels: [f64; 4]) -> Self {
+        Rgba(channels.map(|channel| channel.round().clamp(0., u8::MAX as f64) as u8))
+    }
+
+    fn global_stats(overlapping_patterns: &OverlappingPatterns<Self>) -> GlobalStats {
+        // comparing pixels as `u32`s is much faster than comparing them channel by channel
+        GlobalStats::new(
+            overlapping_patterns
+                .pattern_descriptions_packed(|pixel| u32::from_ne_bytes(pixel.0)),
+        )
+    }
+}
+
+impl ImagePixel for Rgba<u16> {
+    type Key = Self;
+
+    fn to_key(self) -> Self {
+        self
+    }
+
+    fn from_key(key: &Self) -> Self {
+        *key
+    }
+
+    fn transparent() -> Self {
+        Rgba([0, 0, 0, 0])
+    }
+
+    fn grid_from_image(image: &DynamicImage) -> Grid<Self> {
+        grid_from_image_buffer(&image.to_rgba16())
+    }
+
+    fn image_from_grid(grid: &Grid<Self>) -> DynamicImage {
+        DynamicImage::ImageRgba16(image_buffer_from_grid(grid))
+    }
+
+    fn to_channels(self) -> [f64; 4] {
+        self.0.map(|channel| channel as f64)
+    }
+
+    fn from_channels(channels: [f64; 4]) -> Self {
+        Rgba(channels.map(|channel| channel.round().clamp(0., u16::MAX as f64) as u16))
+    }
+}
+
+impl ImagePixel for Rgba<f32> {
+    type Key = [u32; 4];
+
+    fn to_key(self) -> [u32; 4] {
+        self.0.map(f32::to_bits)
+    }
+
+    fn from_key(key: &[u32; 4]) -> Self {
+        Rgba(key.map(f32::from_bits))
+    }
+
+    fn transparent() -> Self {
+        Rgba([0., 0., 0., 0.])
+    }
+
+    fn grid_from_image(image: &DynamicImage) -> Grid<Self> {
+        grid_from_image_buffer(&image.to_rgba32f())
+    }
+
+    fn image_from_grid(grid: &Grid<Self>) -> DynamicImage {
+        DynamicImage::ImageRgba32F(image_buffer_from_grid(grid))
+    }
+
+    fn to_channels(self) -> [f64; 4] {
+        self.0.map(|channel| channel as f64)
+    }
+
+    fn from_channels(channels: [f64; 4]) -> Self {
+        Rgba(channels.map(|channel| channel as f32))
+    }
+}
+
+pub(crate) fn grid_from_image_buffer<T>(
+    image: &ImageBuffer<Rgba<T>, Vec<T>>,
+) -> Grid<Rgba<T>>
+where
+    T: image::Primitive,
+    Rgba<T>: image::Pixel<Subpixel = T>,
+{
+    let size = Size::new(image.width(), image.height());
+    Grid::new_fn(size, |Coord { x, y }| *image.get_pixel(x as u32, y as u32))
+}
+
+fn image_buffer_from_grid<T>(grid: &Grid<Rgba<T>>) -> ImageBuffer<Rgba<T>, Vec<T>>
+where
+    T: image::Primitive,
+    Rgba<T>: image::Pixel<Subpixel = T>,
+{
+    let size = grid.size();
+    ImageBuffer::from_fn(size.width(), size.height(), |x, y| {
+        *grid.get_checked(Coord::new(x as i32, y as i32))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::retry::NumTimes;
+    use crate::{ImagePatterns, Orientation, WrapXY};
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+    use std::num::NonZeroU32;
+    use wfc::ForbidNothing;
+
+    fn round_trip<P: ImagePixel>(input: DynamicImage) -> DynamicImage {
+        let image_patterns = ImagePatterns::<P>::new_pixel(
+            &input,
+            NonZeroU32::new(1).unwrap(),
+            &[Orientation::Original],
+        );
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let wave = image_patterns
+            .collapse_wave_retrying(
+                Size::new(4, 4),
+                WrapXY,
+                ForbidNothing,
+                NumTimes(10),
+                &mut rng,
+            )
+            .unwrap();
+        image_patterns.image_from_wave(&wave)
+    }
+
+    #[test]
+    fn round_trip_u16() {
+        let pixels = [Rgba([257, 1000, 65535, 65535]), Rgba([3, 2, 1, 65534])];
+        let input = ImageBuffer::from_fn(2, 1, |x, _| pixels[x as usize]);
+        let output = round_trip::<Rgba<u16>>(DynamicImage::ImageRgba16(input));
+        let output = match output {
+            DynamicImage::ImageRgba16(output) => output,
+            _ => panic!("expected a 16-bit image"),
+        };
+        assert_eq!((output.width(), output.height()), (4, 4));
+        assert!(output.pixels().all(|pixel| pixels.contains(pixel)));
+    }
+
+    #[test]
+    fn round_trip_f32() {
+        let pixels = [
+            Rgba([0.125, 0.3, 1.5, 1.]),
+            Rgba([0.001, 0.002, 0.003, 0.5]),
+        ];
+        let input = ImageBuffer::from_fn(2, 1, |x, _| pixels[x as usize]);
+        let output = round_trip::<Rgba<f32>>(DynamicImage::ImageRgba32F(input));
+        let output = match output {
+            DynamicImage::ImageRgba32F(output) => output,
+            _ => panic!("expected a floating point image"),
+        };
+        assert_eq!((output.width(), output.height()), (4, 4));
+        assert!(output.pixels().all(|pixel| pixels.contains(pixel)));
+    }
+}
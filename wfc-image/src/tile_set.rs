@@ -0,0 +1,201 @@
+use coord_2d::{Coord, Size};
+use direction::CardinalDirection;
+use grid_2d::Grid;
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::num::NonZeroU32;
+use wfc::retry::CollapseFrontend;
+use wfc::{GlobalStats, PatternId, Wave};
+
+/// A tile in an example arrangement which doesn't appear in the sprite sheet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownTile {
+    /// Position of the offending tile in the example, measured in tiles
+    pub coord: Coord,
+}
+
+impl fmt::Display for UnknownTile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the tile at ({}, {}) doesn't appear in the sprite sheet",
+            self.coord.x, self.coord.y
+        )
+    }
+}
+
+impl error::Error for UnknownTile {}
+
+/// A set of fixed-size tiles cut from a sprite sheet, for use with the "tiled model".
+/// Each tile becomes a pattern, whose id is its index in the sheet counting row by row.
+/// Adjacency rules and weights are learnt from example arrangements of the tiles, or
+/// specified explicitly, and collapsed waves are rendered by drawing whole tiles.
+pub struct ImageTileSet {
+    tile_size: Size,
+    tiles: Vec<RgbaImage>,
+    ids_by_pixels: HashMap<Vec<u8>, PatternId>,
+    weights: Vec<u32>,
+    allowed: Vec<[Vec<PatternId>; 4]>,
+    empty_colour: Rgba<u8>,
+}
+
+impl ImageTileSet {
+    /// Slices `sheet` into tiles of `tile_width` by `tile_height` pixels. Partial tiles at the
+    /// right and bottom edges are ignored. Initially no tile has a weight and no adjacencies
+    /// are allowed.
+    pub fn from_sprite_sheet(
+        sheet: &DynamicImage,
+        tile_width: NonZeroU32,
+        tile_height: NonZeroU32,
+    ) -> Self {
+        let tile_size = Size::new(tile_width.get(), tile_height.get());
+        let sheet = sheet.to_rgba8();
+        let tiles = slice_tiles(&sheet, tile_size);
+        let mut ids_by_pixels = HashMap::new();
+        for (pattern_id, tile) in tiles.iter().enumerate() {
+            ids_by_pixels
+                .entry(tile.as_raw().clone())
+                .or_insert(pattern_id as PatternId);
+        }
+        let num_tiles = tiles.len();
+        Self {
+            tile_size,
+            tiles,
+            ids_by_pixels,
+            weights: vec![0; num_tiles],
+            allowed: (0..num_tiles).map(|_| Default::default()).collect(),
+            empty_colour: Rgba([0, 0, 0, 0]),
+        }
+    }
+
+    pub fn tile_size(&self) -> Size {
+        self.tile_size
+    }
+
+    pub fn num_tiles(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn tile(&self, pattern_id: PatternId) -> &RgbaImage {
+        &self.tiles[pattern_id as usize]
+    }
+
+    /// Returns the id of the first tile in the sheet with exactly the same pixels as `tile`
+    pub fn tile_id(&self, tile: &RgbaImage) -> Option<PatternId> {
+        self.ids_by_pixels.get(tile.as_raw()).cloned()
+    }
+
+    /// Learn adjacency rules and weights from an example arrangement made of tiles from the
+    /// sheet. Every pair of neighbouring tiles in the example is allowed to be adjacent, and
+    /// each occurrence of a tile adds one to its weight. The example is not considered to wrap.
+    pub fn learn_from_example(
+        &mut self,
+        example: &DynamicImage,
+    ) -> Result<(), UnknownTile> {
+        let example = example.to_rgba8();
+        let example_size = Size::new(
+            example.width() / self.tile_size.width(),
+            example.height() / self.tile_size.height(),
+        );
+        let mut ids = Vec::with_capacity(example_size.count());
+        for (tile, coord) in slice_tiles(&example, self.tile_size)
+            .iter()
+            .zip(example_size.coord_iter_row_major())
+        {
+            ids.push(self.tile_id(tile).ok_or(UnknownTile { coord })?);
+        }
+        let id_grid = Grid::new_iterator(example_size, ids.into_iter());
+        for (coord, &pattern_id) in id_grid.enumerate() {
+            self.weights[pattern_id as usize] += 1;
+            for direction in [CardinalDirection::East, CardinalDirection::South] {
+                if let Some(&neighbour) = id_grid.get(coord + direction.coord()) {
+                    self.allow(pattern_id, direction, neighbour);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Allow `neighbour` to appear next to `pattern_id` in the given direction (and
+    /// `pattern_id` next to `neighbour` in the opposite direction)
+    pub fn allow(
+        &mut self,
+        pattern_id: PatternId,
+        direction: CardinalDirection,
+        neighbour: PatternId,
+    ) {
+        insert_unique(
+            &mut self.allowed[pattern_id as usize][direction as usize],
+            neighbour,
+        );
+        insert_unique(
+            &mut self.allowed[neighbour as usize][direction.opposite() as usize],
+            pattern_id,
+        );
+    }
+
+    /// Set the weight of a tile, overriding any weight learnt from examples. A tile with a
+    /// weight of 0 can only be placed if it's the only remaining possibility for a cell.
+    pub fn set_weight(&mut self, pattern_id: PatternId, weight: u32) {
+        self.weights[pattern_id as usize] = weight;
+    }
+
+    pub fn set_empty_colour(&mut self, empty_colour: Rgba<u8>) {
+        self.empty_colour = empty_colour;
+    }
+
+    pub fn global_stats(&self) -> GlobalStats {
+        let weights = self.weights.iter().map(|&w| NonZeroU32::new(w)).collect();
+        GlobalStats::from_adjacency_matrix(weights, self.allowed.clone())
+            .expect("adjacency rules are always recorded symmetrically")
+    }
+
+    /// Draws each cell of the wave as the tile it collapsed to. Cells which are not yet
+    /// collapsed are filled with the empty colour.
+    pub fn image_from_wave(&self, wave: &Wave) -> DynamicImage {
+        let size = wave.grid().size();
+        let mut rgba_image = RgbaImage::from_pixel(
+            size.width() * self.tile_size.width(),
+            size.height() * self.tile_size.height(),
+            self.empty_colour,
+        );
+        wave.grid().enumerate().for_each(|(Coord { x, y }, cell)| {
+            if let Ok(pattern_id) = cell.chosen_pattern_id() {
+                imageops::replace(
+                    &mut rgba_image,
+                    self.tile(pattern_id),
+                    x as i64 * self.tile_size.width() as i64,
+                    y as i64 * self.tile_size.height() as i64,
+                );
+            }
+        });
+        DynamicImage::ImageRgba8(rgba_image)
+    }
+}
+
+impl CollapseFrontend<DynamicImage> for ImageTileSet {
+    fn output_from_wave(&self, wave: &Wave) -> DynamicImage {
+        self.image_from_wave(wave)
+    }
+}
+
+fn slice_tiles(image: &RgbaImage, tile_size: Size) -> Vec<RgbaImage> {
+    let (width, height) = (tile_size.width(), tile_size.height());
+    let mut tiles = Vec::new();
+    for row in 0..(image.height() / height) {
+        for col in 0..(image.width() / width) {
+            let tile =
+                imageops::crop_imm(image, col * width, row * height, width, height);
+            tiles.push(tile.to_image());
+        }
+    }
+    tiles
+}
+
+fn insert_unique(ids: &mut Vec<PatternId>, pattern_id: PatternId) {
+    if !ids.contains(&pattern_id) {
+        ids.push(pattern_id);
+    }
+}
@@ -0,0 +1,370 @@
+use coord_2d::{Coord, Size};
+use grid_2d::Grid;
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+use std::num::NonZeroU32;
+use wfc::retry::CollapseFrontend;
+use wfc::{GlobalStats, PatternId, Wave};
+
+/// Where the colours of Wang tiles are, which determines when two tiles may be neighbours
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WangModel {
+    /// Colours are on the corners of tiles, listed north-west, north-east, south-east,
+    /// south-west. Neighbouring tiles must agree on the colours of the two corners they
+    /// share, so a collapsed wave describes a grid of corner colours one larger in each
+    /// dimension (the "dual grid").
+    Corner,
+    /// Colours are on the edges of tiles, listed north, east, south, west. Neighbouring
+    /// tiles must agree on the colour of the edge they share.
+    Edge,
+}
+
+/// A set of Wang tiles, whose adjacency rules come from the colours of their corners or edges
+/// rather than from example arrangements. Each tile becomes a pattern, whose id is the order
+/// it was added in, and collapsed waves are rendered by drawing whole tiles.
+pub struct WangTileSet {
+    model: WangModel,
+    tile_size: Size,
+    tiles: Vec<RgbaImage>,
+    colours: Vec<[u32; 4]>,
+    weights: Vec<u32>,
+    empty_colour: Rgba<u8>,
+}
+
+impl WangTileSet {
+    pub fn new(model: WangModel, tile_size: Size) -> Self {
+        Self {
+            model,
+            tile_size,
+            tiles: Vec::new(),
+            colours: Vec::new(),
+            weights: Vec::new(),
+            empty_colour: Rgba([0, 0, 0, 0]),
+        }
+    }
+
+    /// Every combination of `palette.len()` colours, each with a weight of 1. Tiles are drawn
+    /// by filling the quarter of the tile nearest each corner, or the triangle against each
+    /// edge, with that corner's or edge's colour from `palette`.
+    pub fn complete(model: WangModel, tile_size: Size, palette: &[Rgba<u8>]) -> Self {
+        let mut wang_tile_set = Self::new(model, tile_size);
+        let num_colours = palette.len() as u32;
+        for index in 0..num_colours.pow(4) {
+            let colours = [
+                index % num_colours,
+                (index / num_colours) % num_colours,
+                (index / num_colours.pow(2)) % num_colours,
+                (index / num_colours.pow(3)) % num_colours,
+            ];
+            wang_tile_set.add_drawn_tile(colours, palette, 1);
+        }
+        wang_tile_set
+    }
+
+    pub fn model(&self) -> WangModel {
+        self.model
+    }
+
+    pub fn tile_size(&self) -> Size {
+        self.tile_size
+    }
+
+    pub fn num_tiles(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn tile(&self, pattern_id: PatternId) -> &RgbaImage {
+        &self.tiles[pattern_id as usize]
+    }
+
+    /// The corner or edge colours of a tile, in the order described by `WangModel`
+    pub fn colours(&self, pattern_id: PatternId) -> [u32; 4] {
+        self.colours[pattern_id as usize]
+    }
+
+    /// Adds a tile drawn as `image`, returning its id. A tile with a weight of 0 can only be
+    /// placed if it's the only remaining possibility for a cell. Panics if the image isn't
+    /// the size of a tile.
+    pub fn add_tile(
+        &mut self,
+        image: &DynamicImage,
+        colours: [u32; 4],
+        weight: u32,
+    ) -> PatternId {
+        let image = image.to_rgba8();
+        assert_eq!(
+            Size::new(image.width(), image.height()),
+            self.tile_size,
+            "tile image differs in size from the tile set"
+        );
+        let pattern_id = self.tiles.len() as PatternId;
+        self.tiles.push(image);
+        self.colours.push(colours);
+        self.weights.push(weight);
+        pattern_id
+    }
+
+    /// Like `add_tile`, but the tile is drawn as described in `complete`, with its colours
+    /// used as indices into `palette`
+    pub fn add_drawn_tile(
+        &mut self,
+        colours: [u32; 4],
+        palette: &[Rgba<u8>],
+        weight: u32,
+    ) -> PatternId {
+        let image = draw_tile(self.model, self.tile_size, colours, palette);
+        self.add_tile(&DynamicImage::ImageRgba8(image), colours, weight)
+    }
+
+    pub fn set_weight(&mut self, pattern_id: PatternId, weight: u32) {
+        self.weights[pattern_id as usize] = weight;
+    }
+
+    pub fn set_empty_colour(&mut self, empty_colour: Rgba<u8>) {
+        self.empty_colour = empty_colour;
+    }
+
+    // The colours along the east, west, south and north sides of a tile, each listed from
+    // north to south or from west to east, so a side matches the facing side of a neighbour
+    // when they're equal
+    fn sides(&self, colours: [u32; 4]) -> ([u32; 2], [u32; 2], [u32; 2], [u32; 2]) {
+        let [a, b, c, d] = colours;
+        match self.model {
+            // north-west, north-east, south-east, south-west
+            WangModel::Corner => ([b, c], [a, d], [d, c], [a, b]),
+            // north, east, south, west
+            WangModel::Edge => ([b, b], [d, d], [c, c], [a, a]),
+        }
+    }
+
+    /// Tiles may be next to each other when they agree on the colours along the side they
+    /// share
+    pub fn global_stats(&self) -> GlobalStats {
+        let weights = self.weights.iter().map(|&w| NonZeroU32::new(w)).collect();
+        let allowed = self
+            .colours
+            .iter()
+            .map(|&colours| {
+                let (east, west, south, north) = self.sides(colours);
+                let mut allowed: [Vec<PatternId>; 4] = Default::default();
+                for (neighbour_id, &neighbour_colours) in self.colours.iter().enumerate()
+                {
+                    let neighbour_id = neighbour_id as PatternId;
+                    let (
+                        neighbour_east,
+                        neighbour_west,
+                        neighbour_south,
+                        neighbour_north,
+                    ) = self.sides(neighbour_colours);
+                    if north == neighbour_south {
+                        allowed[0].push(neighbour_id);
+                    }
+                    if east == neighbour_west {
+                        allowed[1].push(neighbour_id);
+                    }
+                    if south == neighbour_north {
+                        allowed[2].push(neighbour_id);
+                    }
+                    if west == neighbour_east {
+                        allowed[3].push(neighbour_id);
+                    }
+                }
+                allowed
+            })
+            .collect();
+        GlobalStats::from_adjacency_matrix(weights, allowed)
+            .expect("adjacency rules between wang tiles are always symmetric")
+    }
+
+    /// The colour of each corner of a wave collapsed with the corner model, which is one
+    /// larger than the wave in each dimension. Corners of cells which are not yet collapsed
+    /// are `None`. Panics if the tile set uses the edge model.
+    pub fn corner_colours(&self, wave: &Wave) -> Grid<Option<u32>> {
+        assert_eq!(
+            self.model,
+            WangModel::Corner,
+            "only the corner model has corner colours"
+        );
+        let size = wave.grid().size();
+        let mut corners =
+            Grid::new_clone(Size::new(size.width() + 1, size.height() + 1), None);
+        for (coord, cell) in wave.grid().enumerate() {
+            if let Ok(pattern_id) = cell.chosen_pattern_id() {
+                let offsets = [
+                    Coord::new(0, 0),
+                    Coord::new(1, 0),
+                    Coord::new(1, 1),
+                    Coord::new(0, 1),
+                ];
+                for (offset, colour) in offsets.iter().zip(self.colours(pattern_id)) {
+                    *corners.get_checked_mut(coord + *offset) = Some(colour);
+                }
+            }
+        }
+        corners
+    }
+
+    /// Draws each cell of the wave as the tile it collapsed to. Cells which are not yet
+    /// collapsed are filled with the empty colour.
+    pub fn image_from_wave(&self, wave: &Wave) -> DynamicImage {
+        let size = wave.grid().size();
+        let mut rgba_image = RgbaImage::from_pixel(
+            size.width() * self.tile_size.width(),
+            size.height() * self.tile_size.height(),
+            self.empty_colour,
+        );
+        wave.grid().enumerate().for_each(|(Coord { x, y }, cell)| {
+            if let Ok(pattern_id) = cell.chosen_pattern_id() {
+                imageops::replace(
+                    &mut rgba_image,
+                    self.tile(pattern_id),
+                    x as i64 * self.tile_size.width() as i64,
+                    y as i64 * self.tile_size.height() as i64,
+                );
+            }
+        });
+        DynamicImage::ImageRgba8(rgba_image)
+    }
+}
+
+impl CollapseFrontend<DynamicImage> for WangTileSet {
+    fn output_from_wave(&self, wave: &Wave) -> DynamicImage {
+        self.image_from_wave(wave)
+    }
+}
+
+fn draw_tile(
+    model: WangModel,
+    tile_size: Size,
+    colours: [u32; 4],
+    palette: &[Rgba<u8>],
+) -> RgbaImage {
+    let (width, height) = (tile_size.width(), tile_size.height());
+    RgbaImage::from_fn(width, height, |x, y| {
+        // position relative to the centre of the tile, scaled so the tile spans -1 to 1
+        let dx = (2 * x + 1) as f64 / width as f64 - 1.;
+        let dy = (2 * y + 1) as f64 / height as f64 - 1.;
+        let index = match model {
+            WangModel::Corner => match (dx < 0., dy < 0.) {
+                (true, true) => 0,
+                (false, true) => 1,
+                (false, false) => 2,
+                (true, false) => 3,
+            },
+            WangModel::Edge => {
+                if dy.abs() >= dx.abs() {
+                    if dy < 0. {
+                        0
+                    } else {
+                        2
+                    }
+                } else if dx > 0. {
+                    1
+                } else {
+                    3
+                }
+            }
+        };
+        palette[colours[index] as usize]
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use direction::CardinalDirection;
+    use rand::SeedableRng;
+    use rand_xorshift::XorShiftRng;
+    use wfc::wrap::WrapXY;
+    use wfc::{RunOwn, WaveCell};
+
+    const PALETTE: [Rgba<u8>; 2] = [Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255])];
+
+    // Whether a tile with colours `to` may be in `direction` from one with colours `from`,
+    // worked out from where the colours are on each model's tiles
+    fn may_neighbour(
+        model: WangModel,
+        from: [u32; 4],
+        direction: CardinalDirection,
+        to: [u32; 4],
+    ) -> bool {
+        match model {
+            // north-west, north-east, south-east, south-west
+            WangModel::Corner => match direction {
+                CardinalDirection::North => from[0] == to[3] && from[1] == to[2],
+                CardinalDirection::East => from[1] == to[0] && from[2] == to[3],
+                CardinalDirection::South => from[3] == to[0] && from[2] == to[1],
+                CardinalDirection::West => from[0] == to[1] && from[3] == to[2],
+            },
+            // north, east, south, west
+            WangModel::Edge => match direction {
+                CardinalDirection::North => from[0] == to[2],
+                CardinalDirection::East => from[1] == to[3],
+                CardinalDirection::South => from[2] == to[0],
+                CardinalDirection::West => from[3] == to[1],
+            },
+        }
+    }
+
+    fn check_model(model: WangModel) {
+        let wang_tile_set = WangTileSet::complete(model, Size::new(4, 4), &PALETTE);
+        assert_eq!(wang_tile_set.num_tiles(), 16);
+        for from in 0..16 {
+            let (east, west, south, north) =
+                wang_tile_set.sides(wang_tile_set.colours(from));
+            for to in 0..16 {
+                let (to_east, to_west, to_south, to_north) =
+                    wang_tile_set.sides(wang_tile_set.colours(to));
+                for (direction, matches) in [
+                    (CardinalDirection::North, north == to_south),
+                    (CardinalDirection::East, east == to_west),
+                    (CardinalDirection::South, south == to_north),
+                    (CardinalDirection::West, west == to_east),
+                ] {
+                    assert_eq!(
+                        matches,
+                        may_neighbour(
+                            model,
+                            wang_tile_set.colours(from),
+                            direction,
+                            wang_tile_set.colours(to)
+                        ),
+                        "{:?} tile {} {:?} of tile {}",
+                        model,
+                        to,
+                        direction,
+                        from
+                    );
+                }
+            }
+        }
+        let global_stats = wang_tile_set.global_stats();
+        let mut rng = XorShiftRng::seed_from_u64(0);
+        let mut run = RunOwn::new_wrap(Size::new(8, 8), &global_stats, WrapXY, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let wave = run.into_wave();
+        let colours =
+            |cell: &WaveCell| wang_tile_set.colours(cell.chosen_pattern_id().unwrap());
+        for (coord, cell) in wave.grid().enumerate() {
+            for direction in [CardinalDirection::East, CardinalDirection::South] {
+                if let Some(neighbour) = wave.grid().get(coord + direction.coord()) {
+                    assert!(may_neighbour(
+                        model,
+                        colours(cell),
+                        direction,
+                        colours(neighbour)
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn corner() {
+        check_model(WangModel::Corner);
+    }
+
+    #[test]
+    fn edge() {
+        check_model(WangModel::Edge);
+    }
+}
@@ -0,0 +1,17 @@
+use rand::SeedableRng;
+use wfc::dungeon::{self, Connectivity, DungeonGenerator};
+use wfc::Size;
+
+fn main() {
+    let seed = ::std::env::args()
+        .nth(1)
+        .map(|seed| seed.parse().expect("seed must be a number"))
+        .unwrap_or(0);
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+    let mut generator = DungeonGenerator::new();
+    generator.set_connectivity(Connectivity::FillDisconnected);
+    let dungeon = generator
+        .generate(Size::new(60, 24), &mut rng)
+        .expect("Too many contradictions");
+    print!("{}", dungeon::dungeon_to_string(&dungeon));
+}
@@ -0,0 +1,217 @@
+//! Learning adjacency rules between individual tiles from example grids, combined with
+//! hand-authored rules. Each tile becomes one pattern.
+
+use crate::wfc::{GlobalStats, PatternDescription, PatternId, PatternTable, Wave};
+use direction::{CardinalDirection, CardinalDirectionTable, CardinalDirections};
+use grid_2d::Grid;
+use hashbrown::{HashMap, HashSet};
+use std::hash::Hash;
+use std::num::NonZeroU32;
+
+type Rule = (PatternId, CardinalDirection, PatternId);
+
+#[derive(Debug, Clone)]
+pub struct AdjacencyLearner<T: Eq + Clone + Hash> {
+    ids: HashMap<T, PatternId>,
+    tiles: Vec<T>,
+    counts: Vec<u32>,
+    // (a, direction, b) means that b may be placed in direction of a. Rules are always
+    // stored alongside their mirror image.
+    allowed: HashSet<Rule>,
+    forbidden: HashSet<Rule>,
+}
+
+impl<T: Eq + Clone + Hash> Default for AdjacencyLearner<T> {
+    fn default() -> Self {
+        Self {
+            ids: HashMap::new(),
+            tiles: Vec::new(),
+            counts: Vec::new(),
+            allowed: HashSet::new(),
+            forbidden: HashSet::new(),
+        }
+    }
+}
+
+impl<T: Eq + Clone + Hash> AdjacencyLearner<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn id(&mut self, tile: &T) -> PatternId {
+        if let Some(&pattern_id) = self.ids.get(tile) {
+            return pattern_id;
+        }
+        let pattern_id = self.tiles.len() as PatternId;
+        self.ids.insert(tile.clone(), pattern_id);
+        self.tiles.push(tile.clone());
+        self.counts.push(0);
+        pattern_id
+    }
+
+    fn insert(
+        set: &mut HashSet<Rule>,
+        a: PatternId,
+        direction: CardinalDirection,
+        b: PatternId,
+    ) {
+        set.insert((a, direction, b));
+        set.insert((b, direction.opposite(), a));
+    }
+
+    /// Every pair of neighbouring cells of `grid` becomes an allowed adjacency, and each
+    /// cell adds one to the weight of its tile. The grid is not considered to wrap around.
+    pub fn add_example(&mut self, grid: &Grid<T>) {
+        let ids = Grid::new_grid_map_ref(grid, |tile| Some(self.id(tile)));
+        self.add_example_ids(&ids);
+    }
+
+    /// Like `add_example`, but cells containing `empty` are skipped, so `empty` isn't learnt
+    /// as a tile, and no adjacencies are learnt between it and its neighbours
+    pub fn add_example_with_empty(&mut self, grid: &Grid<T>, empty: &T) {
+        let ids = Grid::new_grid_map_ref(grid, |tile| {
+            if tile == empty {
+                None
+            } else {
+                Some(self.id(tile))
+            }
+        });
+        self.add_example_ids(&ids);
+    }
+
+    fn add_example_ids(&mut self, ids: &Grid<Option<PatternId>>) {
+        for (coord, &pattern_id) in ids.enumerate() {
+            let pattern_id = match pattern_id {
+                Some(pattern_id) => pattern_id,
+                None => continue,
+            };
+            self.counts[pattern_id as usize] += 1;
+            for direction in [CardinalDirection::East, CardinalDirection::South] {
+                if let Some(&Some(neighbour_id)) = ids.get(coord + direction.coord()) {
+                    Self::insert(&mut self.allowed, pattern_id, direction, neighbour_id);
+                }
+            }
+        }
+    }
+
+    /// Allow `b` to be placed in `direction` of `a` (and so `a` in the opposite direction of
+    /// `b`). Tiles which don't appear in any example are given a weight of 1.
+    pub fn allow(&mut self, a: T, direction: CardinalDirection, b: T) {
+        let a = self.id(&a);
+        let b = self.id(&b);
+        Self::insert(&mut self.allowed, a, direction, b);
+    }
+
+    /// Prevent `b` from being placed in `direction` of `a`, even if examples or `allow` permit
+    /// it
+    pub fn forbid(&mut self, a: T, direction: CardinalDirection, b: T) {
+        let a = self.id(&a);
+        let b = self.id(&b);
+        Self::insert(&mut self.forbidden, a, direction, b);
+    }
+
+    pub fn num_patterns(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn pattern_id(&self, tile: &T) -> Option<PatternId> {
+        self.ids.get(tile).cloned()
+    }
+
+    pub fn tile(&self, pattern_id: PatternId) -> &T {
+        &self.tiles[pattern_id as usize]
+    }
+
+    pub fn pattern_descriptions(&self) -> PatternTable<PatternDescription> {
+        let mut allowed_neighbours =
+            vec![CardinalDirectionTable::<Vec<PatternId>>::default(); self.tiles.len()];
+        for &(a, direction, b) in self.allowed.difference(&self.forbidden) {
+            allowed_neighbours[a as usize].get_mut(direction).push(b);
+        }
+        allowed_neighbours
+            .into_iter()
+            .zip(self.counts.iter())
+            .map(|(mut allowed_neighbours, &count)| {
+                for direction in CardinalDirections {
+                    allowed_neighbours.get_mut(direction).sort_unstable();
+                }
+                PatternDescription::new(
+                    Some(NonZeroU32::new(count).unwrap_or(NonZeroU32::new(1).unwrap())),
+                    allowed_neighbours,
+                )
+            })
+            .collect()
+    }
+
+    pub fn global_stats(&self) -> GlobalStats {
+        GlobalStats::new(self.pattern_descriptions())
+    }
+
+    /// The tile chosen for each cell of a wave. Panics if a cell is not collapsed.
+    pub fn grid_from_wave(&self, wave: &Wave) -> Grid<T> {
+        Grid::new_grid_map_ref(wave.grid(), |cell| {
+            let pattern_id = cell
+                .chosen_pattern_id()
+                .expect("wave is not fully collapsed");
+            self.tile(pattern_id).clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use coord_2d::{Coord, Size};
+
+    #[test]
+    fn adjacency_learner() {
+        // 'a' and 'b' alternate horizontally
+        let example = Grid::new_fn(
+            Size::new(4, 2),
+            |Coord { x, .. }| {
+                if x % 2 == 0 {
+                    'a'
+                } else {
+                    'b'
+                }
+            },
+        );
+        let mut learner = AdjacencyLearner::new();
+        learner.add_example(&example);
+        learner.allow('a', CardinalDirection::East, 'c');
+        learner.forbid('b', CardinalDirection::East, 'a');
+        let a = learner.pattern_id(&'a').unwrap();
+        let b = learner.pattern_id(&'b').unwrap();
+        let c = learner.pattern_id(&'c').unwrap();
+        let descriptions = learner.pattern_descriptions();
+        assert_eq!(descriptions[a].weight, NonZeroU32::new(4));
+        assert_eq!(descriptions[c].weight, NonZeroU32::new(1));
+        let mut east_of_a = vec![b, c];
+        east_of_a.sort_unstable();
+        assert_eq!(
+            descriptions[a]
+                .allowed_neighbours
+                .get(CardinalDirection::East),
+            &east_of_a
+        );
+        assert_eq!(
+            descriptions[a]
+                .allowed_neighbours
+                .get(CardinalDirection::West),
+            &Vec::<PatternId>::new()
+        );
+        assert_eq!(
+            descriptions[c]
+                .allowed_neighbours
+                .get(CardinalDirection::West),
+            &vec![a]
+        );
+        assert_eq!(
+            descriptions[a]
+                .allowed_neighbours
+                .get(CardinalDirection::South),
+            &vec![a]
+        );
+        assert_eq!(learner.tile(c), &'c');
+    }
+}
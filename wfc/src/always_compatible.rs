@@ -0,0 +1,165 @@
+//! A wrapper around `RunBorrow` for editing cells by hand, whose type tracks whether there
+//! are manual edits which haven't been propagated yet. Observing, stepping and reading the
+//! wave are only possible in the `Ready` state, and the only way back to `Ready` from
+//! `Manual` is to propagate, so a wave can't be collapsed or inspected while it is
+//! inconsistent.
+
+use crate::wfc::{
+    ForbidPattern, Observe, PatternId, PropagateError, RunBorrow, Wave, WaveCellRef,
+};
+use crate::wrap::{Wrap, WrapXY};
+use crate::ForbidNothing;
+use coord_2d::Coord;
+use rand::Rng;
+use std::marker::PhantomData;
+
+/// Every edit has been propagated, so the wave is consistent
+pub struct Ready;
+
+/// Patterns may have been forbidden without being propagated
+pub struct Manual;
+
+pub struct AlwaysCompatible<'a, State, W: Wrap = WrapXY, F: ForbidPattern = ForbidNothing>
+{
+    run: RunBorrow<'a, W, F>,
+    // set if a manual edit left a cell with no compatible patterns
+    contradiction: bool,
+    state: PhantomData<State>,
+}
+
+impl<'a, W: Wrap, F: ForbidPattern> AlwaysCompatible<'a, Ready, W, F> {
+    pub fn new(run: RunBorrow<'a, W, F>) -> Self {
+        Self {
+            run,
+            contradiction: false,
+            state: PhantomData,
+        }
+    }
+
+    pub fn into_run(self) -> RunBorrow<'a, W, F> {
+        self.run
+    }
+
+    pub fn wave(&self) -> &Wave {
+        self.run.wave()
+    }
+
+    pub fn wave_cell_ref(&self, coord: Coord) -> WaveCellRef<'_> {
+        self.run.wave_cell_ref(coord)
+    }
+
+    /// See `RunBorrow::step`
+    pub fn step<R: Rng>(&mut self, rng: &mut R) -> Result<Observe, PropagateError> {
+        self.run.step(rng)
+    }
+
+    /// See `RunBorrow::collapse`
+    pub fn collapse<R: Rng>(&mut self, rng: &mut R) -> Result<(), PropagateError> {
+        self.run.collapse(rng)
+    }
+
+    /// Start editing cells by hand
+    pub fn edit(self) -> AlwaysCompatible<'a, Manual, W, F> {
+        AlwaysCompatible {
+            run: self.run,
+            contradiction: false,
+            state: PhantomData,
+        }
+    }
+}
+
+impl<'a, W: Wrap, F: ForbidPattern> AlwaysCompatible<'a, Manual, W, F> {
+    /// Removes `pattern_id` from the cell at `coord`. Returns an error if this leaves the
+    /// cell with no compatible patterns, after which further edits are ignored and
+    /// `propagate` resets the run.
+    pub fn forbid_pattern(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+    ) -> Result<(), PropagateError> {
+        if self.contradiction {
+            return Err(PropagateError::Contradiction);
+        }
+        let result = self.run.forbid_pattern_unpropagated(coord, pattern_id);
+        self.contradiction = result.is_err();
+        result
+    }
+
+    /// Removes every pattern except `pattern_id` from the cell at `coord`. Contradictions
+    /// are handled as by `forbid_pattern`.
+    pub fn forbid_all_patterns_except(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+    ) -> Result<(), PropagateError> {
+        if self.contradiction {
+            return Err(PropagateError::Contradiction);
+        }
+        let result = self
+            .run
+            .forbid_all_patterns_except_unpropagated(coord, pattern_id);
+        self.contradiction = result.is_err();
+        result
+    }
+
+    /// Propagates the edits, returning to the `Ready` state. If the edits caused a
+    /// contradiction, the run is reset and the error is returned alongside it.
+    pub fn propagate<R: Rng>(
+        mut self,
+        rng: &mut R,
+    ) -> (
+        AlwaysCompatible<'a, Ready, W, F>,
+        Result<(), PropagateError>,
+    ) {
+        let result = if self.contradiction {
+            self.run.reset(rng);
+            Err(PropagateError::Contradiction)
+        } else {
+            self.run.propagate_only(rng)
+        };
+        (AlwaysCompatible::new(self.run), result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::checkerboard_stats;
+    use crate::Context;
+    use coord_2d::Size;
+    use rand::SeedableRng;
+
+    #[test]
+    fn edit_and_propagate() {
+        let global_stats = checkerboard_stats();
+        let mut context = Context::new();
+        let mut wave = Wave::new(Size::new(4, 4));
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let run = RunBorrow::new(&mut context, &mut wave, &global_stats, &mut rng);
+        let mut manual = AlwaysCompatible::new(run).edit();
+        manual
+            .forbid_all_patterns_except(Coord::new(0, 0), 1)
+            .unwrap();
+        let (ready, result) = manual.propagate(&mut rng);
+        result.unwrap();
+        // the edit was propagated to the neighbouring cell
+        assert_eq!(
+            ready
+                .wave_cell_ref(Coord::new(1, 0))
+                .chosen_pattern_id()
+                .ok(),
+            Some(0)
+        );
+        // adjacent cells can't both be 1
+        let mut manual = ready.edit();
+        manual.forbid_pattern(Coord::new(0, 1), 0).unwrap_err();
+        assert!(manual.forbid_pattern(Coord::new(2, 2), 0).is_err());
+        let (mut ready, result) = manual.propagate(&mut rng);
+        assert!(matches!(result, Err(PropagateError::Contradiction)));
+        assert!(ready
+            .wave_cell_ref(Coord::new(0, 0))
+            .chosen_pattern_id()
+            .is_err());
+        ready.collapse(&mut rng).unwrap();
+    }
+}
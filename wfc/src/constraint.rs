@@ -0,0 +1,1053 @@
+use crate::{
+    wfc::{
+        ForbidInterface, ForbidPattern, GlobalStats, ObserveInterface, PatternId,
+        PatternTable, PropagateError, RunBorrow, Wave, WaveCellRef,
+    },
+    wrap::{self, Wrap},
+};
+use coord_2d::{Coord, Size};
+use direction::{CardinalDirection, CardinalDirectionTable, CardinalDirections};
+use grid_2d::Grid;
+use hashbrown::HashMap;
+use rand::Rng;
+use std::borrow::BorrowMut;
+use std::hash::Hash;
+
+/// Prevents any pattern from being observed within `radius` cells (by euclidean distance,
+/// accounting for wrapping) of another observation of the same pattern, breaking up the
+/// obvious repetition that outputs often exhibit. Cells whose pattern is decided by propagation
+/// rather than observation are not restricted.
+#[derive(Debug, Clone, Copy)]
+pub struct ForbidRepetition {
+    pub radius: u32,
+}
+
+impl ForbidRepetition {
+    pub fn new(radius: u32) -> Self {
+        Self { radius }
+    }
+}
+
+impl ForbidPattern for ForbidRepetition {
+    fn forbid<W: Wrap, R: Rng>(&mut self, _fi: &mut ForbidInterface<W>, _rng: &mut R) {}
+    fn observed<W: Wrap>(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+        oi: &mut ObserveInterface<W>,
+    ) {
+        // each cell is visited once, and a radius wide enough to wrap all the way around
+        // mustn't reach back to the observed cell
+        for near in wrap::coords_within_distance::<W>(coord, self.radius, oi.wave_size())
+        {
+            if near == coord {
+                continue;
+            }
+            if oi.forbid_pattern(near, pattern_id).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Forbids `pattern_ids` in every cell within `distance` cells (by euclidean distance) of
+/// `coord`, e.g. to keep hazards away from a spawn point. Distance is measured the shorter way
+/// around along axes which wrap, so the forbidden area continues across the edges of a
+/// wrapping output rather than being cut off.
+#[derive(Debug, Clone)]
+pub struct ForbidNear {
+    pub pattern_ids: Vec<PatternId>,
+    pub coord: Coord,
+    pub distance: u32,
+}
+
+impl ForbidNear {
+    pub fn new(pattern_ids: Vec<PatternId>, coord: Coord, distance: u32) -> Self {
+        Self {
+            pattern_ids,
+            coord,
+            distance,
+        }
+    }
+}
+
+impl ForbidPattern for ForbidNear {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        let coords =
+            wrap::coords_within_distance::<W>(self.coord, self.distance, fi.wave_size());
+        for coord in coords {
+            for &pattern_id in self.pattern_ids.iter() {
+                if fi.forbid_pattern(coord, pattern_id, rng).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Places exactly `count` instances of the patterns in `pattern_ids`, with at least
+/// `min_distance` cells (by euclidean distance, accounting for wrapping) between each pair.
+/// Positions are distinct and chosen at random, and each is forced to be one of `pattern_ids`
+/// chosen at random, while the patterns are forbidden everywhere else. If forcing the
+/// placements leads to a contradiction, or there's no room for them, new positions are
+/// chosen, up to `max_attempts` times, after which the wave is left without any placements.
+/// Failing to find room is reported as a contradiction at (0, 0), so in either case
+/// `RunBorrow::try_reset`, `RunBorrow::reset_checked` and the checked constructors report the
+/// failure. New positions are also chosen each time the run is reset.
+#[derive(Debug, Clone)]
+pub struct PlaceExactly {
+    pub pattern_ids: Vec<PatternId>,
+    pub count: usize,
+    pub min_distance: u32,
+    pub max_attempts: usize,
+}
+
+impl PlaceExactly {
+    pub fn new(pattern_ids: Vec<PatternId>, count: usize, min_distance: u32) -> Self {
+        Self {
+            pattern_ids,
+            count,
+            min_distance,
+            max_attempts: 100,
+        }
+    }
+
+    // Rejection sampling of coords which are far enough apart. Returns `None` if too many
+    // samples are rejected, e.g. because the wave is too small for `count` placements.
+    fn choose_coords<W: Wrap, R: Rng>(
+        &self,
+        fi: &ForbidInterface<W>,
+        rng: &mut R,
+    ) -> Option<Vec<Coord>> {
+        let wave_size = fi.wave_size();
+        let mut too_close = Grid::new_copy(wave_size, false);
+        let mut coords = Vec::with_capacity(self.count);
+        let radius = self.min_distance as i32 - 1;
+        let min_distance_squared = self.min_distance as i32 * self.min_distance as i32;
+        let max_samples = 100 * self.count.max(1);
+        for _ in 0..max_samples {
+            if coords.len() == self.count {
+                break;
+            }
+            let coord = Coord::new(
+                rng.gen_range(0..wave_size.width()) as i32,
+                rng.gen_range(0..wave_size.height()) as i32,
+            );
+            if *too_close.get_checked(coord) {
+                continue;
+            }
+            coords.push(coord);
+            // a `min_distance` of 0 still requires distinct positions
+            *too_close.get_checked_mut(coord) = true;
+            for y in -radius..=radius {
+                for x in -radius..=radius {
+                    if x * x + y * y >= min_distance_squared {
+                        continue;
+                    }
+                    if let Some(coord) = fi.normalize_coord(coord + Coord::new(x, y)) {
+                        *too_close.get_checked_mut(coord) = true;
+                    }
+                }
+            }
+        }
+        if coords.len() == self.count {
+            Some(coords)
+        } else {
+            None
+        }
+    }
+
+    fn try_place<W: Wrap, R: Rng>(
+        &self,
+        coords: &[Coord],
+        fi: &mut ForbidInterface<W>,
+        rng: &mut R,
+    ) -> Result<(), PropagateError> {
+        let wave_size = fi.wave_size();
+        for coord in wave_size.coord_iter_row_major() {
+            if coords.contains(&coord) {
+                let pattern_id =
+                    self.pattern_ids[rng.gen_range(0..self.pattern_ids.len())];
+                fi.forbid_all_patterns_except(coord, pattern_id, rng)?;
+            } else {
+                for &pattern_id in self.pattern_ids.iter() {
+                    fi.forbid_pattern(coord, pattern_id, rng)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ForbidPattern for PlaceExactly {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        if self.pattern_ids.is_empty() {
+            return;
+        }
+        let _ = fi.retry(self.max_attempts, rng, |fi, rng| {
+            match self.choose_coords(fi, rng) {
+                Some(coords) => self.try_place(&coords, fi, rng),
+                None => {
+                    // emptying a cell forces the contradiction which reports the failure
+                    let (coord, pattern_id) = (Coord::new(0, 0), self.pattern_ids[0]);
+                    fi.forbid_all_patterns_except(coord, pattern_id, rng)?;
+                    fi.forbid_pattern(coord, pattern_id, rng)
+                }
+            }
+        });
+    }
+}
+
+/// Limits the number of cells decided to be any of `pattern_ids` (e.g. the patterns
+/// containing a particular resource in a game map) to `max_fraction` of the output. Cells
+/// are counted after the wave is reset and at each observation, including those decided by
+/// propagation or by `ForbidPattern`s applied before this one. Once the limit is reached,
+/// the patterns are forbidden in every undecided cell. Propagation can decide several cells
+/// at once, so the limit may be overshot by cells decided in the same step that reaches it.
+#[derive(Debug, Clone)]
+pub struct MaxFraction {
+    pub pattern_ids: Vec<PatternId>,
+    pub max_fraction: f32,
+    count: usize,
+    // cells which were undecided when last counted
+    undecided: Vec<Coord>,
+}
+
+impl MaxFraction {
+    pub fn new(pattern_ids: Vec<PatternId>, max_fraction: f32) -> Self {
+        Self {
+            pattern_ids,
+            max_fraction,
+            count: 0,
+            undecided: Vec::new(),
+        }
+    }
+
+    /// The number of cells decided to be any of `pattern_ids` when cells were last counted
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    fn budget(&self, wave_size: Size) -> usize {
+        (self.max_fraction * wave_size.count() as f32).floor() as usize
+    }
+
+    // Counts the cells decided since they were last counted, returning whether the limit has
+    // been reached
+    fn recount<'a, C>(&mut self, wave_size: Size, wave_cell_ref: C) -> bool
+    where
+        C: Fn(Coord) -> WaveCellRef<'a>,
+    {
+        let pattern_ids = &self.pattern_ids;
+        let count = &mut self.count;
+        self.undecided
+            .retain(|&coord| match wave_cell_ref(coord).chosen_pattern_id() {
+                Ok(pattern_id) => {
+                    if pattern_ids.contains(&pattern_id) {
+                        *count += 1;
+                    }
+                    false
+                }
+                Err(_) => true,
+            });
+        self.count >= self.budget(wave_size)
+    }
+}
+
+impl ForbidPattern for MaxFraction {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        self.count = 0;
+        self.undecided = fi.wave_size().coord_iter_row_major().collect();
+        if !self.recount(fi.wave_size(), |coord| fi.wave_cell_ref(coord)) {
+            return;
+        }
+        for &coord in self.undecided.iter() {
+            for &pattern_id in self.pattern_ids.iter() {
+                if fi.forbid_pattern(coord, pattern_id, rng).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    fn observed<W: Wrap>(
+        &mut self,
+        _coord: Coord,
+        _pattern_id: PatternId,
+        oi: &mut ObserveInterface<W>,
+    ) {
+        if !self.recount(oi.wave_size(), |coord| oi.wave_cell_ref(coord)) {
+            return;
+        }
+        for &coord in self.undecided.iter() {
+            for &pattern_id in self.pattern_ids.iter() {
+                if oi.forbid_pattern(coord, pattern_id).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Cells of the output painted with labels, for level editors where the user paints areas
+/// which must or mustn't contain certain patterns. Each label is tagged with the patterns it
+/// forbids. Painting a cell which had no label can be applied to a run incrementally with
+/// `ConstraintCanvas::apply`, which only propagates to the cells affected. Changes which relax
+/// constraints (erasing or repainting a cell, or retagging a label) require the run to be
+/// reset, which `apply` does automatically.
+#[derive(Debug, Clone)]
+pub struct ConstraintCanvas<L: Eq + Hash + Clone> {
+    labels: Grid<Option<L>>,
+    forbidden_by_label: HashMap<L, Vec<PatternId>>,
+    // painted cells which haven't been applied to the wave
+    pending: Vec<Coord>,
+    needs_reset: bool,
+}
+
+impl<L: Eq + Hash + Clone> ConstraintCanvas<L> {
+    /// `size` must match the size of the wave the canvas is applied to
+    pub fn new(size: Size) -> Self {
+        Self {
+            labels: Grid::new_fn(size, |_| None),
+            forbidden_by_label: HashMap::new(),
+            pending: Vec::new(),
+            needs_reset: false,
+        }
+    }
+
+    /// Cells painted with `label` may not be any of `pattern_ids`
+    pub fn tag_forbidden(&mut self, label: L, pattern_ids: Vec<PatternId>) {
+        self.forbidden_by_label.insert(label, pattern_ids);
+        self.needs_reset = true;
+    }
+
+    /// Cells painted with `label` must be one of `pattern_ids`
+    pub fn tag_forced(
+        &mut self,
+        label: L,
+        pattern_ids: &[PatternId],
+        num_patterns: usize,
+    ) {
+        let forbidden = (0..num_patterns as PatternId)
+            .filter(|pattern_id| !pattern_ids.contains(pattern_id))
+            .collect();
+        self.tag_forbidden(label, forbidden);
+    }
+
+    pub fn label(&self, coord: Coord) -> Option<&L> {
+        self.labels.get(coord).and_then(Option::as_ref)
+    }
+
+    pub fn paint(&mut self, coord: Coord, label: L) {
+        let cell = self.labels.get_checked_mut(coord);
+        match cell {
+            None => self.pending.push(coord),
+            Some(previous) if *previous != label => self.needs_reset = true,
+            Some(_) => (),
+        }
+        *cell = Some(label);
+    }
+
+    pub fn erase(&mut self, coord: Coord) {
+        if self.labels.get_checked_mut(coord).take().is_some() {
+            self.needs_reset = true;
+        }
+    }
+
+    fn forbid_cell<W: Wrap, R: Rng>(
+        &self,
+        coord: Coord,
+        fi: &mut ForbidInterface<W>,
+        rng: &mut R,
+    ) -> Result<(), PropagateError> {
+        let forbidden = match self.label(coord) {
+            Some(label) => self.forbidden_by_label.get(label),
+            None => None,
+        };
+        for &pattern_id in forbidden.into_iter().flatten() {
+            fi.forbid_pattern(coord, pattern_id, rng)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the changes made since the canvas was last applied to `run`, whose
+    /// `ForbidPattern` is the canvas. Only newly painted cells are forbidden if possible,
+    /// otherwise the run is reset. On contradiction (e.g. when painting over cells which have
+    /// already been decided) the run is reset and the contradiction is returned.
+    pub fn apply<W, F, R>(
+        run: &mut RunBorrow<W, F>,
+        rng: &mut R,
+    ) -> Result<(), PropagateError>
+    where
+        W: Wrap,
+        F: ForbidPattern + BorrowMut<Self>,
+        R: Rng,
+    {
+        if run.forbid_mut().borrow_mut().needs_reset {
+            return run
+                .try_reset(1, rng)
+                .map_err(|_| PropagateError::Contradiction);
+        }
+        run.forbid_with(rng, |canvas, fi, rng| {
+            let canvas: &mut Self = canvas.borrow_mut();
+            for coord in std::mem::take(&mut canvas.pending) {
+                if canvas.forbid_cell(coord, fi, rng).is_err() {
+                    return;
+                }
+            }
+        })
+    }
+}
+
+impl<L: Eq + Hash + Clone> ForbidPattern for ConstraintCanvas<L> {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        self.pending.clear();
+        self.needs_reset = false;
+        for coord in self.labels.coord_iter() {
+            if self.forbid_cell(coord, fi, rng).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Reasons `ConstraintChannel::prepare` can reject published cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelConflict {
+    /// The cell of the publishing wave at `coord` (in the publisher's coordinates) isn't
+    /// decided
+    Undecided { coord: Coord },
+    /// `coord` (in the publisher's coordinates) is outside the publishing wave
+    OutOfBounds { coord: Coord },
+    /// The cell at `coord` (in the subscriber's coordinates) was already given a different
+    /// pattern
+    Mismatch {
+        coord: Coord,
+        existing: PatternId,
+        published: PatternId,
+    },
+}
+
+/// Coordinates the borders between several runs, e.g. chunks or layers of a larger world.
+/// Runs which have been collapsed publish the patterns of some of their cells in two phases:
+/// `prepare` checks the cells for conflicts with everything published so far, then `commit`
+/// makes them visible to subscribers (or `abort` discards them). A run which should match the
+/// published cells uses the `ForceCells` returned by `subscribe` as its `ForbidPattern`. If
+/// the forced cells are incompatible with each other under the subscriber's rules,
+/// `RunBorrow::try_reset` reports it.
+#[derive(Debug, Clone, Default)]
+pub struct ConstraintChannel {
+    committed: HashMap<Coord, PatternId>,
+    prepared: HashMap<Coord, PatternId>,
+}
+
+impl ConstraintChannel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stages the chosen patterns of the cells of `wave` at `coords`, to be forced at
+    /// `coord + offset` in subscribers. Fails without staging anything if a coord is outside
+    /// `wave`, if a cell isn't decided, or if a cell was already committed or prepared with a
+    /// different pattern.
+    pub fn prepare<I: IntoIterator<Item = Coord>>(
+        &mut self,
+        wave: &Wave,
+        coords: I,
+        offset: Coord,
+    ) -> Result<(), ChannelConflict> {
+        let mut prepared = HashMap::new();
+        for coord in coords {
+            let published = wave
+                .grid()
+                .get(coord)
+                .ok_or(ChannelConflict::OutOfBounds { coord })?
+                .chosen_pattern_id()
+                .map_err(|_| ChannelConflict::Undecided { coord })?;
+            let coord = coord + offset;
+            let existing = self
+                .committed
+                .get(&coord)
+                .or_else(|| self.prepared.get(&coord))
+                .or_else(|| prepared.get(&coord));
+            if let Some(&existing) = existing {
+                if existing != published {
+                    return Err(ChannelConflict::Mismatch {
+                        coord,
+                        existing,
+                        published,
+                    });
+                }
+            }
+            prepared.insert(coord, published);
+        }
+        self.prepared.extend(prepared);
+        Ok(())
+    }
+
+    /// Makes all prepared cells visible to subsequent calls to `subscribe`
+    pub fn commit(&mut self) {
+        self.committed.extend(self.prepared.drain());
+    }
+
+    /// Discards all prepared cells
+    pub fn abort(&mut self) {
+        self.prepared.clear();
+    }
+
+    /// A snapshot of the committed cells as a `ForbidPattern`
+    pub fn subscribe(&self) -> ForceCells {
+        let mut cells = self
+            .committed
+            .iter()
+            .map(|(&coord, &pattern_id)| (coord, pattern_id))
+            .collect::<Vec<_>>();
+        // the order of a hash map's iteration isn't deterministic
+        cells.sort_unstable_by_key(|&(coord, _)| (coord.y, coord.x));
+        ForceCells { cells }
+    }
+}
+
+/// Forces each cell in `cells` to be its given pattern. Cells outside a non-wrapping wave are
+/// ignored.
+#[derive(Debug, Clone)]
+pub struct ForceCells {
+    pub cells: Vec<(Coord, PatternId)>,
+}
+
+impl ForbidPattern for ForceCells {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        for &(coord, pattern_id) in self.cells.iter() {
+            let coord = match fi.normalize_coord(coord) {
+                Some(coord) => coord,
+                None => continue,
+            };
+            if fi
+                .forbid_all_patterns_except(coord, pattern_id, rng)
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Reasons `Stamp::validate` can reject a stamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StampError {
+    /// The cell of the stamp at `offset` has a pattern which doesn't exist
+    PatternOutOfRange {
+        offset: Coord,
+        pattern_id: PatternId,
+    },
+    /// The patterns of the cell of the stamp at `offset` and its neighbour in `direction`
+    /// aren't allowed next to each other
+    Incompatible {
+        offset: Coord,
+        direction: CardinalDirection,
+    },
+}
+
+/// A rectangular block of pre-chosen patterns, such as a hand-designed room, to be placed
+/// with its top-left cell at `coord`, so authored content can be mixed with generated
+/// content. Cells of `patterns` which are `None` are left to be generated. As a
+/// `ForbidPattern`, each chosen cell of the block is forced to its pattern, and propagation
+/// carries the consequences out across the block's borders. Cells outside a non-wrapping wave
+/// are ignored.
+#[derive(Debug, Clone)]
+pub struct Stamp {
+    pub coord: Coord,
+    pub patterns: Grid<Option<PatternId>>,
+}
+
+impl Stamp {
+    pub fn new(coord: Coord, patterns: Grid<Option<PatternId>>) -> Self {
+        Self { coord, patterns }
+    }
+
+    /// A stamp for a new output of `output_size`, to be placed next to `wave` in `direction`
+    /// and overlapping it by `overlap` cells, which forces the overlapping cells of the new
+    /// output to the patterns chosen along that edge of `wave`. This lets separately generated
+    /// outputs be joined into a larger one without seams, e.g. with
+    /// `ImagePatterns::image_from_waves_joined` in `wfc_image`. Cells of `wave` which aren't
+    /// collapsed are left to be generated. The strip of overlapping cells is clipped to the
+    /// output if `wave` is longer along the edge. Panics if `overlap` is larger than either
+    /// `wave` or the output in `direction`.
+    pub fn from_wave_edge(
+        wave: &Wave,
+        direction: CardinalDirection,
+        overlap: u32,
+        output_size: Size,
+    ) -> Self {
+        let wave_size = wave.grid().size();
+        let (wave_length, output_length) = match direction {
+            CardinalDirection::North | CardinalDirection::South => {
+                (wave_size.height(), output_size.height())
+            }
+            CardinalDirection::East | CardinalDirection::West => {
+                (wave_size.width(), output_size.width())
+            }
+        };
+        assert!(overlap <= wave_length, "overlap larger than wave");
+        assert!(overlap <= output_length, "overlap larger than output");
+        let overlap_i32 = overlap as i32;
+        let width = wave_size.width().min(output_size.width());
+        let height = wave_size.height().min(output_size.height());
+        let (strip_size, strip_coord, coord) = match direction {
+            CardinalDirection::North => (
+                Size::new(width, overlap),
+                Coord::new(0, 0),
+                Coord::new(0, output_size.height() as i32 - overlap_i32),
+            ),
+            CardinalDirection::East => (
+                Size::new(overlap, height),
+                Coord::new(wave_size.width() as i32 - overlap_i32, 0),
+                Coord::new(0, 0),
+            ),
+            CardinalDirection::South => (
+                Size::new(width, overlap),
+                Coord::new(0, wave_size.height() as i32 - overlap_i32),
+                Coord::new(0, 0),
+            ),
+            CardinalDirection::West => (
+                Size::new(overlap, height),
+                Coord::new(0, 0),
+                Coord::new(output_size.width() as i32 - overlap_i32, 0),
+            ),
+        };
+        let patterns = Grid::new_fn(strip_size, |offset| {
+            wave.grid()
+                .get_checked(strip_coord + offset)
+                .chosen_pattern_id()
+                .ok()
+        });
+        Self::new(coord, patterns)
+    }
+
+    /// Checks that every pattern of the stamp exists in `global_stats`, and that every pair
+    /// of neighbouring chosen cells within the stamp is allowed by its adjacency rules. A
+    /// valid stamp can still contradict its surroundings, e.g. when it's placed too close to
+    /// another stamp, which `RunBorrow::reset_checked` reports.
+    pub fn validate(&self, global_stats: &GlobalStats) -> Result<(), StampError> {
+        let num_patterns = global_stats.num_patterns();
+        for (offset, &pattern_id) in self.patterns.enumerate() {
+            if let Some(pattern_id) = pattern_id {
+                if pattern_id as usize >= num_patterns {
+                    return Err(StampError::PatternOutOfRange { offset, pattern_id });
+                }
+            }
+        }
+        for (offset, &pattern_id) in self.patterns.enumerate() {
+            let pattern_id = match pattern_id {
+                Some(pattern_id) => pattern_id,
+                None => continue,
+            };
+            for direction in [CardinalDirection::East, CardinalDirection::South] {
+                if let Some(&Some(neighbour_id)) =
+                    self.patterns.get(offset + direction.coord())
+                {
+                    if !global_stats
+                        .compatible_patterns_in_direction(pattern_id, direction)
+                        .any(|&id| id == neighbour_id)
+                    {
+                        return Err(StampError::Incompatible { offset, direction });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ForbidPattern for Stamp {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        for (offset, &pattern_id) in self.patterns.enumerate() {
+            let pattern_id = match pattern_id {
+                Some(pattern_id) => pattern_id,
+                None => continue,
+            };
+            let coord = match fi.normalize_coord(self.coord + offset) {
+                Some(coord) => coord,
+                None => continue,
+            };
+            if fi
+                .forbid_all_patterns_except(coord, pattern_id, rng)
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Treats the outside of a non-wrapping output as an implicit "edge" pattern, which each
+/// pattern may only be placed next to if it was seen at the same edge of the input. Cells
+/// along each edge of the output have every pattern forbidden which isn't allowed at that
+/// edge, e.g. only patterns from the input's top row may be placed in the output's top row.
+/// Has no effect on wrapping outputs. See `OverlappingPatterns::edge_patterns`.
+#[derive(Debug, Clone)]
+pub struct EdgePatterns {
+    allowed: PatternTable<CardinalDirectionTable<bool>>,
+}
+
+impl EdgePatterns {
+    /// `allowed[pattern_id]` says whether `pattern_id` may be placed at the edge of the
+    /// output in each direction
+    pub fn new(allowed: PatternTable<CardinalDirectionTable<bool>>) -> Self {
+        Self { allowed }
+    }
+
+    pub fn is_allowed(
+        &self,
+        pattern_id: PatternId,
+        direction: CardinalDirection,
+    ) -> bool {
+        *self.allowed[pattern_id].get(direction)
+    }
+}
+
+impl ForbidPattern for EdgePatterns {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        for coord in fi.wave_size().coord_iter_row_major() {
+            for direction in CardinalDirections {
+                if fi.normalize_coord(coord + direction.coord()).is_some() {
+                    continue;
+                }
+                for (pattern_id, allowed) in self.allowed.enumerate() {
+                    if !*allowed.get(direction)
+                        && fi.forbid_pattern(coord, pattern_id, rng).is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{
+        all_compatible_stats, checkerboard_stats, three_colour_stats,
+    };
+    use crate::wfc::RunOwn;
+    use crate::wrap::WrapXY;
+    use coord_2d::Size;
+    use rand::SeedableRng;
+
+    #[test]
+    fn forbid_repetition() {
+        let global_stats = all_compatible_stats(&[1; 5]);
+        let size = Size::new(6, 6);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run =
+            RunOwn::new_forbid(size, &global_stats, ForbidRepetition::new(1), &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let wave = run.into_wave();
+        for (coord, cell) in wave.grid().enumerate() {
+            let pattern_id = cell.chosen_pattern_id().unwrap();
+            for offset in [Coord::new(1, 0), Coord::new(0, 1)] {
+                let neighbour = wave.grid().get_tiled(coord + offset);
+                assert_ne!(neighbour.chosen_pattern_id().unwrap(), pattern_id);
+            }
+        }
+        // the radius reaches all the way around a small wrapping output
+        let repetition = ForbidRepetition::new(3);
+        let mut run =
+            RunOwn::new_forbid(Size::new(2, 2), &global_stats, repetition, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let mut pattern_ids = run
+            .into_wave()
+            .grid()
+            .iter()
+            .map(|cell| cell.chosen_pattern_id().unwrap())
+            .collect::<Vec<_>>();
+        pattern_ids.sort();
+        pattern_ids.dedup();
+        assert_eq!(pattern_ids.len(), 4);
+    }
+
+    #[test]
+    fn max_fraction() {
+        let global_stats = all_compatible_stats(&[1, 9]);
+        let size = Size::new(10, 10);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let max_fraction = MaxFraction::new(vec![1], 0.2);
+        let mut run = RunOwn::new_forbid(size, &global_stats, max_fraction, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let count = run
+            .into_wave()
+            .grid()
+            .iter()
+            .filter(|cell| cell.chosen_pattern_id().unwrap() == 1)
+            .count();
+        assert_eq!(count, 20);
+        // forcing one cell decides every other cell by propagation, all of which are counted
+        let global_stats = checkerboard_stats();
+        let forbid = (
+            ForceCells {
+                cells: vec![(Coord::new(0, 0), 0)],
+            },
+            MaxFraction::new(vec![0], 0.25),
+        );
+        let mut run =
+            RunOwn::new_forbid(Size::new(4, 4), &global_stats, forbid, &mut rng);
+        assert_eq!(run.forbid_mut().1.count(), 8);
+    }
+
+    #[test]
+    fn place_exactly() {
+        let global_stats = all_compatible_stats(&[1, 1]);
+        let size = Size::new(8, 8);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let place = PlaceExactly::new(vec![1], 4, 3);
+        let mut run = RunOwn::new_forbid(size, &global_stats, place, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let wave = run.into_wave();
+        let placed = wave
+            .grid()
+            .enumerate()
+            .filter(|(_, cell)| cell.chosen_pattern_id().unwrap() == 1)
+            .map(|(coord, _)| coord)
+            .collect::<Vec<_>>();
+        assert_eq!(placed.len(), 4);
+        for &a in placed.iter() {
+            for &b in placed.iter().filter(|&&b| b != a) {
+                let delta = b - a;
+                let dx = delta.x.abs().min(8 - delta.x.abs());
+                let dy = delta.y.abs().min(8 - delta.y.abs());
+                assert!(dx * dx + dy * dy >= 9);
+            }
+        }
+        // every cell must be placed, so positions can't repeat
+        let place = PlaceExactly::new(vec![1], 4, 0);
+        let mut run = RunOwn::new_forbid(Size::new(2, 2), &global_stats, place, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let wave = run.into_wave();
+        assert!(wave
+            .grid()
+            .iter()
+            .all(|cell| cell.chosen_pattern_id().unwrap() == 1));
+        // there's no room for 5 placements
+        let place = PlaceExactly::new(vec![1], 5, 0);
+        assert!(RunOwn::new_wrap_forbid_checked(
+            Size::new(2, 2),
+            &global_stats,
+            WrapXY,
+            place,
+            &mut rng
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn constraint_canvas() {
+        let num_patterns = 3;
+        let global_stats = all_compatible_stats(&[1; 3]);
+        let size = Size::new(4, 4);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut canvas = ConstraintCanvas::new(size);
+        canvas.tag_forced("water", &[0], num_patterns);
+        canvas.tag_forbidden("land", vec![0]);
+        let mut run = RunOwn::new_forbid(size, &global_stats, canvas, &mut rng);
+        let water = Coord::new(1, 1);
+        run.forbid_mut().paint(water, "water");
+        ConstraintCanvas::apply(&mut run.borrow_mut(), &mut rng).unwrap();
+        assert_eq!(run.wave_cell_ref(water).chosen_pattern_id().ok(), Some(0));
+        // repainting relaxes the constraint, so requires a reset
+        run.forbid_mut().paint(water, "land");
+        ConstraintCanvas::apply(&mut run.borrow_mut(), &mut rng).unwrap();
+        assert!(run.wave_cell_ref(water).chosen_pattern_id().is_err());
+        run.collapse(&mut rng).unwrap();
+        assert_ne!(
+            run.wave()
+                .grid()
+                .get_checked(water)
+                .chosen_pattern_id()
+                .ok(),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn constraint_channel() {
+        use crate::wrap::WrapNone;
+        let global_stats = three_colour_stats();
+        let size = Size::new(4, 4);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut west = RunOwn::new_wrap(size, &global_stats, WrapNone, &mut rng);
+        west.collapse(&mut rng).unwrap();
+        let west = west.into_wave();
+        // the east column of the west chunk overlaps the west column of the east chunk
+        let east_column = || (0..4).map(|y| Coord::new(3, y));
+        let mut channel = ConstraintChannel::new();
+        channel
+            .prepare(&west, east_column(), Coord::new(-3, 0))
+            .unwrap();
+        // nothing is visible until the cells are committed
+        assert!(channel.subscribe().cells.is_empty());
+        channel.commit();
+        let mut east = RunOwn::new_wrap_forbid(
+            size,
+            &global_stats,
+            WrapNone,
+            channel.subscribe(),
+            &mut rng,
+        );
+        assert_eq!(east.borrow_mut().try_reset(1, &mut rng), Ok(()));
+        east.collapse(&mut rng).unwrap();
+        let east = east.into_wave();
+        for coord in east_column() {
+            assert_eq!(
+                west.grid().get_checked(coord).chosen_pattern_id().ok(),
+                east.grid()
+                    .get_checked(coord - Coord::new(3, 0))
+                    .chosen_pattern_id()
+                    .ok()
+            );
+        }
+        // neighbouring cells always differ, so publishing the east chunk's second column in
+        // place of its first conflicts
+        let second_column = (0..4).map(|y| Coord::new(1, y));
+        let result = channel.prepare(&east, second_column, Coord::new(-1, 0));
+        assert!(matches!(result, Err(ChannelConflict::Mismatch { .. })));
+        let result = channel.prepare(&east, Some(Coord::new(4, 0)), Coord::new(0, 0));
+        assert_eq!(
+            result,
+            Err(ChannelConflict::OutOfBounds {
+                coord: Coord::new(4, 0)
+            })
+        );
+    }
+
+    #[test]
+    fn stamp() {
+        use crate::wrap::WrapNone;
+        let global_stats = three_colour_stats();
+        let room = [[Some(0), Some(1)], [Some(1), None]];
+        let stamp = Stamp::new(
+            Coord::new(1, 2),
+            Grid::new_fn(Size::new(2, 2), |coord| {
+                room[coord.y as usize][coord.x as usize]
+            }),
+        );
+        assert_eq!(stamp.validate(&global_stats), Ok(()));
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new_wrap_forbid(
+            Size::new(5, 5),
+            &global_stats,
+            WrapNone,
+            stamp.clone(),
+            &mut rng,
+        );
+        run.collapse(&mut rng).unwrap();
+        let wave = run.into_wave();
+        for (offset, &pattern_id) in stamp.patterns.enumerate() {
+            if let Some(pattern_id) = pattern_id {
+                assert_eq!(
+                    wave.grid()
+                        .get_checked(stamp.coord + offset)
+                        .chosen_pattern_id()
+                        .ok(),
+                    Some(pattern_id)
+                );
+            }
+        }
+        let clashing =
+            Stamp::new(Coord::new(0, 0), Grid::new_clone(Size::new(2, 1), Some(0)));
+        assert_eq!(
+            clashing.validate(&global_stats),
+            Err(StampError::Incompatible {
+                offset: Coord::new(0, 0),
+                direction: CardinalDirection::East,
+            })
+        );
+        let unknown =
+            Stamp::new(Coord::new(0, 0), Grid::new_clone(Size::new(1, 1), Some(3)));
+        assert_eq!(
+            unknown.validate(&global_stats),
+            Err(StampError::PatternOutOfRange {
+                offset: Coord::new(0, 0),
+                pattern_id: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn stamp_from_wave_edge() {
+        use crate::wrap::WrapNone;
+        let global_stats = three_colour_stats();
+        let size = Size::new(6, 4);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new_wrap(size, &global_stats, WrapNone, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let first = run.into_wave();
+        for (direction, offset, expected_overlapping) in [
+            (CardinalDirection::East, Coord::new(4, 0), 8),
+            (CardinalDirection::West, Coord::new(-4, 0), 8),
+            (CardinalDirection::South, Coord::new(0, 2), 12),
+            (CardinalDirection::North, Coord::new(0, -2), 12),
+        ] {
+            let stamp = Stamp::from_wave_edge(&first, direction, 2, size);
+            let mut run =
+                RunOwn::new_wrap_forbid(size, &global_stats, WrapNone, stamp, &mut rng);
+            run.collapse(&mut rng).unwrap();
+            let second = run.into_wave();
+            let mut num_overlapping = 0;
+            for (coord, cell) in second.grid().enumerate() {
+                if let Some(first_cell) = first.grid().get(coord + offset) {
+                    assert_eq!(
+                        cell.chosen_pattern_id().ok(),
+                        first_cell.chosen_pattern_id().ok()
+                    );
+                    num_overlapping += 1;
+                }
+            }
+            assert_eq!(num_overlapping, expected_overlapping);
+        }
+        // a narrower output only gets the part of the edge which fits
+        let narrow = Size::new(3, 4);
+        let stamp = Stamp::from_wave_edge(&first, CardinalDirection::South, 2, narrow);
+        assert_eq!(stamp.patterns.size(), Size::new(3, 2));
+        let mut run =
+            RunOwn::new_wrap_forbid(narrow, &global_stats, WrapNone, stamp, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let second = run.into_wave();
+        for coord in Size::new(3, 2).coord_iter_row_major() {
+            assert_eq!(
+                second.grid().get_checked(coord).chosen_pattern_id().ok(),
+                first
+                    .grid()
+                    .get_checked(coord + Coord::new(0, 2))
+                    .chosen_pattern_id()
+                    .ok()
+            );
+        }
+    }
+
+    #[test]
+    fn forbid_near() {
+        use crate::wrap::WrapXY;
+        let global_stats = all_compatible_stats(&[1, 1]);
+        let size = Size::new(6, 6);
+        let forbid_near = ForbidNear::new(vec![0], Coord::new(0, 0), 1);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run =
+            RunOwn::new_wrap_forbid(size, &global_stats, WrapXY, forbid_near, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let wave = run.into_wave();
+        // the forbidden area wraps around to the opposite edges
+        for coord in [
+            Coord::new(0, 0),
+            Coord::new(1, 0),
+            Coord::new(5, 0),
+            Coord::new(0, 1),
+            Coord::new(0, 5),
+        ] {
+            assert_eq!(
+                wave.grid().get_checked(coord).chosen_pattern_id().ok(),
+                Some(1)
+            );
+        }
+    }
+}
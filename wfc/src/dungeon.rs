@@ -0,0 +1,360 @@
+//! Roguelike maps made of walls, floors and doors, with the adjacency rules between them built
+//! in, as an alternative to assembling patterns, constraints and retries by hand. A door sits
+//! between two walls on one axis and two floors on the other, so it always joins two rooms
+//! or corridors.
+
+use crate::{
+    wfc::{
+        ChosenPatternIdError, ForbidInterface, ForbidPattern, GlobalStats, PatternId,
+        RunOwn, Wave,
+    },
+    wrap::{Wrap, WrapNone},
+};
+use coord_2d::{Coord, Size};
+use direction::CardinalDirections;
+use grid_2d::Grid;
+use rand::Rng;
+use std::num::NonZeroU32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DungeonTile {
+    Wall,
+    Floor,
+    Door,
+}
+
+impl DungeonTile {
+    /// Whether the tile can be walked through, i.e. it's a floor or a door
+    pub fn is_passable(self) -> bool {
+        self != DungeonTile::Wall
+    }
+    /// `#` for walls, `.` for floors and `+` for doors
+    pub fn to_char(self) -> char {
+        match self {
+            DungeonTile::Wall => '#',
+            DungeonTile::Floor => '.',
+            DungeonTile::Door => '+',
+        }
+    }
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            '#' => Some(DungeonTile::Wall),
+            '.' => Some(DungeonTile::Floor),
+            '+' => Some(DungeonTile::Door),
+            _ => None,
+        }
+    }
+}
+
+// Doors are split into a pattern for each axis they can be passed through along
+const WALL: PatternId = 0;
+const FLOOR: PatternId = 1;
+const DOOR_NORTH_SOUTH: PatternId = 2;
+const DOOR_EAST_WEST: PatternId = 3;
+
+fn tile(pattern_id: PatternId) -> DungeonTile {
+    match pattern_id {
+        WALL => DungeonTile::Wall,
+        FLOOR => DungeonTile::Floor,
+        _ => DungeonTile::Door,
+    }
+}
+
+fn rules(
+    wall_weight: NonZeroU32,
+    floor_weight: NonZeroU32,
+    door_weight: NonZeroU32,
+) -> GlobalStats {
+    let wall_floor_and = |door| vec![WALL, FLOOR, door];
+    // north, east, south and west
+    let allowed = vec![
+        [
+            wall_floor_and(DOOR_EAST_WEST),
+            wall_floor_and(DOOR_NORTH_SOUTH),
+            wall_floor_and(DOOR_EAST_WEST),
+            wall_floor_and(DOOR_NORTH_SOUTH),
+        ],
+        [
+            wall_floor_and(DOOR_NORTH_SOUTH),
+            wall_floor_and(DOOR_EAST_WEST),
+            wall_floor_and(DOOR_NORTH_SOUTH),
+            wall_floor_and(DOOR_EAST_WEST),
+        ],
+        [vec![FLOOR], vec![WALL], vec![FLOOR], vec![WALL]],
+        [vec![WALL], vec![FLOOR], vec![WALL], vec![FLOOR]],
+    ];
+    GlobalStats::from_adjacency_matrix(
+        vec![
+            Some(wall_weight),
+            Some(floor_weight),
+            Some(door_weight),
+            Some(door_weight),
+        ],
+        allowed,
+    )
+    .expect("the built-in rules are symmetric")
+}
+
+/// What `DungeonGenerator::generate` does about floors which can't be reached from one
+/// another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Disconnected regions are left as they are
+    Any,
+    /// Every region other than the largest is filled with walls
+    FillDisconnected,
+    /// Outputs with more than one region are discarded and generated again
+    Require,
+}
+
+/// Returned by `DungeonGenerator::generate` when every attempt failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DungeonError {
+    /// The last attempt ended in a contradiction
+    Contradiction,
+    /// The last attempt succeeded, but its floors weren't all connected
+    Disconnected,
+}
+
+// Forces walls along every edge of the output
+#[derive(Debug, Clone, Copy)]
+struct BorderWalls;
+
+impl ForbidPattern for BorderWalls {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        for coord in fi.wave_size().coord_iter_row_major() {
+            let on_border = CardinalDirections
+                .into_iter()
+                .any(|direction| fi.normalize_coord(coord + direction.coord()).is_none());
+            if on_border && fi.forbid_all_patterns_except(coord, WALL, rng).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct DungeonGenerator {
+    global_stats: GlobalStats,
+    border_walls: bool,
+    connectivity: Connectivity,
+    max_attempts: usize,
+}
+
+impl Default for DungeonGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DungeonGenerator {
+    /// Walls and floors are equally common and doors are rare, the output is surrounded by
+    /// walls, disconnected regions are filled in, and up to 10 attempts are made
+    pub fn new() -> Self {
+        let weight = |weight| NonZeroU32::new(weight).unwrap();
+        Self {
+            global_stats: rules(weight(8), weight(8), weight(1)),
+            border_walls: true,
+            connectivity: Connectivity::FillDisconnected,
+            max_attempts: 10,
+        }
+    }
+    /// Set how common each tile is relative to the others
+    pub fn set_weights(
+        &mut self,
+        wall_weight: NonZeroU32,
+        floor_weight: NonZeroU32,
+        door_weight: NonZeroU32,
+    ) {
+        self.global_stats = rules(wall_weight, floor_weight, door_weight);
+    }
+    /// Whether every cell on the edge of the output is forced to be a wall
+    pub fn set_border_walls(&mut self, border_walls: bool) {
+        self.border_walls = border_walls;
+    }
+    pub fn set_connectivity(&mut self, connectivity: Connectivity) {
+        self.connectivity = connectivity;
+    }
+    /// The number of outputs generated before giving up, counting those discarded due to
+    /// contradictions or, with `Connectivity::Require`, disconnected regions. At least one
+    /// attempt is always made.
+    pub fn set_max_attempts(&mut self, max_attempts: usize) {
+        self.max_attempts = max_attempts;
+    }
+    /// The rules used by `generate`, for running with constraints or retries of your own.
+    /// Convert the resulting wave with `dungeon_from_wave`.
+    pub fn global_stats(&self) -> &GlobalStats {
+        &self.global_stats
+    }
+    pub fn generate<R: Rng>(
+        &self,
+        size: Size,
+        rng: &mut R,
+    ) -> Result<Grid<DungeonTile>, DungeonError> {
+        let mut error = DungeonError::Contradiction;
+        for _ in 0..self.max_attempts.max(1) {
+            let wave = if self.border_walls {
+                let mut run = RunOwn::new_wrap_forbid(
+                    size,
+                    &self.global_stats,
+                    WrapNone,
+                    BorderWalls,
+                    rng,
+                );
+                run.collapse(rng).map(|()| run.into_wave())
+            } else {
+                let mut run = RunOwn::new_wrap(size, &self.global_stats, WrapNone, rng);
+                run.collapse(rng).map(|()| run.into_wave())
+            };
+            let mut dungeon = match wave {
+                Ok(wave) => dungeon_from_wave(&wave).expect("the wave is collapsed"),
+                Err(_) => {
+                    error = DungeonError::Contradiction;
+                    continue;
+                }
+            };
+            match self.connectivity {
+                Connectivity::Any => (),
+                Connectivity::FillDisconnected => fill_disconnected(&mut dungeon),
+                Connectivity::Require => {
+                    if num_regions(&dungeon) > 1 {
+                        error = DungeonError::Disconnected;
+                        continue;
+                    }
+                }
+            }
+            return Ok(dungeon);
+        }
+        Err(error)
+    }
+}
+
+/// The tile chosen for each cell of a wave collapsed with `DungeonGenerator::global_stats`
+pub fn dungeon_from_wave(wave: &Wave) -> Result<Grid<DungeonTile>, ChosenPatternIdError> {
+    let mut tiles = Vec::with_capacity(wave.size().count());
+    for cell in wave.grid().iter() {
+        tiles.push(tile(cell.chosen_pattern_id()?));
+    }
+    Ok(Grid::new_iterator(wave.size(), tiles.into_iter()))
+}
+
+/// Labels each passable cell with the index of the region of passable cells it belongs to,
+/// where cells are connected to their passable cardinal neighbours. Regions are numbered from
+/// 0 in row-major order of their first cell.
+pub fn regions(dungeon: &Grid<DungeonTile>) -> Grid<Option<usize>> {
+    let mut regions = Grid::new_clone(dungeon.size(), None);
+    let mut num_regions = 0;
+    let mut stack = Vec::new();
+    for (start, tile) in dungeon.enumerate() {
+        if !tile.is_passable() || regions.get_checked(start).is_some() {
+            continue;
+        }
+        *regions.get_checked_mut(start) = Some(num_regions);
+        stack.push(start);
+        while let Some(coord) = stack.pop() {
+            for direction in CardinalDirections {
+                let neighbour = coord + direction.coord();
+                let passable = dungeon
+                    .get(neighbour)
+                    .is_some_and(|tile| tile.is_passable());
+                if passable && regions.get_checked(neighbour).is_none() {
+                    *regions.get_checked_mut(neighbour) = Some(num_regions);
+                    stack.push(neighbour);
+                }
+            }
+        }
+        num_regions += 1;
+    }
+    regions
+}
+
+/// The number of separate regions of passable cells
+pub fn num_regions(dungeon: &Grid<DungeonTile>) -> usize {
+    regions(dungeon)
+        .iter()
+        .filter_map(|&region| region)
+        .max()
+        .map_or(0, |last| last + 1)
+}
+
+/// Fills every region of passable cells except the largest with walls, so that every floor
+/// can be reached from every other floor. Doors always join two floors of the same region,
+/// so the result still obeys the built-in rules.
+pub fn fill_disconnected(dungeon: &mut Grid<DungeonTile>) {
+    let regions = regions(dungeon);
+    let mut sizes = Vec::<usize>::new();
+    for &region in regions.iter().flatten() {
+        if region >= sizes.len() {
+            sizes.resize(region + 1, 0);
+        }
+        sizes[region] += 1;
+    }
+    let largest = (0..sizes.len()).max_by_key(|&region| (sizes[region], !region));
+    for (coord, &region) in regions.enumerate() {
+        if region.is_some() && region != largest {
+            *dungeon.get_checked_mut(coord) = DungeonTile::Wall;
+        }
+    }
+}
+
+/// Renders a dungeon with `DungeonTile::to_char`, one line per row
+pub fn dungeon_to_string(dungeon: &Grid<DungeonTile>) -> String {
+    let mut string = String::new();
+    for y in 0..dungeon.size().height() {
+        for x in 0..dungeon.size().width() {
+            string.push(
+                dungeon
+                    .get_checked(Coord::new(x as i32, y as i32))
+                    .to_char(),
+            );
+        }
+        string.push('\n');
+    }
+    string
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn doors_join_floors() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let dungeon = DungeonGenerator::new()
+            .generate(Size::new(16, 12), &mut rng)
+            .unwrap();
+        assert!(num_regions(&dungeon) <= 1);
+        for (coord, &tile) in dungeon.enumerate() {
+            let on_border =
+                coord.x == 0 || coord.y == 0 || coord.x == 15 || coord.y == 11;
+            if on_border {
+                assert_eq!(tile, DungeonTile::Wall);
+            }
+            if tile == DungeonTile::Door {
+                let at = |dx, dy| *dungeon.get_checked(coord + Coord::new(dx, dy));
+                let north_south = at(0, -1) == DungeonTile::Floor
+                    && at(0, 1) == DungeonTile::Floor
+                    && at(-1, 0) == DungeonTile::Wall
+                    && at(1, 0) == DungeonTile::Wall;
+                let east_west = at(-1, 0) == DungeonTile::Floor
+                    && at(1, 0) == DungeonTile::Floor
+                    && at(0, -1) == DungeonTile::Wall
+                    && at(0, 1) == DungeonTile::Wall;
+                assert!(north_south || east_west);
+            }
+        }
+    }
+
+    #[test]
+    fn fill_disconnected_regions() {
+        let mut dungeon = Grid::new_fn(Size::new(7, 3), |coord| {
+            DungeonTile::from_char("#..#...".as_bytes()[coord.x as usize] as char)
+                .unwrap()
+        });
+        assert_eq!(num_regions(&dungeon), 2);
+        fill_disconnected(&mut dungeon);
+        assert_eq!(num_regions(&dungeon), 1);
+        assert_eq!(dungeon_to_string(&dungeon), "####...\n".repeat(3));
+    }
+}
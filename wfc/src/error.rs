@@ -0,0 +1,581 @@
+//! `Display` and `std::error::Error` for the errors returned by this crate, and `Error`, which
+//! any of them can be converted into with `?`.
+
+#[cfg(feature = "tiled")]
+use crate::tiled::TiledError;
+#[cfg(feature = "serialize")]
+use crate::wfc::ResumeError;
+#[cfg(feature = "debug-invariants")]
+use crate::wfc::WaveInconsistency;
+use crate::{
+    constraint::{ChannelConflict, StampError},
+    dungeon::DungeonError,
+    overlapping::StitchError,
+    statistics::StatisticsCsvError,
+    wfc::{
+        direction_name, AdjacencyMatrixError, CancellableError, Cancelled,
+        ChosenPatternIdError, ForbidCall, ForbidContradictions, OffsetAdjacencyError,
+        PatternTableLengthMismatch, PropagateError, SetupError, WaveTooLarge,
+    },
+};
+use std::error;
+use std::fmt;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    PatternTableLengthMismatch(PatternTableLengthMismatch),
+    AdjacencyMatrix(AdjacencyMatrixError),
+    OffsetAdjacency(OffsetAdjacencyError),
+    WaveTooLarge(WaveTooLarge),
+    ChosenPatternId(ChosenPatternIdError),
+    Propagate(PropagateError),
+    Setup(SetupError),
+    ForbidContradictions(ForbidContradictions),
+    Cancelled(Cancelled),
+    Cancellable(CancellableError),
+    Stitch(StitchError),
+    ChannelConflict(ChannelConflict),
+    Stamp(StampError),
+    StatisticsCsv(StatisticsCsvError),
+    Dungeon(DungeonError),
+    #[cfg(feature = "tiled")]
+    Tiled(TiledError),
+    #[cfg(feature = "serialize")]
+    Resume(ResumeError),
+    #[cfg(feature = "debug-invariants")]
+    WaveInconsistency(WaveInconsistency),
+}
+
+impl Error {
+    fn inner(&self) -> &(dyn error::Error + 'static) {
+        match self {
+            Error::PatternTableLengthMismatch(error) => error,
+            Error::AdjacencyMatrix(error) => error,
+            Error::OffsetAdjacency(error) => error,
+            Error::WaveTooLarge(error) => error,
+            Error::ChosenPatternId(error) => error,
+            Error::Propagate(error) => error,
+            Error::Setup(error) => error,
+            Error::ForbidContradictions(error) => error,
+            Error::Cancelled(error) => error,
+            Error::Cancellable(error) => error,
+            Error::Stitch(error) => error,
+            Error::ChannelConflict(error) => error,
+            Error::Stamp(error) => error,
+            Error::StatisticsCsv(error) => error,
+            Error::Dungeon(error) => error,
+            #[cfg(feature = "tiled")]
+            Error::Tiled(error) => error,
+            #[cfg(feature = "serialize")]
+            Error::Resume(error) => error,
+            #[cfg(feature = "debug-invariants")]
+            Error::WaveInconsistency(error) => error,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.inner(), f)
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.inner().source()
+    }
+}
+
+macro_rules! impl_from {
+    ($($variant:ident($error:ty),)*) => {
+        $(impl From<$error> for Error {
+            fn from(error: $error) -> Self {
+                Error::$variant(error)
+            }
+        })*
+    };
+}
+
+impl_from! {
+    PatternTableLengthMismatch(PatternTableLengthMismatch),
+    AdjacencyMatrix(AdjacencyMatrixError),
+    OffsetAdjacency(OffsetAdjacencyError),
+    WaveTooLarge(WaveTooLarge),
+    ChosenPatternId(ChosenPatternIdError),
+    Propagate(PropagateError),
+    Setup(SetupError),
+    ForbidContradictions(ForbidContradictions),
+    Cancelled(Cancelled),
+    Cancellable(CancellableError),
+    Stitch(StitchError),
+    ChannelConflict(ChannelConflict),
+    Stamp(StampError),
+    StatisticsCsv(StatisticsCsvError),
+    Dungeon(DungeonError),
+}
+
+#[cfg(feature = "tiled")]
+impl_from! {
+    Tiled(TiledError),
+}
+
+#[cfg(feature = "serialize")]
+impl_from! {
+    Resume(ResumeError),
+}
+
+#[cfg(feature = "debug-invariants")]
+impl_from! {
+    WaveInconsistency(WaveInconsistency),
+}
+
+impl fmt::Display for PatternTableLengthMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expected {} patterns but {} were given",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl error::Error for PatternTableLengthMismatch {}
+
+impl fmt::Display for AdjacencyMatrixError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AdjacencyMatrixError::LengthMismatch {
+                num_weights,
+                num_allowed,
+            } => write!(
+                f,
+                "{} weights were given for {} patterns",
+                num_weights, num_allowed
+            ),
+            AdjacencyMatrixError::NeighbourOutOfRange {
+                pattern_id,
+                direction,
+                neighbour_id,
+            } => write!(
+                f,
+                "pattern {} allows non-existent pattern {} {}",
+                pattern_id,
+                neighbour_id,
+                direction_name(direction)
+            ),
+            AdjacencyMatrixError::Asymmetric {
+                pattern_id,
+                direction,
+                neighbour_id,
+            } => write!(
+                f,
+                "pattern {} allows {} {} but {} doesn't allow {} {}",
+                pattern_id,
+                neighbour_id,
+                direction_name(direction),
+                neighbour_id,
+                pattern_id,
+                direction_name(direction.opposite())
+            ),
+            AdjacencyMatrixError::InvalidWeight {
+                pattern_id,
+                direction,
+                neighbour_id,
+            } => write!(
+                f,
+                "pattern {} gives pattern {} {} a weight which isn't positive and finite",
+                pattern_id,
+                neighbour_id,
+                direction_name(direction)
+            ),
+        }
+    }
+}
+
+impl error::Error for AdjacencyMatrixError {}
+
+impl fmt::Display for OffsetAdjacencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OffsetAdjacencyError::ZeroOffset => write!(f, "the offset is zero"),
+            OffsetAdjacencyError::LengthMismatch {
+                num_patterns,
+                num_allowed,
+            } => write!(
+                f,
+                "rules were given for {} patterns but there are {}",
+                num_allowed, num_patterns
+            ),
+            OffsetAdjacencyError::NeighbourOutOfRange {
+                pattern_id,
+                neighbour_id,
+            } => write!(
+                f,
+                "pattern {} allows non-existent pattern {}",
+                pattern_id, neighbour_id
+            ),
+            OffsetAdjacencyError::Unsatisfiable => {
+                write!(f, "the rules don't allow any weighted pattern")
+            }
+        }
+    }
+}
+
+impl error::Error for OffsetAdjacencyError {}
+
+impl fmt::Display for WaveTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the wave would use an estimated {} bytes, more than the maximum of {}",
+            self.estimated_bytes, self.max_bytes
+        )
+    }
+}
+
+impl error::Error for WaveTooLarge {}
+
+impl fmt::Display for ChosenPatternIdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChosenPatternIdError::NoCompatiblePatterns => {
+                write!(f, "no patterns are compatible with the cell")
+            }
+            ChosenPatternIdError::MultipleCompatiblePatterns => {
+                write!(f, "multiple patterns are compatible with the cell")
+            }
+        }
+    }
+}
+
+impl error::Error for ChosenPatternIdError {}
+
+impl fmt::Display for PropagateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PropagateError::Contradiction => write!(f, "contradiction"),
+        }
+    }
+}
+
+impl error::Error for PropagateError {}
+
+impl fmt::Display for SetupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SetupError::UnsatisfiableForbid => {
+                write!(f, "every attempt to forbid patterns caused a contradiction")
+            }
+        }
+    }
+}
+
+impl error::Error for SetupError {}
+
+impl fmt::Display for ForbidContradictions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let first = self.first();
+        let call = match first.call {
+            ForbidCall::ForbidAllPatternsExcept => "forbid_all_patterns_except",
+            ForbidCall::ForbidPattern => "forbid_pattern",
+        };
+        write!(
+            f,
+            "forbidding patterns caused {} contradictions, the first by {} with pattern {} \
+             at ({}, {})",
+            self.iter().len(),
+            call,
+            first.pattern_id,
+            first.coord.x,
+            first.coord.y
+        )
+    }
+}
+
+impl error::Error for ForbidContradictions {}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl error::Error for Cancelled {}
+
+impl fmt::Display for CancellableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CancellableError::Contradiction => write!(f, "contradiction"),
+            CancellableError::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl error::Error for CancellableError {}
+
+impl fmt::Display for StitchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StitchError::NotCollapsed { coord } => {
+                write!(f, "the cell at ({}, {}) isn't collapsed", coord.x, coord.y)
+            }
+            StitchError::OverlapMismatch { coord } => write!(
+                f,
+                "the patterns covering ({}, {}) disagree about its value",
+                coord.x, coord.y
+            ),
+        }
+    }
+}
+
+impl error::Error for StitchError {}
+
+impl fmt::Display for ChannelConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChannelConflict::Undecided { coord } => write!(
+                f,
+                "the published cell at ({}, {}) isn't decided",
+                coord.x, coord.y
+            ),
+            ChannelConflict::OutOfBounds { coord } => write!(
+                f,
+                "the published cell at ({}, {}) is outside the wave",
+                coord.x, coord.y
+            ),
+            ChannelConflict::Mismatch {
+                coord,
+                existing,
+                published,
+            } => write!(
+                f,
+                "pattern {} was published at ({}, {}), which already has pattern {}",
+                published, coord.x, coord.y, existing
+            ),
+        }
+    }
+}
+
+impl error::Error for ChannelConflict {}
+
+impl fmt::Display for StampError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StampError::PatternOutOfRange { offset, pattern_id } => write!(
+                f,
+                "the stamp places non-existent pattern {} at ({}, {})",
+                pattern_id, offset.x, offset.y
+            ),
+            StampError::Incompatible { offset, direction } => write!(
+                f,
+                "the stamp's pattern at ({}, {}) doesn't allow its neighbour {}",
+                offset.x,
+                offset.y,
+                direction_name(*direction)
+            ),
+        }
+    }
+}
+
+impl error::Error for StampError {}
+
+impl fmt::Display for StatisticsCsvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StatisticsCsvError::Io(_) => write!(f, "failed to read statistics"),
+            StatisticsCsvError::InvalidWeightsRow { line } => {
+                write!(f, "invalid row on line {} of the weights", line)
+            }
+            StatisticsCsvError::InvalidAdjacencyRow { line } => {
+                write!(f, "invalid row on line {} of the adjacencies", line)
+            }
+            StatisticsCsvError::PatternOutOfRange { line, pattern_id } => write!(
+                f,
+                "line {} of the adjacencies refers to non-existent pattern {}",
+                line, pattern_id
+            ),
+        }
+    }
+}
+
+impl error::Error for StatisticsCsvError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            StatisticsCsvError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DungeonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DungeonError::Contradiction => {
+                write!(f, "the last attempt to generate the dungeon contradicted")
+            }
+            DungeonError::Disconnected => {
+                write!(f, "the last dungeon generated had disconnected regions")
+            }
+        }
+    }
+}
+
+impl error::Error for DungeonError {}
+
+#[cfg(feature = "tiled")]
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TiledError::Xml(_) => write!(f, "invalid XML"),
+            TiledError::MissingElement(element) => {
+                write!(f, "missing <{}> element", element)
+            }
+            TiledError::MissingAttribute { element, attribute } => write!(
+                f,
+                "<{}> element is missing the \"{}\" attribute",
+                element, attribute
+            ),
+            TiledError::InvalidNumber { element, value } => {
+                write!(f, "invalid number \"{}\" in <{}> element", value, element)
+            }
+            TiledError::UnsupportedEncoding(Some(encoding)) => {
+                write!(f, "unsupported layer encoding \"{}\"", encoding)
+            }
+            TiledError::UnsupportedEncoding(None) => {
+                write!(f, "unsupported layer encoding (only CSV is supported)")
+            }
+            TiledError::WrongNumberOfTiles {
+                layer,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "layer \"{}\" has {} tiles but should have {}",
+                layer, actual, expected
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "tiled")]
+impl error::Error for TiledError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            TiledError::Xml(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl fmt::Display for ResumeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResumeError::Version => {
+                write!(
+                    f,
+                    "the run was suspended by a different version of this library"
+                )
+            }
+            ResumeError::GlobalStatsMismatch => {
+                write!(f, "the run was suspended with different global stats")
+            }
+            ResumeError::WrapMismatch => {
+                write!(f, "the run was suspended with a different wrap")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl error::Error for ResumeError {}
+
+#[cfg(feature = "debug-invariants")]
+impl fmt::Display for WaveInconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WaveInconsistency::PartiallyZeroNumWays { coord, pattern_id } => write!(
+                f,
+                "the number of ways for the cell at ({}, {}) to become pattern {} is zero in \
+                some directions but not others",
+                coord.x, coord.y, pattern_id
+            ),
+            WaveInconsistency::NumCompatiblePatterns {
+                coord,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "the cell at ({}, {}) records {} compatible patterns but has {}",
+                coord.x, coord.y, actual, expected
+            ),
+            WaveInconsistency::NumWeightedCompatiblePatterns {
+                coord,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "the cell at ({}, {}) records {} weighted compatible patterns but has {}",
+                coord.x, coord.y, actual, expected
+            ),
+            WaveInconsistency::SumCompatiblePatternWeight {
+                coord,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "the cell at ({}, {}) records a total weight of {} but it should be {}",
+                coord.x, coord.y, actual, expected
+            ),
+            WaveInconsistency::SumCompatiblePatternWeightLogWeight {
+                coord,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "the cell at ({}, {}) records a total weight log weight of {} but it should be \
+                {}",
+                coord.x, coord.y, actual, expected
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "debug-invariants")]
+impl error::Error for WaveInconsistency {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::checkerboard_stats;
+    use crate::{RunOwn, Size};
+
+    fn collapse_checkerboard(size: Size) -> Result<(), Error> {
+        use rand::SeedableRng;
+        let global_stats = checkerboard_stats();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(size, &global_stats, &mut rng);
+        run.collapse(&mut rng)?;
+        Ok(())
+    }
+
+    #[test]
+    fn error() {
+        assert!(collapse_checkerboard(Size::new(2, 2)).is_ok());
+        // an odd-sized wrapping checkerboard is impossible
+        let error = collapse_checkerboard(Size::new(3, 3)).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Propagate(PropagateError::Contradiction)
+        ));
+        assert_eq!(error.to_string(), "contradiction");
+        assert!(error::Error::source(&error).is_none());
+        let error: Error = AdjacencyMatrixError::LengthMismatch {
+            num_weights: 1,
+            num_allowed: 2,
+        }
+        .into();
+        assert_eq!(error.to_string(), "1 weights were given for 2 patterns");
+    }
+}
@@ -0,0 +1,95 @@
+use crate::wfc::{DebugListener, PatternId};
+use coord_2d::Coord;
+use std::io::{self, Write};
+
+/// A `DebugListener` which writes each collapse event to `W` as a line of JSON, for
+/// analysing or replaying runs in external tools. Each line is an object with an `"event"`
+/// field, one of `"observe"`, `"remove"`, `"contradiction"` or `"retry"`, along with the
+/// coordinate (`"x"`, `"y"`) and pattern (`"pattern_id"`) it concerns where applicable.
+///
+/// Listener methods can't fail, so the first write error is stored and reported by
+/// `finish`, and later events are dropped.
+pub struct JsonEventLog<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> JsonEventLog<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            error: None,
+        }
+    }
+
+    /// Retry methods don't notify listeners, so callers which retry a collapse should call
+    /// this before each new attempt. `attempt` counts from 0 for the first retry.
+    pub fn record_retry(&mut self, attempt: usize) {
+        self.write_line(format_args!(
+            "{{\"event\":\"retry\",\"attempt\":{}}}",
+            attempt
+        ));
+    }
+
+    /// Flushes the writer and returns it, or the first error encountered while writing
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_line(&mut self, args: std::fmt::Arguments) {
+        if self.error.is_none() {
+            if let Err(error) = writeln!(self.writer, "{}", args) {
+                self.error = Some(error);
+            }
+        }
+    }
+}
+
+impl<W: Write> DebugListener for JsonEventLog<W> {
+    fn on_observe(&mut self, coord: Coord, pattern_id: PatternId) {
+        self.write_line(format_args!(
+            "{{\"event\":\"observe\",\"x\":{},\"y\":{},\"pattern_id\":{}}}",
+            coord.x, coord.y, pattern_id
+        ));
+    }
+    fn on_remove_pattern(&mut self, coord: Coord, pattern_id: PatternId) {
+        self.write_line(format_args!(
+            "{{\"event\":\"remove\",\"x\":{},\"y\":{},\"pattern_id\":{}}}",
+            coord.x, coord.y, pattern_id
+        ));
+    }
+    fn on_contradiction(&mut self, coord: Coord) {
+        self.write_line(format_args!(
+            "{{\"event\":\"contradiction\",\"x\":{},\"y\":{}}}",
+            coord.x, coord.y
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_lines() {
+        let mut log = JsonEventLog::new(Vec::new());
+        log.on_observe(Coord::new(1, 2), 3);
+        log.on_remove_pattern(Coord::new(0, 1), 4);
+        log.on_contradiction(Coord::new(5, 6));
+        log.record_retry(0);
+        let output = String::from_utf8(log.finish().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            concat!(
+                "{\"event\":\"observe\",\"x\":1,\"y\":2,\"pattern_id\":3}\n",
+                "{\"event\":\"remove\",\"x\":0,\"y\":1,\"pattern_id\":4}\n",
+                "{\"event\":\"contradiction\",\"x\":5,\"y\":6}\n",
+                "{\"event\":\"retry\",\"attempt\":0}\n",
+            )
+        );
+    }
+}
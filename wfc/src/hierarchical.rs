@@ -0,0 +1,220 @@
+//! Two-pass generation which improves the large-scale structure of big outputs. A coarse
+//! wave, whose patterns come from a downscaled copy of the input, is collapsed first. Each
+//! coarse cell then restricts the fine cells it covers to the fine patterns which were seen
+//! in the input under a coarse value matching the coarse cell's top-left value.
+
+use crate::{
+    orientation::Orientation,
+    overlapping::OverlappingPatterns,
+    retry::{NumTimes, RetryOwn},
+    wfc::{
+        ForbidInterface, ForbidPattern, PatternId, PatternTable, PropagateError, RunOwn,
+        Wave,
+    },
+    wrap::Wrap,
+};
+use coord_2d::Size;
+use grid_2d::Grid;
+use rand::Rng;
+use std::hash::Hash;
+use std::num::NonZeroU32;
+
+/// Shrinks `grid` by `scale` in each dimension by taking the top-left value of each
+/// `scale` by `scale` block. Partial blocks at the right and bottom edges are ignored.
+pub fn downscale<T: Clone>(grid: &Grid<T>, scale: NonZeroU32) -> Grid<T> {
+    let scale = scale.get();
+    let size = Size::new(grid.width() / scale, grid.height() / scale);
+    Grid::new_fn(size, |coord| grid.get_checked(coord * scale as i32).clone())
+}
+
+pub struct HierarchicalPatterns<T: Eq + Clone + Hash> {
+    scale: u32,
+    coarse: OverlappingPatterns<T>,
+    fine: OverlappingPatterns<T>,
+    // the coarse values under which each fine pattern was sampled
+    fine_tags: PatternTable<Vec<T>>,
+}
+
+impl<T: Eq + Clone + Hash> HierarchicalPatterns<T> {
+    /// Fine patterns are taken from `grid` and coarse patterns from `grid` downscaled by
+    /// `scale`. Both use the same pattern size and orientations. Each fine pattern is tagged
+    /// with the value of the downscaled input at the position where it was sampled.
+    pub fn new(
+        grid: Grid<T>,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+        scale: NonZeroU32,
+    ) -> Self {
+        let coarse_grid = downscale(&grid, scale);
+        let scale = scale.get();
+        let fine = OverlappingPatterns::new(grid, pattern_size, orientations);
+        let mut fine_tags = PatternTable::from_vec(vec![Vec::new(); fine.num_patterns()]);
+        for (coord, ids) in fine.id_grid().enumerate() {
+            let coarse_coord = coord / scale as i32;
+            let tag = match coarse_grid.get(coarse_coord) {
+                Some(tag) => tag,
+                None => continue,
+            };
+            for &pattern_id in ids.iter() {
+                let tags: &mut Vec<T> = &mut fine_tags[pattern_id];
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+        }
+        let coarse = OverlappingPatterns::new(coarse_grid, pattern_size, orientations);
+        Self {
+            scale,
+            coarse,
+            fine,
+            fine_tags,
+        }
+    }
+
+    pub fn coarse(&self) -> &OverlappingPatterns<T> {
+        &self.coarse
+    }
+
+    pub fn fine(&self) -> &OverlappingPatterns<T> {
+        &self.fine
+    }
+
+    /// The size of the coarse wave needed to cover a fine wave of `fine_size`
+    pub fn coarse_size(&self, fine_size: Size) -> Size {
+        Size::new(
+            fine_size.width().div_ceil(self.scale),
+            fine_size.height().div_ceil(self.scale),
+        )
+    }
+
+    /// A constraint restricting each cell of a fine wave to the fine patterns tagged with
+    /// the top-left value of the covering cell of `coarse_wave`. Panics if `coarse_wave` is
+    /// not fully collapsed.
+    pub fn coarse_mask(&self, coarse_wave: &Wave) -> CoarseMask {
+        let forbidden_by_coarse_pattern = (0..self.coarse.num_patterns() as PatternId)
+            .map(|coarse_pattern_id| {
+                let value = self.coarse.pattern_top_left_value(coarse_pattern_id);
+                self.fine_tags
+                    .enumerate()
+                    .filter(|(_, tags)| !tags.contains(value))
+                    .map(|(fine_pattern_id, _)| fine_pattern_id)
+                    .collect()
+            })
+            .collect();
+        let coarse_ids = Grid::new_grid_map_ref(coarse_wave.grid(), |cell| {
+            cell.chosen_pattern_id()
+                .expect("coarse wave is not fully collapsed")
+        });
+        CoarseMask {
+            scale: self.scale,
+            coarse_ids,
+            forbidden_by_coarse_pattern,
+        }
+    }
+
+    /// Collapses a coarse wave, then a fine wave of `output_size` constrained by it. Each
+    /// wave is retried up to `num_times` times on contradiction, and if either wave can't
+    /// be collapsed the whole process is retried, also up to `num_times` times. `W` is used
+    /// for both waves, so for wrapping outputs `output_size` should be a multiple of the
+    /// scale.
+    pub fn collapse<W, R>(
+        &self,
+        output_size: Size,
+        wrap: W,
+        num_times: usize,
+        rng: &mut R,
+    ) -> Result<Wave, PropagateError>
+    where
+        W: Wrap + Clone + Sync + Send,
+        R: Rng,
+    {
+        let coarse_global_stats = self.coarse.global_stats();
+        let fine_global_stats = self.fine.global_stats();
+        let coarse_size = self.coarse_size(output_size);
+        let mut result = Err(PropagateError::Contradiction);
+        for _ in 0..=num_times {
+            let coarse_run =
+                RunOwn::new_wrap(coarse_size, &coarse_global_stats, wrap, rng);
+            let coarse_wave = match NumTimes(num_times).retry(coarse_run, rng) {
+                Ok(coarse_wave) => coarse_wave,
+                Err(error) => {
+                    result = Err(error);
+                    continue;
+                }
+            };
+            let fine_run = RunOwn::new_wrap_forbid(
+                output_size,
+                &fine_global_stats,
+                wrap,
+                self.coarse_mask(&coarse_wave),
+                rng,
+            );
+            result = NumTimes(num_times).retry(fine_run, rng);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+}
+
+/// Restricts each cell of a fine wave according to the coarse cell covering it. Created by
+/// `HierarchicalPatterns::coarse_mask`.
+#[derive(Debug, Clone)]
+pub struct CoarseMask {
+    scale: u32,
+    coarse_ids: Grid<PatternId>,
+    forbidden_by_coarse_pattern: PatternTable<Vec<PatternId>>,
+}
+
+impl ForbidPattern for CoarseMask {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        for coord in fi.wave_size().coord_iter_row_major() {
+            let coarse_coord = coord / self.scale as i32;
+            let coarse_pattern_id = match self.coarse_ids.get(coarse_coord) {
+                Some(&coarse_pattern_id) => coarse_pattern_id,
+                None => continue,
+            };
+            for &pattern_id in self.forbidden_by_coarse_pattern[coarse_pattern_id].iter()
+            {
+                if fi.forbid_pattern(coord, pattern_id, rng).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wrap::WrapXY;
+    use coord_2d::Coord;
+    use rand::SeedableRng;
+
+    #[test]
+    fn hierarchical() {
+        // a checkerboard of 2x2 blocks
+        let input = Grid::new_fn(Size::new(8, 8), |Coord { x, y }| (x / 2 + y / 2) % 2);
+        let hierarchical_patterns = HierarchicalPatterns::new(
+            input,
+            NonZeroU32::new(2).unwrap(),
+            &[Orientation::Original],
+            NonZeroU32::new(2).unwrap(),
+        );
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let size = Size::new(8, 8);
+        let wave = hierarchical_patterns
+            .collapse(size, WrapXY, 10, &mut rng)
+            .unwrap();
+        let fine = hierarchical_patterns.fine();
+        let value = |coord| {
+            let pattern_id = wave.grid().get_checked(coord).chosen_pattern_id().unwrap();
+            *fine.pattern_top_left_value(pattern_id)
+        };
+        for coord in size.coord_iter_row_major() {
+            let block = Coord::new(coord.x / 2 * 2, coord.y / 2 * 2);
+            assert_eq!(value(coord), value(block));
+        }
+    }
+}
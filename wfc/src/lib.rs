@@ -1,11 +1,30 @@
+pub mod adjacency;
+pub mod always_compatible;
+pub mod constraint;
+pub mod dungeon;
+pub mod error;
+#[cfg(feature = "event-log")]
+pub mod event_log;
+pub mod hierarchical;
 pub mod orientation;
 pub mod overlapping;
 pub mod retry;
+pub mod sequence;
+pub mod statistics;
+pub mod storage;
+#[cfg(test)]
+mod test_utils;
+#[cfg(feature = "tiled")]
+pub mod tiled;
 mod tiled_slice;
 mod wfc;
 pub mod wrap;
 
 pub use crate::wfc::*;
 pub use coord_2d::{Coord, Size};
+pub use error::Error;
 pub use orientation::Orientation;
+#[cfg(feature = "parallel")]
+pub use rayon;
+pub use storage::CellStorage;
 pub use wrap::Wrap;
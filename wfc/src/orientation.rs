@@ -1,6 +1,10 @@
 use coord_2d::{Coord, Size};
+use grid_2d::Grid;
+#[cfg(feature = "serialize")]
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 #[repr(u8)]
 pub enum Orientation {
     /// ##.
@@ -72,9 +76,36 @@ impl Orientation {
             }
         }
     }
+    /// Whether the orientation swaps the width and height of whatever it's applied to
+    pub fn is_transposing(self) -> bool {
+        matches!(
+            self,
+            Clockwise90
+                | Clockwise270
+                | DiagonallyFlipped
+                | DiagonallyFlippedClockwise180
+        )
+    }
+    /// The size of a grid of `size` once transformed by this orientation
+    pub fn transform_size(self, size: Size) -> Size {
+        if self.is_transposing() {
+            Size::new(size.height(), size.width())
+        } else {
+            size
+        }
+    }
+    /// A copy of `grid` transformed by this orientation, in the same way as patterns are
+    /// transformed when they're extracted in this orientation. Grids needn't be square.
+    pub fn apply_to_grid<T: Clone>(self, grid: &Grid<T>) -> Grid<T> {
+        let size = self.transform_size(grid.size());
+        Grid::new_fn(size, |coord| {
+            grid.get_checked(self.transform_coord(size, coord)).clone()
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct OrientationTable<T> {
     table: [Option<T>; NUM_ORIENTATIONS],
 }
@@ -94,9 +125,18 @@ impl<T> OrientationTable<T> {
     pub fn insert(&mut self, orientation: Orientation, value: T) {
         self.table[orientation as usize] = Some(value);
     }
+    pub fn remove(&mut self, orientation: Orientation) -> Option<T> {
+        self.table[orientation as usize].take()
+    }
     pub fn iter(&self) -> impl Iterator<Item = &T> {
         self.table.iter().filter_map(|t| t.as_ref())
     }
+    /// Each orientation with a value, along with its value, in the order of `ALL`
+    pub fn enumerate(&self) -> impl Iterator<Item = (Orientation, &T)> {
+        ALL.iter()
+            .zip(self.table.iter())
+            .filter_map(|(&orientation, t)| t.as_ref().map(|t| (orientation, t)))
+    }
 }
 
 #[cfg(test)]
@@ -115,4 +155,43 @@ mod test {
             Coord::new(0, 2)
         );
     }
+
+    #[test]
+    fn apply_to_grid() {
+        // 0 1 2
+        // 3 4 5
+        let grid = Grid::new_fn(Size::new(3, 2), |coord| coord.x + coord.y * 3);
+        let rows = |grid: &Grid<i32>| {
+            (0..grid.size().height() as i32)
+                .map(|y| {
+                    (0..grid.size().width() as i32)
+                        .map(|x| *grid.get_checked(Coord::new(x, y)))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let rotated = Orientation::Clockwise90.apply_to_grid(&grid);
+        assert_eq!(rows(&rotated), vec![vec![3, 0], vec![4, 1], vec![5, 2]]);
+        let flipped = Orientation::DiagonallyFlipped.apply_to_grid(&grid);
+        assert_eq!(rows(&flipped), vec![vec![0, 3], vec![1, 4], vec![2, 5]]);
+        let mut turned = grid.clone();
+        for _ in 0..4 {
+            turned = Orientation::Clockwise90.apply_to_grid(&turned);
+        }
+        assert_eq!(rows(&turned), rows(&grid));
+    }
+
+    #[test]
+    fn enumerate() {
+        let mut table = OrientationTable::new();
+        table.insert(Orientation::Clockwise180, 'a');
+        table.insert(Orientation::Original, 'b');
+        assert_eq!(
+            table.enumerate().collect::<Vec<_>>(),
+            vec![
+                (Orientation::Original, &'b'),
+                (Orientation::Clockwise180, &'a')
+            ]
+        );
+    }
 }
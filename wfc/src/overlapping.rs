@@ -1,14 +1,48 @@
+//! The "overlapping model", where patterns are the square regions of an input grid.
+//!
+//! Whether the input and the output are periodic are separate choices:
+//!  - Periodic input is chosen with `InputWrap` when creating `OverlappingPatterns`.
+//!    `InputWrap::Tile` (the default) treats the input as tiling seamlessly, while
+//!    `InputWrap::Clamp` and `InputWrap::IgnoreBorders` are for inputs which don't tile.
+//!  - Periodic output is chosen with the `Wrap` of the run. A periodic output uses e.g.
+//!    `WrapXY` and is read with one value per cell. A non-periodic output uses `WrapNone` with
+//!    a wave of `non_periodic_wave_size`, and is read with `stitch_wave` so the patterns along
+//!    the right and bottom edges aren't cut off.
+
+#[cfg(feature = "serialize")]
+use crate::wfc::Fnv1a;
 use crate::{
+    constraint::EdgePatterns,
     orientation::{self, Orientation, OrientationTable},
     tiled_slice::TiledGridSlice,
-    wfc::{GlobalStats, PatternDescription, PatternId, PatternTable},
+    wfc::{
+        AdjacencyFrequencies, Context, GlobalStats, PatternDescription, PatternId,
+        PatternTable, RunBorrow, Wave,
+    },
+    wrap::Wrap,
 };
 use coord_2d::{Coord, Size};
 use direction::{CardinalDirection, CardinalDirectionTable, CardinalDirections};
 use grid_2d::{CoordIter, Grid};
 use hashbrown::HashMap;
+use rand::Rng;
+#[cfg(feature = "serialize")]
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+#[cfg(feature = "serialize")]
+use std::fs;
 use std::hash::Hash;
-use std::num::NonZeroU32;
+#[cfg(feature = "serialize")]
+use std::hash::Hasher;
+#[cfg(feature = "serialize")]
+use std::io;
+use std::iter;
+use std::mem;
+use std::num::{NonZeroU32, NonZeroUsize};
+#[cfg(feature = "serialize")]
+use std::path::Path;
+use std::time::{Duration, Instant};
 
 fn are_patterns_compatible<T: PartialEq>(
     a: &TiledGridSlice<T>,
@@ -35,12 +69,67 @@ fn are_patterns_compatible<T: PartialEq>(
     a_iter.zip(b_iter).all(|(a, b)| a == b)
 }
 
-#[derive(Debug)]
+// Like `are_patterns_compatible`, for patterns of the common small sizes which have been
+// copied into arrays. The loop bounds are constant so the comparisons can be unrolled.
+fn are_pattern_arrays_compatible<T: PartialEq, const N: usize>(
+    a: &[[&T; N]; N],
+    b: &[[&T; N]; N],
+    b_offset_direction: CardinalDirection,
+) -> bool {
+    let full = 0..N;
+    let overlap = 0..(N - 1);
+    match b_offset_direction {
+        CardinalDirection::North => overlap
+            .clone()
+            .all(|y| full.clone().all(|x| a[y][x] == b[y + 1][x])),
+        CardinalDirection::South => overlap
+            .clone()
+            .all(|y| full.clone().all(|x| a[y + 1][x] == b[y][x])),
+        CardinalDirection::East => full
+            .clone()
+            .all(|y| overlap.clone().all(|x| a[y][x + 1] == b[y][x])),
+        CardinalDirection::West => full
+            .clone()
+            .all(|y| overlap.clone().all(|x| a[y][x] == b[y][x + 1])),
+    }
+}
+
+// Like `are_patterns_compatible`, for patterns whose values have been packed into `u32`s (or
+// otherwise copied) and stored row-major. Each overlapping row (or the whole overlap, for
+// vertical offsets) is a contiguous slice, so the comparisons can use vectorised memory
+// comparisons.
+fn are_packed_patterns_compatible<V: PartialEq>(
+    a: &[V],
+    b: &[V],
+    width: usize,
+    b_offset_direction: CardinalDirection,
+) -> bool {
+    let overlap = a.len() - width;
+    match b_offset_direction {
+        CardinalDirection::North => a[..overlap] == b[width..],
+        CardinalDirection::South => a[width..] == b[..overlap],
+        CardinalDirection::East => a
+            .chunks_exact(width)
+            .zip(b.chunks_exact(width))
+            .all(|(a, b)| a[1..] == b[..width - 1]),
+        CardinalDirection::West => a
+            .chunks_exact(width)
+            .zip(b.chunks_exact(width))
+            .all(|(a, b)| a[..width - 1] == b[1..]),
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Pattern {
     id: PatternId,
     coords: Vec<Coord>,
     count: u32,
+    // how many of the occurrences counted in `count` were found in each orientation
+    orientation_counts: OrientationTable<u32>,
     orientation: Orientation,
+    // index of the example containing `coords`, where 0 is the grid passed to the constructor
+    example: usize,
 }
 
 impl Pattern {
@@ -49,29 +138,219 @@ impl Pattern {
             id,
             coords: Vec::new(),
             count: 0,
+            orientation_counts: OrientationTable::new(),
             orientation,
+            example: 0,
         }
     }
     fn tiled_grid_slice<'a, T>(
         &self,
         grid: &'a Grid<T>,
         size: Size,
+        input_wrap: InputWrap,
     ) -> TiledGridSlice<'a, T> {
-        TiledGridSlice::new(grid, self.coord(), size, self.orientation)
+        input_wrap.tiled_grid_slice(grid, self.coord(), size, self.orientation)
+    }
+    fn add_occurrence(&mut self, orientation: Orientation) {
+        self.count += 1;
+        match self.orientation_counts.get_mut(orientation) {
+            Some(count) => *count += 1,
+            None => self.orientation_counts.insert(orientation, 1),
+        }
     }
     pub fn coord(&self) -> Coord {
         self.coords[0]
     }
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+    /// The number of times the pattern occurs in the input
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+    /// The number of times the pattern occurs in the input when it is transformed by
+    /// `orientation`
+    pub fn count_in_orientation(&self, orientation: Orientation) -> u32 {
+        self.orientation_counts
+            .get(orientation)
+            .cloned()
+            .unwrap_or(0)
+    }
     pub fn clear_count(&mut self) {
         self.count = 0;
+        self.orientation_counts = OrientationTable::new();
     }
 }
 
+/// How patterns are sampled near the edges of the input grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub enum InputWrap {
+    /// The input is treated as tiling seamlessly, so patterns which overlap an edge continue
+    /// from the opposite edge
+    #[default]
+    Tile,
+    /// Patterns which overlap an edge repeat the values along that edge
+    Clamp,
+    /// Only patterns lying entirely within the input are sampled
+    IgnoreBorders,
+}
+
+impl InputWrap {
+    // The size of the region of the input whose cells are the top-left corners of patterns
+    fn sample_size(self, grid_size: Size, pattern_size: Size) -> Size {
+        match self {
+            InputWrap::Tile | InputWrap::Clamp => grid_size,
+            InputWrap::IgnoreBorders => Size::new(
+                (grid_size.width() + 1).saturating_sub(pattern_size.width()),
+                (grid_size.height() + 1).saturating_sub(pattern_size.height()),
+            ),
+        }
+    }
+    fn tiled_grid_slice<'a, T>(
+        self,
+        grid: &'a Grid<T>,
+        coord: Coord,
+        size: Size,
+        orientation: Orientation,
+    ) -> TiledGridSlice<'a, T> {
+        match self {
+            InputWrap::Tile | InputWrap::IgnoreBorders => {
+                TiledGridSlice::new(grid, coord, size, orientation)
+            }
+            InputWrap::Clamp => {
+                TiledGridSlice::new_clamped(grid, coord, size, orientation)
+            }
+        }
+    }
+}
+
+/// How occurrences of patterns which cross an edge of an input sampled with
+/// `InputWrap::Tile` are counted, along the axes of the output which don't wrap. See
+/// `OverlappingPatterns::set_output_wrap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BorderOccurrences {
+    /// Occurrences across edges count as much as any other occurrence
+    Count,
+    /// Occurrences which don't cross an edge count this many times as much as occurrences
+    /// which do, so patterns found across edges are proportionally rarer
+    DownWeight(NonZeroU32),
+    /// Occurrences across edges aren't counted, and patterns which only occur across an edge
+    /// are excluded altogether: they're unweighted and no pattern may be placed next to them,
+    /// so they're never placed
+    Exclude,
+}
+
+// The setting made with `OverlappingPatterns::set_output_wrap`
+#[derive(Debug, Clone, Copy)]
+struct OutputWrap {
+    wraps_x: bool,
+    wraps_y: bool,
+    border_occurrences: BorderOccurrences,
+}
+
+struct PatternExtraction<'a, T> {
+    grid: &'a Grid<T>,
+    mask: Option<&'a Grid<bool>>,
+    pattern_size: Size,
+    orientations: &'a [Orientation],
+    input_wrap: InputWrap,
+}
+
+impl<'a, T: Eq + Hash> PatternExtraction<'a, T> {
+    // Patterns are identified by `key`, which lets patterns of the common small sizes be
+    // hashed and compared as arrays rather than through the orientation transform
+    fn run<K, F>(
+        &self,
+        id_grid: &mut Grid<OrientationTable<PatternId>>,
+        key: F,
+    ) -> PatternTable<Pattern>
+    where
+        K: Eq + Hash,
+        F: Fn(&TiledGridSlice<'a, T>) -> K,
+    {
+        let mut pattern_map = HashMap::new();
+        let mut next_id = 0;
+        let sample_size = self
+            .input_wrap
+            .sample_size(self.grid.size(), self.pattern_size);
+        // whether the pattern at each coord lies entirely within the mask
+        let in_mask = self.mask.map(|mask| {
+            Grid::new_fn(sample_size, |coord| {
+                self.input_wrap
+                    .tiled_grid_slice(
+                        mask,
+                        coord,
+                        self.pattern_size,
+                        Orientation::Original,
+                    )
+                    .iter()
+                    .all(|&included| included)
+            })
+        });
+        for &orientation in self.orientations.iter() {
+            for coord in CoordIter::new(sample_size) {
+                if let Some(in_mask) = in_mask.as_ref() {
+                    if !*in_mask.get_checked(coord) {
+                        continue;
+                    }
+                }
+                let pattern_slice = self.input_wrap.tiled_grid_slice(
+                    self.grid,
+                    coord,
+                    self.pattern_size,
+                    orientation,
+                );
+                let pattern =
+                    pattern_map.entry(key(&pattern_slice)).or_insert_with(|| {
+                        let pattern = Pattern::new(next_id, orientation);
+                        next_id += 1;
+                        pattern
+                    });
+                pattern.coords.push(pattern_slice.offset());
+                pattern.add_occurrence(orientation);
+                id_grid
+                    .get_checked_mut(coord)
+                    .insert(orientation, pattern.id);
+            }
+        }
+        let mut patterns = pattern_map
+            .drain()
+            .map(|(_, pattern)| pattern)
+            .collect::<Vec<_>>();
+        patterns.sort_by_key(|pattern| pattern.id);
+        PatternTable::from_vec(patterns)
+    }
+}
+
+/// The reason a wave couldn't be stitched into an output by `OverlappingPatterns::stitch_wave`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StitchError {
+    /// The cell of the wave at `coord` isn't collapsed
+    NotCollapsed { coord: Coord },
+    /// The patterns covering `coord` in the output disagree about its value
+    OverlapMismatch { coord: Coord },
+}
+
+// An example added with `OverlappingPatterns::add_example`
+struct Example<T> {
+    grid: Grid<T>,
+    id_grid: Grid<OrientationTable<PatternId>>,
+}
+
 pub struct OverlappingPatterns<T: Eq + Clone + Hash> {
     pattern_table: PatternTable<Pattern>,
     pattern_size: Size,
+    orientations: Vec<Orientation>,
+    input_wrap: InputWrap,
+    orientation_weights: Option<OrientationTable<u32>>,
+    output_wrap: Option<OutputWrap>,
     grid: Grid<T>,
     id_grid: Grid<OrientationTable<PatternId>>,
+    extra_examples: Vec<Example<T>>,
+    mask: Option<Grid<bool>>,
+    // neighbours restored by `load_cache`, cleared whenever the patterns change
+    allowed_neighbours: Option<PatternTable<CardinalDirectionTable<Vec<PatternId>>>>,
 }
 
 impl<T: Eq + Clone + Hash> OverlappingPatterns<T> {
@@ -79,64 +358,428 @@ impl<T: Eq + Clone + Hash> OverlappingPatterns<T> {
         grid: Grid<T>,
         pattern_size: NonZeroU32,
         orientations: &[Orientation],
+    ) -> Self {
+        Self::new_input_wrap(grid, pattern_size, orientations, InputWrap::Tile)
+    }
+    /// Like `new`, but choosing how patterns are sampled near the edges of the input. Inputs
+    /// which don't tile seamlessly should use `InputWrap::Clamp` or `InputWrap::IgnoreBorders`
+    /// to avoid learning adjacencies which appear nowhere in the input. With
+    /// `InputWrap::IgnoreBorders`, cells near the right and bottom edges of the input have
+    /// no entries in the id grid.
+    pub fn new_input_wrap(
+        grid: Grid<T>,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+        input_wrap: InputWrap,
+    ) -> Self {
+        Self::extract(grid, None, pattern_size, orientations, input_wrap)
+    }
+    /// Like `new_input_wrap`, but only patterns lying entirely within the cells of `mask`
+    /// which are `true` are extracted, so that regions of the input such as watermarks are
+    /// left out of the model. Cells of the id grid whose pattern leaves the mask have no
+    /// entries. Panics if `mask` isn't the same size as `grid`.
+    pub fn new_masked(
+        grid: Grid<T>,
+        mask: Grid<bool>,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+        input_wrap: InputWrap,
+    ) -> Self {
+        assert_eq!(
+            mask.size(),
+            grid.size(),
+            "mask must be the same size as the input"
+        );
+        Self::extract(grid, Some(mask), pattern_size, orientations, input_wrap)
+    }
+    fn extract(
+        grid: Grid<T>,
+        mask: Option<Grid<bool>>,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+        input_wrap: InputWrap,
     ) -> Self {
         let pattern_size = Size::new(pattern_size.get(), pattern_size.get());
         let empty: OrientationTable<PatternId> = OrientationTable::new();
         let mut id_grid = Grid::new_clone(grid.size(), empty);
-        let pattern_table = {
-            let mut pattern_map = HashMap::new();
-            let mut next_id = 0;
-            for &orientation in orientations.iter() {
-                for coord in CoordIter::new(grid.size()) {
-                    let pattern_slice =
-                        TiledGridSlice::new(&grid, coord, pattern_size, orientation);
-                    let pattern =
-                        pattern_map.entry(pattern_slice.clone()).or_insert_with(|| {
-                            let pattern = Pattern::new(next_id, orientation);
-                            next_id += 1;
-                            pattern
-                        });
-                    pattern.coords.push(pattern_slice.offset());
-                    pattern.count += 1;
-                    id_grid
-                        .get_checked_mut(coord)
-                        .insert(orientation, pattern.id);
-                }
-            }
-            let mut patterns = pattern_map
-                .drain()
-                .map(|(_, pattern)| pattern)
-                .collect::<Vec<_>>();
-            patterns.sort_by_key(|pattern| pattern.id);
-            PatternTable::from_vec(patterns)
+        let extract = PatternExtraction {
+            grid: &grid,
+            mask: mask.as_ref(),
+            pattern_size,
+            orientations,
+            input_wrap,
+        };
+        let pattern_table = match pattern_size.width() {
+            2 => extract.run(&mut id_grid, |slice| slice.to_array::<2>()),
+            3 => extract.run(&mut id_grid, |slice| slice.to_array::<3>()),
+            _ => extract.run(&mut id_grid, |slice| slice.clone()),
         };
         Self {
             pattern_table,
             pattern_size,
+            orientations: orientations.to_vec(),
+            input_wrap,
+            orientation_weights: None,
+            output_wrap: None,
             grid,
             id_grid,
+            extra_examples: Vec::new(),
+            mask,
+            allowed_neighbours: None,
         }
     }
+    /// Learn from another example, as though it had been part of the input from the start.
+    /// Patterns already seen have their counts increased, and new patterns are given the next
+    /// available ids, so existing ids remain valid. Allowed neighbours are derived from the
+    /// patterns, so they reflect the new example the next time `pattern_descriptions` or
+    /// `global_stats` is called. The id grid and the value returned by `grid` still refer to
+    /// the original input.
+    pub fn add_example(&mut self, grid: Grid<T>) {
+        let example = self.extra_examples.len() + 1;
+        let mut ids_by_values = self
+            .pattern_table
+            .enumerate()
+            .map(|(pattern_id, pattern)| (self.pattern_contents(pattern), pattern_id))
+            .collect::<HashMap<_, _>>();
+        let mut patterns = self.pattern_table.drain().collect::<Vec<_>>();
+        let mut id_grid = Grid::new_clone(grid.size(), OrientationTable::new());
+        let sample_size = self.input_wrap.sample_size(grid.size(), self.pattern_size);
+        for &orientation in self.orientations.iter() {
+            for coord in CoordIter::new(sample_size) {
+                let pattern_slice = self.input_wrap.tiled_grid_slice(
+                    &grid,
+                    coord,
+                    self.pattern_size,
+                    orientation,
+                );
+                let values = pattern_slice.iter().cloned().collect::<Vec<_>>();
+                let pattern_id = *ids_by_values.entry(values).or_insert_with(|| {
+                    let pattern_id = patterns.len() as PatternId;
+                    let mut pattern = Pattern::new(pattern_id, orientation);
+                    pattern.example = example;
+                    patterns.push(pattern);
+                    pattern_id
+                });
+                let pattern = &mut patterns[pattern_id as usize];
+                if pattern.example == example {
+                    pattern.coords.push(pattern_slice.offset());
+                }
+                pattern.add_occurrence(orientation);
+                id_grid
+                    .get_checked_mut(coord)
+                    .insert(orientation, pattern_id);
+            }
+        }
+        self.pattern_table = PatternTable::from_vec(patterns);
+        self.extra_examples.push(Example { grid, id_grid });
+        self.allowed_neighbours = None;
+    }
+    fn example_grid(&self, example: usize) -> &Grid<T> {
+        match example {
+            0 => &self.grid,
+            _ => &self.extra_examples[example - 1].grid,
+        }
+    }
+    fn id_grids(&self) -> impl Iterator<Item = &Grid<OrientationTable<PatternId>>> {
+        iter::once(&self.id_grid).chain(self.extra_examples.iter().map(|e| &e.id_grid))
+    }
+    fn pattern_slice(&self, pattern: &Pattern) -> TiledGridSlice<'_, T> {
+        pattern.tiled_grid_slice(
+            self.example_grid(pattern.example),
+            self.pattern_size,
+            self.input_wrap,
+        )
+    }
     pub fn new_all_orientations(grid: Grid<T>, pattern_size: NonZeroU32) -> Self {
         Self::new(grid, pattern_size, &orientation::ALL)
     }
     pub fn new_original_orientation(grid: Grid<T>, pattern_size: NonZeroU32) -> Self {
         Self::new(grid, pattern_size, &[Orientation::Original])
     }
+    /// Removes patterns which occur fewer than `count_threshold` times in the input, which
+    /// can greatly reduce the number of patterns taken from noisy inputs. The remaining
+    /// patterns are given new ids, and cells of the id grid whose pattern was removed have no
+    /// entry for that orientation. Patterns whose count was cleared with `clear_count` are
+    /// removed too, so prune before clearing counts.
+    pub fn prune_below(&mut self, count_threshold: u32) {
+        let mut next_id = 0;
+        let new_ids = self
+            .pattern_table
+            .iter()
+            .map(|pattern| {
+                (pattern.count >= count_threshold).then(|| {
+                    next_id += 1;
+                    next_id - 1
+                })
+            })
+            .collect();
+        self.renumber(new_ids);
+    }
+    /// Gives the patterns new ids in order of their contents, so ids depend only on which
+    /// patterns appear in the input, and not on where they first appear. This makes ids
+    /// stable across changes to the input (e.g. cropping) which don't change its patterns.
+    pub fn sort_ids_by_content(&mut self)
+    where
+        T: Ord,
+    {
+        self.sort_ids_by_content_key(T::clone);
+    }
+    /// Like `sort_ids_by_content`, but values are ordered by `key`, for value types which
+    /// aren't `Ord`
+    pub fn sort_ids_by_content_key<K: Ord, F: FnMut(&T) -> K>(&mut self, mut key: F) {
+        let mut by_content = self
+            .pattern_table
+            .enumerate()
+            .map(|(pattern_id, pattern)| {
+                let values = self.pattern_contents(pattern);
+                (values.iter().map(&mut key).collect::<Vec<_>>(), pattern_id)
+            })
+            .collect::<Vec<_>>();
+        by_content.sort();
+        let mut new_ids = vec![None; by_content.len()];
+        for (new_id, (_, old_id)) in by_content.into_iter().enumerate() {
+            new_ids[old_id as usize] = Some(new_id as PatternId);
+        }
+        self.renumber(new_ids);
+    }
+    // Gives each pattern the id at its index in `new_ids`, removing patterns which map to
+    // `None`. The new ids must be distinct and contiguous from 0.
+    fn renumber(&mut self, new_ids: Vec<Option<PatternId>>) {
+        let mut patterns = self
+            .pattern_table
+            .drain()
+            .zip(new_ids.iter())
+            .filter_map(|(mut pattern, &new_id)| {
+                pattern.id = new_id?;
+                Some(pattern)
+            })
+            .collect::<Vec<_>>();
+        patterns.sort_by_key(|pattern| pattern.id);
+        self.pattern_table = PatternTable::from_vec(patterns);
+        self.allowed_neighbours = None;
+        let id_grids = iter::once(&mut self.id_grid)
+            .chain(self.extra_examples.iter_mut().map(|e| &mut e.id_grid));
+        for ids in id_grids.flat_map(|id_grid| id_grid.iter_mut()) {
+            for &orientation in orientation::ALL.iter() {
+                if let Some(&old_id) = ids.get(orientation) {
+                    match new_ids[old_id as usize] {
+                        Some(new_id) => ids.insert(orientation, new_id),
+                        None => {
+                            ids.remove(orientation);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    /// Multiply the number of times each pattern occurs in each orientation by the weight
+    /// of that orientation, so that e.g. rotated versions of patterns can be made rarer than
+    /// the originals. Orientations missing from `weights` have a weight of 1. A pattern
+    /// whose weighted count is 0 is unweighted, so it will only be chosen if it is the only
+    /// remaining possibility for a cell.
+    pub fn set_orientation_weights(&mut self, weights: OrientationTable<u32>) {
+        self.orientation_weights = Some(weights);
+    }
+    pub fn clear_orientation_weights(&mut self) {
+        self.orientation_weights = None;
+    }
+    /// An input sampled with `InputWrap::Tile` yields patterns which cross its edges, and so
+    /// only exist because the input is treated as tiling. Along the axes on which `wrap`
+    /// doesn't wrap the output, occurrences of such patterns are counted according to
+    /// `border_occurrences` when weighting patterns, so that seams which are only plausible in
+    /// a tiling output are rarer or absent. Axes are matched up through each pattern's
+    /// orientation, so e.g. occurrences across the input's left and right edges of patterns
+    /// rotated by 90 degrees are affected when the output doesn't wrap vertically. Has no
+    /// effect for other `InputWrap`s.
+    pub fn set_output_wrap<W: Wrap>(
+        &mut self,
+        wrap: W,
+        border_occurrences: BorderOccurrences,
+    ) {
+        let _ = wrap;
+        // the neighbour beyond the west or north edge of a wrapping output is on the
+        // opposite edge
+        let size = Size::new(2, 2);
+        self.output_wrap = Some(OutputWrap {
+            wraps_x: W::normalize_coord(Coord::new(-1, 0), size)
+                == Some(Coord::new(1, 0)),
+            wraps_y: W::normalize_coord(Coord::new(0, -1), size)
+                == Some(Coord::new(0, 1)),
+            border_occurrences,
+        });
+    }
+    pub fn clear_output_wrap(&mut self) {
+        self.output_wrap = None;
+    }
+    fn orientation_weight(&self, orientation: Orientation) -> u32 {
+        self.orientation_weights
+            .as_ref()
+            .and_then(|weights| weights.get(orientation).cloned())
+            .unwrap_or(1)
+    }
+    fn pattern_weight(&self, pattern: &Pattern) -> Option<NonZeroU32> {
+        match self.orientation_weights.as_ref() {
+            None => NonZeroU32::new(pattern.count),
+            Some(_) => NonZeroU32::new(
+                orientation::ALL
+                    .iter()
+                    .map(|&orientation| {
+                        pattern.count_in_orientation(orientation)
+                            * self.orientation_weight(orientation)
+                    })
+                    .sum(),
+            ),
+        }
+    }
+    fn pattern_weights(&self) -> PatternTable<Option<NonZeroU32>> {
+        let output_wrap = match self.output_wrap {
+            Some(output_wrap) if self.input_wrap == InputWrap::Tile => output_wrap,
+            _ => {
+                return self
+                    .pattern_table
+                    .map_ref(|pattern| self.pattern_weight(pattern))
+            }
+        };
+        let (interior_weight, border_weight) = match output_wrap.border_occurrences {
+            BorderOccurrences::Count => (1, 1),
+            BorderOccurrences::DownWeight(factor) => (factor.get(), 1),
+            BorderOccurrences::Exclude => (1, 0),
+        };
+        let mut weights = self.pattern_table.map_ref(|_| 0);
+        for (example, id_grid) in self.id_grids().enumerate() {
+            let grid_size = self.example_grid(example).size();
+            for (coord, ids) in id_grid.enumerate() {
+                let crosses_x =
+                    coord.x as u32 + self.pattern_size.width() > grid_size.width();
+                let crosses_y =
+                    coord.y as u32 + self.pattern_size.height() > grid_size.height();
+                for &orientation in self.orientations.iter() {
+                    let pattern_id = match ids.get(orientation) {
+                        Some(&pattern_id) => pattern_id,
+                        None => continue,
+                    };
+                    // whether the input's x axis is the output's x axis in this orientation
+                    let east = orientation
+                        .transform_coord(self.pattern_size, Coord::new(1, 0))
+                        - orientation
+                            .transform_coord(self.pattern_size, Coord::new(0, 0));
+                    let (input_x_wraps, input_y_wraps) = if east.x != 0 {
+                        (output_wrap.wraps_x, output_wrap.wraps_y)
+                    } else {
+                        (output_wrap.wraps_y, output_wrap.wraps_x)
+                    };
+                    let across_border =
+                        (crosses_x && !input_x_wraps) || (crosses_y && !input_y_wraps);
+                    let weight = if across_border {
+                        border_weight
+                    } else {
+                        interior_weight
+                    };
+                    weights[pattern_id] += weight * self.orientation_weight(orientation);
+                }
+            }
+        }
+        self.pattern_table
+            .iter()
+            .zip(weights.iter())
+            .map(|(pattern, &weight)| {
+                // counts cleared with `clear_count` stay cleared
+                if pattern.count == 0 {
+                    None
+                } else {
+                    NonZeroU32::new(weight)
+                }
+            })
+            .collect()
+    }
     pub fn grid(&self) -> &Grid<T> {
         &self.grid
     }
+    /// The mask given to `new_masked`, if any
+    pub fn mask(&self) -> Option<&Grid<bool>> {
+        self.mask.as_ref()
+    }
+    pub fn pattern_size(&self) -> Size {
+        self.pattern_size
+    }
+    pub fn input_wrap(&self) -> InputWrap {
+        self.input_wrap
+    }
+    /// The size of a non-wrapping wave whose output from `stitch_wave` is `output_size`, which
+    /// is `pattern_size - 1` smaller in each dimension. Dimensions smaller than the pattern
+    /// size are treated as the pattern size.
+    pub fn non_periodic_wave_size(&self, output_size: Size) -> Size {
+        Size::new(
+            (output_size.width() + 1)
+                .saturating_sub(self.pattern_size.width())
+                .max(1),
+            (output_size.height() + 1)
+                .saturating_sub(self.pattern_size.height())
+                .max(1),
+        )
+    }
+    fn pattern_contents(&self, pattern: &Pattern) -> Vec<T> {
+        self.pattern_slice(pattern).iter().cloned().collect()
+    }
+    /// The values of a pattern in row-major order, in the orientation in which the pattern
+    /// appears in the output
+    pub fn pattern_values(&self, pattern_id: PatternId) -> impl Iterator<Item = &T> {
+        let tiled_grid_slice = self.pattern_slice(self.pattern(pattern_id));
+        CoordIter::new(tiled_grid_slice.size())
+            .map(move |coord| tiled_grid_slice.get_checked(coord))
+    }
+    /// Collects `pattern_values` into a `Vec`
+    pub fn pattern_to_vec(&self, pattern_id: PatternId) -> Vec<T> {
+        self.pattern_contents(self.pattern(pattern_id))
+    }
+    pub fn num_patterns(&self) -> usize {
+        self.pattern_table.len()
+    }
     pub fn pattern(&self, pattern_id: PatternId) -> &Pattern {
         &self.pattern_table[pattern_id]
     }
     pub fn pattern_mut(&mut self, pattern_id: PatternId) -> &mut Pattern {
         &mut self.pattern_table[pattern_id]
     }
+    /// The value at the top-left of a pattern in the orientation in which the pattern appears
+    /// in the output, which for a rotated or reflected pattern is not the value at the
+    /// pattern's coord in the input
     pub fn pattern_top_left_value(&self, pattern_id: PatternId) -> &T {
-        let pattern = self.pattern(pattern_id);
-        let tiled_grid_slice = pattern.tiled_grid_slice(&self.grid, self.pattern_size);
+        let tiled_grid_slice = self.pattern_slice(self.pattern(pattern_id));
         tiled_grid_slice.get_checked(Coord::new(0, 0))
     }
+    /// Writes out the whole of each cell's pattern rather than just its top-left value, giving
+    /// an output `pattern_size - 1` larger than the wave in each dimension. This is intended
+    /// for waves collapsed with `WrapNone`, where the patterns of cells on the right and bottom
+    /// edges would otherwise be cut off. Fails if a cell isn't collapsed, or if overlapping
+    /// patterns disagree.
+    pub fn stitch_wave(&self, wave: &Wave) -> Result<Grid<T>, StitchError> {
+        let wave_size = wave.grid().size();
+        let output_size = wave_size + self.pattern_size - Size::new(1, 1);
+        let mut output: Grid<Option<T>> = Grid::new_fn(output_size, |_| None);
+        for (coord, cell) in wave.grid().enumerate() {
+            let pattern_id = cell
+                .chosen_pattern_id()
+                .map_err(|_| StitchError::NotCollapsed { coord })?;
+            let tiled_grid_slice = self.pattern_slice(self.pattern(pattern_id));
+            for offset in CoordIter::new(self.pattern_size) {
+                let value = tiled_grid_slice.get_checked(offset);
+                let output_coord = coord + offset;
+                match output.get_checked_mut(output_coord) {
+                    Some(existing) if existing != value => {
+                        return Err(StitchError::OverlapMismatch {
+                            coord: output_coord,
+                        })
+                    }
+                    Some(_) => (),
+                    empty @ None => *empty = Some(value.clone()),
+                }
+            }
+        }
+        Ok(Grid::new_grid_map(output, |value| {
+            value.expect("every output cell is covered by a pattern")
+        }))
+    }
     pub fn id_grid(&self) -> Grid<OrientationTable<PatternId>> {
         self.id_grid.clone()
     }
@@ -155,12 +798,11 @@ impl<T: Eq + Clone + Hash> OverlappingPatterns<T> {
         pattern: &'b Pattern,
         direction: CardinalDirection,
     ) -> impl 'b + Iterator<Item = PatternId> {
-        let tiled_grid_slice = pattern.tiled_grid_slice(&self.grid, self.pattern_size);
+        let tiled_grid_slice = self.pattern_slice(pattern);
         self.pattern_table
             .enumerate()
             .filter(move |(_id, other)| {
-                let other_tiled_grid_slice =
-                    other.tiled_grid_slice(&self.grid, self.pattern_size);
+                let other_tiled_grid_slice = self.pattern_slice(other);
                 are_patterns_compatible(
                     &tiled_grid_slice,
                     &other_tiled_grid_slice,
@@ -170,10 +812,98 @@ impl<T: Eq + Clone + Hash> OverlappingPatterns<T> {
             .map(|(id, _other)| id)
     }
     pub fn pattern_descriptions(&self) -> PatternTable<PatternDescription> {
+        self.exclude_border_only_patterns(self.unexcluded_pattern_descriptions())
+    }
+    // The pattern descriptions before `exclude_border_only_patterns`, whose adjacencies
+    // don't depend on the output wrap
+    fn unexcluded_pattern_descriptions(&self) -> PatternTable<PatternDescription> {
+        self.cached_pattern_descriptions().unwrap_or_else(|| {
+            match self.pattern_size.width() {
+                2 => self.pattern_descriptions_array::<2>(),
+                3 => self.pattern_descriptions_array::<3>(),
+                _ => self.pattern_descriptions_slice(),
+            }
+        })
+    }
+    // With `BorderOccurrences::Exclude`, patterns which only occur across an edge of the
+    // input have no weight, but could still be placed when propagation leaves nothing else,
+    // so they're also removed from every adjacency, leaving them no way to be placed
+    fn exclude_border_only_patterns(
+        &self,
+        mut pattern_descriptions: PatternTable<PatternDescription>,
+    ) -> PatternTable<PatternDescription> {
+        match self.output_wrap {
+            Some(OutputWrap {
+                border_occurrences: BorderOccurrences::Exclude,
+                ..
+            }) => (),
+            _ => return pattern_descriptions,
+        }
+        // patterns cleared with `clear_count` are unweighted but not excluded
+        let excluded = self
+            .pattern_table
+            .iter()
+            .zip(pattern_descriptions.iter())
+            .map(|(pattern, description)| {
+                pattern.count != 0 && description.weight.is_none()
+            })
+            .collect::<Vec<_>>();
+        if !excluded.contains(&true) {
+            return pattern_descriptions;
+        }
+        for (pattern_id, description) in pattern_descriptions.enumerate_mut() {
+            for direction in CardinalDirections {
+                if excluded[pattern_id as usize] {
+                    description.allowed_neighbours[direction].clear();
+                } else {
+                    description.allowed_neighbours[direction]
+                        .retain(|&neighbour_id| !excluded[neighbour_id as usize]);
+                }
+            }
+        }
+        pattern_descriptions
+    }
+    fn cached_pattern_descriptions(&self) -> Option<PatternTable<PatternDescription>> {
+        let allowed_neighbours = self.allowed_neighbours.as_ref()?;
+        Some(
+            self.pattern_weights()
+                .iter()
+                .zip(allowed_neighbours.iter())
+                .map(|(&weight, allowed_neighbours)| {
+                    PatternDescription::new(weight, allowed_neighbours.clone())
+                })
+                .collect(),
+        )
+    }
+    fn pattern_descriptions_array<const N: usize>(
+        &self,
+    ) -> PatternTable<PatternDescription> {
+        let arrays = self
+            .pattern_table
+            .map_ref(|pattern| self.pattern_slice(pattern).to_array::<N>());
+        self.pattern_weights()
+            .iter()
+            .zip(arrays.iter())
+            .map(|(&weight, array)| {
+                let mut allowed_neighbours = CardinalDirectionTable::default();
+                for direction in CardinalDirections {
+                    allowed_neighbours[direction] = arrays
+                        .enumerate()
+                        .filter(|(_id, other)| {
+                            are_pattern_arrays_compatible(array, other, direction)
+                        })
+                        .map(|(id, _other)| id)
+                        .collect::<Vec<_>>();
+                }
+                PatternDescription::new(weight, allowed_neighbours)
+            })
+            .collect::<PatternTable<_>>()
+    }
+    fn pattern_descriptions_slice(&self) -> PatternTable<PatternDescription> {
         self.pattern_table
             .iter()
-            .map(|pattern| {
-                let weight = NonZeroU32::new(pattern.count);
+            .zip(self.pattern_weights().iter())
+            .map(|(pattern, &weight)| {
                 let mut allowed_neighbours = CardinalDirectionTable::default();
                 for direction in CardinalDirections {
                     allowed_neighbours[direction] = self
@@ -184,6 +914,806 @@ impl<T: Eq + Clone + Hash> OverlappingPatterns<T> {
             })
             .collect::<PatternTable<_>>()
     }
+    /// Like `pattern_descriptions`, but values are compared after being packed into `u32`s
+    /// by `pack`, which is much faster for values such as RGBA pixels which fit in 32 bits.
+    /// `pack` must map values to the same `u32` if and only if they are equal.
+    pub fn pattern_descriptions_packed<F: FnMut(&T) -> u32>(
+        &self,
+        mut pack: F,
+    ) -> PatternTable<PatternDescription> {
+        if let Some(pattern_descriptions) = self.cached_pattern_descriptions() {
+            return self.exclude_border_only_patterns(pattern_descriptions);
+        }
+        let width = self.pattern_size.width() as usize;
+        let packed = self.pattern_table.map_ref(|pattern| {
+            self.pattern_slice(pattern)
+                .iter()
+                .map(&mut pack)
+                .collect::<Vec<_>>()
+        });
+        let pattern_descriptions = self
+            .pattern_weights()
+            .iter()
+            .zip(packed.iter())
+            .map(|(&weight, values)| {
+                let mut allowed_neighbours = CardinalDirectionTable::default();
+                for direction in CardinalDirections {
+                    allowed_neighbours[direction] = packed
+                        .enumerate()
+                        .filter(|(_id, other)| {
+                            are_packed_patterns_compatible(
+                                values, other, width, direction,
+                            )
+                        })
+                        .map(|(id, _other)| id)
+                        .collect::<Vec<_>>();
+                }
+                PatternDescription::new(weight, allowed_neighbours)
+            })
+            .collect::<PatternTable<_>>();
+        self.exclude_border_only_patterns(pattern_descriptions)
+    }
+    pub fn global_stats(&self) -> GlobalStats {
+        GlobalStats::new(self.pattern_descriptions())
+    }
+    /// Which patterns were sampled from each edge of the input, for use as an
+    /// `EdgePatterns` with non-wrapping outputs, e.g. a pattern sampled from the input's left
+    /// column may be placed at the western edge of the output. Patterns sampled in other
+    /// orientations are allowed at the correspondingly transformed edges. An input which
+    /// tiles (`InputWrap::Tile`) has no edges, so every pattern is allowed at every edge.
+    pub fn edge_patterns(&self) -> EdgePatterns {
+        let all = self.input_wrap == InputWrap::Tile;
+        let mut allowed = self
+            .pattern_table
+            .map_ref(|_| CardinalDirectionTable::new_array([all; 4]));
+        if all {
+            return EdgePatterns::new(allowed);
+        }
+        for (example, id_grid) in self.id_grids().enumerate() {
+            let grid_size = self.example_grid(example).size();
+            let sample_size = self.input_wrap.sample_size(grid_size, self.pattern_size);
+            // whether the pattern sampled at `coord` touches the input's edge in `direction`
+            let at_edge = |coord: Coord, direction: CardinalDirection| match direction {
+                CardinalDirection::North => coord.y == 0,
+                CardinalDirection::West => coord.x == 0,
+                CardinalDirection::South => {
+                    coord.y as u32 + self.pattern_size.height() >= grid_size.height()
+                }
+                CardinalDirection::East => {
+                    coord.x as u32 + self.pattern_size.width() >= grid_size.width()
+                }
+            };
+            for coord in CoordIter::new(sample_size) {
+                let ids = id_grid.get_checked(coord);
+                for orientation in self.orientations.iter().cloned() {
+                    let pattern_id = match ids.get(orientation) {
+                        Some(&pattern_id) => pattern_id,
+                        None => continue,
+                    };
+                    for direction in CardinalDirections {
+                        // the direction in the input of `direction` within the pattern
+                        let offset = orientation
+                            .transform_coord(self.pattern_size, direction.coord())
+                            - orientation
+                                .transform_coord(self.pattern_size, Coord::new(0, 0));
+                        let input_direction = CardinalDirections
+                            .into_iter()
+                            .find(|d| d.coord() == offset)
+                            .expect("orientations map directions to directions");
+                        if at_edge(coord, input_direction) {
+                            *allowed[pattern_id].get_mut(direction) = true;
+                        }
+                    }
+                }
+            }
+        }
+        EdgePatterns::new(allowed)
+    }
+    /// Counts how often each pattern appears next to each other pattern in the input, for
+    /// use with `GlobalStats::set_adjacency_frequencies`
+    pub fn adjacency_frequencies(&self) -> AdjacencyFrequencies {
+        let mut counts = self
+            .pattern_table
+            .map_ref(|_| CardinalDirectionTable::<HashMap<PatternId, u32>>::default());
+        for (id_grid, (coord, ids)) in self
+            .id_grids()
+            .flat_map(|id_grid| id_grid.enumerate().map(move |entry| (id_grid, entry)))
+        {
+            for orientation in orientation::ALL {
+                let pattern_id = match ids.get(orientation) {
+                    Some(&pattern_id) => pattern_id,
+                    None => continue,
+                };
+                for direction in CardinalDirections {
+                    // the offset in the input between this pattern and its neighbour in
+                    // `direction`, once both are transformed by `orientation`
+                    let offset = orientation
+                        .transform_coord(self.pattern_size, direction.coord())
+                        - orientation
+                            .transform_coord(self.pattern_size, Coord::new(0, 0));
+                    let neighbour_coord = coord + offset;
+                    let neighbour_ids = match self.input_wrap {
+                        InputWrap::Tile => Some(id_grid.get_tiled(neighbour_coord)),
+                        InputWrap::Clamp | InputWrap::IgnoreBorders => {
+                            id_grid.get(neighbour_coord)
+                        }
+                    };
+                    if let Some(&neighbour_id) =
+                        neighbour_ids.and_then(|ids| ids.get(orientation))
+                    {
+                        *counts[pattern_id]
+                            .get_mut(direction)
+                            .entry(neighbour_id)
+                            .or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+        counts.map(|by_direction| {
+            let mut frequencies = CardinalDirectionTable::default();
+            for direction in CardinalDirections {
+                let mut by_neighbour = by_direction
+                    .get(direction)
+                    .iter()
+                    .map(|(&id, &count)| (id, count))
+                    .collect::<Vec<_>>();
+                by_neighbour.sort_unstable();
+                *frequencies.get_mut(direction) = by_neighbour;
+            }
+            frequencies
+        })
+    }
+}
+
+#[cfg(feature = "serialize")]
+const CACHE_VERSION: u32 = 1;
+
+// What `OverlappingPatterns::save_cache` writes. The fields before `pattern_table` identify
+// the input and the settings the patterns were extracted with.
+#[cfg(feature = "serialize")]
+#[derive(Serialize, Deserialize)]
+struct Cache<T> {
+    version: u32,
+    input_hash: u64,
+    pattern_size: Size,
+    orientations: Vec<Orientation>,
+    input_wrap: InputWrap,
+    pattern_table: PatternTable<Pattern>,
+    id_grid: Grid<OrientationTable<PatternId>>,
+    extra_examples: Vec<(Grid<T>, Grid<OrientationTable<PatternId>>)>,
+    allowed_neighbours: PatternTable<CardinalDirectionTable<Vec<PatternId>>>,
+}
+
+#[cfg(feature = "serialize")]
+fn input_hash<T: Hash>(grid: &Grid<T>, mask: Option<&Grid<bool>>) -> u64 {
+    let mut hasher = Fnv1a::new();
+    grid.size().hash(&mut hasher);
+    grid.iter().for_each(|value| value.hash(&mut hasher));
+    if let Some(mask) = mask {
+        mask.iter().for_each(|included| included.hash(&mut hasher));
+    }
+    hasher.finish()
+}
+
+#[cfg(feature = "serialize")]
+pub(crate) fn io_result<T>(result: bincode::Result<T>) -> io::Result<T> {
+    result.map_err(|error| match *error {
+        bincode::ErrorKind::Io(error) => error,
+        error => io::Error::new(io::ErrorKind::InvalidData, error),
+    })
+}
+
+#[cfg(feature = "serialize")]
+impl<T: Eq + Clone + Hash + Serialize + DeserializeOwned> OverlappingPatterns<T> {
+    /// Writes the patterns and the neighbours allowed next to each pattern to `path`, so
+    /// applications which start up repeatedly with the same input can restore them with
+    /// `load_cache` rather than extracting them and computing their adjacencies again.
+    /// Examples, pruning and renumbering are saved, but orientation weights are not.
+    /// Requires the `serialize` feature.
+    pub fn save_cache<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let allowed_neighbours = match self.allowed_neighbours.as_ref() {
+            Some(allowed_neighbours) => allowed_neighbours.clone(),
+            None => self
+                .unexcluded_pattern_descriptions()
+                .iter()
+                .map(|description| description.allowed_neighbours.clone())
+                .collect(),
+        };
+        let cache = Cache {
+            version: CACHE_VERSION,
+            input_hash: input_hash(&self.grid, self.mask.as_ref()),
+            pattern_size: self.pattern_size,
+            orientations: self.orientations.clone(),
+            input_wrap: self.input_wrap,
+            pattern_table: self.pattern_table.clone(),
+            id_grid: self.id_grid.clone(),
+            extra_examples: self
+                .extra_examples
+                .iter()
+                .map(|example| (example.grid.clone(), example.id_grid.clone()))
+                .collect(),
+            allowed_neighbours,
+        };
+        let writer = io::BufWriter::new(fs::File::create(path)?);
+        io_result(bincode::serialize_into(writer, &cache))
+    }
+    /// Like `new_input_wrap` (or `new_masked` if `mask` is given), but restoring the
+    /// patterns saved to `path` by `save_cache` rather than extracting them. Returns `None`
+    /// if there is no file at `path`, or if it was saved from a different input or mask, by a
+    /// different version of this library, or with a different pattern size, orientations or
+    /// input wrap. Requires the `serialize` feature.
+    pub fn load_cache<P: AsRef<Path>>(
+        path: P,
+        grid: &Grid<T>,
+        mask: Option<&Grid<bool>>,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+        input_wrap: InputWrap,
+    ) -> io::Result<Option<Self>> {
+        let file = match fs::File::open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let cache: Cache<T> =
+            io_result(bincode::deserialize_from(io::BufReader::new(file)))?;
+        let pattern_size = Size::new(pattern_size.get(), pattern_size.get());
+        if cache.version != CACHE_VERSION
+            || cache.input_hash != input_hash(grid, mask)
+            || cache.pattern_size != pattern_size
+            || cache.orientations != orientations
+            || cache.input_wrap != input_wrap
+        {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            pattern_table: cache.pattern_table,
+            pattern_size,
+            orientations: cache.orientations,
+            input_wrap,
+            orientation_weights: None,
+            output_wrap: None,
+            grid: grid.clone(),
+            id_grid: cache.id_grid,
+            extra_examples: cache
+                .extra_examples
+                .into_iter()
+                .map(|(grid, id_grid)| Example { grid, id_grid })
+                .collect(),
+            mask: mask.cloned(),
+            allowed_neighbours: Some(cache.allowed_neighbours),
+        }))
+    }
+}
+
+/// The outcome of trying one pattern size in `sweep_pattern_sizes`
+#[derive(Debug, Clone)]
+pub struct PatternSizeReport {
+    pub pattern_size: NonZeroU32,
+    pub num_patterns: usize,
+    pub attempts: usize,
+    pub successes: usize,
+    /// Time spent extracting patterns and making every attempt
+    pub duration: Duration,
+}
+
+impl PatternSizeReport {
+    /// The fraction of attempts which collapsed without contradiction
+    pub fn success_rate(&self) -> f64 {
+        if self.attempts == 0 {
+            0.0
+        } else {
+            self.successes as f64 / self.attempts as f64
+        }
+    }
+}
+
+/// Tries each pattern size from 2 to `max_pattern_size`, making `attempts_per_size` attempts
+/// to collapse a wave of `output_size` with patterns taken from `grid` (with
+/// `InputWrap::Tile`), so a pattern size can be chosen empirically. Larger patterns
+/// reproduce more of the input's structure, but contradict more often and take longer.
+/// Returns a report for each pattern size in increasing order.
+pub fn sweep_pattern_sizes<T, W, R>(
+    grid: &Grid<T>,
+    orientations: &[Orientation],
+    output_size: Size,
+    max_pattern_size: NonZeroU32,
+    attempts_per_size: usize,
+    wrap: W,
+    rng: &mut R,
+) -> Vec<PatternSizeReport>
+where
+    T: Eq + Clone + Hash,
+    W: Wrap,
+    R: Rng,
+{
+    let mut context = Context::new();
+    let mut wave = Wave::new(output_size);
+    (2..=max_pattern_size.get())
+        .filter_map(NonZeroU32::new)
+        .map(|pattern_size| {
+            let start = Instant::now();
+            let overlapping_patterns =
+                OverlappingPatterns::new(grid.clone(), pattern_size, orientations);
+            let global_stats = overlapping_patterns.global_stats();
+            let successes = (0..attempts_per_size)
+                .filter(|_| {
+                    RunBorrow::new_wrap(&mut context, &mut wave, &global_stats, wrap, rng)
+                        .collapse(rng)
+                        .is_ok()
+                })
+                .count();
+            PatternSizeReport {
+                pattern_size,
+                num_patterns: overlapping_patterns.num_patterns(),
+                attempts: attempts_per_size,
+                successes,
+                duration: start.elapsed(),
+            }
+        })
+        .collect()
+}
+
+/// Translates pattern ids between two `OverlappingPatterns`, matching patterns with the same
+/// contents. Useful for comparing or caching results across runs where pattern ids differ,
+/// e.g. because the input was edited.
+#[derive(Debug, Clone)]
+pub struct PatternIdMap {
+    ids: PatternTable<Option<PatternId>>,
+}
+
+impl PatternIdMap {
+    pub fn new<T: Eq + Clone + Hash>(
+        from: &OverlappingPatterns<T>,
+        to: &OverlappingPatterns<T>,
+    ) -> Self {
+        let ids = if from.pattern_size == to.pattern_size {
+            let to_ids = to
+                .pattern_table
+                .enumerate()
+                .map(|(pattern_id, pattern)| (to.pattern_contents(pattern), pattern_id))
+                .collect::<HashMap<_, _>>();
+            from.pattern_table
+                .map_ref(|pattern| to_ids.get(&from.pattern_contents(pattern)).cloned())
+        } else {
+            from.pattern_table.map_ref(|_| None)
+        };
+        Self { ids }
+    }
+    /// The id of the pattern in `to` with the same contents as `pattern_id` in `from`, if
+    /// there is one
+    pub fn get(&self, pattern_id: PatternId) -> Option<PatternId> {
+        self.ids.get(pattern_id).cloned().flatten()
+    }
+}
+
+/// Patterns learnt separately from two samples, `a` and `b`, such as two biomes, combined
+/// into one model. Patterns of each sample may neighbour patterns of the same sample as
+/// usual, while patterns of `a` may only neighbour patterns of `b` where they are seen to do
+/// so in a third "transition" example, so outputs switch between the two samples only
+/// along seams like those in the transition. Patterns of `a` keep their ids, and patterns
+/// of `b` are numbered after them.
+pub struct TransitionPatterns<T: Eq + Clone + Hash> {
+    a: OverlappingPatterns<T>,
+    b: OverlappingPatterns<T>,
+    // for each pattern and direction, the patterns of the other sample allowed there
+    cross_neighbours: PatternTable<CardinalDirectionTable<Vec<PatternId>>>,
+}
+
+impl<T: Eq + Clone + Hash> TransitionPatterns<T> {
+    /// Learns which patterns of `a` neighbour which patterns of `b` from `transition`,
+    /// sampled with the pattern size, orientations and input wrap of `a`. Regions of the
+    /// transition whose patterns don't occur in either sample are ignored. Panics if `a`
+    /// and `b` have different pattern sizes.
+    pub fn new(
+        a: OverlappingPatterns<T>,
+        b: OverlappingPatterns<T>,
+        transition: &Grid<T>,
+    ) -> Self {
+        assert_eq!(
+            a.pattern_size, b.pattern_size,
+            "samples must have the same pattern size"
+        );
+        let num_a = a.num_patterns() as PatternId;
+        let mut ids_by_values = HashMap::<Vec<T>, Vec<PatternId>>::new();
+        for (pattern_id, pattern) in a.pattern_table.enumerate() {
+            ids_by_values
+                .entry(a.pattern_contents(pattern))
+                .or_default()
+                .push(pattern_id);
+        }
+        for (pattern_id, pattern) in b.pattern_table.enumerate() {
+            ids_by_values
+                .entry(b.pattern_contents(pattern))
+                .or_default()
+                .push(num_a + pattern_id);
+        }
+        let num_patterns = a.num_patterns() + b.num_patterns();
+        let mut cross_neighbours = (0..num_patterns)
+            .map(|_| CardinalDirectionTable::<Vec<PatternId>>::default())
+            .collect::<PatternTable<_>>();
+        let sample_size = a.input_wrap.sample_size(transition.size(), a.pattern_size);
+        for &orientation in a.orientations.iter() {
+            let id_grid = Grid::new_fn(sample_size, |coord| {
+                let values = a
+                    .input_wrap
+                    .tiled_grid_slice(transition, coord, a.pattern_size, orientation)
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                ids_by_values.get(&values).cloned().unwrap_or_default()
+            });
+            for (coord, ids) in id_grid.enumerate() {
+                for direction in CardinalDirections {
+                    // as in `OverlappingPatterns::adjacency_frequencies`
+                    let offset = orientation
+                        .transform_coord(a.pattern_size, direction.coord())
+                        - orientation.transform_coord(a.pattern_size, Coord::new(0, 0));
+                    let neighbour_coord = coord + offset;
+                    let neighbour_ids = match a.input_wrap {
+                        InputWrap::Tile => Some(id_grid.get_tiled(neighbour_coord)),
+                        InputWrap::Clamp | InputWrap::IgnoreBorders => {
+                            id_grid.get(neighbour_coord)
+                        }
+                    };
+                    for &pattern_id in ids.iter() {
+                        for &neighbour_id in neighbour_ids.into_iter().flatten() {
+                            if (pattern_id < num_a) != (neighbour_id < num_a) {
+                                cross_neighbours[pattern_id]
+                                    .get_mut(direction)
+                                    .push(neighbour_id);
+                                cross_neighbours[neighbour_id]
+                                    .get_mut(direction.opposite())
+                                    .push(pattern_id);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        for neighbours in cross_neighbours.iter_mut() {
+            for direction in CardinalDirections {
+                let neighbours = neighbours.get_mut(direction);
+                neighbours.sort_unstable();
+                neighbours.dedup();
+            }
+        }
+        Self {
+            a,
+            b,
+            cross_neighbours,
+        }
+    }
+    pub fn a(&self) -> &OverlappingPatterns<T> {
+        &self.a
+    }
+    pub fn b(&self) -> &OverlappingPatterns<T> {
+        &self.b
+    }
+    pub fn num_patterns(&self) -> usize {
+        self.cross_neighbours.len()
+    }
+    /// Whether `pattern_id` is one of the patterns of `b`
+    pub fn is_b(&self, pattern_id: PatternId) -> bool {
+        pattern_id as usize >= self.a.num_patterns()
+    }
+    /// The id of the pattern of `b` whose id in `b` is `b_pattern_id`
+    pub fn b_pattern_id(&self, b_pattern_id: PatternId) -> PatternId {
+        self.a.num_patterns() as PatternId + b_pattern_id
+    }
+    /// The number of pairs of a pattern of `a` and a pattern of `b` which are allowed to be
+    /// neighbours, counting each direction separately
+    pub fn num_cross_adjacencies(&self) -> usize {
+        self.cross_neighbours
+            .iter()
+            .take(self.a.num_patterns())
+            .map(|neighbours| neighbours.iter().map(Vec::len).sum::<usize>())
+            .sum()
+    }
+    fn sample(&self, pattern_id: PatternId) -> (&OverlappingPatterns<T>, PatternId) {
+        if self.is_b(pattern_id) {
+            (&self.b, pattern_id - self.a.num_patterns() as PatternId)
+        } else {
+            (&self.a, pattern_id)
+        }
+    }
+    /// See `OverlappingPatterns::pattern_values`
+    pub fn pattern_values(&self, pattern_id: PatternId) -> impl Iterator<Item = &T> {
+        let (sample, pattern_id) = self.sample(pattern_id);
+        sample.pattern_values(pattern_id)
+    }
+    /// See `OverlappingPatterns::pattern_top_left_value`
+    pub fn pattern_top_left_value(&self, pattern_id: PatternId) -> &T {
+        let (sample, pattern_id) = self.sample(pattern_id);
+        sample.pattern_top_left_value(pattern_id)
+    }
+    pub fn pattern_descriptions(&self) -> PatternTable<PatternDescription> {
+        let num_a = self.a.num_patterns() as PatternId;
+        let mut a_descriptions = self.a.pattern_descriptions();
+        let mut b_descriptions = self.b.pattern_descriptions();
+        for description in b_descriptions.iter_mut() {
+            for direction in CardinalDirections {
+                for pattern_id in description.allowed_neighbours.get_mut(direction) {
+                    *pattern_id += num_a;
+                }
+            }
+        }
+        a_descriptions
+            .drain()
+            .chain(b_descriptions.drain())
+            .zip(self.cross_neighbours.iter())
+            .map(|(mut description, cross_neighbours)| {
+                for direction in CardinalDirections {
+                    description
+                        .allowed_neighbours
+                        .get_mut(direction)
+                        .extend(cross_neighbours.get(direction).iter().cloned());
+                }
+                description
+            })
+            .collect()
+    }
+    pub fn global_stats(&self) -> GlobalStats {
+        GlobalStats::new(self.pattern_descriptions())
+    }
+}
+
+/// Extracts patterns from an input supplied one row at a time, for inputs too large to hold
+/// in memory as a `Grid`. Only the last `pattern_size` rows (and, for `InputWrap::Tile`, the
+/// first `pattern_size - 1` rows) are kept, along with one entry per distinct pattern. With
+/// `set_max_patterns`, the number of distinct patterns kept is bounded too, at the cost of
+/// approximate counts: once the limit is reached, a newly seen pattern replaces the least
+/// common pattern kept so far and inherits its count, so counts of rare patterns are
+/// overestimated and rare patterns seen late may be missing altogether. The least common
+/// pattern is found with a heap, so each occurrence takes logarithmic time in `max_patterns`.
+pub struct PatternStream<T: Eq + Clone + Hash> {
+    width: u32,
+    pattern_size: Size,
+    orientations: Vec<Orientation>,
+    input_wrap: InputWrap,
+    max_patterns: Option<NonZeroUsize>,
+    window: VecDeque<Vec<T>>,
+    // rows which patterns near the bottom edge wrap around to
+    first_rows: Vec<Vec<T>>,
+    num_rows: u32,
+    num_sampled_rows: u32,
+    // the order in which each pattern was first kept, and the number of times it was seen
+    patterns: HashMap<Vec<T>, (usize, u32)>,
+    // with `max_patterns`, the count and index of each kept pattern every time its count
+    // changes, so the least common pattern can be found without scanning every pattern.
+    // Entries which are out of date are skipped when they reach the top.
+    least_common: BinaryHeap<Reverse<(u32, usize)>>,
+    values_by_index: HashMap<usize, Vec<T>>,
+    next_index: usize,
+    approximate: bool,
+}
+
+impl<T: Eq + Clone + Hash> PatternStream<T> {
+    /// A stream for an input `width` values wide, whose patterns are sampled as they would
+    /// be by `OverlappingPatterns::new_input_wrap`
+    pub fn new(
+        width: u32,
+        pattern_size: NonZeroU32,
+        orientations: &[Orientation],
+        input_wrap: InputWrap,
+    ) -> Self {
+        Self {
+            width,
+            pattern_size: Size::new(pattern_size.get(), pattern_size.get()),
+            orientations: orientations.to_vec(),
+            input_wrap,
+            max_patterns: None,
+            window: VecDeque::new(),
+            first_rows: Vec::new(),
+            num_rows: 0,
+            num_sampled_rows: 0,
+            patterns: HashMap::new(),
+            least_common: BinaryHeap::new(),
+            values_by_index: HashMap::new(),
+            next_index: 0,
+            approximate: false,
+        }
+    }
+    /// Keep at most `max_patterns` distinct patterns, making counts approximate if more
+    /// than that are seen
+    pub fn set_max_patterns(&mut self, max_patterns: NonZeroUsize) {
+        self.max_patterns = Some(max_patterns);
+        self.values_by_index = self
+            .patterns
+            .iter()
+            .map(|(values, &(index, _))| (index, values.clone()))
+            .collect();
+        self.rebuild_least_common();
+    }
+    /// Add the next row of the input. Panics if `row` isn't `width` values long.
+    pub fn push_row(&mut self, row: Vec<T>) {
+        assert_eq!(
+            row.len(),
+            self.width as usize,
+            "row must be the width of the input"
+        );
+        if self.input_wrap == InputWrap::Tile
+            && (self.first_rows.len() as u32) + 1 < self.pattern_size.height()
+        {
+            self.first_rows.push(row.clone());
+        }
+        self.num_rows += 1;
+        self.push_window_row(row);
+    }
+    fn push_window_row(&mut self, row: Vec<T>) {
+        self.window.push_back(row);
+        if self.window.len() as u32 == self.pattern_size.height() {
+            self.sample_window();
+            self.window.pop_front();
+            self.num_sampled_rows += 1;
+        }
+    }
+    // Sample the patterns whose top row is the first row of the window
+    fn sample_window(&mut self) {
+        let window =
+            Grid::new_fn(Size::new(self.width, self.pattern_size.height()), |coord| {
+                self.window[coord.y as usize][coord.x as usize].clone()
+            });
+        let sample_size = self
+            .input_wrap
+            .sample_size(window.size(), self.pattern_size);
+        for orientation_index in 0..self.orientations.len() {
+            let orientation = self.orientations[orientation_index];
+            for x in 0..sample_size.width() {
+                let values = self
+                    .input_wrap
+                    .tiled_grid_slice(
+                        &window,
+                        Coord::new(x as i32, 0),
+                        self.pattern_size,
+                        orientation,
+                    )
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>();
+                self.add_occurrence(values);
+            }
+        }
+    }
+    fn add_occurrence(&mut self, values: Vec<T>) {
+        if let Some(&mut (index, ref mut count)) = self.patterns.get_mut(&values) {
+            *count += 1;
+            if self.max_patterns.is_some() {
+                self.least_common.push(Reverse((*count, index)));
+                if self.least_common.len() > self.patterns.len() * 2 {
+                    self.rebuild_least_common();
+                }
+            }
+            return;
+        }
+        let mut count = 1;
+        if let Some(max_patterns) = self.max_patterns {
+            if self.patterns.len() >= max_patterns.get() {
+                let (least_common_values, least_common_count) = self.pop_least_common();
+                self.patterns.remove(&least_common_values);
+                count += least_common_count;
+                self.approximate = true;
+            }
+            self.values_by_index.insert(self.next_index, values.clone());
+            self.least_common.push(Reverse((count, self.next_index)));
+        }
+        self.patterns.insert(values, (self.next_index, count));
+        self.next_index += 1;
+    }
+    // Removes the kept pattern with the lowest count (the earliest kept of those tied) from
+    // `least_common` and `values_by_index`, returning its values and count
+    fn pop_least_common(&mut self) -> (Vec<T>, u32) {
+        loop {
+            let Reverse((count, index)) =
+                self.least_common.pop().expect("max_patterns is non-zero");
+            let is_current = self
+                .values_by_index
+                .get(&index)
+                .is_some_and(|values| self.patterns[values].1 == count);
+            if is_current {
+                let values = self.values_by_index.remove(&index).expect("checked above");
+                return (values, count);
+            }
+        }
+    }
+    fn rebuild_least_common(&mut self) {
+        self.least_common = self
+            .patterns
+            .values()
+            .map(|&(index, count)| Reverse((count, index)))
+            .collect();
+    }
+    /// Sample the patterns which wrap around or overlap the bottom edge of the input, and
+    /// return the patterns of the whole input
+    pub fn finish(mut self) -> StreamedPatterns<T> {
+        match self.input_wrap {
+            InputWrap::Tile => {
+                let first_rows = mem::take(&mut self.first_rows);
+                for row in first_rows.iter().cycle() {
+                    if self.num_sampled_rows >= self.num_rows {
+                        break;
+                    }
+                    self.push_window_row(row.clone());
+                }
+            }
+            InputWrap::Clamp => {
+                while self.num_sampled_rows < self.num_rows {
+                    let last_row = self.window.back().cloned().expect("a row was pushed");
+                    self.push_window_row(last_row);
+                }
+            }
+            InputWrap::IgnoreBorders => (),
+        }
+        let mut patterns = self.patterns.into_iter().collect::<Vec<_>>();
+        patterns.sort_by_key(|(_, (index, _))| *index);
+        let (values, counts) = patterns
+            .into_iter()
+            .map(|(values, (_, count))| (values, count))
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+        StreamedPatterns {
+            pattern_size: self.pattern_size,
+            values: PatternTable::from_vec(values),
+            counts: PatternTable::from_vec(counts),
+            approximate: self.approximate,
+        }
+    }
+}
+
+/// The patterns extracted by a `PatternStream`, numbered in the order they were first seen
+pub struct StreamedPatterns<T> {
+    pattern_size: Size,
+    values: PatternTable<Vec<T>>,
+    counts: PatternTable<u32>,
+    approximate: bool,
+}
+
+impl<T: Eq> StreamedPatterns<T> {
+    pub fn pattern_size(&self) -> Size {
+        self.pattern_size
+    }
+    pub fn num_patterns(&self) -> usize {
+        self.values.len()
+    }
+    /// The values of a pattern in row-major order, in the orientation in which the pattern
+    /// appears in the output
+    pub fn pattern_values(&self, pattern_id: PatternId) -> &[T] {
+        &self.values[pattern_id]
+    }
+    pub fn pattern_top_left_value(&self, pattern_id: PatternId) -> &T {
+        &self.values[pattern_id][0]
+    }
+    /// The number of times the pattern occurs in the input, which may be an overestimate if
+    /// `counts_are_approximate`
+    pub fn count(&self, pattern_id: PatternId) -> u32 {
+        self.counts[pattern_id]
+    }
+    /// Whether more distinct patterns were seen than the stream's `max_patterns`
+    pub fn counts_are_approximate(&self) -> bool {
+        self.approximate
+    }
+    pub fn pattern_descriptions(&self) -> PatternTable<PatternDescription> {
+        let width = self.pattern_size.width() as usize;
+        self.values
+            .iter()
+            .zip(self.counts.iter())
+            .map(|(values, &count)| {
+                let mut allowed_neighbours = CardinalDirectionTable::default();
+                for direction in CardinalDirections {
+                    allowed_neighbours[direction] = self
+                        .values
+                        .enumerate()
+                        .filter(|(_id, other)| {
+                            are_packed_patterns_compatible(
+                                values, other, width, direction,
+                            )
+                        })
+                        .map(|(id, _other)| id)
+                        .collect::<Vec<_>>();
+                }
+                PatternDescription::new(NonZeroU32::new(count), allowed_neighbours)
+            })
+            .collect::<PatternTable<_>>()
+    }
     pub fn global_stats(&self) -> GlobalStats {
         GlobalStats::new(self.pattern_descriptions())
     }
@@ -192,10 +1722,13 @@ impl<T: Eq + Clone + Hash> OverlappingPatterns<T> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::wfc::{PatternTableLengthMismatch, RunOwn};
+    use crate::wrap::{WrapNone, WrapXY, WrapY};
     use coord_2d::{Coord, Size};
     use direction::CardinalDirection;
     use grid_2d::Grid;
     use orientation::Orientation;
+    use rand::SeedableRng;
 
     fn pattern_with_coord(coord: Coord) -> Pattern {
         let mut pattern = Pattern::new(0, Orientation::Original);
@@ -213,24 +1746,670 @@ mod test {
         });
         let pattern_size = Size::new(2, 2);
         assert!(are_patterns_compatible(
-            &pattern_with_coord(Coord::new(0, 0)).tiled_grid_slice(&grid, pattern_size),
-            &pattern_with_coord(Coord::new(1, 0)).tiled_grid_slice(&grid, pattern_size),
+            &pattern_with_coord(Coord::new(0, 0)).tiled_grid_slice(
+                &grid,
+                pattern_size,
+                InputWrap::Tile
+            ),
+            &pattern_with_coord(Coord::new(1, 0)).tiled_grid_slice(
+                &grid,
+                pattern_size,
+                InputWrap::Tile
+            ),
             CardinalDirection::East,
         ));
         assert!(are_patterns_compatible(
-            &pattern_with_coord(Coord::new(0, 0)).tiled_grid_slice(&grid, pattern_size),
-            &pattern_with_coord(Coord::new(1, 0)).tiled_grid_slice(&grid, pattern_size),
+            &pattern_with_coord(Coord::new(0, 0)).tiled_grid_slice(
+                &grid,
+                pattern_size,
+                InputWrap::Tile
+            ),
+            &pattern_with_coord(Coord::new(1, 0)).tiled_grid_slice(
+                &grid,
+                pattern_size,
+                InputWrap::Tile
+            ),
             CardinalDirection::North,
         ));
         assert!(!are_patterns_compatible(
-            &pattern_with_coord(Coord::new(0, 0)).tiled_grid_slice(&grid, pattern_size),
-            &pattern_with_coord(Coord::new(1, 0)).tiled_grid_slice(&grid, pattern_size),
+            &pattern_with_coord(Coord::new(0, 0)).tiled_grid_slice(
+                &grid,
+                pattern_size,
+                InputWrap::Tile
+            ),
+            &pattern_with_coord(Coord::new(1, 0)).tiled_grid_slice(
+                &grid,
+                pattern_size,
+                InputWrap::Tile
+            ),
             CardinalDirection::South,
         ));
         assert!(!are_patterns_compatible(
-            &pattern_with_coord(Coord::new(0, 0)).tiled_grid_slice(&grid, pattern_size),
-            &pattern_with_coord(Coord::new(1, 0)).tiled_grid_slice(&grid, pattern_size),
+            &pattern_with_coord(Coord::new(0, 0)).tiled_grid_slice(
+                &grid,
+                pattern_size,
+                InputWrap::Tile
+            ),
+            &pattern_with_coord(Coord::new(1, 0)).tiled_grid_slice(
+                &grid,
+                pattern_size,
+                InputWrap::Tile
+            ),
             CardinalDirection::West,
         ));
     }
+
+    #[test]
+    fn pattern_arrays() {
+        let grid = Grid::new_fn(Size::new(5, 4), |coord| (coord.x * coord.y) % 3);
+        for pattern_size in [2, 3] {
+            let overlapping_patterns = OverlappingPatterns::new_all_orientations(
+                grid.clone(),
+                NonZeroU32::new(pattern_size).unwrap(),
+            );
+            let expected = overlapping_patterns.pattern_descriptions_slice();
+            let actual = overlapping_patterns.pattern_descriptions();
+            for (expected, actual) in expected.iter().zip(actual.iter()) {
+                assert_eq!(expected.allowed_neighbours, actual.allowed_neighbours);
+            }
+        }
+    }
+
+    #[test]
+    fn packed_patterns() {
+        let grid = Grid::new_fn(Size::new(5, 4), |coord| (coord.x * coord.y) % 3);
+        for pattern_size in [1, 2, 3, 4] {
+            let overlapping_patterns = OverlappingPatterns::new_all_orientations(
+                grid.clone(),
+                NonZeroU32::new(pattern_size).unwrap(),
+            );
+            let expected = overlapping_patterns.pattern_descriptions_slice();
+            let actual =
+                overlapping_patterns.pattern_descriptions_packed(|&value| value as u32);
+            for (expected, actual) in expected.iter().zip(actual.iter()) {
+                assert_eq!(expected.weight, actual.weight);
+                assert_eq!(expected.allowed_neighbours, actual.allowed_neighbours);
+            }
+        }
+    }
+
+    #[test]
+    fn stitch_wave() {
+        use crate::wrap::WrapNone;
+        let grid = Grid::new_fn(Size::new(4, 4), |coord| (coord.x + coord.y) % 4);
+        let overlapping_patterns = OverlappingPatterns::new_original_orientation(
+            grid,
+            NonZeroU32::new(3).unwrap(),
+        );
+        let global_stats = overlapping_patterns.global_stats();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let wave_size = overlapping_patterns.non_periodic_wave_size(Size::new(7, 6));
+        assert_eq!(wave_size, Size::new(5, 4));
+        let mut run = RunOwn::new_wrap(wave_size, &global_stats, WrapNone, &mut rng);
+        assert_eq!(
+            overlapping_patterns
+                .stitch_wave(&run.clone().into_wave())
+                .err(),
+            Some(StitchError::NotCollapsed {
+                coord: Coord::new(0, 0)
+            })
+        );
+        run.collapse(&mut rng).unwrap();
+        let output = overlapping_patterns.stitch_wave(&run.into_wave()).unwrap();
+        assert_eq!(output.size(), Size::new(7, 6));
+        // every anti-diagonal of the input has a single value
+        for (coord, &value) in output.enumerate() {
+            if let Some(&next) = output.get(coord + Coord::new(1, -1)) {
+                assert_eq!(value, next);
+            }
+        }
+    }
+
+    #[test]
+    fn top_left_value_orientation() {
+        // every value is distinct, so no two orientations of a pattern have the same top-left
+        let grid = Grid::new_fn(Size::new(4, 4), |Coord { x, y }| x + y * 4);
+        let pattern_size = Size::new(2, 2);
+        let overlapping_patterns = OverlappingPatterns::new_all_orientations(
+            grid.clone(),
+            NonZeroU32::new(2).unwrap(),
+        );
+        for pattern_id in 0..overlapping_patterns.num_patterns() as PatternId {
+            let pattern = overlapping_patterns.pattern(pattern_id);
+            let top_left = pattern.coord()
+                + pattern
+                    .orientation()
+                    .transform_coord(pattern_size, Coord::new(0, 0));
+            let value = overlapping_patterns.pattern_top_left_value(pattern_id);
+            assert_eq!(value, grid.get_tiled(top_left));
+            assert_eq!(
+                Some(value),
+                overlapping_patterns.pattern_values(pattern_id).next()
+            );
+        }
+    }
+
+    #[test]
+    fn stable_ids() {
+        let grid = Grid::new_fn(Size::new(4, 4), |coord| (coord.x + 2 * coord.y) % 5);
+        let shifted = Grid::new_fn(grid.size(), |coord| {
+            *grid.get_tiled(coord + Coord::new(1, 2))
+        });
+        let pattern_size = NonZeroU32::new(2).unwrap();
+        let mut a = OverlappingPatterns::new_original_orientation(grid, pattern_size);
+        let mut b = OverlappingPatterns::new_original_orientation(shifted, pattern_size);
+        let map = PatternIdMap::new(&a, &b);
+        for (pattern_id, pattern) in a.pattern_table.enumerate() {
+            let other_id = map.get(pattern_id).unwrap();
+            assert_eq!(a.pattern_contents(pattern), b.pattern_to_vec(other_id));
+        }
+        a.sort_ids_by_content();
+        b.sort_ids_by_content();
+        for pattern_id in 0..(a.pattern_table.len() as PatternId) {
+            assert_eq!(a.pattern_to_vec(pattern_id), b.pattern_to_vec(pattern_id));
+        }
+        let ids = a.id_grid_original_orientation();
+        let (top_left, next) = (
+            *ids.get_checked(Coord::new(0, 0)),
+            *ids.get_checked(Coord::new(1, 0)),
+        );
+        assert_eq!(a.pattern_to_vec(top_left), vec![0, 1, 2, 3]);
+        assert_eq!(a.pattern_to_vec(next), vec![1, 2, 3, 4]);
+        assert!(a.pattern_values(next).eq(&[1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn orientation_weights() {
+        let array = [[1, 2, 0], [0, 0, 0], [0, 0, 0]];
+        let grid = Grid::new_fn(Size::new(3, 3), |coord| {
+            array[coord.y as usize][coord.x as usize]
+        });
+        let mut overlapping_patterns = OverlappingPatterns::new(
+            grid,
+            NonZeroU32::new(2).unwrap(),
+            &[Orientation::Original, Orientation::Clockwise90],
+        );
+        let mut weights = OrientationTable::new();
+        weights.insert(Orientation::Clockwise90, 0);
+        overlapping_patterns.set_orientation_weights(weights);
+        let descriptions = overlapping_patterns.pattern_descriptions();
+        for (pattern_id, description) in descriptions.enumerate() {
+            let pattern = overlapping_patterns.pattern(pattern_id);
+            assert_eq!(
+                description.weight.map(NonZeroU32::get).unwrap_or(0),
+                pattern.count_in_orientation(Orientation::Original)
+            );
+        }
+        // 1 and 2 are only vertically adjacent when rotated
+        assert!(descriptions
+            .iter()
+            .any(|description| description.weight.is_none()));
+    }
+
+    #[test]
+    fn add_example() {
+        let pattern_size = NonZeroU32::new(2).unwrap();
+        let mut overlapping_patterns = OverlappingPatterns::new_original_orientation(
+            Grid::new_fn(Size::new(2, 2), |coord| coord.x),
+            pattern_size,
+        );
+        assert_eq!(overlapping_patterns.pattern_table.len(), 2);
+        overlapping_patterns.add_example(Grid::new_fn(Size::new(3, 2), |coord| coord.x));
+        // the new example contains two patterns with 2 which weren't in the original input
+        assert_eq!(overlapping_patterns.pattern_table.len(), 4);
+        let original_id = *overlapping_patterns
+            .id_grid_original_orientation()
+            .get_checked(Coord::new(0, 0));
+        assert_eq!(overlapping_patterns.pattern(original_id).count(), 4);
+        let values = |pattern_id| overlapping_patterns.pattern_to_vec(pattern_id);
+        assert_eq!(values(2), vec![1, 2, 1, 2]);
+        assert_eq!(values(3), vec![2, 0, 2, 0]);
+        let descriptions = overlapping_patterns.pattern_descriptions();
+        assert!(
+            descriptions[original_id].allowed_neighbours[CardinalDirection::West]
+                .contains(&3)
+        );
+        let frequencies = overlapping_patterns.adjacency_frequencies();
+        assert_eq!(frequencies[2].get(CardinalDirection::East), &vec![(3, 2)]);
+    }
+
+    #[test]
+    fn input_wrap() {
+        let grid = Grid::new_fn(Size::new(3, 3), |coord| coord);
+        let pattern_size = NonZeroU32::new(2).unwrap();
+        let num_patterns = |input_wrap| {
+            OverlappingPatterns::new_input_wrap(
+                grid.clone(),
+                pattern_size,
+                &[Orientation::Original],
+                input_wrap,
+            )
+            .pattern_table
+            .len()
+        };
+        assert_eq!(num_patterns(InputWrap::Tile), 9);
+        assert_eq!(num_patterns(InputWrap::Clamp), 9);
+        assert_eq!(num_patterns(InputWrap::IgnoreBorders), 4);
+        let clamped = OverlappingPatterns::new_input_wrap(
+            grid.clone(),
+            pattern_size,
+            &[Orientation::Original],
+            InputWrap::Clamp,
+        );
+        // the pattern in the bottom right corner repeats the corner, rather than wrapping
+        let corner = clamped.id_grid_original_orientation();
+        let corner_id = *corner.get_checked(Coord::new(2, 2));
+        let descriptions = clamped.pattern_descriptions();
+        assert!(
+            descriptions[corner_id].allowed_neighbours[CardinalDirection::East]
+                .contains(&corner_id)
+        );
+    }
+
+    #[test]
+    fn adjacency_frequencies() {
+        let grid = Grid::new_fn(Size::new(2, 2), |coord| coord.x);
+        let overlapping_patterns = OverlappingPatterns::new_original_orientation(
+            grid,
+            NonZeroU32::new(1).unwrap(),
+        );
+        let ids = overlapping_patterns.id_grid_original_orientation();
+        let (a, b) = (
+            *ids.get_checked(Coord::new(0, 0)),
+            *ids.get_checked(Coord::new(1, 0)),
+        );
+        let frequencies = overlapping_patterns.adjacency_frequencies();
+        assert_eq!(frequencies[a].get(CardinalDirection::East), &vec![(b, 2)]);
+        assert_eq!(frequencies[a].get(CardinalDirection::North), &vec![(a, 2)]);
+        let mut global_stats = overlapping_patterns.global_stats();
+        assert_eq!(
+            global_stats.set_adjacency_frequencies(PatternTable::from_vec(Vec::new())),
+            Err(PatternTableLengthMismatch {
+                expected: 2,
+                actual: 0
+            })
+        );
+        global_stats.set_adjacency_frequencies(frequencies).unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(Size::new(4, 4), &global_stats, &mut rng);
+        run.collapse(&mut rng).unwrap();
+    }
+
+    #[test]
+    fn prune_below() {
+        let array = [[0, 0, 0, 1], [0, 0, 0, 0]];
+        let grid = Grid::new_fn(Size::new(4, 2), |coord| {
+            array[coord.y as usize][coord.x as usize]
+        });
+        let mut overlapping_patterns = OverlappingPatterns::new_original_orientation(
+            grid,
+            NonZeroU32::new(1).unwrap(),
+        );
+        assert_eq!(overlapping_patterns.pattern_table.len(), 2);
+        overlapping_patterns.prune_below(2);
+        assert_eq!(overlapping_patterns.pattern_table.len(), 1);
+        let ids = overlapping_patterns.id_grid();
+        assert_eq!(
+            ids.get_checked(Coord::new(0, 0)).get(Orientation::Original),
+            Some(&0)
+        );
+        assert_eq!(
+            ids.get_checked(Coord::new(3, 0)).get(Orientation::Original),
+            None
+        );
+        assert_eq!(
+            overlapping_patterns.pattern_descriptions()[0].allowed_neighbours
+                [CardinalDirection::East],
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn sweep() {
+        let stripes = Grid::new_fn(Size::new(4, 4), |Coord { x, .. }| x % 2);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let reports = sweep_pattern_sizes(
+            &stripes,
+            &[Orientation::Original],
+            Size::new(6, 6),
+            NonZeroU32::new(3).unwrap(),
+            2,
+            crate::wrap::WrapXY,
+            &mut rng,
+        );
+        let pattern_sizes = reports
+            .iter()
+            .map(|report| report.pattern_size.get())
+            .collect::<Vec<_>>();
+        assert_eq!(pattern_sizes, vec![2, 3]);
+        for report in reports {
+            assert_eq!(report.num_patterns, 2);
+            assert_eq!(report.attempts, 2);
+            assert_eq!(report.success_rate(), 1.0);
+        }
+    }
+
+    #[test]
+    fn edge_patterns() {
+        use crate::wrap::WrapNone;
+        // a border of 1s around 0s
+        let size = Size::new(4, 4);
+        let input = Grid::new_fn(size, |Coord { x, y }| {
+            if x == 0 || y == 0 || x == 3 || y == 3 {
+                1
+            } else {
+                0
+            }
+        });
+        let overlapping_patterns = OverlappingPatterns::new_input_wrap(
+            input.clone(),
+            NonZeroU32::new(2).unwrap(),
+            &[Orientation::Original],
+            InputWrap::IgnoreBorders,
+        );
+        let edge_patterns = overlapping_patterns.edge_patterns();
+        let id_grid = overlapping_patterns.id_grid();
+        let id = |coord| {
+            *id_grid
+                .get_checked(coord)
+                .get(Orientation::Original)
+                .unwrap()
+        };
+        let top_left = id(Coord::new(0, 0));
+        let centre = id(Coord::new(1, 1));
+        assert!(edge_patterns.is_allowed(top_left, CardinalDirection::North));
+        assert!(edge_patterns.is_allowed(top_left, CardinalDirection::West));
+        assert!(!edge_patterns.is_allowed(top_left, CardinalDirection::East));
+        assert!(!edge_patterns.is_allowed(centre, CardinalDirection::North));
+        // all 0s is a valid output by adjacency alone, but edges must be 1s
+        let global_stats = overlapping_patterns.global_stats();
+        let wave_size = overlapping_patterns.non_periodic_wave_size(size);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new_wrap_forbid(
+            wave_size,
+            &global_stats,
+            WrapNone,
+            edge_patterns,
+            &mut rng,
+        );
+        run.collapse(&mut rng).unwrap();
+        let output = overlapping_patterns.stitch_wave(run.wave()).unwrap();
+        assert_eq!(output, input);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn cache() {
+        let grid = Grid::new_fn(Size::new(5, 4), |coord| (coord.x * coord.y) % 3);
+        let pattern_size = NonZeroU32::new(2).unwrap();
+        let mut overlapping_patterns =
+            OverlappingPatterns::new(grid.clone(), pattern_size, &orientation::ALL);
+        overlapping_patterns
+            .add_example(Grid::new_fn(Size::new(3, 3), |coord| coord.y % 2));
+        let path =
+            std::env::temp_dir().join(format!("wfc-cache-test-{}", std::process::id()));
+        overlapping_patterns.save_cache(&path).unwrap();
+        let load = |grid: &Grid<i32>, orientations: &[Orientation]| {
+            OverlappingPatterns::load_cache(
+                &path,
+                grid,
+                None,
+                pattern_size,
+                orientations,
+                InputWrap::Tile,
+            )
+            .unwrap()
+        };
+        let loaded = load(&grid, &orientation::ALL).unwrap();
+        assert_eq!(
+            loaded.global_stats().fingerprint(),
+            overlapping_patterns.global_stats().fingerprint()
+        );
+        assert_eq!(
+            loaded.id_grid_original_orientation(),
+            overlapping_patterns.id_grid_original_orientation()
+        );
+        assert_eq!(
+            loaded.pattern_to_vec(7),
+            overlapping_patterns.pattern_to_vec(7)
+        );
+        // a different input or different settings miss the cache
+        assert!(load(&grid, &[Orientation::Original]).is_none());
+        let mut changed = grid.clone();
+        *changed.get_checked_mut(Coord::new(0, 0)) = 2;
+        assert!(load(&changed, &orientation::ALL).is_none());
+        std::fs::remove_file(&path).unwrap();
+        assert!(load(&grid, &orientation::ALL).is_none());
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn cache_output_wrap() {
+        // every row is 0, 0, 1, so the pattern 1, 0 only occurs across the right edge
+        let grid = Grid::new_fn(Size::new(3, 3), |coord| (coord.x == 2) as u8);
+        let pattern_size = NonZeroU32::new(2).unwrap();
+        let orientations = [Orientation::Original];
+        let mut overlapping_patterns =
+            OverlappingPatterns::new(grid.clone(), pattern_size, &orientations);
+        let unwrapped_fingerprint = overlapping_patterns.global_stats().fingerprint();
+        overlapping_patterns.set_output_wrap(WrapNone, BorderOccurrences::Exclude);
+        let path = std::env::temp_dir()
+            .join(format!("wfc-cache-output-wrap-test-{}", std::process::id()));
+        overlapping_patterns.save_cache(&path).unwrap();
+        let mut loaded = OverlappingPatterns::load_cache(
+            &path,
+            &grid,
+            None,
+            pattern_size,
+            &orientations,
+            InputWrap::Tile,
+        )
+        .unwrap()
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // the excluded pattern's adjacencies are restored when the output wrap isn't set
+        assert_eq!(loaded.global_stats().fingerprint(), unwrapped_fingerprint);
+        loaded.set_output_wrap(WrapNone, BorderOccurrences::Exclude);
+        assert_eq!(
+            loaded.global_stats().fingerprint(),
+            overlapping_patterns.global_stats().fingerprint()
+        );
+    }
+
+    #[test]
+    fn mask() {
+        // the right half of the input is a "watermark" of 2s
+        let grid = Grid::new_fn(
+            Size::new(6, 4),
+            |Coord { x, y }| {
+                if x >= 3 {
+                    2
+                } else {
+                    (x + y) % 2
+                }
+            },
+        );
+        let mask = Grid::new_fn(grid.size(), |coord| coord.x < 3);
+        let overlapping_patterns = OverlappingPatterns::new_masked(
+            grid,
+            mask,
+            NonZeroU32::new(2).unwrap(),
+            &[Orientation::Original],
+            InputWrap::IgnoreBorders,
+        );
+        assert_eq!(overlapping_patterns.num_patterns(), 2);
+        for pattern_id in 0..2 {
+            assert!(!overlapping_patterns.pattern_to_vec(pattern_id).contains(&2));
+        }
+        let id_grid = overlapping_patterns.id_grid();
+        assert!(id_grid
+            .get_checked(Coord::new(1, 0))
+            .get(Orientation::Original)
+            .is_some());
+        assert!(id_grid
+            .get_checked(Coord::new(2, 0))
+            .get(Orientation::Original)
+            .is_none());
+    }
+
+    #[test]
+    fn transition_patterns() {
+        let row = |values: &[u8]| {
+            Grid::new_fn(Size::new(values.len() as u32, 1), |coord| {
+                values[coord.x as usize]
+            })
+        };
+        let sample = |values: &[u8]| {
+            OverlappingPatterns::new_input_wrap(
+                row(values),
+                NonZeroU32::new(1).unwrap(),
+                &[Orientation::Original],
+                InputWrap::Clamp,
+            )
+        };
+        let transition_patterns =
+            TransitionPatterns::new(sample(&[0, 1]), sample(&[2, 3]), &row(&[0, 1, 2]));
+        assert_eq!(transition_patterns.num_patterns(), 4);
+        assert_eq!(transition_patterns.b_pattern_id(0), 2);
+        assert_eq!(*transition_patterns.pattern_top_left_value(2), 2);
+        assert_eq!(transition_patterns.num_cross_adjacencies(), 1);
+        let descriptions = transition_patterns.pattern_descriptions();
+        let neighbours = |pattern_id: PatternId, direction: CardinalDirection| {
+            descriptions[pattern_id]
+                .allowed_neighbours
+                .get(direction)
+                .clone()
+        };
+        assert_eq!(neighbours(0, CardinalDirection::East), vec![0, 1]);
+        assert_eq!(neighbours(1, CardinalDirection::East), vec![0, 1, 2]);
+        assert_eq!(neighbours(2, CardinalDirection::West), vec![2, 3, 1]);
+        assert_eq!(neighbours(3, CardinalDirection::West), vec![2, 3]);
+    }
+
+    #[test]
+    fn pattern_stream() {
+        let grid = Grid::new_fn(Size::new(5, 4), |coord| {
+            ((coord.x * 3 + coord.y * coord.y) % 4) as u8
+        });
+        let row = |y| {
+            (0..5)
+                .map(|x| *grid.get_checked(Coord::new(x, y)))
+                .collect::<Vec<_>>()
+        };
+        let pattern_size = NonZeroU32::new(2).unwrap();
+        for input_wrap in [InputWrap::Tile, InputWrap::Clamp, InputWrap::IgnoreBorders] {
+            let overlapping_patterns = OverlappingPatterns::new_input_wrap(
+                grid.clone(),
+                pattern_size,
+                &orientation::ALL,
+                input_wrap,
+            );
+            let mut stream =
+                PatternStream::new(5, pattern_size, &orientation::ALL, input_wrap);
+            for y in 0..4 {
+                stream.push_row(row(y));
+            }
+            let streamed = stream.finish();
+            assert!(!streamed.counts_are_approximate());
+            let mut expected = (0..overlapping_patterns.num_patterns() as PatternId)
+                .map(|pattern_id| {
+                    (
+                        overlapping_patterns.pattern_to_vec(pattern_id),
+                        overlapping_patterns.pattern(pattern_id).count(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let mut actual = (0..streamed.num_patterns() as PatternId)
+                .map(|pattern_id| {
+                    (
+                        streamed.pattern_values(pattern_id).to_vec(),
+                        streamed.count(pattern_id),
+                    )
+                })
+                .collect::<Vec<_>>();
+            expected.sort();
+            actual.sort();
+            assert_eq!(actual, expected);
+        }
+        let mut stream = PatternStream::new(
+            5,
+            pattern_size,
+            &[Orientation::Original],
+            InputWrap::Tile,
+        );
+        stream.set_max_patterns(NonZeroUsize::new(3).unwrap());
+        for y in 0..4 {
+            stream.push_row(row(y));
+        }
+        let streamed = stream.finish();
+        assert!(streamed.counts_are_approximate());
+        assert_eq!(streamed.num_patterns(), 3);
+        let total = (0..3)
+            .map(|pattern_id| streamed.count(pattern_id))
+            .sum::<u32>();
+        assert_eq!(total, 20);
+        // the pattern replaced is the least common, and the earliest kept of those tied
+        let mut stream = PatternStream::new(
+            1,
+            pattern_size,
+            &[Orientation::Original],
+            InputWrap::Tile,
+        );
+        stream.set_max_patterns(NonZeroUsize::new(3).unwrap());
+        for value in [0, 1, 1, 2, 2, 0, 3, 4, 4, 4, 5] {
+            stream.add_occurrence(vec![value]);
+        }
+        let streamed = stream.finish();
+        let patterns = (0..streamed.num_patterns() as PatternId)
+            .map(|pattern_id| {
+                (
+                    streamed.pattern_values(pattern_id).to_vec(),
+                    streamed.count(pattern_id),
+                )
+            })
+            .collect::<Vec<_>>();
+        // 0, 1 and 2 are each seen twice, and are replaced by 3, 4 and 5 in that order
+        assert_eq!(patterns, vec![(vec![3], 3), (vec![4], 5), (vec![5], 3)]);
+    }
+
+    #[test]
+    fn output_wrap() {
+        // every row is 0, 0, 1, so the pattern 1, 0 only occurs across the right edge
+        let grid = Grid::new_fn(Size::new(3, 3), |coord| (coord.x == 2) as u8);
+        let mut overlapping_patterns = OverlappingPatterns::new_original_orientation(
+            grid,
+            NonZeroU32::new(2).unwrap(),
+        );
+        let weights = |overlapping_patterns: &OverlappingPatterns<u8>| {
+            overlapping_patterns
+                .pattern_descriptions()
+                .iter()
+                .map(|description| description.weight.map_or(0, NonZeroU32::get))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(weights(&overlapping_patterns), vec![3, 3, 3]);
+        overlapping_patterns.set_output_wrap(WrapY, BorderOccurrences::Exclude);
+        assert_eq!(weights(&overlapping_patterns), vec![3, 3, 0]);
+        // nothing may be placed next to the excluded pattern
+        let pattern_descriptions = overlapping_patterns.pattern_descriptions();
+        for (pattern_id, description) in pattern_descriptions.enumerate() {
+            for direction in CardinalDirections {
+                let allowed = description.allowed_neighbours.get(direction);
+                if pattern_id == 2 {
+                    assert!(allowed.is_empty());
+                } else {
+                    assert!(!allowed.contains(&2));
+                }
+            }
+        }
+        overlapping_patterns.set_output_wrap(WrapNone, BorderOccurrences::Exclude);
+        assert_eq!(weights(&overlapping_patterns), vec![2, 2, 0]);
+        overlapping_patterns.set_output_wrap(
+            WrapNone,
+            BorderOccurrences::DownWeight(NonZeroU32::new(4).unwrap()),
+        );
+        assert_eq!(weights(&overlapping_patterns), vec![9, 9, 3]);
+        overlapping_patterns.set_output_wrap(WrapXY, BorderOccurrences::Exclude);
+        assert_eq!(weights(&overlapping_patterns), vec![3, 3, 3]);
+    }
 }
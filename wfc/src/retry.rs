@@ -1,8 +1,193 @@
 use crate::{
-    wfc::{ForbidPattern, PropagateError, RunBorrow, RunOwn, RunOwnAll, Wave},
+    wfc::{
+        CancellableError, CancellationToken, Cancelled, ForbidPattern, LastContradiction,
+        PropagateError, RunBorrow, RunOwn, RunOwnAll, Wave,
+    },
     wrap::Wrap,
 };
-use rand::Rng;
+use coord_2d::Coord;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::time::{Duration, Instant};
+
+/// Receives notifications as a retry method makes attempts, e.g. to show "attempt 3/10" or to
+/// collect telemetry about failures. Attempts are numbered from 1, and `max_attempts` is
+/// `None` for methods which retry forever. All methods default to doing nothing.
+pub trait RetryObserver {
+    /// Called before each attempt
+    fn on_attempt_start(&mut self, attempt: usize, max_attempts: Option<usize>) {
+        let _ = (attempt, max_attempts);
+    }
+    /// Called when an attempt hits a contradiction at `coord`, which is `None` if it wasn't
+    /// at a known cell (e.g. it was found by a `ForbidPattern`)
+    fn on_contradiction(&mut self, attempt: usize, coord: Option<Coord>) {
+        let _ = (attempt, coord);
+    }
+    /// Called when an attempt completes without contradiction
+    fn on_success(&mut self, attempt: usize) {
+        let _ = attempt;
+    }
+    /// Called after `on_attempt_start` by retry methods which give each attempt its own
+    /// seed, such as `NumTimesReseed`, with the seed of the attempt
+    fn on_attempt_seed(&mut self, attempt: usize, seed: u64) {
+        let _ = (attempt, seed);
+    }
+}
+
+/// A `RetryObserver` which ignores every notification
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoRetryObserver;
+
+impl RetryObserver for NoRetryObserver {}
+
+impl<O: RetryObserver> RetryObserver for &mut O {
+    fn on_attempt_start(&mut self, attempt: usize, max_attempts: Option<usize>) {
+        (**self).on_attempt_start(attempt, max_attempts);
+    }
+    fn on_contradiction(&mut self, attempt: usize, coord: Option<Coord>) {
+        (**self).on_contradiction(attempt, coord);
+    }
+    fn on_success(&mut self, attempt: usize) {
+        (**self).on_success(attempt);
+    }
+    fn on_attempt_seed(&mut self, attempt: usize, seed: u64) {
+        (**self).on_attempt_seed(attempt, seed);
+    }
+}
+
+// Makes attempts with `collapse` until one succeeds or `max_attempts` attempts have failed,
+// returning the number of the successful attempt. A `max_attempts` of `None` never gives up,
+// which is also what retrying `usize::MAX` times (i.e. `checked_add(1)` overflowing) means.
+fn attempts<O, C>(
+    max_attempts: Option<usize>,
+    observer: &mut O,
+    mut collapse: C,
+) -> Result<usize, PropagateError>
+where
+    O: RetryObserver,
+    C: FnMut(&mut LastContradiction) -> Result<(), PropagateError>,
+{
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        observer.on_attempt_start(attempt, max_attempts);
+        let mut last_contradiction = LastContradiction(None);
+        match collapse(&mut last_contradiction) {
+            Ok(()) => {
+                observer.on_success(attempt);
+                return Ok(attempt);
+            }
+            Err(e) => {
+                observer.on_contradiction(attempt, last_contradiction.0);
+                if Some(attempt) == max_attempts {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+// Like `attempts`, but gives up if `collapse` is cancelled. Cancelled attempts are neither
+// successes nor contradictions, and the location of contradictions isn't known.
+fn cancellable_attempts<O, C>(observer: &mut O, mut collapse: C) -> Result<(), Cancelled>
+where
+    O: RetryObserver,
+    C: FnMut() -> Result<(), CancellableError>,
+{
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        observer.on_attempt_start(attempt, None);
+        match collapse() {
+            Ok(()) => {
+                observer.on_success(attempt);
+                return Ok(());
+            }
+            Err(CancellableError::Contradiction) => {
+                observer.on_contradiction(attempt, None)
+            }
+            Err(CancellableError::Cancelled) => return Err(Cancelled),
+        }
+    }
+}
+
+#[cfg(feature = "parallel")]
+enum AttemptEvent {
+    Start,
+    Contradiction(Option<Coord>),
+    Success,
+}
+
+// The number and output of the first successful attempt made by `par_attempts`, and what
+// happened to each attempt in the order it happened
+#[cfg(feature = "parallel")]
+struct ParAttempts<T> {
+    success: Option<(usize, T)>,
+    events: Vec<(usize, AttemptEvent)>,
+}
+
+// Makes one attempt per rng in parallel, stopping once an attempt succeeds
+#[cfg(feature = "parallel")]
+fn par_attempts<T, PR, C>(rngs: Vec<PR>, collapse: C) -> ParAttempts<T>
+where
+    T: Send,
+    PR: Rng + Send,
+    C: Fn(&mut PR, &mut LastContradiction) -> Result<T, PropagateError> + Sync,
+{
+    use rayon::prelude::*;
+    use std::sync::Mutex;
+    let events = Mutex::new(Vec::new());
+    let push = |attempt, event| events.lock().unwrap().push((attempt, event));
+    let success = rngs
+        .into_par_iter()
+        .enumerate()
+        .filter_map(|(index, mut rng)| {
+            let attempt = index + 1;
+            push(attempt, AttemptEvent::Start);
+            let mut last_contradiction = LastContradiction(None);
+            match collapse(&mut rng, &mut last_contradiction) {
+                Ok(output) => {
+                    push(attempt, AttemptEvent::Success);
+                    Some((attempt, output))
+                }
+                Err(PropagateError::Contradiction) => {
+                    push(attempt, AttemptEvent::Contradiction(last_contradiction.0));
+                    None
+                }
+            }
+        })
+        .find_any(|_| true);
+    ParAttempts {
+        success,
+        events: events.into_inner().unwrap(),
+    }
+}
+
+// Tells `observer` what happened during `par_attempts`, once every attempt has finished.
+// Attempts which succeeded after another success was found aren't reported as successes.
+#[cfg(feature = "parallel")]
+fn observe_par_attempts<T, O: RetryObserver>(
+    max_attempts: usize,
+    ParAttempts { success, events }: ParAttempts<T>,
+    observer: &mut O,
+) -> Result<T, PropagateError> {
+    let chosen = success.as_ref().map(|&(attempt, _)| attempt);
+    for (attempt, event) in events {
+        match event {
+            AttemptEvent::Start => observer.on_attempt_start(attempt, Some(max_attempts)),
+            AttemptEvent::Contradiction(coord) => {
+                observer.on_contradiction(attempt, coord)
+            }
+            AttemptEvent::Success => {
+                if Some(attempt) == chosen {
+                    observer.on_success(attempt);
+                }
+            }
+        }
+    }
+    success
+        .map(|(_, output)| output)
+        .ok_or(PropagateError::Contradiction)
+}
 
 pub trait RetryOwn: private::Sealed {
     type Return;
@@ -10,7 +195,22 @@ pub trait RetryOwn: private::Sealed {
     where
         W: Wrap + Clone + Sync + Send,
         F: ForbidPattern + Clone + Sync + Send,
-        R: Rng;
+        R: Rng,
+    {
+        self.retry_observed(run, rng, &mut NoRetryObserver)
+    }
+    /// Like `retry`, but `observer` is told when each attempt starts and how it ends
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        run: RunOwn<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver;
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -18,23 +218,48 @@ pub struct Forever;
 
 impl RetryOwn for Forever {
     type Return = Wave;
-    fn retry<'a, W, F, R>(
+    fn retry_observed<'a, W, F, R, O>(
         &mut self,
         mut run: RunOwn<'a, W, F>,
         rng: &mut R,
+        observer: &mut O,
     ) -> Self::Return
     where
         W: Wrap + Clone + Sync + Send,
         F: ForbidPattern + Clone + Sync + Send,
         R: Rng,
+        O: RetryObserver,
     {
-        loop {
-            match run.collapse(rng) {
-                Ok(()) => (),
-                Err(PropagateError::Contradiction) => continue,
-            }
-            return run.into_wave();
-        }
+        attempts(None, observer, |listener| {
+            run.collapse_with_listener(rng, listener)
+        })
+        .expect("retrying forever only returns on success");
+        run.into_wave()
+    }
+}
+
+/// Like `Forever`, but gives up with `Cancelled` once the token is cancelled. The token is
+/// checked between steps of each attempt, so cancellation takes effect promptly even if a
+/// single attempt takes a long time.
+#[derive(Debug, Clone)]
+pub struct ForeverCancellable(pub CancellationToken);
+
+impl RetryOwn for ForeverCancellable {
+    type Return = Result<Wave, Cancelled>;
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        mut run: RunOwn<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        cancellable_attempts(observer, || run.collapse_cancellable(&self.0, rng))?;
+        Ok(run.into_wave())
     }
 }
 
@@ -51,34 +276,157 @@ pub struct ParNumTimes(pub usize);
 #[cfg(feature = "parallel")]
 impl RetryOwn for ParNumTimes {
     type Return = Result<Wave, PropagateError>;
-    fn retry<'a, W, F, R>(&mut self, run: RunOwn<'a, W, F>, rng: &mut R) -> Self::Return
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        run: RunOwn<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
     where
         W: Wrap + Clone + Sync + Send,
         F: ForbidPattern + Clone + Sync + Send,
         R: Rng,
+        O: RetryObserver,
     {
+        RetryOwn::retry_observed(
+            &mut ParNumTimesWithRng::xor_shift(self.0),
+            run,
+            rng,
+            observer,
+        )
+    }
+}
+
+/// Like `ParNumTimes`, but each attempt uses an rng created by passing a seed taken from the
+/// caller's rng to `seeder`, allowing the family of rng used by each thread to be chosen.
+#[cfg(feature = "parallel")]
+#[derive(Debug, Clone, Copy)]
+pub struct ParNumTimesWithRng<S> {
+    pub num_times: usize,
+    pub seeder: S,
+}
+
+#[cfg(feature = "parallel")]
+impl<S> ParNumTimesWithRng<S> {
+    pub fn new(num_times: usize, seeder: S) -> Self {
+        Self { num_times, seeder }
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl ParNumTimesWithRng<fn(u64) -> rand_xorshift::XorShiftRng> {
+    // Each thread runs with a different rng so they can produce different results.  The
+    // `RetryOwn` trait doesn't provide a way to produce new rngs of type `R` besides `clone`,
+    // which won't help since we want each rng to be different.  Instead, by default each thread
+    // runs with a `XorShiftRng` seeded with a random number taken from the original rng.
+    // `XorShiftRng` is chosen because it is fast, and a cryptographically secure rng (which it
+    // is not) is not required for this purpose.
+    fn xor_shift(num_times: usize) -> Self {
         use rand::SeedableRng;
-        use rand_xorshift::XorShiftRng;
-        use rayon::prelude::*;
-        // Each thread runs with a different rng so they can produce different results.  The
-        // `RetryOwn` trait doesn't provide a way to produce new rngs of type `R` besides `clone`,
-        // which won't help since we want each rng to be different.  Instead, each thread runs with
-        // a `XorShiftRng` seeded with a random number taken from the original rng. `XorShiftRng`
-        // is chosen because it is fast, and a cryptographically secure rng (which it is not) is
-        // not required for this purpose. It does mean that the rng used by this runner can't be
-        // chosen by the caller, but the only way to allow this is to change the `RetryOwn`
-        // interface which doesn't seem worth it.
-        let rngs = (0..self.0)
-            .map(|_| XorShiftRng::seed_from_u64(rng.gen()))
+        Self::new(num_times, rand_xorshift::XorShiftRng::seed_from_u64)
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<S, PR> RetryOwn for ParNumTimesWithRng<S>
+where
+    S: Fn(u64) -> PR,
+    PR: Rng + Send,
+{
+    type Return = Result<Wave, PropagateError>;
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        run: RunOwn<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        let rngs = (0..self.num_times)
+            .map(|_| (self.seeder)(rng.gen()))
             .collect::<Vec<_>>();
-        rngs.into_par_iter()
-            .filter_map(|mut rng| {
+        let outcome = par_attempts(rngs, |rng, listener| {
+            let mut runner = run.clone();
+            runner
+                .collapse_with_listener(rng, listener)
+                .map(|()| runner.into_wave())
+        });
+        observe_par_attempts(self.num_times, outcome, observer)
+    }
+}
+
+/// Like `ParNumTimesWithRng`, but attempts run on `pool` rather than rayon's global thread
+/// pool, so applications with their own worker threads (e.g. game engines) can limit the
+/// number of threads used for generation.
+#[cfg(feature = "parallel")]
+#[derive(Debug, Clone, Copy)]
+pub struct ParNumTimesInPool<'p, S> {
+    pub num_times: usize,
+    pub seeder: S,
+    pub pool: &'p rayon::ThreadPool,
+}
+
+#[cfg(feature = "parallel")]
+impl<'p> ParNumTimesInPool<'p, fn(u64) -> rand_xorshift::XorShiftRng> {
+    /// Each attempt uses a `XorShiftRng`, as with `ParNumTimes`
+    pub fn new(num_times: usize, pool: &'p rayon::ThreadPool) -> Self {
+        use rand::SeedableRng;
+        Self {
+            num_times,
+            seeder: rand_xorshift::XorShiftRng::seed_from_u64,
+            pool,
+        }
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<'p, S> ParNumTimesInPool<'p, S> {
+    pub fn with_seeder(num_times: usize, seeder: S, pool: &'p rayon::ThreadPool) -> Self {
+        Self {
+            num_times,
+            seeder,
+            pool,
+        }
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<'p, S, PR> RetryOwn for ParNumTimesInPool<'p, S>
+where
+    S: Fn(u64) -> PR,
+    PR: Rng + Send,
+{
+    type Return = Result<Wave, PropagateError>;
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        run: RunOwn<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        let rngs = (0..self.num_times)
+            .map(|_| (self.seeder)(rng.gen()))
+            .collect::<Vec<_>>();
+        // the observer isn't necessarily `Send`, so it's told about the attempts after
+        // leaving the pool
+        let outcome = self.pool.install(|| {
+            par_attempts(rngs, |rng, listener| {
                 let mut runner = run.clone();
-                let collapse_result = runner.collapse(&mut rng);
-                collapse_result.map(|_| runner.into_wave()).ok()
+                runner
+                    .collapse_with_listener(rng, listener)
+                    .map(|()| runner.into_wave())
             })
-            .find_any(|_| true)
-            .ok_or(PropagateError::Contradiction)
+        });
+        observe_par_attempts(self.num_times, outcome, observer)
     }
 }
 
@@ -89,24 +437,234 @@ pub struct NumTimes(pub usize);
 
 impl RetryOwn for NumTimes {
     type Return = Result<Wave, PropagateError>;
-    fn retry<'a, W, F, R>(
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        mut run: RunOwn<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        attempts(self.0.checked_add(1), observer, |listener| {
+            run.collapse_with_listener(rng, listener)
+        })?;
+        Ok(run.into_wave())
+    }
+}
+
+/// Like `NumTimes`, but returns a `RetryReport` describing the attempts along with the
+/// result, so callers can log how hard the output was to generate and adapt (e.g. by
+/// increasing the pattern size) without writing their own retry loop
+#[derive(Debug, Clone, Copy)]
+pub struct NumTimesWithReport(pub usize);
+
+/// The result of retrying with `NumTimesWithReport`, along with what happened on the way
+#[derive(Debug)]
+pub struct RetryReport<T> {
+    /// The output of the successful attempt, or the error of the last attempt
+    pub result: Result<T, PropagateError>,
+    /// The number of attempts made, including the successful one
+    pub num_attempts: usize,
+    /// The total time spent on every attempt
+    pub elapsed: Duration,
+    /// Where each failed attempt, in order, hit a contradiction. An entry is `None` if the
+    /// contradiction wasn't at a known cell (e.g. it was found by a `ForbidPattern`).
+    pub contradictions: Vec<Option<Coord>>,
+}
+
+impl<T> RetryReport<T> {
+    pub fn map<U, M: FnOnce(T) -> U>(self, m: M) -> RetryReport<U> {
+        RetryReport {
+            result: self.result.map(m),
+            num_attempts: self.num_attempts,
+            elapsed: self.elapsed,
+            contradictions: self.contradictions,
+        }
+    }
+}
+
+impl NumTimesWithReport {
+    fn report<O, C>(&self, observer: &mut O, mut collapse: C) -> RetryReport<()>
+    where
+        O: RetryObserver,
+        C: FnMut(&mut LastContradiction) -> Result<(), PropagateError>,
+    {
+        let start = Instant::now();
+        let mut contradictions = Vec::new();
+        let result = attempts(self.0.checked_add(1), observer, |last_contradiction| {
+            let result = collapse(last_contradiction);
+            if result.is_err() {
+                contradictions.push(last_contradiction.0);
+            }
+            result
+        });
+        RetryReport {
+            num_attempts: contradictions.len() + result.is_ok() as usize,
+            result: result.map(|_| ()),
+            elapsed: start.elapsed(),
+            contradictions,
+        }
+    }
+}
+
+impl RetryOwn for NumTimesWithReport {
+    type Return = RetryReport<Wave>;
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        mut run: RunOwn<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        self.report(observer, |listener| {
+            run.collapse_with_listener(rng, listener)
+        })
+        .map(|()| run.into_wave())
+    }
+}
+
+/// Retry method which, on contradiction, restarts only the cells within euclidean distance
+/// `radius` of the contradiction and keeps the decisions made elsewhere, rather than
+/// restarting the whole wave. This is much faster than `NumTimes` for large outputs. Gives
+/// up after `max_restarts` partial restarts.
+#[derive(Debug, Clone, Copy)]
+pub struct PartialRestart {
+    pub radius: u32,
+    pub max_restarts: usize,
+}
+
+impl RetryOwn for PartialRestart {
+    type Return = Result<Wave, PropagateError>;
+    fn retry_observed<'a, W, F, R, O>(
         &mut self,
         mut run: RunOwn<'a, W, F>,
         rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        run.borrow_mut().collapse_with_partial_restarts_observed(
+            self.radius,
+            self.max_restarts,
+            rng,
+            observer,
+        )?;
+        Ok(run.into_wave())
+    }
+}
+
+/// Retry method which makes up to `attempts` attempts, where attempt `i` uses a `StdRng`
+/// seeded with `base_seed + i`. Returns the first successful wave along with the seed which
+/// produced it. Since each attempt starts by resetting the run with its own rng, that output
+/// can be reproduced by creating a run with a `StdRng` seeded with the returned seed and
+/// collapsing it with the same rng, e.g. `RunOwn::new(size, &global_stats, &mut rng)` followed
+/// by `run.collapse(&mut rng)`. The rng passed to `retry` is not used.
+#[derive(Debug, Clone, Copy)]
+pub struct SeedScan {
+    pub attempts: usize,
+    pub base_seed: u64,
+}
+
+impl SeedScan {
+    fn scan<'a, W, F, O>(
+        &self,
+        run: &mut RunBorrow<'a, W, F>,
+        observer: &mut O,
+    ) -> Result<u64, PropagateError>
+    where
+        W: Wrap,
+        F: ForbidPattern,
+        O: RetryObserver,
+    {
+        if self.attempts == 0 {
+            return Err(PropagateError::Contradiction);
+        }
+        let mut seed = self.base_seed;
+        attempts(Some(self.attempts), observer, |listener| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            run.reset(&mut rng);
+            let result = run.collapse_with_listener(&mut rng, listener);
+            if result.is_err() {
+                seed = seed.wrapping_add(1);
+            }
+            result
+        })?;
+        Ok(seed)
+    }
+}
+
+impl RetryOwn for SeedScan {
+    type Return = Result<(Wave, u64), PropagateError>;
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        mut run: RunOwn<'a, W, F>,
+        _rng: &mut R,
+        observer: &mut O,
     ) -> Self::Return
     where
         W: Wrap + Clone + Sync + Send,
         F: ForbidPattern + Clone + Sync + Send,
         R: Rng,
+        O: RetryObserver,
+    {
+        let seed = self.scan(&mut run.borrow_mut(), observer)?;
+        Ok((run.into_wave(), seed))
+    }
+}
+
+/// Like `NumTimes`, but each attempt resets the run with a `StdRng` seeded with a fresh seed
+/// drawn from the rng passed to `retry`, rather than continuing from where the previous
+/// attempt left the rng. Returns the first successful wave along with the seed which produced
+/// it, and tells the observer the seed of every attempt with `on_attempt_seed`. Any attempt,
+/// including failed ones, can be reproduced in isolation in the same way as with `SeedScan`.
+#[derive(Debug, Clone, Copy)]
+pub struct NumTimesReseed(pub usize);
+
+impl NumTimesReseed {
+    fn reseed<'a, W, F, R, O>(
+        &self,
+        run: &mut RunBorrow<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Result<u64, PropagateError>
+    where
+        W: Wrap,
+        F: ForbidPattern,
+        R: Rng,
+        O: RetryObserver,
     {
+        let max_attempts = self.0.checked_add(1);
+        let mut attempt = 0;
         loop {
-            match run.collapse(rng) {
-                Ok(()) => return Ok(run.into_wave()),
+            attempt += 1;
+            let seed = rng.gen();
+            observer.on_attempt_start(attempt, max_attempts);
+            observer.on_attempt_seed(attempt, seed);
+            let mut attempt_rng = StdRng::seed_from_u64(seed);
+            run.reset(&mut attempt_rng);
+            let mut last_contradiction = LastContradiction(None);
+            match run.collapse_with_listener(&mut attempt_rng, &mut last_contradiction) {
+                Ok(()) => {
+                    observer.on_success(attempt);
+                    return Ok(seed);
+                }
                 Err(e) => {
-                    if self.0 == 0 {
+                    observer.on_contradiction(attempt, last_contradiction.0);
+                    if Some(attempt) == max_attempts {
                         return Err(e);
-                    } else {
-                        self.0 -= 1;
                     }
                 }
             }
@@ -114,87 +672,382 @@ impl RetryOwn for NumTimes {
     }
 }
 
+impl RetryOwn for NumTimesReseed {
+    type Return = Result<(Wave, u64), PropagateError>;
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        mut run: RunOwn<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        let seed = self.reseed(&mut run.borrow_mut(), rng, observer)?;
+        Ok((run.into_wave(), seed))
+    }
+}
+
+/// Maps the wave returned by a `RetryOwn` to another type, keeping the shape of the return
+/// value. For example, `NumTimes` maps `Result<Wave, PropagateError>` to `Result<T,
+/// PropagateError>`.
+pub trait RetryOutput<T>: RetryOwn {
+    type Output;
+    fn map_output<M: FnOnce(Wave) -> T>(r: Self::Return, m: M) -> Self::Output;
+}
+
+impl<T> RetryOutput<T> for Forever {
+    type Output = T;
+    fn map_output<M: FnOnce(Wave) -> T>(r: Self::Return, m: M) -> Self::Output {
+        m(r)
+    }
+}
+
+impl<T> RetryOutput<T> for NumTimes {
+    type Output = Result<T, PropagateError>;
+    fn map_output<M: FnOnce(Wave) -> T>(r: Self::Return, m: M) -> Self::Output {
+        r.map(m)
+    }
+}
+
+impl<T> RetryOutput<T> for NumTimesWithReport {
+    type Output = RetryReport<T>;
+    fn map_output<M: FnOnce(Wave) -> T>(r: Self::Return, m: M) -> Self::Output {
+        r.map(m)
+    }
+}
+
+impl<T> RetryOutput<T> for PartialRestart {
+    type Output = Result<T, PropagateError>;
+    fn map_output<M: FnOnce(Wave) -> T>(r: Self::Return, m: M) -> Self::Output {
+        r.map(m)
+    }
+}
+
+impl<T> RetryOutput<T> for SeedScan {
+    type Output = Result<(T, u64), PropagateError>;
+    fn map_output<M: FnOnce(Wave) -> T>(r: Self::Return, m: M) -> Self::Output {
+        r.map(|(wave, seed)| (m(wave), seed))
+    }
+}
+
+impl<T> RetryOutput<T> for NumTimesReseed {
+    type Output = Result<(T, u64), PropagateError>;
+    fn map_output<M: FnOnce(Wave) -> T>(r: Self::Return, m: M) -> Self::Output {
+        r.map(|(wave, seed)| (m(wave), seed))
+    }
+}
+
+impl<T> RetryOutput<T> for ForeverCancellable {
+    type Output = Result<T, Cancelled>;
+    fn map_output<M: FnOnce(Wave) -> T>(r: Self::Return, m: M) -> Self::Output {
+        r.map(m)
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<T> RetryOutput<T> for ParNumTimes {
+    type Output = Result<T, PropagateError>;
+    fn map_output<M: FnOnce(Wave) -> T>(r: Self::Return, m: M) -> Self::Output {
+        r.map(m)
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<T, S, PR> RetryOutput<T> for ParNumTimesWithRng<S>
+where
+    S: Fn(u64) -> PR,
+    PR: Rng + Send,
+{
+    type Output = Result<T, PropagateError>;
+    fn map_output<M: FnOnce(Wave) -> T>(r: Self::Return, m: M) -> Self::Output {
+        r.map(m)
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<'p, T, S, PR> RetryOutput<T> for ParNumTimesInPool<'p, S>
+where
+    S: Fn(u64) -> PR,
+    PR: Rng + Send,
+{
+    type Output = Result<T, PropagateError>;
+    fn map_output<M: FnOnce(Wave) -> T>(r: Self::Return, m: M) -> Self::Output {
+        r.map(m)
+    }
+}
+
+/// Converts a collapsed wave into a frontend's output type (e.g. an image). Implementing
+/// `output_from_wave` is enough to convert the return value of any retry method.
+pub trait CollapseFrontend<T> {
+    fn output_from_wave(&self, wave: &Wave) -> T;
+
+    fn output_from_retry<RT: RetryOutput<T>>(&self, r: RT::Return) -> RT::Output {
+        RT::map_output(r, |wave| self.output_from_wave(&wave))
+    }
+}
+
 pub trait RetryOwnAll: private::Sealed {
     type Return;
     fn retry<W, F, R>(&mut self, run: RunOwnAll<W, F>, rng: &mut R) -> Self::Return
     where
         W: Wrap + Clone + Sync + Send,
         F: ForbidPattern + Clone + Sync + Send,
-        R: Rng;
+        R: Rng,
+    {
+        self.retry_observed(run, rng, &mut NoRetryObserver)
+    }
+    /// Like `retry`, but `observer` is told when each attempt starts and how it ends
+    fn retry_observed<W, F, R, O>(
+        &mut self,
+        run: RunOwnAll<W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver;
 }
 
 impl RetryOwnAll for Forever {
     type Return = Wave;
-    fn retry<W, F, R>(&mut self, mut run: RunOwnAll<W, F>, rng: &mut R) -> Self::Return
+    fn retry_observed<W, F, R, O>(
+        &mut self,
+        mut run: RunOwnAll<W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
     where
         W: Wrap + Clone + Sync + Send,
         F: ForbidPattern + Clone + Sync + Send,
         R: Rng,
+        O: RetryObserver,
     {
-        loop {
-            match run.collapse(rng) {
-                Ok(()) => (),
-                Err(PropagateError::Contradiction) => continue,
-            }
-            return run.into_wave();
-        }
+        attempts(None, observer, |listener| {
+            run.collapse_with_listener(rng, listener)
+        })
+        .expect("retrying forever only returns on success");
+        run.into_wave()
     }
 }
 
 impl RetryOwnAll for NumTimes {
     type Return = Result<Wave, PropagateError>;
-    fn retry<W, F, R>(&mut self, mut run: RunOwnAll<W, F>, rng: &mut R) -> Self::Return
+    fn retry_observed<W, F, R, O>(
+        &mut self,
+        mut run: RunOwnAll<W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
     where
         W: Wrap + Clone + Sync + Send,
         F: ForbidPattern + Clone + Sync + Send,
         R: Rng,
+        O: RetryObserver,
     {
-        loop {
-            match run.collapse(rng) {
-                Ok(()) => return Ok(run.into_wave()),
-                Err(e) => {
-                    if self.0 == 0 {
-                        return Err(e);
-                    } else {
-                        self.0 -= 1;
-                    }
-                }
-            }
-        }
+        attempts(self.0.checked_add(1), observer, |listener| {
+            run.collapse_with_listener(rng, listener)
+        })?;
+        Ok(run.into_wave())
+    }
+}
+
+impl RetryOwnAll for NumTimesWithReport {
+    type Return = RetryReport<Wave>;
+    fn retry_observed<W, F, R, O>(
+        &mut self,
+        mut run: RunOwnAll<W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        self.report(observer, |listener| {
+            run.collapse_with_listener(rng, listener)
+        })
+        .map(|()| run.into_wave())
+    }
+}
+
+impl RetryOwnAll for PartialRestart {
+    type Return = Result<Wave, PropagateError>;
+    fn retry_observed<W, F, R, O>(
+        &mut self,
+        mut run: RunOwnAll<W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        run.borrow_mut().collapse_with_partial_restarts_observed(
+            self.radius,
+            self.max_restarts,
+            rng,
+            observer,
+        )?;
+        Ok(run.into_wave())
+    }
+}
+
+impl RetryOwnAll for SeedScan {
+    type Return = Result<(Wave, u64), PropagateError>;
+    fn retry_observed<W, F, R, O>(
+        &mut self,
+        mut run: RunOwnAll<W, F>,
+        _rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        let seed = self.scan(&mut run.borrow_mut(), observer)?;
+        Ok((run.into_wave(), seed))
+    }
+}
+
+impl RetryOwnAll for NumTimesReseed {
+    type Return = Result<(Wave, u64), PropagateError>;
+    fn retry_observed<W, F, R, O>(
+        &mut self,
+        mut run: RunOwnAll<W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        let seed = self.reseed(&mut run.borrow_mut(), rng, observer)?;
+        Ok((run.into_wave(), seed))
+    }
+}
+
+impl RetryOwnAll for ForeverCancellable {
+    type Return = Result<Wave, Cancelled>;
+    fn retry_observed<W, F, R, O>(
+        &mut self,
+        mut run: RunOwnAll<W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        cancellable_attempts(observer, || run.collapse_cancellable(&self.0, rng))?;
+        Ok(run.into_wave())
     }
 }
 
 #[cfg(feature = "parallel")]
 impl RetryOwnAll for ParNumTimes {
     type Return = Result<Wave, PropagateError>;
-    fn retry<W, F, R>(&mut self, run: RunOwnAll<W, F>, rng: &mut R) -> Self::Return
+    fn retry_observed<W, F, R, O>(
+        &mut self,
+        run: RunOwnAll<W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
     where
         W: Wrap + Clone + Sync + Send,
         F: ForbidPattern + Clone + Sync + Send,
         R: Rng,
+        O: RetryObserver,
     {
-        use rand::SeedableRng;
-        use rand_xorshift::XorShiftRng;
-        use rayon::prelude::*;
-        // Each thread runs with a different rng so they can produce different results.  The
-        // `RetryOwn` trait doesn't provide a way to produce new rngs of type `R` besides `clone`,
-        // which won't help since we want each rng to be different.  Instead, each thread runs with
-        // a `XorShiftRng` seeded with a random number taken from the original rng. `XorShiftRng`
-        // is chosen because it is fast, and a cryptographically secure rng (which it is not) is
-        // not required for this purpose. It does mean that the rng used by this runner can't be
-        // chosen by the caller, but the only way to allow this is to change the `RetryOwn`
-        // interface which doesn't seem worth it.
-        let rngs = (0..self.0)
-            .map(|_| XorShiftRng::seed_from_u64(rng.gen()))
+        RetryOwnAll::retry_observed(
+            &mut ParNumTimesWithRng::xor_shift(self.0),
+            run,
+            rng,
+            observer,
+        )
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<S, PR> RetryOwnAll for ParNumTimesWithRng<S>
+where
+    S: Fn(u64) -> PR,
+    PR: Rng + Send,
+{
+    type Return = Result<Wave, PropagateError>;
+    fn retry_observed<W, F, R, O>(
+        &mut self,
+        run: RunOwnAll<W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        let rngs = (0..self.num_times)
+            .map(|_| (self.seeder)(rng.gen()))
+            .collect::<Vec<_>>();
+        let outcome = par_attempts(rngs, |rng, listener| {
+            let mut runner = run.clone();
+            runner
+                .collapse_with_listener(rng, listener)
+                .map(|()| runner.into_wave())
+        });
+        observe_par_attempts(self.num_times, outcome, observer)
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<'p, S, PR> RetryOwnAll for ParNumTimesInPool<'p, S>
+where
+    S: Fn(u64) -> PR,
+    PR: Rng + Send,
+{
+    type Return = Result<Wave, PropagateError>;
+    fn retry_observed<W, F, R, O>(
+        &mut self,
+        run: RunOwnAll<W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap + Clone + Sync + Send,
+        F: ForbidPattern + Clone + Sync + Send,
+        R: Rng,
+        O: RetryObserver,
+    {
+        let rngs = (0..self.num_times)
+            .map(|_| (self.seeder)(rng.gen()))
             .collect::<Vec<_>>();
-        rngs.into_par_iter()
-            .filter_map(|mut rng| {
+        // the observer isn't necessarily `Send`, so it's told about the attempts after
+        // leaving the pool
+        let outcome = self.pool.install(|| {
+            par_attempts(rngs, |rng, listener| {
                 let mut runner = run.clone();
-                let collapse_result = runner.collapse(&mut rng);
-                collapse_result.map(|_| runner.into_wave()).ok()
+                runner
+                    .collapse_with_listener(rng, listener)
+                    .map(|()| runner.into_wave())
             })
-            .find_any(|_| true)
-            .ok_or(PropagateError::Contradiction)
+        });
+        observe_par_attempts(self.num_times, outcome, observer)
     }
 }
 
@@ -208,54 +1061,160 @@ pub trait RetryBorrow: private::Sealed {
     where
         W: Wrap,
         F: ForbidPattern,
-        R: Rng;
+        R: Rng,
+    {
+        self.retry_observed(run, rng, &mut NoRetryObserver)
+    }
+    /// Like `retry`, but `observer` is told when each attempt starts and how it ends
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        run: &mut RunBorrow<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap,
+        F: ForbidPattern,
+        R: Rng,
+        O: RetryObserver;
 }
 
 impl RetryBorrow for Forever {
     type Return = ();
-    fn retry<'a, W, F, R>(
+    fn retry_observed<'a, W, F, R, O>(
         &mut self,
         run: &mut RunBorrow<'a, W, F>,
         rng: &mut R,
+        observer: &mut O,
     ) -> Self::Return
     where
         W: Wrap,
         F: ForbidPattern,
         R: Rng,
+        O: RetryObserver,
     {
-        loop {
-            match run.collapse(rng) {
-                Ok(()) => break,
-                Err(PropagateError::Contradiction) => continue,
-            }
-        }
+        attempts(None, observer, |listener| {
+            run.collapse_with_listener(rng, listener)
+        })
+        .expect("retrying forever only returns on success");
     }
 }
 
 impl RetryBorrow for NumTimes {
     type Return = Result<(), PropagateError>;
-    fn retry<'a, W, F, R>(
+    fn retry_observed<'a, W, F, R, O>(
         &mut self,
         run: &mut RunBorrow<'a, W, F>,
         rng: &mut R,
+        observer: &mut O,
     ) -> Self::Return
     where
         W: Wrap,
         F: ForbidPattern,
         R: Rng,
+        O: RetryObserver,
     {
-        loop {
-            match run.collapse(rng) {
-                Ok(()) => return Ok(()),
-                Err(e) => {
-                    if self.0 == 0 {
-                        return Err(e);
-                    } else {
-                        self.0 -= 1;
-                    }
-                }
-            }
-        }
+        attempts(self.0.checked_add(1), observer, |listener| {
+            run.collapse_with_listener(rng, listener)
+        })
+        .map(|_| ())
+    }
+}
+
+impl RetryBorrow for NumTimesWithReport {
+    type Return = RetryReport<()>;
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        run: &mut RunBorrow<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap,
+        F: ForbidPattern,
+        R: Rng,
+        O: RetryObserver,
+    {
+        self.report(observer, |listener| {
+            run.collapse_with_listener(rng, listener)
+        })
+    }
+}
+
+impl RetryBorrow for PartialRestart {
+    type Return = Result<(), PropagateError>;
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        run: &mut RunBorrow<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap,
+        F: ForbidPattern,
+        R: Rng,
+        O: RetryObserver,
+    {
+        run.collapse_with_partial_restarts_observed(
+            self.radius,
+            self.max_restarts,
+            rng,
+            observer,
+        )
+    }
+}
+
+impl RetryBorrow for SeedScan {
+    type Return = Result<u64, PropagateError>;
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        run: &mut RunBorrow<'a, W, F>,
+        _rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap,
+        F: ForbidPattern,
+        R: Rng,
+        O: RetryObserver,
+    {
+        self.scan(run, observer)
+    }
+}
+
+impl RetryBorrow for NumTimesReseed {
+    type Return = Result<u64, PropagateError>;
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        run: &mut RunBorrow<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap,
+        F: ForbidPattern,
+        R: Rng,
+        O: RetryObserver,
+    {
+        self.reseed(run, rng, observer)
+    }
+}
+
+impl RetryBorrow for ForeverCancellable {
+    type Return = Result<(), Cancelled>;
+    fn retry_observed<'a, W, F, R, O>(
+        &mut self,
+        run: &mut RunBorrow<'a, W, F>,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Self::Return
+    where
+        W: Wrap,
+        F: ForbidPattern,
+        R: Rng,
+        O: RetryObserver,
+    {
+        cancellable_attempts(observer, || run.collapse_cancellable(&self.0, rng))
     }
 }
 
@@ -266,7 +1225,120 @@ mod private {
 
     impl Sealed for Forever {}
     impl Sealed for NumTimes {}
+    impl Sealed for NumTimesWithReport {}
+    impl Sealed for ForeverCancellable {}
+    impl Sealed for PartialRestart {}
+    impl Sealed for SeedScan {}
+    impl Sealed for NumTimesReseed {}
 
     #[cfg(feature = "parallel")]
     impl Sealed for ParNumTimes {}
+    #[cfg(feature = "parallel")]
+    impl<S> Sealed for ParNumTimesWithRng<S> {}
+    #[cfg(feature = "parallel")]
+    impl<'p, S> Sealed for ParNumTimesInPool<'p, S> {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{checkerboard_stats, three_colour_stats};
+    use crate::wfc::Context;
+    use coord_2d::Size;
+    use grid_2d::Grid;
+
+    #[test]
+    fn num_times_reseed() {
+        use rand::SeedableRng;
+        struct Seeds(Vec<u64>);
+        impl RetryObserver for Seeds {
+            fn on_attempt_seed(&mut self, attempt: usize, seed: u64) {
+                assert_eq!(attempt, self.0.len() + 1);
+                self.0.push(seed);
+            }
+        }
+        let global_stats = three_colour_stats();
+        let size = Size::new(6, 6);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut seeds = Seeds(Vec::new());
+        let (wave, seed) = RunOwn::new(size, &global_stats, &mut rng)
+            .collapse_retrying_observed(NumTimesReseed(100), &mut seeds, &mut rng)
+            .unwrap();
+        assert_eq!(seeds.0.last(), Some(&seed));
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut run = RunOwn::new(size, &global_stats, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let ids = |wave: &Wave| {
+            Grid::new_grid_map_ref(wave.grid(), |cell| cell.chosen_pattern_id().unwrap())
+        };
+        assert_eq!(ids(&wave), ids(&run.into_wave()));
+    }
+
+    #[test]
+    fn num_times_with_report() {
+        use rand::SeedableRng;
+        let global_stats = checkerboard_stats();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let report = RunOwn::new(Size::new(4, 4), &global_stats, &mut rng)
+            .collapse_retrying(NumTimesWithReport(2), &mut rng);
+        assert!(report.result.is_ok());
+        assert_eq!(report.num_attempts, 1);
+        assert!(report.contradictions.is_empty());
+        // a wrapping checkerboard can't have an odd width
+        let size = Size::new(5, 4);
+        let report = RunOwn::new(size, &global_stats, &mut rng)
+            .collapse_retrying(NumTimesWithReport(2), &mut rng);
+        assert!(report.result.is_err());
+        assert_eq!(report.num_attempts, 3);
+        assert_eq!(report.contradictions.len(), 3);
+        assert!(report
+            .contradictions
+            .iter()
+            .all(|coord| coord.is_some_and(|coord| coord.is_valid(size))));
+    }
+
+    #[test]
+    fn retry_observer() {
+        use rand::SeedableRng;
+        #[derive(Debug, PartialEq)]
+        enum Event {
+            Start(usize, Option<usize>),
+            Contradiction(usize),
+            Success(usize),
+        }
+        impl RetryObserver for Vec<Event> {
+            fn on_attempt_start(&mut self, attempt: usize, max_attempts: Option<usize>) {
+                self.push(Event::Start(attempt, max_attempts));
+            }
+            fn on_contradiction(&mut self, attempt: usize, coord: Option<Coord>) {
+                assert!(coord.is_some());
+                self.push(Event::Contradiction(attempt));
+            }
+            fn on_success(&mut self, attempt: usize) {
+                self.push(Event::Success(attempt));
+            }
+        }
+        let global_stats = checkerboard_stats();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut events = Vec::new();
+        let mut wave = Wave::new(Size::new(4, 4));
+        let mut context = Context::new();
+        let mut run = RunBorrow::new(&mut context, &mut wave, &global_stats, &mut rng);
+        run.collapse_retrying_observed(Forever, &mut events, &mut rng);
+        assert_eq!(events, vec![Event::Start(1, None), Event::Success(1)]);
+        // a wrapping checkerboard can't have an odd width
+        let mut events = Vec::new();
+        let result = RunOwn::new(Size::new(5, 4), &global_stats, &mut rng)
+            .collapse_retrying_observed(NumTimes(1), &mut events, &mut rng);
+        assert!(result.is_err());
+        assert_eq!(
+            events,
+            vec![
+                Event::Start(1, Some(2)),
+                Event::Contradiction(1),
+                Event::Start(2, Some(2)),
+                Event::Contradiction(2),
+            ]
+        );
+    }
 }
@@ -0,0 +1,167 @@
+//! Generation of 1D sequences (e.g. melodies or lines of text) from an example sequence.
+//! Patterns are windows of consecutive values from the input, and the output is a single
+//! row which doesn't wrap.
+
+use crate::{
+    retry::{CollapseFrontend, RetryOutput},
+    wfc::{GlobalStats, PatternDescription, PatternId, PatternTable, RunOwn, Wave},
+    wrap::WrapNone,
+};
+use coord_2d::{Coord, Size};
+use direction::CardinalDirectionTable;
+use hashbrown::HashMap;
+use rand::Rng;
+use std::hash::Hash;
+use std::num::NonZeroU32;
+
+pub struct SequencePatterns<T: Eq + Clone + Hash> {
+    window_size: usize,
+    patterns: PatternTable<Vec<T>>,
+    counts: PatternTable<u32>,
+}
+
+impl<T: Eq + Clone + Hash> SequencePatterns<T> {
+    /// Every window of `window_size` consecutive values in `input` becomes a pattern. The
+    /// input is not considered to wrap around. Panics if `input` is shorter than
+    /// `window_size`.
+    pub fn new(input: &[T], window_size: NonZeroU32) -> Self {
+        let window_size = window_size.get() as usize;
+        assert!(
+            input.len() >= window_size,
+            "input is shorter than the window size"
+        );
+        let mut ids = HashMap::new();
+        let mut patterns = Vec::new();
+        let mut counts = Vec::new();
+        for window in input.windows(window_size) {
+            let pattern_id = *ids.entry(window).or_insert_with(|| {
+                patterns.push(window.to_vec());
+                counts.push(0);
+                (patterns.len() - 1) as PatternId
+            });
+            counts[pattern_id as usize] += 1;
+        }
+        Self {
+            window_size,
+            patterns: PatternTable::from_vec(patterns),
+            counts: PatternTable::from_vec(counts),
+        }
+    }
+
+    pub fn num_patterns(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn pattern(&self, pattern_id: PatternId) -> &[T] {
+        &self.patterns[pattern_id]
+    }
+
+    pub fn pattern_descriptions(&self) -> PatternTable<PatternDescription> {
+        self.patterns
+            .enumerate()
+            .map(|(pattern_id, pattern)| {
+                let east = self
+                    .patterns
+                    .enumerate()
+                    .filter(|(_, other)| pattern[1..] == other[..self.window_size - 1])
+                    .map(|(other_id, _)| other_id)
+                    .collect();
+                let west = self
+                    .patterns
+                    .enumerate()
+                    .filter(|(_, other)| other[1..] == pattern[..self.window_size - 1])
+                    .map(|(other_id, _)| other_id)
+                    .collect();
+                // The output is a single row, so there are never neighbours to the north or
+                // south, but each pattern must be allowed there for it to be placed at all.
+                let allowed_neighbours = CardinalDirectionTable::new_array([
+                    vec![pattern_id],
+                    east,
+                    vec![pattern_id],
+                    west,
+                ]);
+                PatternDescription::new(
+                    NonZeroU32::new(self.counts[pattern_id]),
+                    allowed_neighbours,
+                )
+            })
+            .collect()
+    }
+
+    pub fn global_stats(&self) -> GlobalStats {
+        GlobalStats::new(self.pattern_descriptions())
+    }
+
+    /// Reads the sequence out of a fully-collapsed single-row wave. The sequence is the first
+    /// value of each cell's pattern followed by the rest of the last cell's pattern, so it is
+    /// `window_size - 1` longer than the wave. Panics if a cell is not collapsed.
+    pub fn sequence_from_wave(&self, wave: &Wave) -> Vec<T> {
+        let width = wave.grid().size().width() as i32;
+        let mut sequence = Vec::new();
+        for x in 0..width {
+            let pattern_id = wave
+                .grid()
+                .get_checked(Coord::new(x, 0))
+                .chosen_pattern_id()
+                .expect("wave is not fully collapsed");
+            let pattern = self.pattern(pattern_id);
+            if x == width - 1 {
+                sequence.extend_from_slice(pattern);
+            } else {
+                sequence.push(pattern[0].clone());
+            }
+        }
+        sequence
+    }
+
+    /// Generate a sequence of `output_len` values, retrying on contradiction according to
+    /// `retry`
+    pub fn collapse_retrying<RT, R>(
+        &self,
+        output_len: usize,
+        mut retry: RT,
+        rng: &mut R,
+    ) -> RT::Output
+    where
+        RT: RetryOutput<Vec<T>>,
+        R: Rng,
+    {
+        let global_stats = self.global_stats();
+        let wave_len = (output_len + 1).saturating_sub(self.window_size).max(1);
+        let output_size = Size::new(wave_len as u32, 1);
+        let run = RunOwn::new_wrap(output_size, &global_stats, WrapNone, rng);
+        RT::map_output(retry.retry(run, rng), |wave| {
+            let mut sequence = self.sequence_from_wave(&wave);
+            sequence.truncate(output_len);
+            sequence
+        })
+    }
+}
+
+impl<T: Eq + Clone + Hash> CollapseFrontend<Vec<T>> for SequencePatterns<T> {
+    fn output_from_wave(&self, wave: &Wave) -> Vec<T> {
+        self.sequence_from_wave(wave)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::retry::NumTimes;
+    use rand::SeedableRng;
+
+    #[test]
+    fn sequence() {
+        let input = "abcabdabcabd".chars().collect::<Vec<_>>();
+        let sequence_patterns =
+            SequencePatterns::new(&input, NonZeroU32::new(3).unwrap());
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let output = sequence_patterns
+            .collapse_retrying(20, NumTimes(10), &mut rng)
+            .unwrap();
+        assert_eq!(output.len(), 20);
+        for window in output.windows(3) {
+            assert!(input.windows(3).any(|w| w == window));
+        }
+    }
+}
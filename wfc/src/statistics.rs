@@ -0,0 +1,399 @@
+//! Export of a model's pattern weights and neighbourhood statistics, so they can be analysed
+//! or modified outside of this crate (e.g. smoothing adjacency counts in a machine learning
+//! pipeline) and turned back into a `GlobalStats`. Each direction's statistics are a dense
+//! row-major matrix, which e.g. `ndarray::Array2::from_shape_vec` can use directly, and the
+//! whole model can be written to and read from a pair of CSV files.
+
+use crate::wfc::{
+    direction_name, AdjacencyMatrixError, AdjacencyWeights, GlobalStats,
+    PatternDescription, PatternId, PatternTable,
+};
+use direction::{CardinalDirection, CardinalDirectionTable, CardinalDirections};
+use std::io::{self, BufRead, Write};
+use std::num::NonZeroU32;
+
+#[derive(Debug)]
+pub enum StatisticsCsvError {
+    Io(io::Error),
+    /// Line `line` of the weights CSV, counting from 1, isn't a row for the next pattern
+    InvalidWeightsRow {
+        line: usize,
+    },
+    /// Line `line` of the adjacency CSV, counting from 1, isn't a valid row
+    InvalidAdjacencyRow {
+        line: usize,
+    },
+    /// Line `line` of the adjacency CSV refers to a pattern without a weights row
+    PatternOutOfRange {
+        line: usize,
+        pattern_id: PatternId,
+    },
+}
+
+impl From<io::Error> for StatisticsCsvError {
+    fn from(error: io::Error) -> Self {
+        StatisticsCsvError::Io(error)
+    }
+}
+
+/// The weight of each pattern, and for each direction a matrix whose entry at row `a` and
+/// column `b` is the weight of pattern `b` being next to pattern `a` in that direction. An
+/// entry of 0 means `b` isn't allowed there. Weights of 0 mean the pattern is unweighted.
+///
+/// Adjacency entries are exported as the factor each neighbour multiplies a candidate's
+/// weight by when observing a cell, so stats with adjacency frequencies (see
+/// `GlobalStats::set_adjacency_frequencies`) export one more than the number of times each
+/// adjacency was seen, stats with both adjacency weights and frequencies export the product
+/// of the two factors, and stats without either export 1 for each allowed adjacency.
+/// Importing with `global_stats` treats entries as adjacency weights, so a round trip
+/// preserves both which patterns are allowed and how they're chosen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatternStatistics {
+    weights: Vec<u32>,
+    adjacency: CardinalDirectionTable<Vec<f64>>,
+}
+
+impl PatternStatistics {
+    /// Statistics for `num_patterns` unweighted patterns, none of which may be next to each
+    /// other
+    pub fn new(num_patterns: usize) -> Self {
+        let matrix = vec![0.; num_patterns * num_patterns];
+        Self {
+            weights: vec![0; num_patterns],
+            adjacency: CardinalDirectionTable::new_array([
+                matrix.clone(),
+                matrix.clone(),
+                matrix.clone(),
+                matrix,
+            ]),
+        }
+    }
+
+    pub fn from_global_stats(global_stats: &GlobalStats) -> Self {
+        let num_patterns = global_stats.num_patterns();
+        let mut statistics = Self::new(num_patterns);
+        for pattern_id in 0..num_patterns as PatternId {
+            statistics.weights[pattern_id as usize] = global_stats
+                .pattern_stats(pattern_id)
+                .map_or(0, |pattern_weight| pattern_weight.weight());
+            for direction in CardinalDirections {
+                for &neighbour_id in
+                    global_stats.compatible_patterns_in_direction(pattern_id, direction)
+                {
+                    // when both are set, selection multiplies by both factors
+                    let weight = global_stats.adjacency_weights().map_or(1., |weights| {
+                        lookup(&weights[pattern_id][direction], neighbour_id)
+                            .unwrap_or(1.)
+                    });
+                    let frequency =
+                        global_stats
+                            .adjacency_frequencies()
+                            .map_or(1., |frequencies| {
+                                lookup(&frequencies[pattern_id][direction], neighbour_id)
+                                    .map_or(1., |count| count as f64 + 1.)
+                            });
+                    statistics.set(
+                        pattern_id,
+                        direction,
+                        neighbour_id,
+                        weight * frequency,
+                    );
+                }
+            }
+        }
+        statistics
+    }
+
+    pub fn num_patterns(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn weights(&self) -> &[u32] {
+        &self.weights
+    }
+
+    pub fn weights_mut(&mut self) -> &mut [u32] {
+        &mut self.weights
+    }
+
+    /// The `num_patterns` by `num_patterns` matrix of adjacency weights in `direction`, in
+    /// row-major order
+    pub fn matrix(&self, direction: CardinalDirection) -> &[f64] {
+        &self.adjacency[direction]
+    }
+
+    /// Like `matrix`, but mutable. Changes to a direction must be mirrored in the opposite
+    /// direction (with rows and columns swapped) where they affect which patterns are
+    /// allowed, or `global_stats` will reject the rules as asymmetric.
+    pub fn matrix_mut(&mut self, direction: CardinalDirection) -> &mut [f64] {
+        &mut self.adjacency[direction]
+    }
+
+    pub fn get(
+        &self,
+        pattern_id: PatternId,
+        direction: CardinalDirection,
+        neighbour_id: PatternId,
+    ) -> f64 {
+        self.adjacency[direction][self.index(pattern_id, neighbour_id)]
+    }
+
+    pub fn set(
+        &mut self,
+        pattern_id: PatternId,
+        direction: CardinalDirection,
+        neighbour_id: PatternId,
+        value: f64,
+    ) {
+        let index = self.index(pattern_id, neighbour_id);
+        self.adjacency[direction][index] = value;
+    }
+
+    fn index(&self, pattern_id: PatternId, neighbour_id: PatternId) -> usize {
+        pattern_id as usize * self.num_patterns() + neighbour_id as usize
+    }
+
+    fn allowed(&self, pattern_id: PatternId) -> [Vec<(PatternId, f64)>; 4] {
+        let num_patterns = self.num_patterns();
+        let row = pattern_id as usize * num_patterns;
+        let mut allowed: [Vec<(PatternId, f64)>; 4] = Default::default();
+        for (direction, allowed) in CardinalDirections.into_iter().zip(allowed.iter_mut())
+        {
+            for (neighbour_id, &value) in self.adjacency[direction]
+                [row..row + num_patterns]
+                .iter()
+                .enumerate()
+            {
+                if value > 0. {
+                    allowed.push((neighbour_id as PatternId, value));
+                }
+            }
+        }
+        allowed
+    }
+
+    /// Pattern descriptions allowing the adjacencies with positive entries, for
+    /// `GlobalStats::new`. These don't include the adjacency weights themselves, which are
+    /// given by `adjacency_weights`.
+    pub fn pattern_descriptions(&self) -> PatternTable<PatternDescription> {
+        (0..self.num_patterns() as PatternId)
+            .map(|pattern_id| {
+                PatternDescription::new(
+                    NonZeroU32::new(self.weights[pattern_id as usize]),
+                    CardinalDirectionTable::new_array(
+                        self.allowed(pattern_id).map(|allowed| {
+                            allowed.into_iter().map(|(id, _)| id).collect()
+                        }),
+                    ),
+                )
+            })
+            .collect()
+    }
+
+    /// The positive entries of each matrix, for `GlobalStats::set_adjacency_weights`
+    pub fn adjacency_weights(&self) -> AdjacencyWeights {
+        (0..self.num_patterns() as PatternId)
+            .map(|pattern_id| CardinalDirectionTable::new_array(self.allowed(pattern_id)))
+            .collect()
+    }
+
+    /// Builds a `GlobalStats` allowing the adjacencies with positive entries, using the
+    /// entries as adjacency weights. Fails if the allowed adjacencies aren't symmetric, or if
+    /// an entry isn't finite.
+    pub fn global_stats(&self) -> Result<GlobalStats, AdjacencyMatrixError> {
+        GlobalStats::from_weighted_adjacency_matrix(
+            self.weights
+                .iter()
+                .map(|&weight| NonZeroU32::new(weight))
+                .collect(),
+            (0..self.num_patterns() as PatternId)
+                .map(|pattern_id| self.allowed(pattern_id))
+                .collect(),
+        )
+    }
+
+    /// Writes the weight of each pattern as CSV with the columns `pattern_id` and `weight`
+    pub fn write_weights_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "pattern_id,weight")?;
+        for (pattern_id, weight) in self.weights.iter().enumerate() {
+            writeln!(writer, "{},{}", pattern_id, weight)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the non-zero adjacency entries as CSV with the columns `direction` (one of
+    /// `north`, `east`, `south` or `west`), `pattern_id`, `neighbour_id` and `weight`
+    pub fn write_adjacency_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "direction,pattern_id,neighbour_id,weight")?;
+        for direction in CardinalDirections {
+            for pattern_id in 0..self.num_patterns() as PatternId {
+                for neighbour_id in 0..self.num_patterns() as PatternId {
+                    let value = self.get(pattern_id, direction, neighbour_id);
+                    if value != 0. {
+                        writeln!(
+                            writer,
+                            "{},{},{},{}",
+                            direction_name(direction),
+                            pattern_id,
+                            neighbour_id,
+                            value
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads statistics in the format written by `write_weights_csv` and
+    /// `write_adjacency_csv`. The weights rows must list the patterns in order of id, while
+    /// adjacency rows may be in any order, with entries which aren't listed being 0.
+    pub fn read_csv<R: BufRead, S: BufRead>(
+        weights: R,
+        adjacency: S,
+    ) -> Result<Self, StatisticsCsvError> {
+        let mut pattern_weights = Vec::new();
+        for (index, line) in weights.lines().enumerate().skip(1) {
+            let line = line?;
+            let invalid = StatisticsCsvError::InvalidWeightsRow { line: index + 1 };
+            match line.trim().split(',').collect::<Vec<_>>().as_slice() {
+                [pattern_id, weight] => {
+                    if pattern_id.trim().parse() != Ok(pattern_weights.len()) {
+                        return Err(invalid);
+                    }
+                    pattern_weights.push(weight.trim().parse().map_err(|_| invalid)?);
+                }
+                [""] => (),
+                _ => return Err(invalid),
+            }
+        }
+        let mut statistics = Self::new(pattern_weights.len());
+        statistics.weights = pattern_weights;
+        for (index, line) in adjacency.lines().enumerate().skip(1) {
+            let line = line?;
+            let invalid = || StatisticsCsvError::InvalidAdjacencyRow { line: index + 1 };
+            match line.trim().split(',').collect::<Vec<_>>().as_slice() {
+                [direction, pattern_id, neighbour_id, value] => {
+                    let direction = CardinalDirections
+                        .into_iter()
+                        .find(|&d| direction_name(d) == direction.trim())
+                        .ok_or_else(invalid)?;
+                    let value = value.trim().parse().map_err(|_| invalid())?;
+                    let parse_pattern_id = |pattern_id: &str| {
+                        let pattern_id: PatternId =
+                            pattern_id.trim().parse().map_err(|_| invalid())?;
+                        if pattern_id as usize >= statistics.num_patterns() {
+                            return Err(StatisticsCsvError::PatternOutOfRange {
+                                line: index + 1,
+                                pattern_id,
+                            });
+                        }
+                        Ok(pattern_id)
+                    };
+                    let pattern_id = parse_pattern_id(pattern_id)?;
+                    let neighbour_id = parse_pattern_id(neighbour_id)?;
+                    statistics.set(pattern_id, direction, neighbour_id, value);
+                }
+                [""] => (),
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(statistics)
+    }
+}
+
+fn lookup<T: Copy>(entries: &[(PatternId, T)], pattern_id: PatternId) -> Option<T> {
+    entries
+        .iter()
+        .find(|&&(id, _)| id == pattern_id)
+        .map(|&(_, value)| value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let checkerboard =
+            |other: PatternId| [vec![other], vec![other], vec![other], vec![other]];
+        let mut global_stats = GlobalStats::from_adjacency_matrix(
+            vec![NonZeroU32::new(3), NonZeroU32::new(1)],
+            vec![checkerboard(1), checkerboard(0)],
+        )
+        .unwrap();
+        global_stats
+            .set_adjacency_frequencies(
+                [0, 1]
+                    .into_iter()
+                    .map(|pattern_id: PatternId| {
+                        let neighbours = vec![(1 - pattern_id, 4)];
+                        CardinalDirectionTable::new_array([
+                            neighbours.clone(),
+                            neighbours.clone(),
+                            neighbours.clone(),
+                            neighbours,
+                        ])
+                    })
+                    .collect(),
+            )
+            .unwrap();
+        let statistics = PatternStatistics::from_global_stats(&global_stats);
+        assert_eq!(statistics.weights(), &[3, 1]);
+        assert_eq!(
+            statistics.matrix(CardinalDirection::East),
+            &[0., 5., 5., 0.]
+        );
+        // adjacency weights multiply the frequencies rather than replacing them
+        let mut weighted = global_stats.clone();
+        weighted
+            .set_adjacency_weights(
+                [0, 1]
+                    .into_iter()
+                    .map(|pattern_id: PatternId| {
+                        let neighbours = vec![(1 - pattern_id, 2.)];
+                        CardinalDirectionTable::new_array([
+                            neighbours.clone(),
+                            neighbours.clone(),
+                            neighbours.clone(),
+                            neighbours,
+                        ])
+                    })
+                    .collect(),
+            )
+            .unwrap();
+        assert_eq!(
+            PatternStatistics::from_global_stats(&weighted)
+                .matrix(CardinalDirection::East),
+            &[0., 10., 10., 0.]
+        );
+        let mut weights_csv = Vec::new();
+        let mut adjacency_csv = Vec::new();
+        statistics.write_weights_csv(&mut weights_csv).unwrap();
+        statistics.write_adjacency_csv(&mut adjacency_csv).unwrap();
+        let read =
+            PatternStatistics::read_csv(weights_csv.as_slice(), adjacency_csv.as_slice())
+                .unwrap();
+        assert_eq!(read, statistics);
+        let imported = read.global_stats().unwrap();
+        assert_eq!(PatternStatistics::from_global_stats(&imported), statistics);
+        // allowing a pattern next to itself in only one direction is asymmetric
+        let mut asymmetric = statistics.clone();
+        asymmetric.set(0, CardinalDirection::East, 0, 1.);
+        assert!(matches!(
+            asymmetric.global_stats(),
+            Err(AdjacencyMatrixError::Asymmetric { .. })
+        ));
+        let result = PatternStatistics::read_csv(
+            weights_csv.as_slice(),
+            "direction,pattern_id,neighbour_id,weight\nnorth,0,2,1\n".as_bytes(),
+        );
+        assert!(matches!(
+            result,
+            Err(StatisticsCsvError::PatternOutOfRange {
+                line: 2,
+                pattern_id: 2
+            })
+        ));
+    }
+}
@@ -0,0 +1,116 @@
+//! Abstraction over the 2D storage of a wave's cells, so a wave can live in a caller's own
+//! grid type (e.g. chunked arrays or ECS-backed storage) rather than a `grid_2d::Grid`.
+
+use coord_2d::{Coord, CoordIterRowMajor, Size};
+use grid_2d::Grid;
+
+/// A rectangle of `T` indexable by coordinate. Every coordinate within `size` must refer to
+/// a cell for as long as the storage is in use.
+pub trait CellStorage<T> {
+    fn size(&self) -> Size;
+    fn get(&self, coord: Coord) -> Option<&T>;
+    fn get_mut(&mut self, coord: Coord) -> Option<&mut T>;
+
+    fn get_checked(&self, coord: Coord) -> &T {
+        self.get(coord).expect("coord out of bounds")
+    }
+
+    fn get_checked_mut(&mut self, coord: Coord) -> &mut T {
+        self.get_mut(coord).expect("coord out of bounds")
+    }
+
+    /// Every coordinate of the storage in row-major order
+    fn coord_iter(&self) -> CoordIterRowMajor {
+        self.size().coord_iter_row_major()
+    }
+}
+
+impl<T> CellStorage<T> for Grid<T> {
+    fn size(&self) -> Size {
+        Grid::size(self)
+    }
+
+    fn get(&self, coord: Coord) -> Option<&T> {
+        Grid::get(self, coord)
+    }
+
+    fn get_mut(&mut self, coord: Coord) -> Option<&mut T> {
+        Grid::get_mut(self, coord)
+    }
+
+    fn get_checked(&self, coord: Coord) -> &T {
+        Grid::get_checked(self, coord)
+    }
+
+    fn get_checked_mut(&mut self, coord: Coord) -> &mut T {
+        Grid::get_checked_mut(self, coord)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::all_compatible_stats;
+    use crate::{Context, RunBorrow, Wave, WaveCell};
+
+    // cells stored column by column
+    struct ColumnMajor {
+        size: Size,
+        cells: Vec<WaveCell>,
+    }
+
+    impl ColumnMajor {
+        fn index(&self, coord: Coord) -> Option<usize> {
+            if coord.is_valid(self.size) {
+                Some(coord.x as usize * self.size.height() as usize + coord.y as usize)
+            } else {
+                None
+            }
+        }
+    }
+
+    impl CellStorage<WaveCell> for ColumnMajor {
+        fn size(&self) -> Size {
+            self.size
+        }
+        fn get(&self, coord: Coord) -> Option<&WaveCell> {
+            self.index(coord).map(|index| &self.cells[index])
+        }
+        fn get_mut(&mut self, coord: Coord) -> Option<&mut WaveCell> {
+            self.index(coord).map(move |index| &mut self.cells[index])
+        }
+    }
+
+    #[test]
+    fn custom_storage() {
+        use rand::SeedableRng;
+        let global_stats = all_compatible_stats(&[1, 2, 3]);
+        let size = Size::new(5, 3);
+        let mut context = Context::new();
+        let mut grid_wave = Wave::new(size);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        RunBorrow::new(&mut context, &mut grid_wave, &global_stats, &mut rng)
+            .collapse(&mut rng)
+            .unwrap();
+        let mut wave = Wave::from_storage(ColumnMajor {
+            size,
+            cells: (0..size.count()).map(|_| WaveCell::default()).collect(),
+        });
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        RunBorrow::new_wrap_forbid(
+            &mut context,
+            &mut wave,
+            &global_stats,
+            crate::wrap::WrapXY,
+            crate::ForbidNothing,
+            &mut rng,
+        )
+        .collapse(&mut rng)
+        .unwrap();
+        assert_eq!(
+            wave.to_debug_string(&global_stats),
+            grid_wave.to_debug_string(&global_stats)
+        );
+        assert_eq!(wave.storage().size(), size);
+    }
+}
@@ -0,0 +1,53 @@
+//! Adjacency rules shared by the tests of several modules
+
+use crate::wfc::{GlobalStats, PatternId};
+use std::num::NonZeroU32;
+
+/// Two equally weighted patterns, each of which may only be next to the other
+pub(crate) fn checkerboard_stats() -> GlobalStats {
+    let weight = NonZeroU32::new(1);
+    let checkerboard = |other| [vec![other], vec![other], vec![other], vec![other]];
+    GlobalStats::from_adjacency_matrix(
+        vec![weight, weight],
+        vec![checkerboard(1), checkerboard(0)],
+    )
+    .unwrap()
+}
+
+/// Three equally weighted patterns, none of which may be next to itself, i.e. colouring the
+/// cells with 3 colours such that no two neighbours are the same colour
+pub(crate) fn three_colour_stats() -> GlobalStats {
+    let weight = NonZeroU32::new(1);
+    let others = |pattern_id: PatternId| {
+        let others = (0..3).filter(|&id| id != pattern_id).collect::<Vec<_>>();
+        [others.clone(), others.clone(), others.clone(), others]
+    };
+    GlobalStats::from_adjacency_matrix(
+        vec![weight, weight, weight],
+        vec![others(0), others(1), others(2)],
+    )
+    .unwrap()
+}
+
+/// The adjacency rules of a pattern which may be next to any of `num_patterns` patterns in
+/// every direction
+pub(crate) fn all_compatible(num_patterns: PatternId) -> [Vec<PatternId>; 4] {
+    let all = (0..num_patterns).collect::<Vec<_>>();
+    [all.clone(), all.clone(), all.clone(), all]
+}
+
+/// Patterns with the given weights (0 meaning unweighted), any of which may be next to any
+/// other
+pub(crate) fn all_compatible_stats(weights: &[u32]) -> GlobalStats {
+    let num_patterns = weights.len() as PatternId;
+    GlobalStats::from_adjacency_matrix(
+        weights
+            .iter()
+            .map(|&weight| NonZeroU32::new(weight))
+            .collect(),
+        (0..num_patterns)
+            .map(|_| all_compatible(num_patterns))
+            .collect(),
+    )
+    .unwrap()
+}
@@ -0,0 +1,506 @@
+//! Interop with the Tiled map editor (https://www.mapeditor.org). Example maps are read from
+//! `.tmx` files, whose tile layers provide the tiles, weights and adjacencies of an
+//! `AdjacencyLearner`, and collapsed waves can be written back out as `.tmx` files which use
+//! the same tilesets. External tilesets (`.tsx` files) can be read with `Tileset::parse`.
+//! Only orthogonal maps with CSV-encoded layer data are supported.
+
+use crate::adjacency::AdjacencyLearner;
+use crate::wfc::Wave;
+use coord_2d::Size;
+use grid_2d::Grid;
+use std::io::{Read, Write};
+use std::str::FromStr;
+use xml::attribute::OwnedAttribute;
+use xml::reader::{EventReader, XmlEvent as ReadEvent};
+use xml::writer::{EmitterConfig, EventWriter, XmlEvent as WriteEvent};
+
+/// A tile id as it appears in layer data. 0 means there is no tile, and the top bits encode
+/// flips and rotations, so a flipped tile is distinct from the unflipped one.
+pub type Gid = u32;
+
+/// The `Gid` of cells with no tile
+pub const EMPTY_GID: Gid = 0;
+
+#[derive(Debug)]
+pub enum TiledError {
+    Xml(xml::reader::Error),
+    MissingElement(&'static str),
+    MissingAttribute {
+        element: &'static str,
+        attribute: &'static str,
+    },
+    InvalidNumber {
+        element: &'static str,
+        value: String,
+    },
+    /// Layer data uses an encoding other than CSV
+    UnsupportedEncoding(Option<String>),
+    /// A layer's data doesn't have one tile per cell
+    WrongNumberOfTiles {
+        layer: String,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl From<xml::reader::Error> for TiledError {
+    fn from(error: xml::reader::Error) -> Self {
+        TiledError::Xml(error)
+    }
+}
+
+fn attribute<'a>(
+    attributes: &'a [OwnedAttribute],
+    element: &'static str,
+    name: &'static str,
+) -> Result<&'a str, TiledError> {
+    attributes
+        .iter()
+        .find(|attribute| attribute.name.local_name == name)
+        .map(|attribute| attribute.value.as_str())
+        .ok_or(TiledError::MissingAttribute {
+            element,
+            attribute: name,
+        })
+}
+
+fn parse_number<T: FromStr>(element: &'static str, value: &str) -> Result<T, TiledError> {
+    value.trim().parse().map_err(|_| TiledError::InvalidNumber {
+        element,
+        value: value.to_string(),
+    })
+}
+
+fn number_attribute<T: FromStr>(
+    attributes: &[OwnedAttribute],
+    element: &'static str,
+    name: &'static str,
+) -> Result<T, TiledError> {
+    parse_number(element, attribute(attributes, element, name)?)
+}
+
+/// A tileset, as described by a `.tsx` file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tileset {
+    pub name: String,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub tile_count: u32,
+    pub columns: u32,
+    /// The path of the tileset's image, relative to the `.tsx` file
+    pub image: Option<String>,
+}
+
+impl Tileset {
+    pub fn parse<R: Read>(reader: R) -> Result<Self, TiledError> {
+        let mut tileset = None;
+        for event in EventReader::new(reader) {
+            if let ReadEvent::StartElement {
+                name, attributes, ..
+            } = event?
+            {
+                match name.local_name.as_str() {
+                    "tileset" => {
+                        tileset = Some(Tileset::from_attributes(&attributes)?);
+                    }
+                    "image" => {
+                        if let Some(tileset) = tileset.as_mut() {
+                            tileset.image = Some(
+                                attribute(&attributes, "image", "source")?.to_string(),
+                            );
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        tileset.ok_or(TiledError::MissingElement("tileset"))
+    }
+
+    fn from_attributes(attributes: &[OwnedAttribute]) -> Result<Self, TiledError> {
+        Ok(Tileset {
+            name: attribute(attributes, "tileset", "name")?.to_string(),
+            tile_width: number_attribute(attributes, "tileset", "tilewidth")?,
+            tile_height: number_attribute(attributes, "tileset", "tileheight")?,
+            tile_count: number_attribute(attributes, "tileset", "tilecount")?,
+            columns: number_attribute(attributes, "tileset", "columns")?,
+            image: None,
+        })
+    }
+
+    fn write<W: Write>(
+        &self,
+        writer: &mut EventWriter<W>,
+        first_gid: Gid,
+    ) -> Result<(), xml::writer::Error> {
+        let first_gid = first_gid.to_string();
+        let tile_width = self.tile_width.to_string();
+        let tile_height = self.tile_height.to_string();
+        let tile_count = self.tile_count.to_string();
+        let columns = self.columns.to_string();
+        writer.write(
+            WriteEvent::start_element("tileset")
+                .attr("firstgid", &first_gid)
+                .attr("name", &self.name)
+                .attr("tilewidth", &tile_width)
+                .attr("tileheight", &tile_height)
+                .attr("tilecount", &tile_count)
+                .attr("columns", &columns),
+        )?;
+        if let Some(image) = self.image.as_ref() {
+            writer.write(WriteEvent::start_element("image").attr("source", image))?;
+            writer.write(WriteEvent::end_element())?;
+        }
+        writer.write(WriteEvent::end_element())
+    }
+}
+
+/// A tileset used by a map, which is either external (`source`) or embedded in the map
+/// (`tileset`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TilesetRef {
+    pub first_gid: Gid,
+    /// The path of the `.tsx` file, relative to the map, for external tilesets
+    pub source: Option<String>,
+    /// The tileset itself, for tilesets embedded in the map
+    pub tileset: Option<Tileset>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TileLayer {
+    pub name: String,
+    pub tiles: Grid<Gid>,
+}
+
+/// An orthogonal map, as described by a `.tmx` file. Only tile layers are kept.
+#[derive(Debug, Clone)]
+pub struct TiledMap {
+    pub size: Size,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub tilesets: Vec<TilesetRef>,
+    pub layers: Vec<TileLayer>,
+}
+
+impl TiledMap {
+    pub fn parse<R: Read>(reader: R) -> Result<Self, TiledError> {
+        let mut map = None;
+        let mut in_tileset = false;
+        let mut layer_name = None;
+        let mut data = None;
+        for event in EventReader::new(reader) {
+            match event? {
+                ReadEvent::StartElement {
+                    name, attributes, ..
+                } => match name.local_name.as_str() {
+                    "map" => {
+                        map = Some(TiledMap {
+                            size: Size::new(
+                                number_attribute(&attributes, "map", "width")?,
+                                number_attribute(&attributes, "map", "height")?,
+                            ),
+                            tile_width: number_attribute(
+                                &attributes,
+                                "map",
+                                "tilewidth",
+                            )?,
+                            tile_height: number_attribute(
+                                &attributes,
+                                "map",
+                                "tileheight",
+                            )?,
+                            tilesets: Vec::new(),
+                            layers: Vec::new(),
+                        });
+                    }
+                    "tileset" => {
+                        let map =
+                            map.as_mut().ok_or(TiledError::MissingElement("map"))?;
+                        let source = attribute(&attributes, "tileset", "source")
+                            .ok()
+                            .map(str::to_string);
+                        let tileset = match source {
+                            Some(_) => None,
+                            None => Some(Tileset::from_attributes(&attributes)?),
+                        };
+                        map.tilesets.push(TilesetRef {
+                            first_gid: number_attribute(
+                                &attributes,
+                                "tileset",
+                                "firstgid",
+                            )?,
+                            source,
+                            tileset,
+                        });
+                        in_tileset = true;
+                    }
+                    "image" if in_tileset => {
+                        let tileset = map
+                            .as_mut()
+                            .and_then(|map| map.tilesets.last_mut())
+                            .and_then(|tileset_ref| tileset_ref.tileset.as_mut());
+                        if let Some(tileset) = tileset {
+                            tileset.image = Some(
+                                attribute(&attributes, "image", "source")?.to_string(),
+                            );
+                        }
+                    }
+                    "layer" => {
+                        layer_name =
+                            Some(attribute(&attributes, "layer", "name")?.to_string());
+                    }
+                    "data" if layer_name.is_some() => {
+                        let encoding = attribute(&attributes, "data", "encoding").ok();
+                        if encoding != Some("csv") {
+                            return Err(TiledError::UnsupportedEncoding(
+                                encoding.map(str::to_string),
+                            ));
+                        }
+                        data = Some(String::new());
+                    }
+                    _ => (),
+                },
+                ReadEvent::Characters(text) => {
+                    if let Some(data) = data.as_mut() {
+                        data.push_str(&text);
+                    }
+                }
+                ReadEvent::EndElement { name } if name.local_name == "tileset" => {
+                    in_tileset = false;
+                }
+                ReadEvent::EndElement { name } if name.local_name == "layer" => {
+                    let map = map.as_mut().ok_or(TiledError::MissingElement("map"))?;
+                    let name = layer_name.take().unwrap_or_default();
+                    let data = data.take().ok_or(TiledError::MissingElement("data"))?;
+                    let gids = data
+                        .split(',')
+                        .filter(|value| !value.trim().is_empty())
+                        .map(|value| parse_number("data", value))
+                        .collect::<Result<Vec<Gid>, _>>()?;
+                    if gids.len() != map.size.count() {
+                        return Err(TiledError::WrongNumberOfTiles {
+                            layer: name,
+                            expected: map.size.count(),
+                            actual: gids.len(),
+                        });
+                    }
+                    let mut gids = gids.into_iter();
+                    let tiles = Grid::new_fn(map.size, |_| gids.next().unwrap());
+                    map.layers.push(TileLayer { name, tiles });
+                }
+                _ => (),
+            }
+        }
+        map.ok_or(TiledError::MissingElement("map"))
+    }
+
+    /// An `AdjacencyLearner` with the tiles of every layer added as examples. Empty cells
+    /// (`EMPTY_GID`) are skipped, so they are never generated.
+    pub fn learner(&self) -> AdjacencyLearner<Gid> {
+        let mut learner = AdjacencyLearner::new();
+        for layer in self.layers.iter() {
+            learner.add_example_with_empty(&layer.tiles, &EMPTY_GID);
+        }
+        learner
+    }
+
+    /// A map with the same tile size and tilesets as `self` whose only layer, named
+    /// `layer_name`, contains the tiles chosen for each cell of `wave`. Panics if a cell is
+    /// not collapsed.
+    pub fn with_wave(
+        &self,
+        learner: &AdjacencyLearner<Gid>,
+        wave: &Wave,
+        layer_name: &str,
+    ) -> Self {
+        let tiles = learner.grid_from_wave(wave);
+        Self {
+            size: tiles.size(),
+            tile_width: self.tile_width,
+            tile_height: self.tile_height,
+            tilesets: self.tilesets.clone(),
+            layers: vec![TileLayer {
+                name: layer_name.to_string(),
+                tiles,
+            }],
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), xml::writer::Error> {
+        let mut writer = EmitterConfig::new()
+            .perform_indent(true)
+            .create_writer(writer);
+        let width = self.size.width().to_string();
+        let height = self.size.height().to_string();
+        let tile_width = self.tile_width.to_string();
+        let tile_height = self.tile_height.to_string();
+        let next_layer_id = (self.layers.len() + 1).to_string();
+        writer.write(
+            WriteEvent::start_element("map")
+                .attr("version", "1.10")
+                .attr("orientation", "orthogonal")
+                .attr("renderorder", "right-down")
+                .attr("width", &width)
+                .attr("height", &height)
+                .attr("tilewidth", &tile_width)
+                .attr("tileheight", &tile_height)
+                .attr("infinite", "0")
+                .attr("nextlayerid", &next_layer_id)
+                .attr("nextobjectid", "1"),
+        )?;
+        for tileset_ref in self.tilesets.iter() {
+            if let Some(source) = tileset_ref.source.as_ref() {
+                let first_gid = tileset_ref.first_gid.to_string();
+                writer.write(
+                    WriteEvent::start_element("tileset")
+                        .attr("firstgid", &first_gid)
+                        .attr("source", source),
+                )?;
+                writer.write(WriteEvent::end_element())?;
+            } else if let Some(tileset) = tileset_ref.tileset.as_ref() {
+                tileset.write(&mut writer, tileset_ref.first_gid)?;
+            }
+        }
+        for (index, layer) in self.layers.iter().enumerate() {
+            let id = (index + 1).to_string();
+            writer.write(
+                WriteEvent::start_element("layer")
+                    .attr("id", &id)
+                    .attr("name", &layer.name)
+                    .attr("width", &width)
+                    .attr("height", &height),
+            )?;
+            writer.write(WriteEvent::start_element("data").attr("encoding", "csv"))?;
+            let rows = layer
+                .tiles
+                .rows()
+                .map(|row| {
+                    row.iter()
+                        .map(|gid| gid.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",")
+                })
+                .collect::<Vec<_>>();
+            writer.write(WriteEvent::characters(&format!("\n{}\n", rows.join(",\n"))))?;
+            writer.write(WriteEvent::end_element())?;
+            writer.write(WriteEvent::end_element())?;
+        }
+        writer.write(WriteEvent::end_element())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wfc::RunOwn;
+    use rand::SeedableRng;
+
+    const EXAMPLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" renderorder="right-down" width="4" height="2" tilewidth="16" tileheight="16" infinite="0" nextlayerid="2" nextobjectid="1">
+ <tileset firstgid="1" source="terrain.tsx"/>
+ <layer id="1" name="ground" width="4" height="2">
+  <data encoding="csv">
+1,2,1,2,
+2,1,2,1
+</data>
+ </layer>
+</map>
+"#;
+
+    #[test]
+    fn tiled_round_trip() {
+        let map = TiledMap::parse(EXAMPLE.as_bytes()).unwrap();
+        assert_eq!(map.size, Size::new(4, 2));
+        assert_eq!(
+            map.tilesets,
+            vec![TilesetRef {
+                first_gid: 1,
+                source: Some("terrain.tsx".to_string()),
+                tileset: None,
+            }]
+        );
+        let learner = map.learner();
+        assert_eq!(learner.num_patterns(), 2);
+        let global_stats = learner.global_stats();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(Size::new(6, 6), &global_stats, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let output = map.with_wave(&learner, &run.into_wave(), "generated");
+        let mut tmx = Vec::new();
+        output.write(&mut tmx).unwrap();
+        let parsed = TiledMap::parse(tmx.as_slice()).unwrap();
+        assert_eq!(parsed.size, Size::new(6, 6));
+        assert_eq!(parsed.tilesets, map.tilesets);
+        assert_eq!(parsed.layers[0].name, "generated");
+        assert_eq!(parsed.layers[0].tiles, output.layers[0].tiles);
+        // the example is a checkerboard, so the output is too
+        let tiles = &parsed.layers[0].tiles;
+        for (coord, &gid) in tiles.enumerate() {
+            let east = *tiles.get_tiled(coord + coord_2d::Coord::new(1, 0));
+            assert_ne!(gid, east);
+        }
+    }
+
+    #[test]
+    fn embedded_tileset_and_empty_cells() {
+        let tmx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" renderorder="right-down" width="3" height="2" tilewidth="16" tileheight="16" infinite="0" nextlayerid="2" nextobjectid="1">
+ <tileset firstgid="1" name="terrain" tilewidth="16" tileheight="16" tilecount="4" columns="2">
+  <image source="terrain.png" width="32" height="32"/>
+ </tileset>
+ <layer id="1" name="ground" width="3" height="2">
+  <data encoding="csv">
+1,2,0,
+2,1,0
+</data>
+ </layer>
+</map>
+"#;
+        let map = TiledMap::parse(tmx.as_bytes()).unwrap();
+        let tileset = Tileset {
+            name: "terrain".to_string(),
+            tile_width: 16,
+            tile_height: 16,
+            tile_count: 4,
+            columns: 2,
+            image: Some("terrain.png".to_string()),
+        };
+        assert_eq!(
+            map.tilesets,
+            vec![TilesetRef {
+                first_gid: 1,
+                source: None,
+                tileset: Some(tileset),
+            }]
+        );
+        let learner = map.learner();
+        assert_eq!(learner.num_patterns(), 2);
+        assert_eq!(learner.pattern_id(&EMPTY_GID), None);
+        let mut tmx = Vec::new();
+        map.write(&mut tmx).unwrap();
+        let parsed = TiledMap::parse(tmx.as_slice()).unwrap();
+        assert_eq!(parsed.tilesets, map.tilesets);
+        assert_eq!(parsed.layers[0].tiles, map.layers[0].tiles);
+    }
+
+    #[test]
+    fn tileset() {
+        let tsx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.10" name="terrain" tilewidth="16" tileheight="16" tilecount="4" columns="2">
+ <image source="terrain.png" width="32" height="32"/>
+</tileset>
+"#;
+        let tileset = Tileset::parse(tsx.as_bytes()).unwrap();
+        assert_eq!(
+            tileset,
+            Tileset {
+                name: "terrain".to_string(),
+                tile_width: 16,
+                tile_height: 16,
+                tile_count: 4,
+                columns: 2,
+                image: Some("terrain.png".to_string()),
+            }
+        );
+    }
+}
@@ -9,6 +9,7 @@ pub struct TiledGridSlice<'a, T: 'a> {
     offset: Coord,
     size: Size,
     orientation: Orientation,
+    clamp: bool,
 }
 
 pub struct TiledGridSliceIter<'a, T: 'a> {
@@ -37,6 +38,20 @@ impl<'a, T> TiledGridSlice<'a, T> {
             offset,
             size,
             orientation,
+            clamp: false,
+        }
+    }
+    /// Like `new`, but coordinates beyond the edge of the grid take the value of the nearest
+    /// cell on the edge, rather than wrapping around to the opposite edge
+    pub fn new_clamped(
+        grid: &'a Grid<T>,
+        offset: Coord,
+        size: Size,
+        orientation: Orientation,
+    ) -> Self {
+        TiledGridSlice {
+            clamp: true,
+            ..Self::new(grid, offset, size, orientation)
         }
     }
     pub fn size(&self) -> Size {
@@ -44,7 +59,17 @@ impl<'a, T> TiledGridSlice<'a, T> {
     }
     fn get_valid(&self, coord: Coord) -> &'a T {
         let transformed_coord = self.orientation.transform_coord(self.size, coord);
-        self.grid.get_tiled(self.offset + transformed_coord)
+        let coord = self.offset + transformed_coord;
+        if self.clamp {
+            let grid_size = self.grid.size();
+            let clamped = Coord::new(
+                coord.x.clamp(0, grid_size.x() as i32 - 1),
+                coord.y.clamp(0, grid_size.y() as i32 - 1),
+            );
+            self.grid.get_checked(clamped)
+        } else {
+            self.grid.get_tiled(coord)
+        }
     }
     pub fn get_checked(&self, coord: Coord) -> &'a T {
         if coord.is_valid(self.size) {
@@ -56,6 +81,14 @@ impl<'a, T> TiledGridSlice<'a, T> {
     pub fn offset(&self) -> Coord {
         self.offset
     }
+    /// Copies references to the values of an `N` by `N` slice into an array, indexed by row
+    /// then column, so they can be compared without transforming coordinates each time
+    pub fn to_array<const N: usize>(&self) -> [[&'a T; N]; N] {
+        assert!(self.size == Size::new(N as u32, N as u32));
+        std::array::from_fn(|y| {
+            std::array::from_fn(|x| self.get_valid(Coord::new(x as i32, y as i32)))
+        })
+    }
     pub fn iter(&self) -> TiledGridSliceIter<T> {
         TiledGridSliceIter {
             grid: self,
@@ -1,34 +1,114 @@
+#[cfg(feature = "serialize")]
+use crate::overlapping::io_result;
 use crate::{
-    retry,
-    wrap::{Wrap, WrapXY},
+    retry::{self, RetryObserver},
+    storage::CellStorage,
+    wrap::{self, Wrap, WrapXY},
 };
 use coord_2d::{Coord, Size};
 use direction::{CardinalDirection, CardinalDirectionTable, CardinalDirections};
 use grid_2d::Grid;
 use hashbrown::HashMap;
-use rand::Rng;
-use std::cmp::Ordering;
+use rand::{Rng, RngCore};
+#[cfg(feature = "serialize")]
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::borrow::{Borrow, BorrowMut};
+use std::cmp::{self, Ordering};
 use std::collections::BinaryHeap;
+use std::fmt;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::hash::Hasher;
+#[cfg(feature = "serialize")]
+use std::io;
 use std::iter;
 use std::marker::PhantomData;
+use std::mem;
 use std::num::NonZeroU32;
+#[cfg(feature = "async")]
+use std::num::NonZeroUsize;
 use std::ops::{Index, IndexMut};
+#[cfg(feature = "async")]
+use std::pin::Pin;
 use std::slice;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+#[cfg(feature = "async")]
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, Instant};
 
 pub type PatternId = u32;
 
 #[derive(Default, Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct PatternTable<T> {
     table: Vec<T>,
 }
 
+/// The number of items given when building a `PatternTable` differs from the number expected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatternTableLengthMismatch {
+    pub expected: usize,
+    pub actual: usize,
+}
+
 impl<T> PatternTable<T> {
     pub fn from_vec(table: Vec<T>) -> Self {
         Self { table }
     }
+    /// Collects `iter` into a table, failing unless it yields exactly `len` items
+    pub fn try_from_iter_with_len<I>(
+        iter: I,
+        len: usize,
+    ) -> Result<Self, PatternTableLengthMismatch>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let table = Vec::from_iter(iter);
+        if table.len() == len {
+            Ok(Self { table })
+        } else {
+            Err(PatternTableLengthMismatch {
+                expected: len,
+                actual: table.len(),
+            })
+        }
+    }
     pub fn len(&self) -> usize {
         self.table.len()
     }
+    pub fn get(&self, pattern_id: PatternId) -> Option<&T> {
+        self.table.get(pattern_id as usize)
+    }
+    pub fn get_mut(&mut self, pattern_id: PatternId) -> Option<&mut T> {
+        self.table.get_mut(pattern_id as usize)
+    }
+    pub fn map<U, F: FnMut(T) -> U>(self, f: F) -> PatternTable<U> {
+        PatternTable {
+            table: self.table.into_iter().map(f).collect(),
+        }
+    }
+    pub fn map_ref<U, F: FnMut(&T) -> U>(&self, f: F) -> PatternTable<U> {
+        PatternTable {
+            table: self.table.iter().map(f).collect(),
+        }
+    }
+    /// Pairs up the entries for each pattern in two tables, failing if the tables have
+    /// different lengths
+    pub fn zip<U>(
+        self,
+        other: PatternTable<U>,
+    ) -> Result<PatternTable<(T, U)>, PatternTableLengthMismatch> {
+        if self.len() != other.len() {
+            return Err(PatternTableLengthMismatch {
+                expected: self.len(),
+                actual: other.len(),
+            });
+        }
+        Ok(PatternTable {
+            table: self.table.into_iter().zip(other.table).collect(),
+        })
+    }
     pub fn drain(&mut self) -> ::std::vec::Drain<T> {
         self.table.drain(..)
     }
@@ -79,24 +159,39 @@ impl<T> IndexMut<PatternId> for PatternTable<T> {
     }
 }
 
+// The type entropies are computed in. Each cell's sum of `weight * log(weight)` is updated by
+// subtraction as patterns are removed, so the `f64-entropy` feature trades a little speed for
+// less rounding error, and so for entropies which order the same way on every platform.
+#[cfg(not(feature = "f64-entropy"))]
+type EntropyFloat = f32;
+#[cfg(feature = "f64-entropy")]
+type EntropyFloat = f64;
+
+// Entropies are exposed as `f32` whichever type they're computed in
+#[allow(clippy::unnecessary_cast)]
+fn entropy_to_f32(entropy: EntropyFloat) -> f32 {
+    entropy as f32
+}
+
 #[derive(Clone)]
 pub struct PatternWeight {
     weight: NonZeroU32,
-    weight_log_weight: f32,
+    weight_log_weight: EntropyFloat,
 }
 
 impl PatternWeight {
     pub fn new(weight: NonZeroU32) -> Self {
+        let weight_float = weight.get() as EntropyFloat;
         Self {
             weight,
-            weight_log_weight: (weight.get() as f32) * (weight.get() as f32).log2(),
+            weight_log_weight: weight_float * weight_float.log2(),
         }
     }
     pub fn weight(&self) -> u32 {
         self.weight.get()
     }
     pub fn weight_log_weight(&self) -> f32 {
-        self.weight_log_weight
+        entropy_to_f32(self.weight_log_weight)
     }
 }
 
@@ -106,9 +201,35 @@ pub struct GlobalStats {
     compatibility_per_pattern: PatternTable<CardinalDirectionTable<Vec<PatternId>>>,
     num_weighted_patterns: u32,
     sum_pattern_weight: u32,
-    sum_pattern_weight_log_weight: f32,
+    sum_pattern_weight_log_weight: EntropyFloat,
+    adjacency_frequencies: Option<AdjacencyFrequencies>,
+    adjacency_weights: Option<AdjacencyWeights>,
+    offset_rules: Vec<OffsetRule>,
+    // weights are raised to the power of 1 / temperature when choosing a pattern to observe
+    temperature: f32,
+}
+
+// Adjacency rules between cells separated by `offset`, enforced in addition to the rules for
+// cardinal neighbours. Rules are always added in pairs with opposite offsets, so the rule at
+// index `i ^ 1` is the reverse of the rule at index `i`.
+#[derive(Clone, Debug)]
+struct OffsetRule {
+    offset: Coord,
+    // the patterns allowed at `offset` from a cell with each pattern
+    compatible: PatternTable<Vec<PatternId>>,
+    // the number of patterns which allow each pattern at `offset` from them
+    num_ways: PatternTable<u32>,
 }
 
+/// For each pattern and direction, the number of times each neighbouring pattern was seen in
+/// that direction in the input, as `(neighbour_id, count)` pairs
+pub type AdjacencyFrequencies =
+    PatternTable<CardinalDirectionTable<Vec<(PatternId, u32)>>>;
+
+/// For each pattern and direction, a weight for each neighbouring pattern in that direction,
+/// as `(neighbour_id, weight)` pairs. Neighbours which aren't listed have a weight of 1.
+pub type AdjacencyWeights = PatternTable<CardinalDirectionTable<Vec<(PatternId, f64)>>>;
+
 struct NumWaysToBecomeEachPatternByDirection<'a> {
     iter: slice::Iter<'a, CardinalDirectionTable<Vec<PatternId>>>,
 }
@@ -148,6 +269,178 @@ impl PatternDescription {
     }
 }
 
+/// Reasons an adjacency matrix can be rejected by `GlobalStats::from_adjacency_matrix`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdjacencyMatrixError {
+    /// The number of weights differs from the number of adjacency entries
+    LengthMismatch {
+        num_weights: usize,
+        num_allowed: usize,
+    },
+    /// A pattern lists a neighbour which isn't one of the patterns
+    NeighbourOutOfRange {
+        pattern_id: PatternId,
+        direction: CardinalDirection,
+        neighbour_id: PatternId,
+    },
+    /// `pattern_id` allows `neighbour_id` in `direction`, but `neighbour_id` doesn't allow
+    /// `pattern_id` in the opposite direction
+    Asymmetric {
+        pattern_id: PatternId,
+        direction: CardinalDirection,
+        neighbour_id: PatternId,
+    },
+    /// The weight of `neighbour_id` in `direction` from `pattern_id` isn't positive and finite
+    InvalidWeight {
+        pattern_id: PatternId,
+        direction: CardinalDirection,
+        neighbour_id: PatternId,
+    },
+}
+
+/// Reasons rules can be rejected by `GlobalStats::add_offset_adjacency`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OffsetAdjacencyError {
+    /// The offset is (0, 0)
+    ZeroOffset,
+    /// The number of adjacency entries differs from the number of patterns
+    LengthMismatch {
+        num_patterns: usize,
+        num_allowed: usize,
+    },
+    /// A pattern lists a neighbour which isn't one of the patterns
+    NeighbourOutOfRange {
+        pattern_id: PatternId,
+        neighbour_id: PatternId,
+    },
+    /// Together with the existing rules, no pattern with a weight could appear anywhere, as
+    /// every such pattern is either never allowed at some offset or never allowed in some
+    /// cardinal direction
+    Unsatisfiable,
+}
+
+/// Returned by constructors which take a memory cap, when a wave's estimated size (see
+/// `GlobalStats::estimated_wave_bytes`) exceeds the cap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaveTooLarge {
+    pub estimated_bytes: usize,
+    pub max_bytes: usize,
+}
+
+/// A potential problem with adjacency rules, reported by `GlobalStats::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdjacencyLint {
+    /// Nothing may be placed next to the pattern in `direction`, so the pattern can only be
+    /// placed at an edge of a non-wrapping output
+    NoAllowedNeighbour {
+        pattern_id: PatternId,
+        direction: CardinalDirection,
+    },
+    /// The pattern lists a neighbour which isn't one of the patterns
+    NeighbourOutOfRange {
+        pattern_id: PatternId,
+        direction: CardinalDirection,
+        neighbour_id: PatternId,
+    },
+    /// `pattern_id` allows `neighbour_id` in `direction`, but `neighbour_id` doesn't allow
+    /// `pattern_id` in the opposite direction
+    Asymmetric {
+        pattern_id: PatternId,
+        direction: CardinalDirection,
+        neighbour_id: PatternId,
+    },
+    /// The pattern has no weight, so it will never be chosen during observation
+    Unweighted { pattern_id: PatternId },
+}
+
+impl AdjacencyLint {
+    pub fn is_error(&self) -> bool {
+        match self {
+            AdjacencyLint::NeighbourOutOfRange { .. }
+            | AdjacencyLint::Asymmetric { .. } => true,
+            AdjacencyLint::NoAllowedNeighbour { .. }
+            | AdjacencyLint::Unweighted { .. } => false,
+        }
+    }
+}
+
+/// Neighbouring decided cells whose patterns aren't allowed next to each other, found by
+/// `Wave::validate_against`. The cell at `neighbour_coord` is at `offset` from the cell at
+/// `coord` (before wrapping), which is a cardinal direction's coord for cardinal neighbours,
+/// or the offset of a rule added with `GlobalStats::add_offset_adjacency`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdjacencyViolation {
+    pub coord: Coord,
+    pub pattern_id: PatternId,
+    pub offset: Coord,
+    pub neighbour_coord: Coord,
+    pub neighbour_id: PatternId,
+}
+
+fn direction_adjective(direction: CardinalDirection) -> &'static str {
+    match direction {
+        CardinalDirection::North => "northern",
+        CardinalDirection::East => "eastern",
+        CardinalDirection::South => "southern",
+        CardinalDirection::West => "western",
+    }
+}
+
+pub(crate) fn direction_name(direction: CardinalDirection) -> &'static str {
+    match direction {
+        CardinalDirection::North => "north",
+        CardinalDirection::East => "east",
+        CardinalDirection::South => "south",
+        CardinalDirection::West => "west",
+    }
+}
+
+impl fmt::Display for AdjacencyLint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AdjacencyLint::NoAllowedNeighbour {
+                pattern_id,
+                direction,
+            } => write!(
+                f,
+                "pattern {} has no allowed {} neighbour",
+                pattern_id,
+                direction_adjective(direction)
+            ),
+            AdjacencyLint::NeighbourOutOfRange {
+                pattern_id,
+                direction,
+                neighbour_id,
+            } => write!(
+                f,
+                "pattern {} allows non-existent pattern {} {}",
+                pattern_id,
+                neighbour_id,
+                direction_name(direction)
+            ),
+            AdjacencyLint::Asymmetric {
+                pattern_id,
+                direction,
+                neighbour_id,
+            } => write!(
+                f,
+                "patterns {} and {} are asymmetric ({} allows {} {} but {} doesn't allow {} {})",
+                pattern_id,
+                neighbour_id,
+                pattern_id,
+                neighbour_id,
+                direction_name(direction),
+                neighbour_id,
+                pattern_id,
+                direction_name(direction.opposite())
+            ),
+            AdjacencyLint::Unweighted { pattern_id } => {
+                write!(f, "pattern {} has no weight", pattern_id)
+            }
+        }
+    }
+}
+
 struct OptionSliceIter<'a, T> {
     iter: slice::Iter<'a, Option<T>>,
 }
@@ -159,6 +452,40 @@ impl<'a, T> Iterator for OptionSliceIter<'a, T> {
     }
 }
 
+// 64-bit FNV-1a, used rather than `std::hash` because its output is specified
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+    // integers are written little-endian so the output doesn't depend on the platform
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+    fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_le_bytes());
+    }
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes());
+    }
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64);
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
 impl GlobalStats {
     pub fn new(mut pattern_descriptions: PatternTable<PatternDescription>) -> Self {
         let pattern_weights = pattern_descriptions
@@ -177,7 +504,7 @@ impl GlobalStats {
             .sum();
         let sum_pattern_weight_log_weight = pattern_weights
             .iter()
-            .filter_map(|p| p.as_ref().map(|p| p.weight_log_weight()))
+            .filter_map(|p| p.as_ref().map(|p| p.weight_log_weight))
             .sum();
         Self {
             pattern_weights,
@@ -185,7 +512,447 @@ impl GlobalStats {
             num_weighted_patterns,
             sum_pattern_weight,
             sum_pattern_weight_log_weight,
+            adjacency_frequencies: None,
+            adjacency_weights: None,
+            offset_rules: Vec::new(),
+            temperature: 1.0,
+        }
+    }
+    /// Build a `GlobalStats` from a weight per pattern and, for each pattern, the patterns
+    /// allowed next to it in each direction. Adjacency lists are indexed by direction in the
+    /// order north, east, south, west. Every adjacency must be listed from both sides (if `a`
+    /// allows `b` to its east, `b` must allow `a` to its west), as the propagator relies on this.
+    pub fn from_adjacency_matrix(
+        weights: Vec<Option<NonZeroU32>>,
+        allowed: Vec<[Vec<PatternId>; 4]>,
+    ) -> Result<Self, AdjacencyMatrixError> {
+        if weights.len() != allowed.len() {
+            return Err(AdjacencyMatrixError::LengthMismatch {
+                num_weights: weights.len(),
+                num_allowed: allowed.len(),
+            });
+        }
+        let pattern_descriptions = weights
+            .into_iter()
+            .zip(allowed)
+            .map(|(weight, allowed)| {
+                PatternDescription::new(
+                    weight,
+                    CardinalDirectionTable::new_array(allowed),
+                )
+            })
+            .collect::<PatternTable<_>>();
+        let global_stats = Self::new(pattern_descriptions);
+        for lint in global_stats.validate() {
+            match lint {
+                AdjacencyLint::NeighbourOutOfRange {
+                    pattern_id,
+                    direction,
+                    neighbour_id,
+                } => {
+                    return Err(AdjacencyMatrixError::NeighbourOutOfRange {
+                        pattern_id,
+                        direction,
+                        neighbour_id,
+                    })
+                }
+                AdjacencyLint::Asymmetric {
+                    pattern_id,
+                    direction,
+                    neighbour_id,
+                } => {
+                    return Err(AdjacencyMatrixError::Asymmetric {
+                        pattern_id,
+                        direction,
+                        neighbour_id,
+                    })
+                }
+                AdjacencyLint::NoAllowedNeighbour { .. }
+                | AdjacencyLint::Unweighted { .. } => (),
+            }
+        }
+        Ok(global_stats)
+    }
+    /// Check the adjacency rules for common mistakes. Problems for which `is_error` returns
+    /// `true` will cause incorrect behaviour during propagation, while the remaining problems
+    /// are reported because they frequently lead to contradictions or unused patterns.
+    pub fn validate(&self) -> Vec<AdjacencyLint> {
+        let num_patterns = self.num_patterns();
+        let mut lints = Vec::new();
+        for (pattern_id, compatible_patterns_by_direction) in
+            self.compatibility_per_pattern.enumerate()
+        {
+            if self.pattern_weights[pattern_id].is_none() {
+                lints.push(AdjacencyLint::Unweighted { pattern_id });
+            }
+            for (direction, neighbours) in compatible_patterns_by_direction.enumerate() {
+                if neighbours.is_empty() {
+                    lints.push(AdjacencyLint::NoAllowedNeighbour {
+                        pattern_id,
+                        direction,
+                    });
+                }
+                for &neighbour_id in neighbours {
+                    if neighbour_id as usize >= num_patterns {
+                        lints.push(AdjacencyLint::NeighbourOutOfRange {
+                            pattern_id,
+                            direction,
+                            neighbour_id,
+                        });
+                    } else if !self.compatibility_per_pattern[neighbour_id]
+                        .get(direction.opposite())
+                        .contains(&pattern_id)
+                    {
+                        lints.push(AdjacencyLint::Asymmetric {
+                            pattern_id,
+                            direction,
+                            neighbour_id,
+                        });
+                    }
+                }
+            }
+        }
+        lints
+    }
+    /// Returns the patterns which can't be placed anywhere in an output of the given size and
+    /// wrapping without causing a contradiction. A pattern is unsatisfiable if there is a
+    /// direction in which it has no satisfiable neighbours and in which every cell of the output
+    /// has a neighbour (because the output wraps in that direction), or if it has no satisfiable
+    /// neighbours in both directions along a non-wrapping axis with more than one cell.
+    pub fn unsatisfiable_patterns<W: Wrap>(
+        &self,
+        output_size: Size,
+        wrap: W,
+    ) -> Vec<PatternId> {
+        let _ = wrap;
+        self.unsatisfiable_patterns_with_wrap::<W>(output_size)
+    }
+    fn unsatisfiable_patterns_with_wrap<W: Wrap>(
+        &self,
+        output_size: Size,
+    ) -> Vec<PatternId> {
+        let wraps_x = W::normalize_coord(Coord::new(-1, 0), output_size).is_some();
+        let wraps_y = W::normalize_coord(Coord::new(0, -1), output_size).is_some();
+        let mut unsatisfiable = vec![false; self.num_patterns()];
+        loop {
+            let mut changed = false;
+            for (pattern_id, compatible_patterns_by_direction) in
+                self.compatibility_per_pattern.enumerate()
+            {
+                if unsatisfiable[pattern_id as usize] {
+                    continue;
+                }
+                let has_neighbour = |direction: CardinalDirection| {
+                    compatible_patterns_by_direction
+                        .get(direction)
+                        .iter()
+                        .any(|&neighbour_id| !unsatisfiable[neighbour_id as usize])
+                };
+                let axis_is_unsatisfiable =
+                    |wraps: bool,
+                     len: u32,
+                     a: CardinalDirection,
+                     b: CardinalDirection| {
+                        let (has_a, has_b) = (has_neighbour(a), has_neighbour(b));
+                        if wraps {
+                            !has_a || !has_b
+                        } else {
+                            len > 1 && !has_a && !has_b
+                        }
+                    };
+                if axis_is_unsatisfiable(
+                    wraps_x,
+                    output_size.x(),
+                    CardinalDirection::East,
+                    CardinalDirection::West,
+                ) || axis_is_unsatisfiable(
+                    wraps_y,
+                    output_size.y(),
+                    CardinalDirection::North,
+                    CardinalDirection::South,
+                ) {
+                    unsatisfiable[pattern_id as usize] = true;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        unsatisfiable
+            .iter()
+            .enumerate()
+            .filter(|&(_, &unsatisfiable)| unsatisfiable)
+            .map(|(pattern_id, _)| pattern_id as PatternId)
+            .collect()
+    }
+    /// Like `from_adjacency_matrix`, but each allowed neighbour carries a weight, which is
+    /// used as by `set_adjacency_weights`. Weights may differ between directions and between
+    /// the two sides of an adjacency, so for example a river can prefer to continue straight
+    /// rather than turn.
+    pub fn from_weighted_adjacency_matrix(
+        weights: Vec<Option<NonZeroU32>>,
+        allowed: Vec<[Vec<(PatternId, f64)>; 4]>,
+    ) -> Result<Self, AdjacencyMatrixError> {
+        let allowed_ids = allowed
+            .iter()
+            .map(|allowed| {
+                allowed
+                    .clone()
+                    .map(|neighbours| neighbours.into_iter().map(|(id, _)| id).collect())
+            })
+            .collect();
+        let mut global_stats = Self::from_adjacency_matrix(weights, allowed_ids)?;
+        global_stats.set_adjacency_weights(
+            allowed
+                .into_iter()
+                .map(CardinalDirectionTable::new_array)
+                .collect(),
+        )?;
+        Ok(global_stats)
+    }
+    /// Make the choice of pattern for a cell depend on the cell's already-decided neighbours.
+    /// A candidate's weight is multiplied, for each decided neighbour, by one more than the
+    /// number of times that neighbour was seen next to the candidate in the same direction in
+    /// the input. This better preserves the input's second-order statistics. Entropy is still
+    /// computed from the unmodified weights. Fails unless `adjacency_frequencies` has an entry
+    /// for each pattern.
+    pub fn set_adjacency_frequencies(
+        &mut self,
+        adjacency_frequencies: AdjacencyFrequencies,
+    ) -> Result<(), PatternTableLengthMismatch> {
+        if adjacency_frequencies.len() != self.num_patterns() {
+            return Err(PatternTableLengthMismatch {
+                expected: self.num_patterns(),
+                actual: adjacency_frequencies.len(),
+            });
+        }
+        self.adjacency_frequencies = Some(adjacency_frequencies);
+        Ok(())
+    }
+    pub fn adjacency_frequencies(&self) -> Option<&AdjacencyFrequencies> {
+        self.adjacency_frequencies.as_ref()
+    }
+    pub fn clear_adjacency_frequencies(&mut self) {
+        self.adjacency_frequencies = None;
+    }
+    /// Like `set_adjacency_frequencies`, but a candidate's weight is multiplied, for each
+    /// decided neighbour, by the weight given to that neighbour in its direction from the
+    /// candidate. Weights only affect selection: propagation tracks whether each pattern is
+    /// still supported by its neighbours, which doesn't depend on how strongly they're
+    /// preferred, so the adjacency rules alone decide which patterns are allowed, and a
+    /// weight only matters once the neighbour it applies to has been decided. Fails if the
+    /// table doesn't have an entry for each pattern, or if any weight isn't positive and
+    /// finite.
+    pub fn set_adjacency_weights(
+        &mut self,
+        adjacency_weights: AdjacencyWeights,
+    ) -> Result<(), AdjacencyMatrixError> {
+        if adjacency_weights.len() != self.num_patterns() {
+            return Err(AdjacencyMatrixError::LengthMismatch {
+                num_weights: self.num_patterns(),
+                num_allowed: adjacency_weights.len(),
+            });
+        }
+        for (pattern_id, weights_by_direction) in adjacency_weights.enumerate() {
+            for direction in CardinalDirections {
+                for &(neighbour_id, weight) in weights_by_direction.get(direction) {
+                    if !(weight > 0.0 && weight.is_finite()) {
+                        return Err(AdjacencyMatrixError::InvalidWeight {
+                            pattern_id,
+                            direction,
+                            neighbour_id,
+                        });
+                    }
+                }
+            }
+        }
+        self.adjacency_weights = Some(adjacency_weights);
+        Ok(())
+    }
+    pub fn adjacency_weights(&self) -> Option<&AdjacencyWeights> {
+        self.adjacency_weights.as_ref()
+    }
+    pub fn clear_adjacency_weights(&mut self) {
+        self.adjacency_weights = None;
+    }
+    /// When a cell is observed, choose among its patterns with each weight raised to the
+    /// power of `1 / temperature`. The default of 1 uses the weights as they are. Lower
+    /// temperatures favour frequent patterns, down to 0 which always chooses the heaviest
+    /// pattern, and higher temperatures approach a uniform choice, trading faithfulness to
+    /// the input for variety. Entropy is still computed from the unmodified weights. Panics if
+    /// `temperature` is negative or NaN.
+    pub fn set_temperature(&mut self, temperature: f32) {
+        assert!(temperature >= 0.0, "temperature must not be negative");
+        self.temperature = temperature;
+    }
+    pub fn temperature(&self) -> f32 {
+        self.temperature
+    }
+    /// Adds adjacency rules between cells separated by `offset`, such as diagonal neighbours
+    /// or cells a knight's move apart, which are propagated along with the rules for cardinal
+    /// neighbours. `allowed[p]` lists the patterns which may appear at `offset` from a cell
+    /// with pattern `p`. The reverse rules for `-offset` are derived automatically. These rules
+    /// can't be used with wraps where a cell may be the neighbour of more than one cell.
+    pub fn add_offset_adjacency(
+        &mut self,
+        offset: Coord,
+        allowed: Vec<Vec<PatternId>>,
+    ) -> Result<(), OffsetAdjacencyError> {
+        let num_patterns = self.num_patterns();
+        if offset == Coord::new(0, 0) {
+            return Err(OffsetAdjacencyError::ZeroOffset);
+        }
+        if allowed.len() != num_patterns {
+            return Err(OffsetAdjacencyError::LengthMismatch {
+                num_patterns,
+                num_allowed: allowed.len(),
+            });
+        }
+        // duplicate neighbours are ignored, as they would otherwise be counted twice
+        let mut compatible = vec![Vec::new(); num_patterns];
+        let mut reverse = vec![Vec::new(); num_patterns];
+        for (pattern_id, neighbours) in allowed.iter().enumerate() {
+            for &neighbour_id in neighbours {
+                if neighbour_id as usize >= num_patterns {
+                    return Err(OffsetAdjacencyError::NeighbourOutOfRange {
+                        pattern_id: pattern_id as PatternId,
+                        neighbour_id,
+                    });
+                }
+                if !compatible[pattern_id].contains(&neighbour_id) {
+                    compatible[pattern_id].push(neighbour_id);
+                    reverse[neighbour_id as usize].push(pattern_id as PatternId);
+                }
+            }
+        }
+        let rule =
+            |offset: Coord, compatible: &Vec<Vec<PatternId>>, reverse: &Vec<Vec<_>>| {
+                OffsetRule {
+                    offset,
+                    compatible: PatternTable::from_vec(compatible.clone()),
+                    num_ways: reverse.iter().map(|ids| ids.len() as u32).collect(),
+                }
+            };
+        self.offset_rules.push(rule(offset, &compatible, &reverse));
+        self.offset_rules
+            .push(rule(Coord::new(0, 0) - offset, &reverse, &compatible));
+        let excluded = self.offset_excluded_pattern_ids();
+        let any_weighted_pattern_remains = self
+            .num_ways_to_become_each_pattern_by_direction()
+            .enumerate()
+            .any(|(pattern_id, num_ways)| {
+                self.pattern_weights[pattern_id as PatternId].is_some()
+                    && !NumWaysToBecomePattern::new(num_ways).is_zero()
+                    && !excluded.contains(&(pattern_id as PatternId))
+            });
+        if !any_weighted_pattern_remains {
+            self.offset_rules.truncate(self.offset_rules.len() - 2);
+            return Err(OffsetAdjacencyError::Unsatisfiable);
+        }
+        Ok(())
+    }
+    // The patterns which no pattern allows at the offset of some offset rule, and which the
+    // cardinal rules don't already rule out. They are removed from every cell on reset.
+    fn offset_excluded_pattern_ids(&self) -> Vec<PatternId> {
+        if self.offset_rules.is_empty() {
+            return Vec::new();
+        }
+        self.num_ways_to_become_each_pattern_by_direction()
+            .enumerate()
+            .filter(|&(pattern_id, num_ways)| {
+                !NumWaysToBecomePattern::new(num_ways).is_zero()
+                    && self
+                        .offset_rules
+                        .iter()
+                        .any(|rule| rule.num_ways[pattern_id as PatternId] == 0)
+            })
+            .map(|(pattern_id, _)| pattern_id as PatternId)
+            .collect()
+    }
+    pub fn clear_offset_adjacency(&mut self) {
+        self.offset_rules.clear();
+    }
+    /// An estimate of the number of bytes used by a wave of `size` once it has been reset with
+    /// these stats. Saturates at `usize::MAX` rather than overflowing.
+    pub fn estimated_wave_bytes(&self, size: Size) -> usize {
+        let num_patterns = self.num_patterns();
+        let per_cell = mem::size_of::<WaveCell>()
+            .saturating_add(num_patterns.saturating_mul(
+                mem::size_of::<NumWaysToBecomePattern>() + mem::size_of::<u32>(),
+            ))
+            .saturating_add(
+                (self.offset_rules.len())
+                    .saturating_mul(num_patterns)
+                    .saturating_mul(mem::size_of::<u32>()),
+            );
+        (size.width() as usize)
+            .saturating_mul(size.height() as usize)
+            .saturating_mul(per_cell)
+    }
+    /// A hash of the weights, adjacency rules (including offset adjacency), adjacency
+    /// frequencies and temperature, which is the same on
+    /// every platform and in every version of this crate. The order in which neighbours are
+    /// listed doesn't affect the result. Intended for golden tests, to check that an input
+    /// still produces the same rules.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = Fnv1a::new();
+        hasher.write_u32(self.num_patterns() as u32);
+        for (weight, compatible_patterns_by_direction) in self
+            .pattern_weights
+            .iter()
+            .zip(self.compatibility_per_pattern.iter())
+        {
+            hasher.write_u32(weight.as_ref().map_or(0, |weight| weight.weight()));
+            for neighbours in compatible_patterns_by_direction.iter() {
+                let mut neighbours = neighbours.clone();
+                neighbours.sort_unstable();
+                hasher.write_u32(neighbours.len() as u32);
+                neighbours.into_iter().for_each(|id| hasher.write_u32(id));
+            }
         }
+        if let Some(adjacency_frequencies) = self.adjacency_frequencies.as_ref() {
+            for frequencies_by_direction in adjacency_frequencies.iter() {
+                for frequencies in frequencies_by_direction.iter() {
+                    let mut frequencies = frequencies.clone();
+                    frequencies.sort_unstable();
+                    hasher.write_u32(frequencies.len() as u32);
+                    for (id, count) in frequencies {
+                        hasher.write_u32(id);
+                        hasher.write_u32(count);
+                    }
+                }
+            }
+        }
+        if let Some(adjacency_weights) = self.adjacency_weights.as_ref() {
+            for weights_by_direction in adjacency_weights.iter() {
+                for weights in weights_by_direction.iter() {
+                    let mut weights = weights.clone();
+                    weights.sort_unstable_by_key(|&(id, _)| id);
+                    hasher.write_u32(weights.len() as u32);
+                    for (id, weight) in weights {
+                        let bits = weight.to_bits();
+                        hasher.write_u32(id);
+                        hasher.write_u32(bits as u32);
+                        hasher.write_u32((bits >> 32) as u32);
+                    }
+                }
+            }
+        }
+        for rule in self.offset_rules.iter() {
+            hasher.write_u32(rule.offset.x as u32);
+            hasher.write_u32(rule.offset.y as u32);
+            for neighbours in rule.compatible.iter() {
+                let mut neighbours = neighbours.clone();
+                neighbours.sort_unstable();
+                hasher.write_u32(neighbours.len() as u32);
+                neighbours.into_iter().for_each(|id| hasher.write_u32(id));
+            }
+        }
+        if self.temperature != 1.0 {
+            hasher.write_u32(self.temperature.to_bits());
+        }
+        hasher.finish()
     }
     fn num_weighted_patterns(&self) -> u32 {
         self.num_weighted_patterns
@@ -193,13 +960,13 @@ impl GlobalStats {
     fn sum_pattern_weight(&self) -> u32 {
         self.sum_pattern_weight
     }
-    fn sum_pattern_weight_log_weight(&self) -> f32 {
+    fn sum_pattern_weight_log_weight(&self) -> EntropyFloat {
         self.sum_pattern_weight_log_weight
     }
-    fn num_patterns(&self) -> usize {
+    pub(crate) fn num_patterns(&self) -> usize {
         self.pattern_weights.len()
     }
-    fn pattern_stats(&self, pattern_id: PatternId) -> Option<&PatternWeight> {
+    pub(crate) fn pattern_stats(&self, pattern_id: PatternId) -> Option<&PatternWeight> {
         self.pattern_weights[pattern_id].as_ref()
     }
     fn pattern_stats_option_iter(&self) -> OptionSliceIter<PatternWeight> {
@@ -207,7 +974,7 @@ impl GlobalStats {
             iter: self.pattern_weights.iter(),
         }
     }
-    fn compatible_patterns_in_direction(
+    pub(crate) fn compatible_patterns_in_direction(
         &self,
         pattern_id: PatternId,
         direction: CardinalDirection,
@@ -216,6 +983,28 @@ impl GlobalStats {
             .get(direction)
             .iter()
     }
+    // Whether every pattern may be next to every pattern in every direction, with nothing
+    // else constraining neighbours, in which case the cells of a wave are independent
+    fn is_unconstrained(&self) -> bool {
+        let num_patterns = self.num_patterns();
+        if self.adjacency_frequencies.is_some()
+            || self.adjacency_weights.is_some()
+            || !self.offset_rules.is_empty()
+        {
+            return false;
+        }
+        let mut seen = vec![false; num_patterns];
+        self.compatibility_per_pattern.iter().all(|by_direction| {
+            by_direction.iter().all(|compatible| {
+                if compatible.len() < num_patterns {
+                    return false;
+                }
+                seen.iter_mut().for_each(|seen| *seen = false);
+                compatible.iter().for_each(|&id| seen[id as usize] = true);
+                seen.iter().all(|&seen| seen)
+            })
+        })
+    }
     fn compatible_patterns_by_direction(
         &self,
     ) -> slice::Iter<CardinalDirectionTable<Vec<PatternId>>> {
@@ -231,29 +1020,94 @@ impl GlobalStats {
 }
 
 #[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 struct WaveCellStats {
     num_weighted_compatible_patterns: u32,
     // n0 + n1 + n2 + ...
     sum_compatible_pattern_weight: u32,
     // n0*log(n0) + n1*log(n1) + n2*log(n2) + ...
-    sum_compatible_pattern_weight_log_weight: f32,
+    sum_compatible_pattern_weight_log_weight: EntropyFloat,
+    // the value of `num_weighted_compatible_patterns` when the above sum was last computed
+    // from scratch
+    num_weighted_compatible_patterns_at_last_sum: u32,
+    // the weight of each compatible pattern, for choosing a pattern in O(log n)
+    cumulative_weights: CumulativeWeights,
+}
+
+/// A Fenwick tree of pattern weights. Finding the pattern at a given cumulative weight
+/// visits the patterns in the same order as a linear scan would, so it picks the same
+/// pattern for the same random number.
+#[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+struct CumulativeWeights {
+    tree: Vec<u32>,
+}
+
+impl CumulativeWeights {
+    fn init(&mut self, weights: impl Iterator<Item = u32>) {
+        self.tree.clear();
+        self.tree.extend(weights);
+        let len = self.tree.len();
+        for i in 0..len {
+            let parent = i | (i + 1);
+            if parent < len {
+                self.tree[parent] += self.tree[i];
+            }
+        }
+    }
+    fn total(&self) -> u32 {
+        let mut total = 0;
+        let mut len = self.tree.len();
+        while len > 0 {
+            total += self.tree[len - 1];
+            len &= len - 1;
+        }
+        total
+    }
+    fn remove(&mut self, index: usize, weight: u32) {
+        let mut i = index;
+        while i < self.tree.len() {
+            self.tree[i] -= weight;
+            i |= i + 1;
+        }
+    }
+    /// The first index whose cumulative weight exceeds `remaining`
+    fn find(&self, mut remaining: u32) -> usize {
+        let mut index = 0;
+        let mut step = self.tree.len().next_power_of_two();
+        while step > 0 {
+            let next = index + step;
+            if next <= self.tree.len() && self.tree[next - 1] <= remaining {
+                remaining -= self.tree[next - 1];
+                index = next;
+            }
+            step /= 2;
+        }
+        index
+    }
 }
 
 impl WaveCellStats {
-    fn remove_compatible_pattern(&mut self, pattern_stats: &PatternWeight) {
+    fn remove_compatible_pattern(
+        &mut self,
+        pattern_id: PatternId,
+        pattern_stats: &PatternWeight,
+    ) {
         assert!(self.num_weighted_compatible_patterns >= 1);
         assert!(self.sum_compatible_pattern_weight >= pattern_stats.weight());
 
         self.num_weighted_compatible_patterns -= 1;
         self.sum_compatible_pattern_weight -= pattern_stats.weight();
-        self.sum_compatible_pattern_weight_log_weight -=
-            pattern_stats.weight_log_weight();
+        self.cumulative_weights
+            .remove(pattern_id as usize, pattern_stats.weight());
+        self.sum_compatible_pattern_weight_log_weight -= pattern_stats.weight_log_weight;
     }
-    fn entropy(&self) -> f32 {
+    fn entropy(&self) -> EntropyFloat {
         assert!(self.sum_compatible_pattern_weight > 0);
 
         // log(n0+n1+n2+...) - (n0*log(n0) + n1*log(n1) + n2*log(n2) + ...) / (n0+n1+n2+...)
-        let sum_compatible_pattern_weight = self.sum_compatible_pattern_weight as f32;
+        let sum_compatible_pattern_weight =
+            self.sum_compatible_pattern_weight as EntropyFloat;
         sum_compatible_pattern_weight.log2()
             - (self.sum_compatible_pattern_weight_log_weight
                 / sum_compatible_pattern_weight)
@@ -261,6 +1115,7 @@ impl WaveCellStats {
 }
 
 #[derive(Default, Clone, Debug)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 struct NumWaysToBecomePattern {
     direction_table: CardinalDirectionTable<u32>,
 }
@@ -310,16 +1165,60 @@ impl NumWaysToBecomePattern {
     }
 }
 
+// Choose a pattern with probability proportional to its weight raised to the power of
+// `1 / temperature`, where a temperature of 0 chooses the (first) heaviest pattern. Weights
+// are divided by the largest weight first so that low temperatures don't overflow.
+fn choose_with_temperature<R: Rng>(
+    weights: &[(PatternId, f64)],
+    temperature: f32,
+    rng: &mut R,
+) -> PatternId {
+    let (heaviest_id, max_weight) = weights.iter().fold(
+        (None, 0.0),
+        |(heaviest_id, max_weight), &(pattern_id, weight)| {
+            if weight > max_weight {
+                (Some(pattern_id), weight)
+            } else {
+                (heaviest_id, max_weight)
+            }
+        },
+    );
+    let heaviest_id =
+        heaviest_id.expect("The weight is positive and based on global_stats");
+    if temperature == 0.0 {
+        return heaviest_id;
+    }
+    let exponent = 1.0 / temperature as f64;
+    let scaled = |weight: f64| (weight / max_weight).powf(exponent);
+    let total: f64 = weights.iter().map(|&(_, weight)| scaled(weight)).sum();
+    let mut remaining = rng.gen_range(0.0..total);
+    for &(pattern_id, weight) in weights {
+        let weight = scaled(weight);
+        if remaining < weight {
+            return pattern_id;
+        }
+        remaining -= weight;
+    }
+    // only reachable due to floating point rounding
+    heaviest_id
+}
+
 #[derive(Default, Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct WaveCell {
     // random value to break entropy ties
     noise: u32,
+    // added to the cell's entropy when ordering cells for observation
+    priority: EntropyFloat,
     num_compatible_patterns: u32,
     stats: WaveCellStats,
     // Keep track of the number of ways each neighbour could be assigned a pattern to allow this
     // cell to be each pattern. This doubles as a way of keeping track of which patterns are
     // compatible with this cell.
     num_ways_to_become_each_pattern: PatternTable<NumWaysToBecomePattern>,
+    // As above, for each pattern and each of `GlobalStats::offset_rules`, indexed by
+    // `rule_index * num_patterns + pattern_id`. Only meaningful while the pattern is compatible.
+    num_ways_to_become_each_pattern_by_offset: Vec<u32>,
 }
 
 enum DecrementNumWaysToBecomePattern {
@@ -332,8 +1231,9 @@ enum DecrementNumWaysToBecomePattern {
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 struct EntropyWithNoise {
-    entropy: f32,
+    entropy: EntropyFloat,
     noise: u32,
     // Record this field of WaveCellStats at the time of creating this entry.  This value will be
     // different from the cell's value when retrieved from the heap if and only if the cell's
@@ -381,6 +1281,19 @@ impl WaveCell {
             Err(ChosenPatternIdError::MultipleCompatiblePatterns)
         }
     }
+    /// The number of patterns (weighted or not) still compatible with this cell
+    pub fn num_compatible_patterns(&self) -> u32 {
+        self.num_compatible_patterns
+    }
+    /// The Shannon entropy of the weights of the patterns still compatible with this cell,
+    /// which is 0 once the cell is decided. Returns `None` if no weighted patterns remain.
+    pub fn entropy(&self) -> Option<f32> {
+        if self.stats.sum_compatible_pattern_weight == 0 {
+            None
+        } else {
+            Some(entropy_to_f32(self.stats.entropy()))
+        }
+    }
     fn weighted_compatible_stats_enumerate<'a>(
         &'a self,
         global_stats: &'a GlobalStats,
@@ -401,140 +1314,732 @@ impl WaveCell {
                 },
             )
     }
-    fn sum_compatible_pattern_weight(&self, global_stats: &GlobalStats) -> u32 {
-        self.num_ways_to_become_each_pattern
-            .iter()
-            .zip(global_stats.pattern_stats_option_iter())
-            .filter_map(|(num_ways_to_become_pattern, pattern_stats)| {
-                if num_ways_to_become_pattern.is_zero() {
-                    None
-                } else {
-                    pattern_stats.map(|pattern_stats| pattern_stats.weight())
-                }
-            })
-            .sum()
-    }
     fn decrement_num_ways_to_become_pattern(
         &mut self,
         pattern_id: PatternId,
         direction: CardinalDirection,
         global_stats: &GlobalStats,
     ) -> DecrementNumWaysToBecomePattern {
-        use self::DecrementNumWaysToBecomePattern as D;
         match self.num_ways_to_become_each_pattern[pattern_id].try_decrement(direction) {
-            Some(DecrementedToZero) => {
-                assert!(self.num_compatible_patterns >= 1);
-                self.num_compatible_patterns -= 1;
-                if let Some(pattern_stats) = global_stats.pattern_stats(pattern_id) {
-                    self.stats.remove_compatible_pattern(pattern_stats);
-                    match self.stats.num_weighted_compatible_patterns {
-                        0 => {
-                            if self.num_compatible_patterns == 0 {
-                                D::RemovedFinalCompatiblePattern
-                            } else {
-                                D::RemovedFinalWeightedCompatiblePattern
-                            }
-                        }
-                        _ => {
-                            assert!(self.num_compatible_patterns != 0);
-                            if self.num_compatible_patterns == 1 {
-                                assert!(self.stats.num_weighted_compatible_patterns == 1);
-                                D::Finalized
-                            } else {
-                                D::RemovedWeightedPatternMultipleCandidatesRemain
-                            }
-                        }
-                    }
-                } else {
-                    D::RemovedNonWeightedPattern
-                }
-            }
-            None => D::NoPatternRemoved,
+            Some(DecrementedToZero) => self.pattern_removed(pattern_id, global_stats),
+            None => DecrementNumWaysToBecomePattern::NoPatternRemoved,
         }
     }
-    fn entropy_with_noise(&self) -> EntropyWithNoise {
-        let entropy = self.stats.entropy();
-        let noise = self.noise;
-        let num_weighted_compatible_patterns =
-            self.stats.num_weighted_compatible_patterns;
-        EntropyWithNoise {
-            entropy,
+    fn decrement_num_ways_to_become_pattern_by_offset(
+        &mut self,
+        pattern_id: PatternId,
+        rule_index: usize,
+        global_stats: &GlobalStats,
+    ) -> DecrementNumWaysToBecomePattern {
+        if self.num_ways_to_become_each_pattern[pattern_id].is_zero() {
+            return DecrementNumWaysToBecomePattern::NoPatternRemoved;
+        }
+        let index = rule_index * global_stats.num_patterns() + pattern_id as usize;
+        let count = &mut self.num_ways_to_become_each_pattern_by_offset[index];
+        *count -= 1;
+        if *count == 0 {
+            self.remove_pattern(pattern_id, global_stats)
+        } else {
+            DecrementNumWaysToBecomePattern::NoPatternRemoved
+        }
+    }
+    // Subtracting from the sum of `weight * log(weight)` accumulates rounding error, which
+    // matters more as the sum shrinks, so the sum is recomputed from the compatible patterns
+    // each time the number of weighted compatible patterns halves. This costs O(log n)
+    // amortised per removal, the same as updating the cumulative weights.
+    fn resum_weight_log_weight(&mut self, global_stats: &GlobalStats) {
+        let num_weighted = self.stats.num_weighted_compatible_patterns;
+        if num_weighted * 2 > self.stats.num_weighted_compatible_patterns_at_last_sum {
+            return;
+        }
+        self.stats.num_weighted_compatible_patterns_at_last_sum = num_weighted;
+        self.stats.sum_compatible_pattern_weight_log_weight = if num_weighted == 0 {
+            0.
+        } else {
+            self.num_ways_to_become_each_pattern
+                .enumerate()
+                .filter(|(_, num_ways_to_become_pattern)| {
+                    !num_ways_to_become_pattern.is_zero()
+                })
+                .filter_map(|(pattern_id, _)| global_stats.pattern_stats(pattern_id))
+                .map(|pattern_stats| pattern_stats.weight_log_weight)
+                .sum()
+        };
+    }
+    fn remove_pattern(
+        &mut self,
+        pattern_id: PatternId,
+        global_stats: &GlobalStats,
+    ) -> DecrementNumWaysToBecomePattern {
+        if self.num_ways_to_become_each_pattern[pattern_id].is_zero() {
+            return DecrementNumWaysToBecomePattern::NoPatternRemoved;
+        }
+        self.num_ways_to_become_each_pattern[pattern_id].clear_all_directions();
+        self.pattern_removed(pattern_id, global_stats)
+    }
+    fn pattern_removed(
+        &mut self,
+        pattern_id: PatternId,
+        global_stats: &GlobalStats,
+    ) -> DecrementNumWaysToBecomePattern {
+        use self::DecrementNumWaysToBecomePattern as D;
+        assert!(self.num_compatible_patterns >= 1);
+        self.num_compatible_patterns -= 1;
+        if let Some(pattern_stats) = global_stats.pattern_stats(pattern_id) {
+            self.stats
+                .remove_compatible_pattern(pattern_id, pattern_stats);
+            self.resum_weight_log_weight(global_stats);
+            match self.stats.num_weighted_compatible_patterns {
+                0 => {
+                    if self.num_compatible_patterns == 0 {
+                        D::RemovedFinalCompatiblePattern
+                    } else {
+                        D::RemovedFinalWeightedCompatiblePattern
+                    }
+                }
+                _ => {
+                    assert!(self.num_compatible_patterns != 0);
+                    if self.num_compatible_patterns == 1 {
+                        assert!(self.stats.num_weighted_compatible_patterns == 1);
+                        D::Finalized
+                    } else {
+                        D::RemovedWeightedPatternMultipleCandidatesRemain
+                    }
+                }
+            }
+        } else {
+            D::RemovedNonWeightedPattern
+        }
+    }
+    fn entropy_with_noise(&self) -> EntropyWithNoise {
+        let entropy = self.stats.entropy() + self.priority;
+        let noise = self.noise;
+        let num_weighted_compatible_patterns =
+            self.stats.num_weighted_compatible_patterns;
+        EntropyWithNoise {
+            entropy,
             noise,
             num_weighted_compatible_patterns,
         }
     }
+    // `multipliers` are the soft constraints of the cell
     fn choose_pattern_id<R: Rng>(
         &self,
         global_stats: &GlobalStats,
+        multipliers: &[(PatternId, f64)],
         rng: &mut R,
     ) -> PatternId {
         assert!(self.stats.num_weighted_compatible_patterns >= 1);
         assert!(self.stats.sum_compatible_pattern_weight >= 1);
         assert_eq!(
-            self.sum_compatible_pattern_weight(global_stats),
+            self.stats.cumulative_weights.total(),
             self.stats.sum_compatible_pattern_weight
         );
+        if global_stats.temperature != 1.0 || !multipliers.is_empty() {
+            let weights = self
+                .weighted_compatible_stats_enumerate(global_stats)
+                .map(|(pattern_id, pattern_stats)| {
+                    let weight = pattern_stats.weight() as f64;
+                    (pattern_id, weight * multiplier(multipliers, pattern_id))
+                })
+                .collect::<Vec<_>>();
+            return choose_with_temperature(&weights, global_stats.temperature, rng);
+        }
 
-        let mut remaining = rng.gen_range(0..self.stats.sum_compatible_pattern_weight);
+        let remaining = rng.gen_range(0..self.stats.sum_compatible_pattern_weight);
+        let pattern_id = self.stats.cumulative_weights.find(remaining) as PatternId;
+        assert!(global_stats.pattern_stats(pattern_id).is_some());
+        assert!(!self.num_ways_to_become_each_pattern[pattern_id].is_zero());
+        pattern_id
+    }
+    fn choose_pattern_id_with_neighbours<R: Rng>(
+        &self,
+        global_stats: &GlobalStats,
+        decided_neighbours: &CardinalDirectionTable<Option<PatternId>>,
+        multipliers: &[(PatternId, f64)],
+        rng: &mut R,
+    ) -> PatternId {
+        let weight = |pattern_id: PatternId, pattern_stats: &PatternWeight| {
+            let mut weight =
+                pattern_stats.weight() as f64 * multiplier(multipliers, pattern_id);
+            for direction in CardinalDirections {
+                if let Some(neighbour_id) = *decided_neighbours.get(direction) {
+                    if let Some(adjacency_frequencies) =
+                        &global_stats.adjacency_frequencies
+                    {
+                        let count = adjacency_frequencies[pattern_id]
+                            .get(direction)
+                            .iter()
+                            .find(|&&(id, _)| id == neighbour_id)
+                            .map_or(0, |&(_, count)| count);
+                        weight *= (count + 1) as f64;
+                    }
+                    if let Some(adjacency_weights) = &global_stats.adjacency_weights {
+                        weight *= adjacency_weights[pattern_id]
+                            .get(direction)
+                            .iter()
+                            .find(|&&(id, _)| id == neighbour_id)
+                            .map_or(1.0, |&(_, weight)| weight);
+                    }
+                }
+            }
+            weight
+        };
+        if global_stats.temperature != 1.0 {
+            let weights = self
+                .weighted_compatible_stats_enumerate(global_stats)
+                .map(|(pattern_id, pattern_stats)| {
+                    (pattern_id, weight(pattern_id, pattern_stats))
+                })
+                .collect::<Vec<_>>();
+            return choose_with_temperature(&weights, global_stats.temperature, rng);
+        }
+        let total: f64 = self
+            .weighted_compatible_stats_enumerate(global_stats)
+            .map(|(pattern_id, pattern_stats)| weight(pattern_id, pattern_stats))
+            .sum();
+        let mut remaining = rng.gen_range(0.0..total);
+        let mut last = None;
         for (pattern_id, pattern_stats) in
             self.weighted_compatible_stats_enumerate(global_stats)
         {
-            if remaining >= pattern_stats.weight() {
-                remaining -= pattern_stats.weight();
-            } else {
-                assert!(global_stats.pattern_stats(pattern_id).is_some());
+            let weight = weight(pattern_id, pattern_stats);
+            if remaining < weight {
                 return pattern_id;
             }
+            remaining -= weight;
+            last = Some(pattern_id);
         }
-        unreachable!("The weight is positive and based on global_stats");
+        // only reachable due to floating point rounding
+        last.expect("The weight is positive and based on global_stats")
     }
-    fn init<R: Rng>(&mut self, global_stats: &GlobalStats, rng: &mut R) {
-        self.noise = rng.gen();
+    #[cfg(feature = "debug-invariants")]
+    fn check_consistency(
+        &self,
+        coord: Coord,
+        global_stats: &GlobalStats,
+    ) -> Result<(), WaveInconsistency> {
+        use self::WaveInconsistency as I;
+        let mut expected = WaveCellStats::default();
+        let mut num_compatible_patterns = 0;
+        for (pattern_id, num_ways_to_become_pattern) in
+            self.num_ways_to_become_each_pattern.enumerate()
+        {
+            let num_zero = num_ways_to_become_pattern
+                .direction_table
+                .iter()
+                .filter(|&&count| count == 0)
+                .count();
+            match num_zero {
+                0 => (),
+                4 => continue,
+                _ => return Err(I::PartiallyZeroNumWays { coord, pattern_id }),
+            }
+            num_compatible_patterns += 1;
+            if let Some(pattern_stats) = global_stats.pattern_stats(pattern_id) {
+                expected.num_weighted_compatible_patterns += 1;
+                expected.sum_compatible_pattern_weight += pattern_stats.weight();
+                expected.sum_compatible_pattern_weight_log_weight +=
+                    pattern_stats.weight_log_weight;
+            }
+        }
+        if num_compatible_patterns != self.num_compatible_patterns {
+            return Err(I::NumCompatiblePatterns {
+                coord,
+                expected: num_compatible_patterns,
+                actual: self.num_compatible_patterns,
+            });
+        }
+        if expected.num_weighted_compatible_patterns
+            != self.stats.num_weighted_compatible_patterns
+        {
+            return Err(I::NumWeightedCompatiblePatterns {
+                coord,
+                expected: expected.num_weighted_compatible_patterns,
+                actual: self.stats.num_weighted_compatible_patterns,
+            });
+        }
+        if expected.sum_compatible_pattern_weight
+            != self.stats.sum_compatible_pattern_weight
+        {
+            return Err(I::SumCompatiblePatternWeight {
+                coord,
+                expected: expected.sum_compatible_pattern_weight,
+                actual: self.stats.sum_compatible_pattern_weight,
+            });
+        }
+        // the recorded value accumulates rounding errors as patterns are removed
+        let expected_log_weight = expected.sum_compatible_pattern_weight_log_weight;
+        let actual_log_weight = self.stats.sum_compatible_pattern_weight_log_weight;
+        if (expected_log_weight - actual_log_weight).abs()
+            > 1e-3 * expected_log_weight.abs().max(1.0)
+        {
+            return Err(I::SumCompatiblePatternWeightLogWeight {
+                coord,
+                expected: entropy_to_f32(expected_log_weight),
+                actual: entropy_to_f32(actual_log_weight),
+            });
+        }
+        Ok(())
+    }
+    // `offset_excluded` must be `global_stats.offset_excluded_pattern_ids()`, which is passed
+    // in so it's only computed once per wave
+    fn init(
+        &mut self,
+        noise: u32,
+        priority: EntropyFloat,
+        global_stats: &GlobalStats,
+        offset_excluded: &[PatternId],
+    ) {
+        self.noise = noise;
+        self.priority = priority;
         self.num_compatible_patterns = global_stats.num_patterns() as u32;
         self.stats.num_weighted_compatible_patterns =
             global_stats.num_weighted_patterns();
         self.stats.sum_compatible_pattern_weight = global_stats.sum_pattern_weight();
         self.stats.sum_compatible_pattern_weight_log_weight =
             global_stats.sum_pattern_weight_log_weight();
+        self.stats.num_weighted_compatible_patterns_at_last_sum =
+            global_stats.num_weighted_patterns();
+        self.stats.cumulative_weights.init(
+            global_stats
+                .pattern_stats_option_iter()
+                .map(|pattern_stats| pattern_stats.map_or(0, PatternWeight::weight)),
+        );
         self.num_ways_to_become_each_pattern
             .resize(global_stats.num_patterns(), Default::default());
         self.num_ways_to_become_each_pattern
             .iter_mut()
             .zip(global_stats.num_ways_to_become_each_pattern_by_direction())
             .for_each(|(dst, src)| *dst = NumWaysToBecomePattern::new(src));
+        self.num_ways_to_become_each_pattern_by_offset.clear();
+        for rule in global_stats.offset_rules.iter() {
+            self.num_ways_to_become_each_pattern_by_offset
+                .extend(rule.num_ways.iter());
+        }
+        // as with cardinal directions, a pattern which nothing allows is never compatible,
+        // but unlike those it still counts towards the stats until it's removed here. The
+        // wave's context propagates the removals.
+        for &pattern_id in offset_excluded {
+            self.remove_pattern(pattern_id, global_stats);
+        }
     }
 }
 
+/// The state of every cell of the output. By default cells are stored in a `Grid`, but any
+/// `CellStorage` can be used instead by constructing the wave with `Wave::from_storage`.
 #[derive(Clone)]
-pub struct Wave {
-    grid: Grid<WaveCell>,
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct Wave<S: CellStorage<WaveCell> = Grid<WaveCell>> {
+    cells: S,
+    noise: Option<Grid<u32>>,
+    priority: Option<Grid<f32>>,
+    soft_constraints: Option<SoftConstraints>,
+    resolve_unweighted_cells: bool,
+}
+
+/// Per-cell multipliers applied to the weights of patterns when choosing which pattern a cell
+/// becomes, to discourage (multiplier below 1) or encourage (above 1) patterns in particular
+/// cells without forbidding them outright. A penalised pattern is still chosen if it's the
+/// only one compatible with a cell. Penalties don't affect the order in which cells are
+/// observed.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
+pub struct SoftConstraints {
+    multipliers: HashMap<Coord, Vec<(PatternId, f64)>>,
+}
+
+impl SoftConstraints {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Multiply the weight of `pattern_id` by `multiplier` when choosing a pattern for the cell
+    /// at `coord`, replacing any multiplier previously set for that cell and pattern. Panics
+    /// unless `multiplier` is positive and finite.
+    pub fn set(&mut self, coord: Coord, pattern_id: PatternId, multiplier: f64) {
+        assert!(
+            multiplier > 0.0 && multiplier.is_finite(),
+            "multiplier must be positive and finite"
+        );
+        let multipliers = self.multipliers.entry(coord).or_default();
+        match multipliers.iter_mut().find(|(id, _)| *id == pattern_id) {
+            Some((_, existing)) => *existing = multiplier,
+            None => multipliers.push((pattern_id, multiplier)),
+        }
+    }
+    /// The multiplier of `pattern_id` at `coord`, which is 1 unless set
+    pub fn get(&self, coord: Coord, pattern_id: PatternId) -> f64 {
+        multiplier(self.cell(coord), pattern_id)
+    }
+    pub fn remove_cell(&mut self, coord: Coord) {
+        self.multipliers.remove(&coord);
+    }
+    pub fn clear(&mut self) {
+        self.multipliers.clear();
+    }
+    fn cell(&self, coord: Coord) -> &[(PatternId, f64)] {
+        self.multipliers
+            .get(&coord)
+            .map_or(&[], |multipliers| multipliers.as_slice())
+    }
+}
+
+fn multiplier(multipliers: &[(PatternId, f64)], pattern_id: PatternId) -> f64 {
+    multipliers
+        .iter()
+        .find(|&&(id, _)| id == pattern_id)
+        .map_or(1.0, |&(_, multiplier)| multiplier)
 }
 
 impl Wave {
     pub fn new(size: Size) -> Self {
+        Self::from_storage(Grid::new_default(size))
+    }
+    /// Like `new`, but fails without allocating if a wave of `size` would use more than
+    /// `max_bytes` once reset with `global_stats`
+    pub fn try_new(
+        size: Size,
+        global_stats: &GlobalStats,
+        max_bytes: usize,
+    ) -> Result<Self, WaveTooLarge> {
+        let estimated_bytes = global_stats.estimated_wave_bytes(size);
+        if estimated_bytes > max_bytes {
+            return Err(WaveTooLarge {
+                estimated_bytes,
+                max_bytes,
+            });
+        }
+        Ok(Self::new(size))
+    }
+    /// Change the size of the wave. The existing cells are moved into a new grid of `size`,
+    /// so the per-pattern tables of as many cells as fit (which make up most of a wave's
+    /// memory) are reused, and only the grid itself and any additional cells are allocated.
+    /// The contents of the wave are left unspecified, so it must be reset (e.g. by
+    /// `RunBorrow::new` or `RunBorrow::reset`) before it is used. Noise set with `set_noise`
+    /// and priorities set with `set_priority` are cleared if the size changes.
+    pub fn resize(&mut self, size: Size) {
+        if size == self.cells.size() {
+            return;
+        }
+        self.noise = None;
+        self.priority = None;
+        let grid = mem::replace(&mut self.cells, Grid::new_default(Size::new(0, 0)));
+        let cells = grid
+            .into_enumerate()
+            .map(|(_, cell)| cell)
+            .chain(iter::repeat_with(WaveCell::default))
+            .take(size.count());
+        self.cells = Grid::new_iterator(size, cells);
+    }
+    pub fn grid(&self) -> &Grid<WaveCell> {
+        &self.cells
+    }
+}
+
+impl<S: CellStorage<WaveCell>> Index<Coord> for Wave<S> {
+    type Output = WaveCell;
+    /// Panics if `coord` is outside the wave
+    fn index(&self, coord: Coord) -> &Self::Output {
+        self.cells.get_checked(coord)
+    }
+}
+
+impl<S: CellStorage<WaveCell>> Wave<S> {
+    /// A wave whose cells live in `storage`. The existing contents of the cells are ignored, as
+    /// the wave is reset before it is used.
+    pub fn from_storage(storage: S) -> Self {
         Self {
-            grid: Grid::new_default(size),
+            cells: storage,
+            noise: None,
+            priority: None,
+            soft_constraints: None,
+            resolve_unweighted_cells: false,
         }
     }
+    pub fn storage(&self) -> &S {
+        &self.cells
+    }
+    pub fn into_storage(self) -> S {
+        self.cells
+    }
+    pub fn size(&self) -> Size {
+        self.cells.size()
+    }
+    /// The cell at `coord`, or `None` if it's outside the wave
+    pub fn get(&self, coord: Coord) -> Option<&WaveCell> {
+        self.cells.get(coord)
+    }
+    /// Use the values in `noise` rather than random numbers to break ties between cells of
+    /// equal entropy. Of the cells with the lowest entropy, the one with the lowest noise is
+    /// observed next, so e.g. blue noise spreads decisions evenly across the output. Takes
+    /// effect the next time the wave is reset. Panics if `noise` differs in size from the wave.
+    pub fn set_noise(&mut self, noise: Grid<u32>) {
+        assert_eq!(
+            noise.size(),
+            self.cells.size(),
+            "noise must be the same size as the wave"
+        );
+        self.noise = Some(noise);
+    }
+    /// Go back to breaking ties with random numbers
+    pub fn clear_noise(&mut self) {
+        self.noise = None;
+    }
+    /// Add the values in `priority` to the entropy of each cell when choosing which cell to
+    /// observe next, so cells with lower priority values are observed sooner. E.g. a grid of
+    /// distances from a focal point makes generation proceed outwards from that point, and a
+    /// large negative value around an important region makes that region finish first. The
+    /// entropies reported by `WaveCell::entropy` are unaffected. Takes effect the next time
+    /// the wave is reset. Panics if `priority` differs in size from the wave or contains a
+    /// value which isn't finite.
+    pub fn set_priority(&mut self, priority: Grid<f32>) {
+        assert_eq!(
+            priority.size(),
+            self.cells.size(),
+            "priority must be the same size as the wave"
+        );
+        assert!(
+            priority.iter().all(|value| value.is_finite()),
+            "priority must be finite"
+        );
+        self.priority = Some(priority);
+    }
+    pub fn priority(&self) -> Option<&Grid<f32>> {
+        self.priority.as_ref()
+    }
+    /// Go back to observing cells in order of entropy alone
+    pub fn clear_priority(&mut self) {
+        self.priority = None;
+    }
+    /// Apply `soft_constraints` when choosing patterns for cells. Unlike noise, this takes
+    /// effect from the next observation.
+    pub fn set_soft_constraints(&mut self, soft_constraints: SoftConstraints) {
+        self.soft_constraints = Some(soft_constraints);
+    }
+    pub fn soft_constraints(&self) -> Option<&SoftConstraints> {
+        self.soft_constraints.as_ref()
+    }
+    pub fn soft_constraints_mut(&mut self) -> Option<&mut SoftConstraints> {
+        self.soft_constraints.as_mut()
+    }
+    pub fn clear_soft_constraints(&mut self) {
+        self.soft_constraints = None;
+    }
+    /// Cells whose weighted patterns are all ruled out while several unweighted patterns
+    /// remain are normally left undecided, and reported as not collapsed once the rest of
+    /// the wave is. When `resolve` is `true`, such cells are instead observed as soon as
+    /// they appear, ahead of any other cell, choosing uniformly between their remaining
+    /// patterns. Off by default.
+    pub fn set_resolve_unweighted_cells(&mut self, resolve: bool) {
+        self.resolve_unweighted_cells = resolve;
+    }
+    pub fn resolve_unweighted_cells(&self) -> bool {
+        self.resolve_unweighted_cells
+    }
     fn init<R: Rng>(&mut self, global_stats: &GlobalStats, rng: &mut R) {
-        self.grid
-            .iter_mut()
-            .for_each(|cell| cell.init(global_stats, rng));
+        let offset_excluded = global_stats.offset_excluded_pattern_ids();
+        for coord in self.cells.coord_iter() {
+            let noise = match self.noise.as_ref() {
+                Some(noise) => *noise.get_checked(coord),
+                None => rng.gen(),
+            };
+            let priority = match self.priority.as_ref() {
+                Some(priority) => EntropyFloat::from(*priority.get_checked(coord)),
+                None => 0.0,
+            };
+            self.cells.get_checked_mut(coord).init(
+                noise,
+                priority,
+                global_stats,
+                &offset_excluded,
+            );
+        }
     }
-    pub fn grid(&self) -> &Grid<WaveCell> {
-        &self.grid
+    /// Each cell in row-major order, e.g. for drawing the wave part way through collapsing
+    /// when the run isn't at hand. `global_stats` must be the stats the wave was reset with.
+    pub fn wave_cell_ref_iter<'a>(
+        &'a self,
+        global_stats: &'a GlobalStats,
+    ) -> impl Iterator<Item = WaveCellRef<'a>> {
+        self.cells.coord_iter().map(move |coord| WaveCellRef {
+            wave_cell: self.cells.get_checked(coord),
+            global_stats,
+        })
+    }
+    /// The number of cells which have collapsed to each pattern, e.g. to compare the
+    /// distribution of patterns in the output with their weights. Cells which aren't decided
+    /// are not counted. The wave must have been reset.
+    pub fn pattern_histogram(&self) -> PatternTable<u32> {
+        let num_patterns = self.cells.coord_iter().next().map_or(0, |coord| {
+            self.cells
+                .get_checked(coord)
+                .num_ways_to_become_each_pattern
+                .len()
+        });
+        let mut histogram = PatternTable::from_vec(vec![0; num_patterns]);
+        for coord in self.cells.coord_iter() {
+            if let Ok(pattern_id) = self.cells.get_checked(coord).chosen_pattern_id() {
+                histogram[pattern_id] += 1;
+            }
+        }
+        histogram
+    }
+    /// Checks that every pair of neighbouring decided cells is allowed by the adjacency rules
+    /// of `global_stats`, including any offset rules, with neighbours found according to
+    /// `wrap`, returning the pairs which aren't. Each pair is reported once: cardinal
+    /// neighbours from the cell to the west or north, and cells related by an offset rule
+    /// from the cell the offset passed to `GlobalStats::add_offset_adjacency` is measured
+    /// from. Propagation never produces violations, so this is a safety net for code which
+    /// edits waves in other ways, and for testing custom constraints.
+    pub fn validate_against<W: Wrap>(
+        &self,
+        global_stats: &GlobalStats,
+        wrap: W,
+    ) -> Vec<AdjacencyViolation> {
+        let _ = wrap;
+        let size = self.cells.size();
+        let mut violations = Vec::new();
+        for coord in self.cells.coord_iter() {
+            let pattern_id = match self.cells.get_checked(coord).chosen_pattern_id() {
+                Ok(pattern_id) => pattern_id,
+                Err(_) => continue,
+            };
+            let cardinal = [CardinalDirection::East, CardinalDirection::South]
+                .into_iter()
+                .map(|direction| {
+                    let compatible = global_stats.compatibility_per_pattern[pattern_id]
+                        .get(direction)
+                        .as_slice();
+                    (direction.coord(), compatible)
+                });
+            // rules are added in pairs, the second of each being the reverse of the first
+            let offset = global_stats
+                .offset_rules
+                .iter()
+                .step_by(2)
+                .map(|rule| (rule.offset, rule.compatible[pattern_id].as_slice()));
+            for (offset, compatible) in cardinal.chain(offset) {
+                let neighbour_coord = match W::normalize_coord(coord + offset, size) {
+                    Some(neighbour_coord) => neighbour_coord,
+                    None => continue,
+                };
+                if let Ok(neighbour_id) =
+                    self.cells.get_checked(neighbour_coord).chosen_pattern_id()
+                {
+                    if !compatible.contains(&neighbour_id) {
+                        violations.push(AdjacencyViolation {
+                            coord,
+                            pattern_id,
+                            offset,
+                            neighbour_coord,
+                            neighbour_id,
+                        });
+                    }
+                }
+            }
+        }
+        violations
+    }
+    /// The number of patterns still compatible with each cell, e.g. for finding the most
+    /// constrained undecided cell
+    pub fn candidate_count_grid(&self) -> Grid<u32> {
+        Grid::new_fn(self.cells.size(), |coord| {
+            self.cells.get_checked(coord).num_compatible_patterns()
+        })
+    }
+    /// Renders the wave as text with a line per row, giving the chosen pattern id of each
+    /// cell, `?` for cells which aren't decided, and `!` for cells with no compatible patterns.
+    /// Cells are padded to the width of the largest pattern id in `global_stats`. Intended for
+    /// golden tests, to check that a given input and seed still produce the same output.
+    pub fn to_debug_string(&self, global_stats: &GlobalStats) -> String {
+        let width = global_stats
+            .num_patterns()
+            .saturating_sub(1)
+            .to_string()
+            .len();
+        let size = self.cells.size();
+        let mut string = String::new();
+        for y in 0..size.y() {
+            let cells = (0..size.x())
+                .map(|x| {
+                    let cell = self.cells.get_checked(Coord::new(x as i32, y as i32));
+                    let cell = match cell.chosen_pattern_id() {
+                        Ok(pattern_id) => pattern_id.to_string(),
+                        Err(ChosenPatternIdError::MultipleCompatiblePatterns) => {
+                            "?".to_string()
+                        }
+                        Err(ChosenPatternIdError::NoCompatiblePatterns) => {
+                            "!".to_string()
+                        }
+                    };
+                    format!("{:>width$}", cell, width = width)
+                })
+                .collect::<Vec<_>>();
+            string.push_str(&cells.join(" "));
+            string.push('\n');
+        }
+        string
+    }
+    /// Checks that the bookkeeping of each cell agrees with its table of the number of ways
+    /// to become each pattern, returning the first disagreement found. Intended for property
+    /// tests and fuzzers looking for bugs in propagation.
+    #[cfg(feature = "debug-invariants")]
+    pub fn check_consistency(
+        &self,
+        global_stats: &GlobalStats,
+    ) -> Result<(), WaveInconsistency> {
+        for coord in self.cells.coord_iter() {
+            self.cells
+                .get_checked(coord)
+                .check_consistency(coord, global_stats)?;
+        }
+        Ok(())
     }
 }
 
+/// A disagreement between a cell's recorded statistics and the patterns which are actually
+/// compatible with it. In each variant `expected` is computed from the compatible patterns
+/// and `actual` is the recorded value.
+#[cfg(feature = "debug-invariants")]
+#[derive(Debug, Clone, PartialEq)]
+pub enum WaveInconsistency {
+    /// The number of ways to become a pattern is zero in some directions but not others
+    PartiallyZeroNumWays { coord: Coord, pattern_id: PatternId },
+    NumCompatiblePatterns {
+        coord: Coord,
+        expected: u32,
+        actual: u32,
+    },
+    NumWeightedCompatiblePatterns {
+        coord: Coord,
+        expected: u32,
+        actual: u32,
+    },
+    SumCompatiblePatternWeight {
+        coord: Coord,
+        expected: u32,
+        actual: u32,
+    },
+    SumCompatiblePatternWeightLogWeight {
+        coord: Coord,
+        expected: f32,
+        actual: f32,
+    },
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 struct RemovedPattern {
     coord: Coord,
     pattern_id: PatternId,
 }
 
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 struct Propagator {
     removed_patterns_to_propagate: Vec<RemovedPattern>,
+    // cells which lost their last weighted pattern while several unweighted patterns remain
+    unweighted_coords: Vec<Coord>,
 }
 
 struct Contradiction;
@@ -542,74 +2047,158 @@ struct Contradiction;
 impl Propagator {
     fn clear(&mut self) {
         self.removed_patterns_to_propagate.clear();
+        self.unweighted_coords.clear();
     }
-    fn propagate<W: Wrap>(
+    fn propagate<W: Wrap, L: DebugListener, S: CellStorage<WaveCell>>(
         &mut self,
-        wave: &mut Wave,
+        wave: &mut Wave<S>,
         global_stats: &GlobalStats,
         entropy_changes_by_coord: &mut HashMap<Coord, EntropyWithNoise>,
         num_cells_with_more_than_one_weighted_compatible_pattern: &mut u32,
+        listener: &mut L,
     ) -> Result<(), Contradiction> {
         entropy_changes_by_coord.clear();
-        let wave_size = wave.grid.size();
+        let wave_size = wave.cells.size();
         while let Some(removed_pattern) = self.removed_patterns_to_propagate.pop() {
+            listener.on_remove_pattern(removed_pattern.coord, removed_pattern.pattern_id);
             for direction in CardinalDirections {
-                let coord_to_update = if let Some(coord_to_update) = W::normalize_coord(
-                    removed_pattern.coord + direction.coord(),
+                for coord_to_update in
+                    coords_to_update::<W>(removed_pattern.coord, direction, wave_size)
+                        .into_iter()
+                        .flatten()
+                {
+                    let cell = wave.cells.get_checked_mut(coord_to_update);
+                    for &pattern_id in global_stats.compatible_patterns_in_direction(
+                        removed_pattern.pattern_id,
+                        direction,
+                    ) {
+                        let outcome = cell.decrement_num_ways_to_become_pattern(
+                            pattern_id,
+                            direction,
+                            global_stats,
+                        );
+                        self.record_decrement(
+                            outcome,
+                            coord_to_update,
+                            pattern_id,
+                            cell,
+                            entropy_changes_by_coord,
+                            num_cells_with_more_than_one_weighted_compatible_pattern,
+                            listener,
+                        )?;
+                    }
+                }
+            }
+            for (rule_index, rule) in global_stats.offset_rules.iter().enumerate() {
+                let coord_to_update = match W::normalize_coord(
+                    removed_pattern.coord + rule.offset,
                     wave_size,
                 ) {
-                    coord_to_update
-                } else {
-                    continue;
+                    Some(coord_to_update) => coord_to_update,
+                    None => continue,
                 };
-                let cell = wave.grid.get_checked_mut(coord_to_update);
-                for &pattern_id in global_stats.compatible_patterns_in_direction(
-                    removed_pattern.pattern_id,
-                    direction,
-                ) {
-                    use self::DecrementNumWaysToBecomePattern as D;
-                    match cell.decrement_num_ways_to_become_pattern(
+                let cell = wave.cells.get_checked_mut(coord_to_update);
+                for &pattern_id in rule.compatible[removed_pattern.pattern_id].iter() {
+                    let outcome = cell.decrement_num_ways_to_become_pattern_by_offset(
                         pattern_id,
-                        direction,
+                        rule_index,
                         global_stats,
-                    ) {
-                        D::NoPatternRemoved => continue,
-                        D::RemovedNonWeightedPattern => (),
-                        D::RemovedWeightedPatternMultipleCandidatesRemain => {
-                            let entropy = cell.entropy_with_noise();
-                            entropy_changes_by_coord
-                                .entry(coord_to_update)
-                                .and_modify(|existing_entropy| {
-                                    if entropy < *existing_entropy {
-                                        *existing_entropy = entropy;
-                                    }
-                                })
-                                .or_insert(entropy);
-                        }
-                        D::Finalized => {
-                            *num_cells_with_more_than_one_weighted_compatible_pattern -=
-                                1;
-                            entropy_changes_by_coord.remove(&coord_to_update);
-                        }
-                        D::RemovedFinalCompatiblePattern => {
-                            return Err(Contradiction);
-                        }
-                        D::RemovedFinalWeightedCompatiblePattern => {
-                            entropy_changes_by_coord.remove(&coord_to_update);
-                        }
-                    }
-                    self.removed_patterns_to_propagate.push(RemovedPattern {
-                        coord: coord_to_update,
+                    );
+                    self.record_decrement(
+                        outcome,
+                        coord_to_update,
                         pattern_id,
-                    });
+                        cell,
+                        entropy_changes_by_coord,
+                        num_cells_with_more_than_one_weighted_compatible_pattern,
+                        listener,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Handles the result of reducing the number of ways the cell at `coord` can become
+    // `pattern_id`, queueing the pattern's removal for propagation if it was removed
+    #[allow(clippy::too_many_arguments)]
+    fn record_decrement<L: DebugListener>(
+        &mut self,
+        outcome: DecrementNumWaysToBecomePattern,
+        coord: Coord,
+        pattern_id: PatternId,
+        cell: &WaveCell,
+        entropy_changes_by_coord: &mut HashMap<Coord, EntropyWithNoise>,
+        num_cells_with_more_than_one_weighted_compatible_pattern: &mut u32,
+        listener: &mut L,
+    ) -> Result<(), Contradiction> {
+        use self::DecrementNumWaysToBecomePattern as D;
+        if !matches!(outcome, D::NoPatternRemoved) {
+            listener.on_narrow(coord, cell.num_compatible_patterns);
+        }
+        match outcome {
+            D::NoPatternRemoved => return Ok(()),
+            D::RemovedNonWeightedPattern => (),
+            D::RemovedWeightedPatternMultipleCandidatesRemain => {
+                let entropy = cell.entropy_with_noise();
+                entropy_changes_by_coord
+                    .entry(coord)
+                    .and_modify(|existing_entropy| {
+                        if entropy < *existing_entropy {
+                            *existing_entropy = entropy;
+                        }
+                    })
+                    .or_insert(entropy);
+            }
+            D::Finalized => {
+                *num_cells_with_more_than_one_weighted_compatible_pattern -= 1;
+                entropy_changes_by_coord.remove(&coord);
+            }
+            D::RemovedFinalCompatiblePattern => {
+                listener.on_contradiction(coord);
+                return Err(Contradiction);
+            }
+            D::RemovedFinalWeightedCompatiblePattern => {
+                entropy_changes_by_coord.remove(&coord);
+                if cell.num_compatible_patterns > 1 {
+                    self.unweighted_coords.push(coord);
                 }
             }
         }
+        self.removed_patterns_to_propagate
+            .push(RemovedPattern { coord, pattern_id });
         Ok(())
     }
 }
 
+// The cells whose neighbour in the direction opposite to `direction` is `coord`. For wraps
+// where this is one-to-one it's just the neighbour of `coord` in `direction`, but when
+// clamping or mirroring a cell can be the neighbour of itself or of two cells.
+fn coords_to_update<W: Wrap>(
+    coord: Coord,
+    direction: CardinalDirection,
+    wave_size: Size,
+) -> [Option<Coord>; 3] {
+    let forward = W::normalize_coord(coord + direction.coord(), wave_size);
+    if W::ONE_TO_ONE {
+        return [forward, None, None];
+    }
+    let is_neighbour_of_coord = |candidate: &Coord| {
+        W::normalize_coord(*candidate - direction.coord(), wave_size) == Some(coord)
+    };
+    let backward = W::normalize_coord(coord - direction.coord(), wave_size);
+    let forward = forward.filter(is_neighbour_of_coord);
+    let same = Some(coord).filter(is_neighbour_of_coord);
+    let backward = backward.filter(is_neighbour_of_coord);
+    [
+        forward,
+        same.filter(|&same| forward != Some(same)),
+        backward.filter(|&backward| forward != Some(backward) && same != Some(backward)),
+    ]
+}
+
 #[derive(PartialEq, Eq, Debug, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 struct CoordEntropy {
     coord: Coord,
     entropy_with_noise: EntropyWithNoise,
@@ -636,6 +2225,7 @@ impl Ord for CoordEntropy {
 }
 
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 struct Observer {
     entropy_priority_queue: BinaryHeap<CoordEntropy>,
 }
@@ -666,7 +2256,7 @@ impl<'a> CellAtCoordMut<'a> {
                     if let Some(pattern_stats) = global_stats.pattern_stats(pattern_id) {
                         self.wave_cell
                             .stats
-                            .remove_compatible_pattern(pattern_stats);
+                            .remove_compatible_pattern(pattern_id, pattern_stats);
                     }
                     propagator
                         .removed_patterns_to_propagate
@@ -677,6 +2267,7 @@ impl<'a> CellAtCoordMut<'a> {
                 }
             }
         }
+        self.wave_cell.resum_weight_log_weight(global_stats);
     }
 }
 
@@ -690,21 +2281,39 @@ impl Observer {
     fn clear(&mut self) {
         self.entropy_priority_queue.clear();
     }
-    fn choose_next_cell<'a>(&mut self, wave: &'a mut Wave) -> ChooseNextCell<'a> {
+    // An entry is stale if its cell's entropy has changed or its cell has been decided since
+    // it was queued. Stale entries are skipped when they reach the front of the queue.
+    fn is_current<S: CellStorage<WaveCell>>(
+        coord_entropy: &CoordEntropy,
+        wave: &Wave<S>,
+    ) -> bool {
+        let wave_cell = wave.cells.get_checked(coord_entropy.coord);
+        wave_cell.stats.num_weighted_compatible_patterns
+            == coord_entropy
+                .entropy_with_noise
+                .num_weighted_compatible_patterns
+            && wave_cell.num_compatible_patterns > 1
+    }
+    fn num_stale<S: CellStorage<WaveCell>>(&self, wave: &Wave<S>) -> usize {
+        self.entropy_priority_queue
+            .iter()
+            .filter(|coord_entropy| !Self::is_current(coord_entropy, wave))
+            .count()
+    }
+    fn compact<S: CellStorage<WaveCell>>(&mut self, wave: &Wave<S>) {
+        let mut entries = mem::take(&mut self.entropy_priority_queue).into_vec();
+        entries.retain(|coord_entropy| Self::is_current(coord_entropy, wave));
+        entries.shrink_to_fit();
+        self.entropy_priority_queue = BinaryHeap::from(entries);
+    }
+    fn choose_next_cell<'a, S: CellStorage<WaveCell>>(
+        &mut self,
+        wave: &'a mut Wave<S>,
+    ) -> ChooseNextCell<'a> {
         while let Some(coord_entropy) = self.entropy_priority_queue.pop() {
-            let index = wave
-                .grid
-                .index_of_coord(coord_entropy.coord)
-                .expect("Coord out of bounds");
-            let wave_cell = wave.grid.get_index_checked(index);
-            if wave_cell.stats.num_weighted_compatible_patterns
-                == coord_entropy
-                    .entropy_with_noise
-                    .num_weighted_compatible_patterns
-                && wave_cell.num_compatible_patterns > 1
-            {
+            if Self::is_current(&coord_entropy, wave) {
                 return ChooseNextCell::MinEntropyCell(CellAtCoordMut {
-                    wave_cell: wave.grid.get_index_checked_mut(index),
+                    wave_cell: wave.cells.get_checked_mut(coord_entropy.coord),
                     coord: coord_entropy.coord,
                 });
             }
@@ -714,11 +2323,19 @@ impl Observer {
 }
 
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct Context {
     propagator: Propagator,
     entropy_changes_by_coord: HashMap<Coord, EntropyWithNoise>,
     observer: Observer,
     num_cells_with_more_than_one_weighted_compatible_pattern: u32,
+    // every observation since the last reset, in order, so they can be replayed after a
+    // partial restart
+    observations: Vec<(Coord, PatternId)>,
+    // When the global stats don't constrain neighbours, collapsing is just sampling each cell
+    // by weight, so cells are observed in row-major order from this index instead of in
+    // order of entropy, and observations aren't propagated.
+    unconstrained_index: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -727,26 +2344,117 @@ pub enum Observe {
     Complete,
 }
 
+/// A limit on the amount of work done by a single call to `collapse_with_budget`
+#[derive(Debug, Clone, Copy)]
+pub enum Budget {
+    Steps(usize),
+    Duration(Duration),
+}
+
+impl Budget {
+    fn is_exhausted(self, num_steps: usize, start: Instant) -> bool {
+        match self {
+            Budget::Steps(max_steps) => num_steps >= max_steps,
+            Budget::Duration(max_duration) => start.elapsed() >= max_duration,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BudgetOutcome {
+    /// The budget ran out before the wave was fully collapsed. Collapsing can be resumed by
+    /// calling `collapse_with_budget` again.
+    Exhausted,
+    Complete,
+}
+
 #[derive(Debug)]
 pub enum PropagateError {
     Contradiction,
 }
 
-struct WaveCellHandle<'a> {
-    cell_at_coord_mut: CellAtCoordMut<'a>,
-    propagator: &'a mut Propagator,
-    global_stats: &'a GlobalStats,
+/// Returned by `RunBorrow::try_reset` when a run couldn't be set up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetupError {
+    /// Every attempt to apply the run's `ForbidPattern` caused a contradiction
+    UnsatisfiableForbid,
 }
 
-impl<'a> WaveCellHandle<'a> {
-    fn new(
-        wave: &'a mut Wave,
-        coord: Coord,
-        propagator: &'a mut Propagator,
-        global_stats: &'a GlobalStats,
-    ) -> Self {
-        let cell_at_coord_mut = CellAtCoordMut {
-            wave_cell: wave.grid.get_checked_mut(coord),
+/// The `ForbidInterface` method whose call caused a contradiction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForbidCall {
+    ForbidAllPatternsExcept,
+    ForbidPattern,
+}
+
+/// A call to a `ForbidInterface` method which caused a contradiction. The wave is reset
+/// after each contradiction, so patterns forbidden by earlier calls are lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForbidContradiction {
+    pub call: ForbidCall,
+    pub coord: Coord,
+    pub pattern_id: PatternId,
+}
+
+/// Returned by `RunBorrow::reset_checked` and the `new_wrap_forbid_checked` constructors
+/// when applying the run's `ForbidPattern` caused contradictions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForbidContradictions {
+    contradictions: Vec<ForbidContradiction>,
+}
+
+impl ForbidContradictions {
+    /// The calls which caused contradictions, in the order they were made
+    pub fn iter(&self) -> slice::Iter<'_, ForbidContradiction> {
+        self.contradictions.iter()
+    }
+    pub fn first(&self) -> &ForbidContradiction {
+        &self.contradictions[0]
+    }
+}
+
+/// A flag which can be set (e.g. from a GUI thread) to stop a collapse in progress. Clones
+/// share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, atomic::Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(atomic::Ordering::Relaxed)
+    }
+}
+
+/// Returned by retry methods which stop when a `CancellationToken` is cancelled
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Cancelled;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CancellableError {
+    Contradiction,
+    Cancelled,
+}
+
+struct WaveCellHandle<'a> {
+    cell_at_coord_mut: CellAtCoordMut<'a>,
+    propagator: &'a mut Propagator,
+    global_stats: &'a GlobalStats,
+}
+
+impl<'a> WaveCellHandle<'a> {
+    fn new<S: CellStorage<WaveCell>>(
+        wave: &'a mut Wave<S>,
+        coord: Coord,
+        propagator: &'a mut Propagator,
+        global_stats: &'a GlobalStats,
+    ) -> Self {
+        let cell_at_coord_mut = CellAtCoordMut {
+            wave_cell: wave.cells.get_checked_mut(coord),
             coord,
         };
         Self {
@@ -780,7 +2488,10 @@ impl<'a> WaveCellHandle<'a> {
             self.cell_at_coord_mut
                 .wave_cell
                 .stats
-                .remove_compatible_pattern(pattern_stats);
+                .remove_compatible_pattern(pattern_id, pattern_stats);
+            self.cell_at_coord_mut
+                .wave_cell
+                .resum_weight_log_weight(self.global_stats);
         }
         self.propagator
             .removed_patterns_to_propagate
@@ -795,34 +2506,113 @@ impl Context {
     pub fn new() -> Self {
         Default::default()
     }
-    fn init(&mut self, wave: &Wave, global_stats: &GlobalStats) {
+    /// Like `new`, but allocates up front for running on waves of `size` with `global_stats`,
+    /// so the internal buffers rarely need to grow while collapsing. Buffers keep their
+    /// capacity across resets, so a context reused for many runs only allocates in its first
+    /// few runs either way.
+    pub fn with_capacity(size: Size, global_stats: &GlobalStats) -> Self {
+        let num_cells = size.count();
+        Self {
+            propagator: Propagator {
+                // an observation removes up to every other pattern from the observed cell,
+                // and each removal can in turn remove every pattern from a neighbour
+                removed_patterns_to_propagate: Vec::with_capacity(
+                    global_stats.num_patterns() * 5,
+                ),
+                unweighted_coords: Vec::new(),
+            },
+            entropy_changes_by_coord: HashMap::with_capacity(num_cells),
+            observer: Observer {
+                // every cell is queued on reset, and again each time its entropy changes
+                entropy_priority_queue: BinaryHeap::with_capacity(num_cells * 2),
+            },
+            num_cells_with_more_than_one_weighted_compatible_pattern: 0,
+            observations: Vec::with_capacity(num_cells),
+            unconstrained_index: None,
+        }
+    }
+    /// The number of entries in the queue of cells waiting to be observed in order of
+    /// entropy. A cell is queued again each time its entropy changes, and outdated entries
+    /// are only discarded when they reach the front of the queue, so during long collapses
+    /// the queue can grow well beyond the number of cells. See `stale_fraction` and `compact`.
+    pub fn heap_len(&self) -> usize {
+        self.observer.entropy_priority_queue.len()
+    }
+    /// The fraction of entries in the entropy queue which are outdated, because their cell's
+    /// entropy has changed or their cell has been decided since they were queued. Returns 0
+    /// if the queue is empty. `wave` must be the wave this context was last run on.
+    pub fn stale_fraction<S: CellStorage<WaveCell>>(&self, wave: &Wave<S>) -> f64 {
+        let heap_len = self.heap_len();
+        if heap_len == 0 {
+            return 0.;
+        }
+        self.observer.num_stale(wave) as f64 / heap_len as f64
+    }
+    /// Removes outdated entries from the entropy queue and frees the memory they used. This
+    /// takes time linear in the length of the queue. The same cells remain to be observed,
+    /// though cells with exactly equal entropy may be observed in a different order. `wave`
+    /// must be the wave this context was last run on.
+    pub fn compact<S: CellStorage<WaveCell>>(&mut self, wave: &Wave<S>) {
+        self.observer.compact(wave);
+    }
+    fn init<S: CellStorage<WaveCell>>(
+        &mut self,
+        wave: &Wave<S>,
+        global_stats: &GlobalStats,
+    ) {
         self.propagator.clear();
         self.observer.clear();
         self.entropy_changes_by_coord.clear();
+        self.observations.clear();
+        self.unconstrained_index = None;
         if global_stats.num_weighted_patterns() > 1 {
-            self.num_cells_with_more_than_one_weighted_compatible_pattern =
-                wave.grid.size().count() as u32;
-            wave.grid.enumerate().for_each(|(coord, cell)| {
-                self.observer.entropy_priority_queue.push(CoordEntropy {
-                    coord,
-                    entropy_with_noise: cell.entropy_with_noise(),
-                });
-            });
+            // noise and priorities choose the order cells are observed in, so they rule out
+            // the fast path
+            if wave.noise.is_none()
+                && wave.priority.is_none()
+                && global_stats.is_unconstrained()
+            {
+                self.num_cells_with_more_than_one_weighted_compatible_pattern =
+                    wave.cells.size().count() as u32;
+                self.unconstrained_index = Some(0);
+                return;
+            }
+            // patterns removed by offset rules may leave cells with at most one weighted
+            // pattern, and their neighbours are narrowed when the removals are propagated
+            let offset_excluded = global_stats.offset_excluded_pattern_ids();
+            self.num_cells_with_more_than_one_weighted_compatible_pattern = 0;
+            for coord in wave.cells.coord_iter() {
+                for &pattern_id in offset_excluded.iter() {
+                    self.propagator
+                        .removed_patterns_to_propagate
+                        .push(RemovedPattern { coord, pattern_id });
+                }
+                let wave_cell = wave.cells.get_checked(coord);
+                if wave_cell.stats.num_weighted_compatible_patterns > 1 {
+                    self.num_cells_with_more_than_one_weighted_compatible_pattern += 1;
+                    self.observer.entropy_priority_queue.push(CoordEntropy {
+                        coord,
+                        entropy_with_noise: wave_cell.entropy_with_noise(),
+                    });
+                }
+            }
         } else {
             self.num_cells_with_more_than_one_weighted_compatible_pattern = 0;
         }
     }
-    fn propagate<W: Wrap>(
+    fn propagate<W: Wrap, L: DebugListener, S: CellStorage<WaveCell>>(
         &mut self,
-        wave: &mut Wave,
+        wave: &mut Wave<S>,
         global_stats: &GlobalStats,
+        listener: &mut L,
     ) -> Result<(), PropagateError> {
         self.propagator
-            .propagate::<W>(
+            .propagate::<W, L, S>(
                 wave,
                 global_stats,
                 &mut self.entropy_changes_by_coord,
                 &mut self.num_cells_with_more_than_one_weighted_compatible_pattern,
+                listener,
             )
             .map_err(|_: Contradiction| PropagateError::Contradiction)?;
         for (coord, entropy_with_noise) in self.entropy_changes_by_coord.drain() {
@@ -833,53 +2623,428 @@ impl Context {
         }
         Ok(())
     }
-    fn observe<R: Rng>(
+    fn observe<W: Wrap, R: Rng, L: DebugListener, S: CellStorage<WaveCell>>(
         &mut self,
-        wave: &mut Wave,
+        wave: &mut Wave<S>,
         global_stats: &GlobalStats,
         rng: &mut R,
-    ) -> Observe {
+        listener: &mut L,
+    ) -> Observation {
+        if wave.resolve_unweighted_cells {
+            if let Some(observation) =
+                self.observe_unweighted(wave, global_stats, rng, listener)
+            {
+                return observation;
+            }
+        }
         if self.num_cells_with_more_than_one_weighted_compatible_pattern == 0 {
-            return Observe::Complete;
+            return Observation::Complete;
         }
-        let mut cell_at_coord = match self.observer.choose_next_cell(wave) {
-            ChooseNextCell::NoCellsWithMultipleWeightedPatterns => {
-                return Observe::Complete;
+        let coord = if let Some(index) = self.unconstrained_index.as_mut() {
+            let width = wave.cells.size().width() as usize;
+            let num_cells = wave.cells.size().count();
+            loop {
+                if *index >= num_cells {
+                    return Observation::Complete;
+                }
+                let coord = Coord::new((*index % width) as i32, (*index / width) as i32);
+                *index += 1;
+                let wave_cell = wave.cells.get_checked(coord);
+                if wave_cell.stats.num_weighted_compatible_patterns > 1 {
+                    break coord;
+                }
             }
-            ChooseNextCell::MinEntropyCell(cell_at_coord) => cell_at_coord,
+        } else {
+            match self.observer.choose_next_cell(wave) {
+                ChooseNextCell::NoCellsWithMultipleWeightedPatterns => {
+                    return Observation::Complete;
+                }
+                ChooseNextCell::MinEntropyCell(cell_at_coord) => cell_at_coord.coord,
+            }
+        };
+        let multipliers = wave
+            .soft_constraints
+            .as_ref()
+            .map_or(&[][..], |soft_constraints| soft_constraints.cell(coord));
+        let pattern_id = if global_stats.adjacency_frequencies.is_none()
+            && global_stats.adjacency_weights.is_none()
+        {
+            wave.cells.get_checked(coord).choose_pattern_id(
+                global_stats,
+                multipliers,
+                rng,
+            )
+        } else {
+            let mut decided_neighbours = CardinalDirectionTable::default();
+            for direction in CardinalDirections {
+                let neighbour_coord =
+                    W::normalize_coord(coord + direction.coord(), wave.cells.size());
+                *decided_neighbours.get_mut(direction) =
+                    neighbour_coord.and_then(|neighbour_coord| {
+                        wave.cells
+                            .get_checked(neighbour_coord)
+                            .chosen_pattern_id()
+                            .ok()
+                    });
+            }
+            wave.cells
+                .get_checked(coord)
+                .choose_pattern_id_with_neighbours(
+                    global_stats,
+                    &decided_neighbours,
+                    multipliers,
+                    rng,
+                )
+        };
+        let mut cell_at_coord = CellAtCoordMut {
+            wave_cell: wave.cells.get_checked_mut(coord),
+            coord,
         };
-        let pattern_id = cell_at_coord.wave_cell.choose_pattern_id(global_stats, rng);
+        listener.on_observe(cell_at_coord.coord, pattern_id);
+        let num_removed_patterns_to_propagate =
+            self.propagator.removed_patterns_to_propagate.len();
         cell_at_coord.remove_all_patterns_except_one(
             pattern_id,
             &global_stats,
             &mut self.propagator,
         );
+        if self.unconstrained_index.is_some() {
+            // removing patterns can't affect the neighbours, so skip propagation
+            for removed_pattern in self
+                .propagator
+                .removed_patterns_to_propagate
+                .drain(num_removed_patterns_to_propagate..)
+            {
+                listener
+                    .on_remove_pattern(removed_pattern.coord, removed_pattern.pattern_id);
+            }
+        }
         self.num_cells_with_more_than_one_weighted_compatible_pattern -= 1;
-        Observe::Incomplete
+        self.observations.push((coord, pattern_id));
+        Observation::Observed {
+            coord: cell_at_coord.coord,
+            pattern_id,
+        }
+    }
+    // Observe a cell left with only unweighted compatible patterns, if there is one, choosing
+    // uniformly between its patterns
+    fn observe_unweighted<R: Rng, L: DebugListener, S: CellStorage<WaveCell>>(
+        &mut self,
+        wave: &mut Wave<S>,
+        global_stats: &GlobalStats,
+        rng: &mut R,
+        listener: &mut L,
+    ) -> Option<Observation> {
+        while let Some(coord) = self.propagator.unweighted_coords.pop() {
+            let wave_cell = wave.cells.get_checked_mut(coord);
+            // the cell may have been narrowed further since it was recorded
+            if wave_cell.num_compatible_patterns <= 1 {
+                continue;
+            }
+            let index = rng.gen_range(0..wave_cell.num_compatible_patterns as usize);
+            let pattern_id = wave_cell
+                .num_ways_to_become_each_pattern
+                .enumerate()
+                .filter(|(_, num_ways_to_become_pattern)| {
+                    !num_ways_to_become_pattern.is_zero()
+                })
+                .map(|(pattern_id, _)| pattern_id)
+                .nth(index)
+                .expect("num_compatible_patterns counts the compatible patterns");
+            let mut cell_at_coord = CellAtCoordMut { wave_cell, coord };
+            listener.on_observe(coord, pattern_id);
+            cell_at_coord.remove_all_patterns_except_one(
+                pattern_id,
+                global_stats,
+                &mut self.propagator,
+            );
+            self.observations.push((coord, pattern_id));
+            return Some(Observation::Observed { coord, pattern_id });
+        }
+        None
+    }
+    // Forbid a pattern part way through collapsing, keeping the observer up to date. The removal
+    // is queued to be propagated along with the current observation.
+    fn forbid_pattern_during_collapse<S: CellStorage<WaveCell>>(
+        &mut self,
+        wave: &mut Wave<S>,
+        global_stats: &GlobalStats,
+        coord: Coord,
+        pattern_id: PatternId,
+    ) -> Result<(), Contradiction> {
+        use self::DecrementNumWaysToBecomePattern as D;
+        let cell = wave.cells.get_checked_mut(coord);
+        let outcome = cell.remove_pattern(pattern_id, global_stats);
+        if cell.num_compatible_patterns == 0 {
+            return Err(Contradiction);
+        }
+        match outcome {
+            D::NoPatternRemoved => return Ok(()),
+            D::RemovedNonWeightedPattern => (),
+            D::RemovedFinalWeightedCompatiblePattern => {
+                if cell.num_compatible_patterns > 1 {
+                    self.propagator.unweighted_coords.push(coord);
+                }
+            }
+            D::RemovedWeightedPatternMultipleCandidatesRemain => {
+                self.observer.entropy_priority_queue.push(CoordEntropy {
+                    coord,
+                    entropy_with_noise: cell.entropy_with_noise(),
+                });
+            }
+            D::Finalized => {
+                self.num_cells_with_more_than_one_weighted_compatible_pattern -= 1;
+            }
+            D::RemovedFinalCompatiblePattern => return Err(Contradiction),
+        }
+        self.propagator
+            .removed_patterns_to_propagate
+            .push(RemovedPattern { coord, pattern_id });
+        Ok(())
+    }
+}
+
+/// The result of `RunBorrow::observe_only`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Observation {
+    /// Every cell was already decided, so nothing was observed
+    Complete,
+    /// The cell at `coord` was observed to be `pattern_id`
+    Observed { coord: Coord, pattern_id: PatternId },
+}
+
+/// Receives notifications of the individual decisions made while collapsing a wave, for
+/// building debuggers and visualisations. All methods default to doing nothing.
+pub trait DebugListener {
+    /// Called when the cell at `coord` is observed to be `pattern_id`
+    fn on_observe(&mut self, coord: Coord, pattern_id: PatternId) {
+        let _ = (coord, pattern_id);
+    }
+    /// Called when the removal of `pattern_id` from the cell at `coord` is propagated
+    fn on_remove_pattern(&mut self, coord: Coord, pattern_id: PatternId) {
+        let _ = (coord, pattern_id);
+    }
+    /// Called when the cell at `coord` is left with no compatible patterns
+    fn on_contradiction(&mut self, coord: Coord) {
+        let _ = coord;
+    }
+    /// Called when propagation removes a pattern from the cell at `coord`, leaving it with
+    /// `num_compatible_patterns` compatible patterns (0 on contradiction)
+    fn on_narrow(&mut self, coord: Coord, num_compatible_patterns: u32) {
+        let _ = (coord, num_compatible_patterns);
+    }
+}
+
+#[derive(Clone)]
+pub struct NoDebugListener;
+impl DebugListener for NoDebugListener {}
+
+// Adapts a closure into a `DebugListener` which is only interested in observations
+struct OnObserve<C: FnMut(Coord, PatternId)>(C);
+
+impl<C: FnMut(Coord, PatternId)> DebugListener for OnObserve<C> {
+    fn on_observe(&mut self, coord: Coord, pattern_id: PatternId) {
+        (self.0)(coord, pattern_id);
+    }
+}
+
+// Remembers where the most recent contradiction happened
+pub(crate) struct LastContradiction(pub(crate) Option<Coord>);
+
+impl DebugListener for LastContradiction {
+    fn on_contradiction(&mut self, coord: Coord) {
+        self.0 = Some(coord);
+    }
+}
+
+impl<L: DebugListener> DebugListener for &mut L {
+    fn on_observe(&mut self, coord: Coord, pattern_id: PatternId) {
+        (**self).on_observe(coord, pattern_id);
+    }
+    fn on_remove_pattern(&mut self, coord: Coord, pattern_id: PatternId) {
+        (**self).on_remove_pattern(coord, pattern_id);
+    }
+    fn on_contradiction(&mut self, coord: Coord) {
+        (**self).on_contradiction(coord);
+    }
+    fn on_narrow(&mut self, coord: Coord, num_compatible_patterns: u32) {
+        (**self).on_narrow(coord, num_compatible_patterns);
+    }
+}
+
+/// Counts how often propagation leaves each cell with few compatible patterns, to find the
+/// areas of the output where contradictions are likely. Counts accumulate over every run the
+/// listener is passed to, including retries after contradictions, so e.g. anchors forced by
+/// a `ForbidPattern` can be moved away from the worst cells.
+#[derive(Debug, Clone)]
+pub struct TroubleSpots {
+    threshold: u32,
+    counts: HashMap<Coord, u32>,
+}
+
+impl TroubleSpots {
+    /// A cell is counted each time propagation leaves it with `threshold` or fewer
+    /// compatible patterns. Contradictions are always counted.
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            counts: HashMap::new(),
+        }
+    }
+    pub fn count(&self, coord: Coord) -> u32 {
+        self.counts.get(&coord).cloned().unwrap_or(0)
+    }
+    /// Up to `n` of the cells with the highest counts, most troubled first. Cells with equal
+    /// counts are in row-major order.
+    pub fn worst(&self, n: usize) -> Vec<(Coord, u32)> {
+        let mut counts = self
+            .counts
+            .iter()
+            .map(|(&coord, &count)| (coord, count))
+            .collect::<Vec<_>>();
+        counts.sort_unstable_by_key(|&(coord, count)| {
+            (cmp::Reverse(count), coord.y, coord.x)
+        });
+        counts.truncate(n);
+        counts
+    }
+    pub fn clear(&mut self) {
+        self.counts.clear();
+    }
+}
+
+impl DebugListener for TroubleSpots {
+    fn on_narrow(&mut self, coord: Coord, num_compatible_patterns: u32) {
+        if num_compatible_patterns <= self.threshold {
+            *self.counts.entry(coord).or_insert(0) += 1;
+        }
     }
 }
 
 pub trait ForbidPattern {
     fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R);
+    /// Called each time the cell at `coord` is observed to be `pattern_id`, before the
+    /// observation is propagated, allowing constraints which depend on the decisions made so
+    /// far to forbid further patterns. Does nothing by default.
+    fn observed<W: Wrap>(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+        oi: &mut ObserveInterface<W>,
+    ) {
+        let _ = (coord, pattern_id, oi);
+    }
 }
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serialize", derive(Serialize, Deserialize))]
 pub struct ForbidNothing;
 impl ForbidPattern for ForbidNothing {
     fn forbid<W: Wrap, R: Rng>(&mut self, _fi: &mut ForbidInterface<W>, _rng: &mut R) {}
 }
 
 pub struct ForbidRef<'a, F: ForbidPattern>(&'a mut F);
+impl<'a, F: ForbidPattern> Borrow<F> for ForbidRef<'a, F> {
+    fn borrow(&self) -> &F {
+        self.0
+    }
+}
+impl<'a, F: ForbidPattern> BorrowMut<F> for ForbidRef<'a, F> {
+    fn borrow_mut(&mut self) -> &mut F {
+        self.0
+    }
+}
 impl<'a, F: ForbidPattern> ForbidPattern for ForbidRef<'a, F> {
     fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
         self.0.forbid(fi, rng);
     }
+    fn observed<W: Wrap>(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+        oi: &mut ObserveInterface<W>,
+    ) {
+        self.0.observed(coord, pattern_id, oi);
+    }
+}
+
+/// Applies both constraints, in order
+impl<A: ForbidPattern, B: ForbidPattern> ForbidPattern for (A, B) {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        self.0.forbid(fi, rng);
+        self.1.forbid(fi, rng);
+    }
+    fn observed<W: Wrap>(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+        oi: &mut ObserveInterface<W>,
+    ) {
+        self.0.observed(coord, pattern_id, oi);
+        self.1.observed(coord, pattern_id, oi);
+    }
+}
+
+/// Forbids every pattern reported by `GlobalStats::unsatisfiable_patterns` in every cell,
+/// sparing the observer from choosing patterns which are guaranteed to cause contradictions
+#[derive(Clone)]
+pub struct ForbidUnsatisfiable;
+impl ForbidPattern for ForbidUnsatisfiable {
+    fn forbid<W: Wrap, R: Rng>(&mut self, fi: &mut ForbidInterface<W>, rng: &mut R) {
+        let wave_size = fi.wave_size();
+        let unsatisfiable_patterns = fi
+            .core
+            .global_stats()
+            .unsatisfiable_patterns_with_wrap::<W>(wave_size);
+        for pattern_id in unsatisfiable_patterns {
+            for coord in wave_size.coord_iter_row_major() {
+                if fi.forbid_pattern(coord, pattern_id, rng).is_err() {
+                    // every pattern is unsatisfiable, so there's nothing to be gained by
+                    // continuing
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Completes on its second poll, after waking its task on the first, returning control to the
+// executor in between
+#[cfg(feature = "async")]
+struct YieldNow {
+    yielded: bool,
+}
+
+#[cfg(feature = "async")]
+impl YieldNow {
+    fn new() -> Self {
+        Self { yielded: false }
+    }
+}
+
+#[cfg(feature = "async")]
+impl Future for YieldNow {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<()> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
 }
 
 /// Represents a running instance of wfc which borrows its resources, making it
 /// possible to re-use memory across multiple runs.
-pub struct RunBorrow<'a, W: Wrap = WrapXY, F: ForbidPattern = ForbidNothing> {
-    core: RunBorrowCore<'a, W>,
+pub struct RunBorrow<
+    'a,
+    W: Wrap = WrapXY,
+    F: ForbidPattern = ForbidNothing,
+    S: CellStorage<WaveCell> = Grid<WaveCell>,
+> {
+    core: RunBorrowCore<'a, W, S>,
     forbid: F,
 }
 
@@ -918,24 +3083,40 @@ impl<'a, F: ForbidPattern> RunBorrow<'a, WrapXY, F> {
     }
 }
 
-impl<'a, W: Wrap, F: ForbidPattern> RunBorrow<'a, W, F> {
+impl<'a, W: Wrap, F: ForbidPattern, S: CellStorage<WaveCell>> RunBorrow<'a, W, F, S> {
     pub fn new_wrap_forbid<R: Rng>(
         context: &'a mut Context,
-        wave: &'a mut Wave,
+        wave: &'a mut Wave<S>,
         global_stats: &'a GlobalStats,
         wrap: W,
         mut forbid: F,
         rng: &mut R,
     ) -> Self {
         let mut core = RunBorrowCore::new(context, wave, global_stats, wrap, rng);
-        forbid.forbid(&mut ForbidInterface(&mut core), rng);
+        forbid.forbid(&mut ForbidInterface::new(&mut core), rng);
         Self { core, forbid }
     }
+    /// Like `new_wrap_forbid`, but fails if applying `forbid` causes a contradiction,
+    /// reporting which calls to the `ForbidInterface` caused contradictions
+    pub fn new_wrap_forbid_checked<R: Rng>(
+        context: &'a mut Context,
+        wave: &'a mut Wave<S>,
+        global_stats: &'a GlobalStats,
+        wrap: W,
+        mut forbid: F,
+        rng: &mut R,
+    ) -> Result<Self, ForbidContradictions> {
+        let mut core = RunBorrowCore::new(context, wave, global_stats, wrap, rng);
+        let mut forbid_interface = ForbidInterface::new(&mut core);
+        forbid.forbid(&mut forbid_interface, rng);
+        forbid_interface.check()?;
+        Ok(Self { core, forbid })
+    }
 }
 
-struct RunBorrowCore<'a, W: Wrap = WrapXY> {
+struct RunBorrowCore<'a, W: Wrap = WrapXY, S: CellStorage<WaveCell> = Grid<WaveCell>> {
     context: &'a mut Context,
-    wave: &'a mut Wave,
+    wave: &'a mut Wave<S>,
     global_stats: &'a GlobalStats,
     output_wrap: PhantomData<W>,
 }
@@ -996,6 +3177,50 @@ impl<'a> WaveCellRef<'a> {
     pub fn sum_compatible_pattern_weight(&self) -> u32 {
         self.wave_cell.stats.sum_compatible_pattern_weight
     }
+    pub fn chosen_pattern_id(&self) -> Result<PatternId, ChosenPatternIdError> {
+        self.wave_cell.chosen_pattern_id()
+    }
+    /// Whether the cell may still be `pattern_id`
+    pub fn is_pattern_compatible(&self, pattern_id: PatternId) -> bool {
+        self.wave_cell
+            .num_ways_to_become_each_pattern
+            .get(pattern_id)
+            .is_some_and(|num_ways_to_become_pattern| {
+                !num_ways_to_become_pattern.is_zero()
+            })
+    }
+    /// The patterns the cell may still be, in order of id
+    pub fn compatible_pattern_ids(&self) -> impl '_ + Iterator<Item = PatternId> {
+        self.wave_cell
+            .num_ways_to_become_each_pattern
+            .enumerate()
+            .filter(|(_, num_ways_to_become_pattern)| {
+                !num_ways_to_become_pattern.is_zero()
+            })
+            .map(|(pattern_id, _)| pattern_id)
+    }
+    /// The patterns which the adjacency rules allow in the neighbouring cell in `direction`,
+    /// given the patterns this cell may still be, in order of id
+    pub fn compatible_in_direction(
+        &self,
+        direction: CardinalDirection,
+    ) -> Vec<PatternId> {
+        let mut compatible = vec![false; self.global_stats.num_patterns()];
+        for pattern_id in self.compatible_pattern_ids() {
+            for &neighbour_id in self
+                .global_stats
+                .compatible_patterns_in_direction(pattern_id, direction)
+            {
+                compatible[neighbour_id as usize] = true;
+            }
+        }
+        compatible
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, compatible)| compatible)
+            .map(|(neighbour_id, _)| neighbour_id as PatternId)
+            .collect()
+    }
     pub fn enumerate_compatible_pattern_weights(
         &self,
     ) -> EnumerateCompatiblePatternWeights {
@@ -1022,39 +3247,387 @@ impl<'a> WaveCellRef<'a> {
     }
 }
 
-impl<'a, W: Wrap, F: ForbidPattern> RunBorrow<'a, W, F> {
+impl<'a, W: Wrap, F: ForbidPattern, S: CellStorage<WaveCell>> RunBorrow<'a, W, F, S> {
+    /// Resets the wave and applies the `ForbidPattern`. If forbidding patterns causes a
+    /// contradiction, the wave is left reset without the remaining patterns forbidden. Use
+    /// `try_reset` to detect this.
     pub fn reset<R: Rng>(&mut self, rng: &mut R) {
         self.core.reset(rng);
         self.forbid
-            .forbid(&mut ForbidInterface(&mut self.core), rng);
+            .forbid(&mut ForbidInterface::new(&mut self.core), rng);
     }
 
-    pub fn step<R: Rng>(&mut self, rng: &mut R) -> Result<Observe, PropagateError> {
-        let result = self.core.step(rng);
-        if result.is_err() {
-            self.reset(rng);
+    /// Like `reset`, but if forbidding patterns causes a contradiction, resets and forbids
+    /// again, up to `max_attempts` attempts in total (which may help if the `ForbidPattern`
+    /// makes random choices). Contradictions which the `ForbidPattern` recovered from with
+    /// `ForbidInterface::retry` don't count. Returns an error if every attempt caused a
+    /// contradiction, in which case the wave is left as by `reset`. Panics if `max_attempts`
+    /// is 0, as the wave wouldn't be reset at all.
+    pub fn try_reset<R: Rng>(
+        &mut self,
+        max_attempts: usize,
+        rng: &mut R,
+    ) -> Result<(), SetupError> {
+        assert!(max_attempts > 0, "try_reset needs at least one attempt");
+        for _ in 0..max_attempts {
+            self.core.reset(rng);
+            let mut forbid_interface = ForbidInterface::new(&mut self.core);
+            self.forbid.forbid(&mut forbid_interface, rng);
+            if forbid_interface.check().is_ok() {
+                return Ok(());
+            }
         }
-        result
+        Err(SetupError::UnsatisfiableForbid)
     }
 
-    pub fn collapse<R: Rng>(&mut self, rng: &mut R) -> Result<(), PropagateError> {
-        let result = self.core.collapse(rng);
-        if result.is_err() {
-            self.reset(rng);
-        }
-        result
+    /// Like `reset`, but if forbidding patterns causes a contradiction, returns the calls to
+    /// the `ForbidInterface` which caused contradictions
+    pub fn reset_checked<R: Rng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<(), ForbidContradictions> {
+        self.core.reset(rng);
+        let mut forbid_interface = ForbidInterface::new(&mut self.core);
+        self.forbid.forbid(&mut forbid_interface, rng);
+        forbid_interface.check()
     }
 
-    pub fn wave_cell_ref(&self, coord: Coord) -> WaveCellRef {
-        self.core.wave_cell_ref(coord)
+    pub fn forbid_mut(&mut self) -> &mut F {
+        &mut self.forbid
     }
 
-    pub fn wave_cell_ref_iter(&self) -> impl Iterator<Item = WaveCellRef> {
-        self.core.wave_cell_ref_iter()
+    // Removes `pattern_id` from the cell at `coord` without propagating the removal, which
+    // is left for the next `propagate_only`. Used by `AlwaysCompatible` in its manual state.
+    pub(crate) fn forbid_pattern_unpropagated(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+    ) -> Result<(), PropagateError> {
+        CoreInterface::forbid_pattern_during_collapse(&mut self.core, coord, pattern_id)
+            .map_err(|_: Contradiction| PropagateError::Contradiction)
     }
 
-    pub fn wave_cell_ref_enumerate(&self) -> impl Iterator<Item = (Coord, WaveCellRef)> {
-        self.core.wave_cell_ref_enumerate()
+    pub(crate) fn forbid_all_patterns_except_unpropagated(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+    ) -> Result<(), PropagateError> {
+        for other in 0..self.core.global_stats.num_patterns() as PatternId {
+            if other != pattern_id {
+                self.forbid_pattern_unpropagated(coord, other)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forbid more patterns without resetting the wave, e.g. after the state of the
+    /// `ForbidPattern` has changed. `f` is passed the `ForbidPattern` and an interface to the
+    /// wave, as in `ForbidPattern::forbid`. Only the cells affected by the newly forbidden
+    /// patterns are propagated to. If this causes a contradiction, the run is reset (which
+    /// calls `ForbidPattern::forbid`) and the contradiction is returned.
+    pub fn forbid_with<R: Rng, G>(
+        &mut self,
+        rng: &mut R,
+        f: G,
+    ) -> Result<(), PropagateError>
+    where
+        G: FnOnce(&mut F, &mut ForbidInterface<W>, &mut R),
+    {
+        let mut forbid_interface = ForbidInterface::new(&mut self.core);
+        f(&mut self.forbid, &mut forbid_interface, rng);
+        if forbid_interface.check().is_err() {
+            self.reset(rng);
+            Err(PropagateError::Contradiction)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The first half of `step`, which observes a single cell without propagating the
+    /// result, so the wave can be inspected in between (e.g. to visualise how the
+    /// observation ripples out). Each call must be followed by `propagate_only` before
+    /// observing again. On contradiction the run is reset.
+    pub fn observe_only<R: Rng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<Observation, PropagateError> {
+        let result =
+            self.core
+                .observe_with_listener(rng, &mut NoDebugListener, &mut self.forbid);
+        if result.is_err() {
+            self.reset(rng);
+        }
+        result
+    }
+
+    /// The second half of `step`, which propagates the consequences of the last
+    /// `observe_only`. On contradiction the run is reset.
+    pub fn propagate_only<R: Rng>(&mut self, rng: &mut R) -> Result<(), PropagateError> {
+        let result = self.core.propagate();
+        if result.is_err() {
+            self.reset(rng);
+        }
+        result
+    }
+
+    pub fn step<R: Rng>(&mut self, rng: &mut R) -> Result<Observe, PropagateError> {
+        let result = self.core.step(rng, &mut self.forbid);
+        if result.is_err() {
+            self.reset(rng);
+        }
+        result
+    }
+
+    pub fn collapse<R: Rng>(&mut self, rng: &mut R) -> Result<(), PropagateError> {
+        let result = self.core.collapse(rng, &mut self.forbid);
+        if result.is_err() {
+            self.reset(rng);
+        }
+        result
+    }
+
+    /// Collapse the wave until it is complete or `budget` runs out, allowing generation to be
+    /// spread across multiple calls (e.g. one per frame). On contradiction the run is reset.
+    pub fn collapse_with_budget<R: Rng>(
+        &mut self,
+        budget: Budget,
+        rng: &mut R,
+    ) -> Result<BudgetOutcome, PropagateError> {
+        let result = self
+            .core
+            .collapse_with_budget(budget, rng, &mut self.forbid);
+        if result.is_err() {
+            self.reset(rng);
+        }
+        result
+    }
+
+    /// Like `collapse`, but `token` is checked before each step, and collapsing stops with
+    /// `CancellableError::Cancelled` once it is cancelled. On contradiction the run is reset.
+    pub fn collapse_cancellable<R: Rng>(
+        &mut self,
+        token: &CancellationToken,
+        rng: &mut R,
+    ) -> Result<(), CancellableError> {
+        let result = self.core.collapse_cancellable(token, rng, &mut self.forbid);
+        if result == Err(CancellableError::Contradiction) {
+            self.reset(rng);
+        }
+        result
+    }
+
+    /// Like `collapse`, but returns control to the executor after every `steps_per_yield`
+    /// steps, so collapsing can share a thread with other work such as a browser's event loop
+    /// or a game's main loop. Requires the `async` feature. On contradiction the run is reset.
+    #[cfg(feature = "async")]
+    pub async fn collapse_async<R: Rng>(
+        &mut self,
+        steps_per_yield: NonZeroUsize,
+        rng: &mut R,
+    ) -> Result<(), PropagateError> {
+        self.collapse_async_with(steps_per_yield, YieldNow::new, rng)
+            .await
+    }
+
+    /// Like `collapse_async`, but awaits the future returned by `yield_now` between batches of
+    /// steps, e.g. an executor's own `yield_now`, or a future which completes on the next frame
+    #[cfg(feature = "async")]
+    pub async fn collapse_async_with<R, Y, YF>(
+        &mut self,
+        steps_per_yield: NonZeroUsize,
+        mut yield_now: YF,
+        rng: &mut R,
+    ) -> Result<(), PropagateError>
+    where
+        R: Rng,
+        Y: Future<Output = ()>,
+        YF: FnMut() -> Y,
+    {
+        loop {
+            for _ in 0..steps_per_yield.get() {
+                if let Observe::Complete = self.step(rng)? {
+                    return Ok(());
+                }
+            }
+            yield_now().await;
+        }
+    }
+
+    /// Like `step`, but reports observations, pattern removals and contradictions to
+    /// `listener` as they happen
+    pub fn step_with_listener<R: Rng, L: DebugListener>(
+        &mut self,
+        rng: &mut R,
+        listener: &mut L,
+    ) -> Result<Observe, PropagateError> {
+        let result = self
+            .core
+            .step_with_listener(rng, listener, &mut self.forbid);
+        if result.is_err() {
+            self.reset(rng);
+        }
+        result
+    }
+
+    /// Like `step`, but calls `on_cell_decided` with the cell and pattern chosen by the
+    /// observation, e.g. to give feedback in interactive tools
+    pub fn step_with<R: Rng, C: FnMut(Coord, PatternId)>(
+        &mut self,
+        rng: &mut R,
+        on_cell_decided: C,
+    ) -> Result<Observe, PropagateError> {
+        self.step_with_listener(rng, &mut OnObserve(on_cell_decided))
+    }
+
+    /// Like `collapse`, but reports observations, pattern removals and contradictions to
+    /// `listener` as they happen
+    pub fn collapse_with_listener<R: Rng, L: DebugListener>(
+        &mut self,
+        rng: &mut R,
+        listener: &mut L,
+    ) -> Result<(), PropagateError> {
+        let result = self
+            .core
+            .collapse_with_listener(rng, listener, &mut self.forbid);
+        if result.is_err() {
+            self.reset(rng);
+        }
+        result
+    }
+
+    /// Like `collapse`, but on contradiction only the cells within euclidean distance
+    /// `radius` of the contradiction (measured as by `wrap::distance_squared`) are
+    /// restarted, and the decisions made
+    /// elsewhere are kept. Gives up with a contradiction after `max_restarts` partial
+    /// restarts, in which case the run is reset. This avoids throwing away almost all the
+    /// work done on large outputs, where contradictions are common but local.
+    pub fn collapse_with_partial_restarts<R: Rng>(
+        &mut self,
+        radius: u32,
+        max_restarts: usize,
+        rng: &mut R,
+    ) -> Result<(), PropagateError> {
+        self.collapse_with_partial_restarts_observed(
+            radius,
+            max_restarts,
+            rng,
+            &mut retry::NoRetryObserver,
+        )
+    }
+
+    // Like `collapse_with_partial_restarts`, treating the first collapse and each partial
+    // restart as an attempt
+    pub(crate) fn collapse_with_partial_restarts_observed<R: Rng, O: RetryObserver>(
+        &mut self,
+        radius: u32,
+        max_restarts: usize,
+        rng: &mut R,
+        observer: &mut O,
+    ) -> Result<(), PropagateError> {
+        let max_attempts = max_restarts.checked_add(1);
+        let mut num_restarts = 0;
+        observer.on_attempt_start(1, max_attempts);
+        loop {
+            let mut last_contradiction = LastContradiction(None);
+            match self.core.collapse_with_listener(
+                rng,
+                &mut last_contradiction,
+                &mut self.forbid,
+            ) {
+                Ok(()) => {
+                    observer.on_success(num_restarts + 1);
+                    return Ok(());
+                }
+                Err(e) => {
+                    observer.on_contradiction(num_restarts + 1, last_contradiction.0);
+                    if num_restarts == max_restarts {
+                        self.reset(rng);
+                        return Err(e);
+                    }
+                    num_restarts += 1;
+                    observer.on_attempt_start(num_restarts + 1, max_attempts);
+                    match last_contradiction.0 {
+                        Some(coord) => self.restart_around(coord, radius, rng),
+                        None => self.reset(rng),
+                    }
+                }
+            }
+        }
+    }
+
+    // Resets the wave and replays every observation made outside `radius` of `coord`, except
+    // for the most recent one which led to the contradiction. Propagating the replayed
+    // observations restores the constraints they impose on the restarted cells. They are a
+    // subset of the decisions which were consistent before the contradiction, so replaying
+    // them can only fail if the forbid callbacks behave differently the second time, in
+    // which case the whole wave is restarted.
+    fn restart_around<R: Rng>(&mut self, coord: Coord, radius: u32, rng: &mut R) {
+        let mut observations = mem::take(&mut self.core.context.observations);
+        observations.pop();
+        let wave_size = self.core.wave.cells.size();
+        let mut restarted = Grid::new_copy(wave_size, false);
+        for coord in wrap::coords_within_distance::<W>(coord, radius, wave_size) {
+            *restarted.get_checked_mut(coord) = true;
+        }
+        self.reset(rng);
+        for &(coord, pattern_id) in observations.iter() {
+            if *restarted.get_checked(coord) {
+                continue;
+            }
+            if self
+                .core
+                .replay_observation(coord, pattern_id, &mut self.forbid)
+                .is_err()
+            {
+                self.reset(rng);
+                return;
+            }
+        }
+    }
+
+    pub fn wave(&self) -> &Wave<S> {
+        self.core.wave
+    }
+
+    /// See `Context::heap_len`
+    pub fn heap_len(&self) -> usize {
+        self.core.context.heap_len()
+    }
+
+    /// See `Context::stale_fraction`
+    pub fn stale_fraction(&self) -> f64 {
+        self.core.context.stale_fraction(self.core.wave)
+    }
+
+    /// See `Context::compact`
+    pub fn compact(&mut self) {
+        self.core.context.compact(self.core.wave);
+    }
+
+    /// See `Wave::candidate_count_grid`
+    pub fn candidate_count_grid(&self) -> Grid<u32> {
+        self.core.wave.candidate_count_grid()
+    }
+
+    pub fn wave_cell_ref(&self, coord: Coord) -> WaveCellRef {
+        self.core.wave_cell_ref(coord)
+    }
+
+    pub fn wave_cell_ref_iter(&self) -> impl Iterator<Item = WaveCellRef> {
+        self.core.wave_cell_ref_iter()
+    }
+
+    pub fn wave_cell_ref_enumerate(&self) -> impl Iterator<Item = (Coord, WaveCellRef)> {
+        self.core.wave_cell_ref_enumerate()
+    }
+}
+
+impl<'a, W: Wrap, F: ForbidPattern> RunBorrow<'a, W, F> {
+    /// Like `reset`, but first changes the size of the wave to `size` with `Wave::resize`, so
+    /// the output size can be changed while reusing the per-pattern tables of its cells
+    pub fn reset_with_size<R: Rng>(&mut self, size: Size, rng: &mut R) {
+        self.core.wave.resize(size);
+        self.reset(rng);
     }
 
     pub fn collapse_retrying<R, RB>(&mut self, mut retry: RB, rng: &mut R) -> RB::Return
@@ -1064,50 +3637,153 @@ impl<'a, W: Wrap, F: ForbidPattern> RunBorrow<'a, W, F> {
     {
         retry.retry(self, rng)
     }
+
+    /// Like `collapse_retrying`, but `observer` is told when each attempt starts and how it
+    /// ends, e.g. to show progress
+    pub fn collapse_retrying_observed<R, RB, O>(
+        &mut self,
+        mut retry: RB,
+        observer: &mut O,
+        rng: &mut R,
+    ) -> RB::Return
+    where
+        R: Rng,
+        RB: retry::RetryBorrow,
+        O: RetryObserver,
+    {
+        retry.retry_observed(self, rng, observer)
+    }
 }
 
-impl<'a, W: Wrap> RunBorrowCore<'a, W> {
+impl<'a, W: Wrap, S: CellStorage<WaveCell>> RunBorrowCore<'a, W, S> {
     fn new<R: Rng>(
         context: &'a mut Context,
-        wave: &'a mut Wave,
+        wave: &'a mut Wave<S>,
         global_stats: &'a GlobalStats,
         output_wrap: W,
         rng: &mut R,
     ) -> Self {
         let _ = output_wrap;
-        wave.init(global_stats, rng);
-        context.init(wave, global_stats);
-        Self {
+        let mut core = Self {
             context,
             wave,
             global_stats,
             output_wrap: PhantomData,
-        }
+        };
+        core.reset(rng);
+        core
     }
 
     fn reset<R: Rng>(&mut self, rng: &mut R) {
+        assert!(
+            W::ONE_TO_ONE || self.global_stats.offset_rules.is_empty(),
+            "offset adjacency can't be used with wraps where a cell may be the neighbour of \
+            more than one cell"
+        );
         self.wave.init(self.global_stats, rng);
         self.context.init(&self.wave, self.global_stats);
     }
 
     fn propagate(&mut self) -> Result<(), PropagateError> {
-        self.context.propagate::<W>(self.wave, self.global_stats)
+        self.propagate_with_listener(&mut NoDebugListener)
+    }
+
+    fn propagate_with_listener<L: DebugListener>(
+        &mut self,
+        listener: &mut L,
+    ) -> Result<(), PropagateError> {
+        self.context
+            .propagate::<W, L, S>(self.wave, self.global_stats, listener)
+    }
+
+    fn observe_with_listener<R: Rng, L: DebugListener, F: ForbidPattern>(
+        &mut self,
+        rng: &mut R,
+        listener: &mut L,
+        forbid: &mut F,
+    ) -> Result<Observation, PropagateError> {
+        // removals queued by `reset` for patterns excluded by offset rules
+        if !self
+            .context
+            .propagator
+            .removed_patterns_to_propagate
+            .is_empty()
+        {
+            self.propagate_with_listener(listener)?;
+        }
+        let observation = self.context.observe::<W, _, _, S>(
+            self.wave,
+            self.global_stats,
+            rng,
+            listener,
+        );
+        match observation {
+            Observation::Complete => Ok(observation),
+            Observation::Observed { coord, pattern_id } => {
+                let mut observe_interface = ObserveInterface::new(self);
+                forbid.observed(coord, pattern_id, &mut observe_interface);
+                if let Some(coord) = observe_interface.contradiction {
+                    listener.on_contradiction(coord);
+                    return Err(PropagateError::Contradiction);
+                }
+                Ok(observation)
+            }
+        }
     }
 
-    fn observe<R: Rng>(&mut self, rng: &mut R) -> Observe {
-        self.context.observe(self.wave, self.global_stats, rng)
+    fn step<R: Rng, F: ForbidPattern>(
+        &mut self,
+        rng: &mut R,
+        forbid: &mut F,
+    ) -> Result<Observe, PropagateError> {
+        self.step_with_listener(rng, &mut NoDebugListener, forbid)
     }
 
-    fn step<R: Rng>(&mut self, rng: &mut R) -> Result<Observe, PropagateError> {
-        match self.observe(rng) {
-            Observe::Complete => Ok(Observe::Complete),
-            Observe::Incomplete => {
-                self.propagate()?;
+    fn step_with_listener<R: Rng, L: DebugListener, F: ForbidPattern>(
+        &mut self,
+        rng: &mut R,
+        listener: &mut L,
+        forbid: &mut F,
+    ) -> Result<Observe, PropagateError> {
+        match self.observe_with_listener(rng, listener, forbid)? {
+            Observation::Complete => Ok(Observe::Complete),
+            Observation::Observed { .. } => {
+                self.propagate_with_listener(listener)?;
                 Ok(Observe::Incomplete)
             }
         }
     }
 
+    fn replay_observation<F: ForbidPattern>(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+        forbid: &mut F,
+    ) -> Result<(), PropagateError> {
+        let wave_cell = self.wave.cells.get_checked_mut(coord);
+        if wave_cell.num_ways_to_become_each_pattern[pattern_id].is_zero() {
+            return Err(PropagateError::Contradiction);
+        }
+        // only cells with several weighted patterns are counted, so cells observed by
+        // `observe_unweighted` may not be
+        if wave_cell.stats.num_weighted_compatible_patterns > 1 {
+            self.context
+                .num_cells_with_more_than_one_weighted_compatible_pattern -= 1;
+        }
+        CellAtCoordMut { wave_cell, coord }.remove_all_patterns_except_one(
+            pattern_id,
+            self.global_stats,
+            &mut self.context.propagator,
+        );
+        self.context.observations.push((coord, pattern_id));
+        let mut observe_interface = ObserveInterface::new(self);
+        forbid.observed(coord, pattern_id, &mut observe_interface);
+        if observe_interface.contradiction.is_some() {
+            return Err(PropagateError::Contradiction);
+        }
+        self.propagate()
+    }
+
     fn wave_cell_handle(&mut self, coord: Coord) -> WaveCellHandle {
         WaveCellHandle::new(
             self.wave,
@@ -1136,19 +3812,69 @@ impl<'a, W: Wrap> RunBorrowCore<'a, W> {
         self.propagate()
     }
 
-    fn collapse<R: Rng>(&mut self, rng: &mut R) -> Result<(), PropagateError> {
+    fn collapse<R: Rng, F: ForbidPattern>(
+        &mut self,
+        rng: &mut R,
+        forbid: &mut F,
+    ) -> Result<(), PropagateError> {
+        self.collapse_with_listener(rng, &mut NoDebugListener, forbid)
+    }
+
+    fn collapse_with_listener<R: Rng, L: DebugListener, F: ForbidPattern>(
+        &mut self,
+        rng: &mut R,
+        listener: &mut L,
+        forbid: &mut F,
+    ) -> Result<(), PropagateError> {
         loop {
-            match self.observe(rng) {
+            match self.step_with_listener(rng, listener, forbid)? {
                 Observe::Complete => return Ok(()),
-                Observe::Incomplete => {
-                    self.propagate()?;
+                Observe::Incomplete => (),
+            }
+        }
+    }
+
+    fn collapse_with_budget<R: Rng, F: ForbidPattern>(
+        &mut self,
+        budget: Budget,
+        rng: &mut R,
+        forbid: &mut F,
+    ) -> Result<BudgetOutcome, PropagateError> {
+        let start = Instant::now();
+        let mut num_steps = 0;
+        loop {
+            if budget.is_exhausted(num_steps, start) {
+                return Ok(BudgetOutcome::Exhausted);
+            }
+            match self.step(rng, forbid)? {
+                Observe::Complete => return Ok(BudgetOutcome::Complete),
+                Observe::Incomplete => num_steps += 1,
+            }
+        }
+    }
+
+    fn collapse_cancellable<R: Rng, F: ForbidPattern>(
+        &mut self,
+        token: &CancellationToken,
+        rng: &mut R,
+        forbid: &mut F,
+    ) -> Result<(), CancellableError> {
+        loop {
+            if token.is_cancelled() {
+                return Err(CancellableError::Cancelled);
+            }
+            match self.step(rng, forbid) {
+                Ok(Observe::Complete) => return Ok(()),
+                Ok(Observe::Incomplete) => (),
+                Err(PropagateError::Contradiction) => {
+                    return Err(CancellableError::Contradiction)
                 }
             }
         }
     }
 
-    fn wave_cell_ref(&self, coord: Coord) -> WaveCellRef {
-        let wave_cell = self.wave.grid.get_checked(coord);
+    fn wave_cell_ref(&self, coord: Coord) -> WaveCellRef<'_> {
+        let wave_cell = self.wave.cells.get_checked(coord);
         WaveCellRef {
             wave_cell,
             global_stats: self.global_stats,
@@ -1156,57 +3882,249 @@ impl<'a, W: Wrap> RunBorrowCore<'a, W> {
     }
 
     fn wave_cell_ref_iter(&self) -> impl Iterator<Item = WaveCellRef> {
-        self.wave.grid.iter().map(move |wave_cell| WaveCellRef {
-            wave_cell,
-            global_stats: self.global_stats,
-        })
+        self.wave_cell_ref_enumerate()
+            .map(|(_, wave_cell_ref)| wave_cell_ref)
     }
 
     fn wave_cell_ref_enumerate(&self) -> impl Iterator<Item = (Coord, WaveCellRef)> {
-        self.wave.grid.enumerate().map(move |(coord, wave_cell)| {
-            let wave_cell_ref = WaveCellRef {
-                wave_cell,
-                global_stats: self.global_stats,
-            };
-            (coord, wave_cell_ref)
-        })
+        self.wave
+            .cells
+            .coord_iter()
+            .map(move |coord| (coord, self.wave_cell_ref(coord)))
     }
 }
 
-pub struct ForbidInterface<'a, 'b, W: Wrap>(&'a mut RunBorrowCore<'b, W>);
+// The operations on a run needed by `ForbidInterface` and `ObserveInterface`, as a trait object
+// so that they (and so `ForbidPattern`) don't depend on how the wave is stored
+trait CoreInterface {
+    fn wave_size(&self) -> Size;
+    fn global_stats(&self) -> &GlobalStats;
+    fn wave_cell_ref(&self, coord: Coord) -> WaveCellRef<'_>;
+    fn forbid_all_patterns_except(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+    ) -> Result<(), PropagateError>;
+    fn forbid_pattern(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+    ) -> Result<(), PropagateError>;
+    fn forbid_pattern_during_collapse(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+    ) -> Result<(), Contradiction>;
+    fn reset(&mut self, rng: &mut dyn RngCore);
+}
 
-impl<'a, 'b, W: Wrap> ForbidInterface<'a, 'b, W> {
-    pub fn wave_size(&self) -> Size {
-        self.0.wave.grid.size()
+impl<'a, W: Wrap, S: CellStorage<WaveCell>> CoreInterface for RunBorrowCore<'a, W, S> {
+    fn wave_size(&self) -> Size {
+        self.wave.cells.size()
     }
-
-    pub fn forbid_all_patterns_except<R: Rng>(
+    fn global_stats(&self) -> &GlobalStats {
+        self.global_stats
+    }
+    fn wave_cell_ref(&self, coord: Coord) -> WaveCellRef<'_> {
+        RunBorrowCore::wave_cell_ref(self, coord)
+    }
+    fn forbid_all_patterns_except(
         &mut self,
         coord: Coord,
         pattern_id: PatternId,
-        rng: &mut R,
     ) -> Result<(), PropagateError> {
-        let result = self.0.forbid_all_patterns_except(coord, pattern_id);
-        if result.is_err() {
-            self.0.reset(rng);
-        }
-        result
+        RunBorrowCore::forbid_all_patterns_except(self, coord, pattern_id)
     }
-
-    pub fn forbid_pattern<R: Rng>(
+    fn forbid_pattern(
         &mut self,
         coord: Coord,
         pattern_id: PatternId,
-        rng: &mut R,
     ) -> Result<(), PropagateError> {
-        let result = self.0.forbid_pattern(coord, pattern_id);
-        if result.is_err() {
-            self.0.reset(rng);
-        }
-        result
+        RunBorrowCore::forbid_pattern(self, coord, pattern_id)
     }
-}
-
+    fn forbid_pattern_during_collapse(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+    ) -> Result<(), Contradiction> {
+        self.context.forbid_pattern_during_collapse(
+            self.wave,
+            self.global_stats,
+            coord,
+            pattern_id,
+        )
+    }
+    fn reset(&mut self, mut rng: &mut dyn RngCore) {
+        RunBorrowCore::reset(self, &mut rng)
+    }
+}
+
+pub struct ForbidInterface<'a, 'b, W: Wrap> {
+    core: &'a mut (dyn CoreInterface + 'a),
+    // calls which caused a contradiction, after each of which the wave was reset, except for
+    // those made by attempts which `retry` recovered from
+    contradictions: Vec<ForbidContradiction>,
+    phantom: PhantomData<(&'b (), W)>,
+}
+
+impl<'a, 'b, W: Wrap> ForbidInterface<'a, 'b, W> {
+    fn new<S: CellStorage<WaveCell>>(core: &'a mut RunBorrowCore<'b, W, S>) -> Self {
+        Self {
+            core,
+            contradictions: Vec::new(),
+            phantom: PhantomData,
+        }
+    }
+
+    fn check(self) -> Result<(), ForbidContradictions> {
+        if self.contradictions.is_empty() {
+            Ok(())
+        } else {
+            Err(ForbidContradictions {
+                contradictions: self.contradictions,
+            })
+        }
+    }
+
+    fn record_contradiction<R: Rng>(
+        &mut self,
+        call: ForbidCall,
+        coord: Coord,
+        pattern_id: PatternId,
+        rng: &mut R,
+    ) {
+        self.contradictions.push(ForbidContradiction {
+            call,
+            coord,
+            pattern_id,
+        });
+        self.core.reset(rng);
+    }
+
+    pub fn wave_size(&self) -> Size {
+        self.core.wave_size()
+    }
+
+    /// Wraps `coord` according to the output's wrapping, or returns `None` if it lies outside a
+    /// non-wrapping output
+    pub fn normalize_coord(&self, coord: Coord) -> Option<Coord> {
+        W::normalize_coord(coord, self.wave_size())
+    }
+
+    pub fn wave_cell_ref(&self, coord: Coord) -> WaveCellRef<'_> {
+        self.core.wave_cell_ref(coord)
+    }
+
+    /// Calls `attempt` until it succeeds, up to `max_attempts` times, for `ForbidPattern`s
+    /// which make random choices and can start over when those choices cause a
+    /// contradiction. An attempt which causes a contradiction leaves the wave reset, so the
+    /// next one starts afresh. The contradictions caused by failed attempts are only reported
+    /// (e.g. by `RunBorrow::reset_checked`) if every attempt fails, in which case the result
+    /// of the last attempt is returned.
+    pub fn retry<R, A>(
+        &mut self,
+        max_attempts: usize,
+        rng: &mut R,
+        mut attempt: A,
+    ) -> Result<(), PropagateError>
+    where
+        R: Rng,
+        A: FnMut(&mut Self, &mut R) -> Result<(), PropagateError>,
+    {
+        let num_contradictions = self.contradictions.len();
+        let mut result = Err(PropagateError::Contradiction);
+        for _ in 0..max_attempts {
+            result = attempt(self, rng);
+            if result.is_ok() {
+                self.contradictions.truncate(num_contradictions);
+                break;
+            }
+        }
+        result
+    }
+
+    pub fn forbid_all_patterns_except<R: Rng>(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+        rng: &mut R,
+    ) -> Result<(), PropagateError> {
+        let result = self.core.forbid_all_patterns_except(coord, pattern_id);
+        if result.is_err() {
+            self.record_contradiction(
+                ForbidCall::ForbidAllPatternsExcept,
+                coord,
+                pattern_id,
+                rng,
+            );
+        }
+        result
+    }
+
+    pub fn forbid_pattern<R: Rng>(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+        rng: &mut R,
+    ) -> Result<(), PropagateError> {
+        let result = self.core.forbid_pattern(coord, pattern_id);
+        if result.is_err() {
+            self.record_contradiction(ForbidCall::ForbidPattern, coord, pattern_id, rng);
+        }
+        result
+    }
+}
+
+/// Passed to `ForbidPattern::observed` to allow patterns to be forbidden part way through
+/// collapsing. Forbidden patterns are propagated along with the observation.
+pub struct ObserveInterface<'a, 'b, W: Wrap> {
+    core: &'a mut (dyn CoreInterface + 'a),
+    contradiction: Option<Coord>,
+    phantom: PhantomData<(&'b (), W)>,
+}
+
+impl<'a, 'b, W: Wrap> ObserveInterface<'a, 'b, W> {
+    fn new<S: CellStorage<WaveCell>>(core: &'a mut RunBorrowCore<'b, W, S>) -> Self {
+        Self {
+            core,
+            contradiction: None,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn wave_size(&self) -> Size {
+        self.core.wave_size()
+    }
+
+    /// Wraps `coord` according to the output's wrapping, or returns `None` if it lies outside a
+    /// non-wrapping output
+    pub fn normalize_coord(&self, coord: Coord) -> Option<Coord> {
+        W::normalize_coord(coord, self.wave_size())
+    }
+
+    pub fn wave_cell_ref(&self, coord: Coord) -> WaveCellRef<'_> {
+        self.core.wave_cell_ref(coord)
+    }
+
+    /// Removes a pattern from the cell at `coord`. If this leaves the cell with no compatible
+    /// patterns, the current step fails with a contradiction.
+    pub fn forbid_pattern(
+        &mut self,
+        coord: Coord,
+        pattern_id: PatternId,
+    ) -> Result<(), PropagateError> {
+        if self.contradiction.is_some() {
+            return Err(PropagateError::Contradiction);
+        }
+        self.core
+            .forbid_pattern_during_collapse(coord, pattern_id)
+            .map_err(|_: Contradiction| {
+                self.contradiction = Some(coord);
+                PropagateError::Contradiction
+            })
+    }
+}
+
 #[derive(Clone)]
 /// Represents a running instance of wfc which allocates and owns its resources
 pub struct RunOwn<'a, W: Wrap = WrapXY, F: ForbidPattern = ForbidNothing> {
@@ -1217,6 +4135,124 @@ pub struct RunOwn<'a, W: Wrap = WrapXY, F: ForbidPattern = ForbidNothing> {
     forbid: F,
 }
 
+/// Reasons `SuspendedRun::resume` can refuse to resume a run
+#[cfg(feature = "serialize")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResumeError {
+    /// The run was suspended by a different version of this library
+    Version,
+    /// The run was suspended with different `GlobalStats`, according to
+    /// `GlobalStats::fingerprint`
+    GlobalStatsMismatch,
+    /// The run was suspended with a `Wrap` which treats the edges of the output differently
+    WrapMismatch,
+}
+
+#[cfg(feature = "serialize")]
+const SUSPENDED_RUN_VERSION: u32 = 1;
+
+// Where coordinates just beyond each edge of an output of `size` end up, which tells apart
+// every way of wrapping an output at least 3 cells across
+#[cfg(feature = "serialize")]
+fn wrap_probe<W: Wrap>(size: Size) -> Vec<Option<Coord>> {
+    let (width, height) = (size.width() as i32, size.height() as i32);
+    [
+        Coord::new(-1, 0),
+        Coord::new(-2, 0),
+        Coord::new(width, 0),
+        Coord::new(0, -1),
+        Coord::new(0, -2),
+        Coord::new(0, height),
+    ]
+    .into_iter()
+    .map(|coord| W::normalize_coord(coord, size))
+    .collect()
+}
+
+/// A partially collapsed `RunOwn`, detached from the `GlobalStats` it borrows so that it can
+/// be serialised (e.g. to disk) and resumed with `resume`, possibly in a later process. This
+/// allows long generations to be interrupted. The rng isn't part of the run, so to carry on
+/// exactly as the suspended run would have, the caller must save and restore the state of
+/// their rng too. Requires the `serialize` feature.
+#[cfg(feature = "serialize")]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SuspendedRun<F> {
+    version: u32,
+    global_stats_fingerprint: u64,
+    wrap_probe: Vec<Option<Coord>>,
+    context: Context,
+    wave: Wave,
+    forbid: F,
+}
+
+#[cfg(feature = "serialize")]
+impl<F: ForbidPattern> SuspendedRun<F> {
+    /// Continue the run with `global_stats` and `wrap`, which must be the same as those the
+    /// run was suspended with
+    pub fn resume<'a, W: Wrap>(
+        self,
+        global_stats: &'a GlobalStats,
+        wrap: W,
+    ) -> Result<RunOwn<'a, W, F>, ResumeError> {
+        let _ = wrap;
+        if self.version != SUSPENDED_RUN_VERSION {
+            return Err(ResumeError::Version);
+        }
+        if self.global_stats_fingerprint != global_stats.fingerprint() {
+            return Err(ResumeError::GlobalStatsMismatch);
+        }
+        if self.wrap_probe != wrap_probe::<W>(self.wave.size()) {
+            return Err(ResumeError::WrapMismatch);
+        }
+        Ok(RunOwn {
+            context: self.context,
+            wave: self.wave,
+            global_stats,
+            output_wrap: PhantomData,
+            forbid: self.forbid,
+        })
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<'a, W: Wrap, F: ForbidPattern + Clone> RunOwn<'a, W, F> {
+    /// A copy of the run which can be serialised, and later resumed with
+    /// `SuspendedRun::resume`. Requires the `serialize` feature.
+    pub fn suspend(&self) -> SuspendedRun<F> {
+        SuspendedRun {
+            version: SUSPENDED_RUN_VERSION,
+            global_stats_fingerprint: self.global_stats.fingerprint(),
+            wrap_probe: wrap_probe::<W>(self.wave.size()),
+            context: self.context.clone(),
+            wave: self.wave.clone(),
+            forbid: self.forbid.clone(),
+        }
+    }
+    /// Writes the suspended run to `writer`. Requires the `serialize` feature.
+    pub fn save<Wr: io::Write>(&self, writer: Wr) -> io::Result<()>
+    where
+        F: Serialize,
+    {
+        io_result(bincode::serialize_into(writer, &self.suspend()))
+    }
+    /// Reads a run written by `save` and resumes it. Fails with `io::ErrorKind::InvalidData`
+    /// if it can't be resumed (see `ResumeError`). Requires the `serialize` feature.
+    pub fn load<Rd: io::Read>(
+        reader: Rd,
+        global_stats: &'a GlobalStats,
+        wrap: W,
+    ) -> io::Result<Self>
+    where
+        F: DeserializeOwned,
+    {
+        let suspended_run: SuspendedRun<F> =
+            io_result(bincode::deserialize_from(reader))?;
+        suspended_run
+            .resume(global_stats, wrap)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+}
+
 pub enum OwnedObserve<'a, W: Wrap> {
     Complete(Wave),
     Incomplete(RunOwn<'a, W>),
@@ -1274,7 +4310,7 @@ where
     ) -> Self {
         let _ = wrap;
         let wave = Wave::new(output_size);
-        let context = Context::new();
+        let context = Context::with_capacity(output_size, global_stats);
         let mut s = Self {
             context,
             wave,
@@ -1285,6 +4321,51 @@ where
         s.borrow_mut().reset(rng);
         s
     }
+    /// Like `new_wrap_forbid`, but fails if applying `forbid` causes a contradiction,
+    /// reporting which calls to the `ForbidInterface` caused contradictions
+    pub fn new_wrap_forbid_checked<R: Rng>(
+        output_size: Size,
+        global_stats: &'a GlobalStats,
+        wrap: W,
+        forbid: F,
+        rng: &mut R,
+    ) -> Result<Self, ForbidContradictions> {
+        let _ = wrap;
+        let wave = Wave::new(output_size);
+        let context = Context::with_capacity(output_size, global_stats);
+        let mut s = Self {
+            context,
+            wave,
+            global_stats,
+            output_wrap: PhantomData,
+            forbid,
+        };
+        s.borrow_mut().reset_checked(rng)?;
+        Ok(s)
+    }
+    /// Like `new_wrap_forbid`, but fails without allocating the wave if it would use more
+    /// than `max_bytes`
+    pub fn try_new_wrap_forbid<R: Rng>(
+        output_size: Size,
+        global_stats: &'a GlobalStats,
+        wrap: W,
+        forbid: F,
+        max_bytes: usize,
+        rng: &mut R,
+    ) -> Result<Self, WaveTooLarge> {
+        let _ = wrap;
+        let wave = Wave::try_new(output_size, global_stats, max_bytes)?;
+        let context = Context::with_capacity(output_size, global_stats);
+        let mut s = Self {
+            context,
+            wave,
+            global_stats,
+            output_wrap: PhantomData,
+            forbid,
+        };
+        s.borrow_mut().reset(rng);
+        Ok(s)
+    }
 }
 
 impl<'a, W: Wrap, F: ForbidPattern> RunOwn<'a, W, F>
@@ -1304,6 +4385,21 @@ where
         }
     }
 
+    pub fn forbid_mut(&mut self) -> &mut F {
+        &mut self.forbid
+    }
+
+    pub fn observe_only<R: Rng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<Observation, PropagateError> {
+        self.borrow_mut().observe_only(rng)
+    }
+
+    pub fn propagate_only<R: Rng>(&mut self, rng: &mut R) -> Result<(), PropagateError> {
+        self.borrow_mut().propagate_only(rng)
+    }
+
     pub fn step<R: Rng>(&mut self, rng: &mut R) -> Result<Observe, PropagateError> {
         self.borrow_mut().step(rng)
     }
@@ -1312,8 +4408,108 @@ where
         self.borrow_mut().collapse(rng)
     }
 
+    pub fn collapse_with_budget<R: Rng>(
+        &mut self,
+        budget: Budget,
+        rng: &mut R,
+    ) -> Result<BudgetOutcome, PropagateError> {
+        self.borrow_mut().collapse_with_budget(budget, rng)
+    }
+
+    pub fn collapse_cancellable<R: Rng>(
+        &mut self,
+        token: &CancellationToken,
+        rng: &mut R,
+    ) -> Result<(), CancellableError> {
+        self.borrow_mut().collapse_cancellable(token, rng)
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn collapse_async<R: Rng>(
+        &mut self,
+        steps_per_yield: NonZeroUsize,
+        rng: &mut R,
+    ) -> Result<(), PropagateError> {
+        self.borrow_mut().collapse_async(steps_per_yield, rng).await
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn collapse_async_with<R, Y, YF>(
+        &mut self,
+        steps_per_yield: NonZeroUsize,
+        yield_now: YF,
+        rng: &mut R,
+    ) -> Result<(), PropagateError>
+    where
+        R: Rng,
+        Y: Future<Output = ()>,
+        YF: FnMut() -> Y,
+    {
+        self.borrow_mut()
+            .collapse_async_with(steps_per_yield, yield_now, rng)
+            .await
+    }
+
+    pub fn step_with_listener<R: Rng, L: DebugListener>(
+        &mut self,
+        rng: &mut R,
+        listener: &mut L,
+    ) -> Result<Observe, PropagateError> {
+        self.borrow_mut().step_with_listener(rng, listener)
+    }
+
+    pub fn step_with<R: Rng, C: FnMut(Coord, PatternId)>(
+        &mut self,
+        rng: &mut R,
+        on_cell_decided: C,
+    ) -> Result<Observe, PropagateError> {
+        self.borrow_mut().step_with(rng, on_cell_decided)
+    }
+
+    pub fn collapse_with_listener<R: Rng, L: DebugListener>(
+        &mut self,
+        rng: &mut R,
+        listener: &mut L,
+    ) -> Result<(), PropagateError> {
+        self.borrow_mut().collapse_with_listener(rng, listener)
+    }
+
+    pub fn collapse_with_partial_restarts<R: Rng>(
+        &mut self,
+        radius: u32,
+        max_restarts: usize,
+        rng: &mut R,
+    ) -> Result<(), PropagateError> {
+        self.borrow_mut()
+            .collapse_with_partial_restarts(radius, max_restarts, rng)
+    }
+
+    pub fn wave(&self) -> &Wave {
+        &self.wave
+    }
+
+    /// See `Context::heap_len`
+    pub fn heap_len(&self) -> usize {
+        self.context.heap_len()
+    }
+
+    /// See `Context::stale_fraction`
+    pub fn stale_fraction(&self) -> f64 {
+        self.context.stale_fraction(&self.wave)
+    }
+
+    /// See `Context::compact`
+    pub fn compact(&mut self) {
+        self.context.compact(&self.wave);
+    }
+
+    /// See `Wave::candidate_count_grid`
+    pub fn candidate_count_grid(&self) -> Grid<u32> {
+        self.wave.candidate_count_grid()
+    }
+
     pub fn wave_cell_ref(&self, coord: Coord) -> WaveCellRef {
-        let wave_cell = self.wave.grid.get_checked(coord);
+        let wave_cell = self.wave.cells.get_checked(coord);
         WaveCellRef {
             wave_cell,
             global_stats: self.global_stats,
@@ -1321,14 +4517,14 @@ where
     }
 
     pub fn wave_cell_ref_iter(&self) -> impl Iterator<Item = WaveCellRef> {
-        self.wave.grid.iter().map(move |wave_cell| WaveCellRef {
+        self.wave.cells.iter().map(move |wave_cell| WaveCellRef {
             wave_cell,
             global_stats: self.global_stats,
         })
     }
 
     pub fn wave_cell_ref_enumerate(&self) -> impl Iterator<Item = (Coord, WaveCellRef)> {
-        self.wave.grid.enumerate().map(move |(coord, wave_cell)| {
+        self.wave.cells.enumerate().map(move |(coord, wave_cell)| {
             let wave_cell_ref = WaveCellRef {
                 wave_cell,
                 global_stats: self.global_stats,
@@ -1341,6 +4537,19 @@ where
         self.wave
     }
 
+    /// Convert into a `RunOwnAll` holding its own copy of the `GlobalStats`, keeping any
+    /// progress made so far. This allows a partially-collapsed run to outlive the stats it
+    /// borrowed, e.g. to be returned from the function which created them.
+    pub fn into_run_own_all(self) -> RunOwnAll<W, F> {
+        RunOwnAll {
+            context: self.context,
+            wave: self.wave,
+            global_stats: self.global_stats.clone(),
+            output_wrap: self.output_wrap,
+            forbid: self.forbid,
+        }
+    }
+
     pub fn collapse_retrying<R, RO>(self, mut retry: RO, rng: &mut R) -> RO::Return
     where
         R: Rng,
@@ -1348,6 +4557,86 @@ where
     {
         retry.retry(self, rng)
     }
+
+    /// Like `collapse_retrying`, but `observer` is told when each attempt starts and how it
+    /// ends, e.g. to show progress
+    pub fn collapse_retrying_observed<R, RO, O>(
+        self,
+        mut retry: RO,
+        observer: &mut O,
+        rng: &mut R,
+    ) -> RO::Return
+    where
+        R: Rng,
+        RO: retry::RetryOwn,
+        O: RetryObserver,
+    {
+        retry.retry_observed(self, rng, observer)
+    }
+
+    /// Produce `n` independent outputs, each collapsed with its own copy of `retry`. Rather
+    /// than allocating a wave per output, this run is reset with `rng` (which also redraws
+    /// its noise) before each output is collapsed in place, and `f` is called with the
+    /// result of retrying and the finished wave, e.g. to clone the wave or save it.
+    /// Returns the values returned by `f`.
+    pub fn collapse_many<R, RB, T, C>(
+        &mut self,
+        n: usize,
+        retry: RB,
+        rng: &mut R,
+        mut f: C,
+    ) -> Vec<T>
+    where
+        R: Rng,
+        RB: retry::RetryBorrow + Clone,
+        C: FnMut(RB::Return, &Wave) -> T,
+    {
+        (0..n)
+            .map(|_| {
+                let mut run = self.borrow_mut();
+                run.reset(rng);
+                let result = retry.clone().retry(&mut run, rng);
+                f(result, &self.wave)
+            })
+            .collect()
+    }
+
+    /// Like `collapse_many`, but outputs are produced in parallel, each rayon thread reusing
+    /// its own copy of this run. Each output is collapsed with an rng of type `PR` (e.g.
+    /// `XorShiftRng`) seeded with a random number taken from `rng`, so results are
+    /// reproducible given the seed of `rng`. Runs on the current rayon thread pool, so to
+    /// limit the threads used call this from inside `rayon::ThreadPool::install`.
+    #[cfg(feature = "parallel")]
+    pub fn par_collapse_many<PR, R, RB, T, C>(
+        &self,
+        n: usize,
+        retry: RB,
+        rng: &mut R,
+        f: C,
+    ) -> Vec<T>
+    where
+        PR: Rng + rand::SeedableRng,
+        R: Rng,
+        RB: retry::RetryBorrow + Clone + Send + Sync,
+        T: Send,
+        C: Fn(RB::Return, &Wave) -> T + Send + Sync,
+    {
+        use rayon::prelude::*;
+        let seeds = (0..n).map(|_| rng.gen()).collect::<Vec<u64>>();
+        seeds
+            .into_par_iter()
+            .map_init(
+                || self.clone(),
+                |run_own, seed| {
+                    let mut rng = PR::seed_from_u64(seed);
+                    let mut run = run_own.borrow_mut();
+                    run.reset(&mut rng);
+                    let result = retry.clone().retry(&mut run, &mut rng);
+                    f(result, &run_own.wave)
+                },
+            )
+            .collect()
+    }
 }
 
 #[derive(Clone)]
@@ -1409,7 +4698,7 @@ where
     ) -> Self {
         let _ = wrap;
         let wave = Wave::new(output_size);
-        let context = Context::new();
+        let context = Context::with_capacity(output_size, &global_stats);
         let mut s = Self {
             context,
             wave,
@@ -1420,12 +4709,39 @@ where
         s.borrow_mut().reset(rng);
         s
     }
-}
-
-impl<W: Wrap, F: ForbidPattern> RunOwnAll<W, F>
-where
-    F: Clone + Sync + Send,
+    /// Like `new_wrap_forbid`, but fails without allocating the wave if it would use more
+    /// than `max_bytes`
+    pub fn try_new_wrap_forbid<R: Rng>(
+        output_size: Size,
+        global_stats: GlobalStats,
+        wrap: W,
+        forbid: F,
+        max_bytes: usize,
+        rng: &mut R,
+    ) -> Result<Self, WaveTooLarge> {
+        let _ = wrap;
+        let wave = Wave::try_new(output_size, &global_stats, max_bytes)?;
+        let context = Context::with_capacity(output_size, &global_stats);
+        let mut s = Self {
+            context,
+            wave,
+            global_stats,
+            output_wrap: PhantomData,
+            forbid,
+        };
+        s.borrow_mut().reset(rng);
+        Ok(s)
+    }
+}
+
+impl<W: Wrap, F: ForbidPattern> RunOwnAll<W, F>
+where
+    F: Clone + Sync + Send,
 {
+    pub fn global_stats(&self) -> &GlobalStats {
+        &self.global_stats
+    }
+
     pub fn borrow_mut(&mut self) -> RunBorrow<W, ForbidRef<F>> {
         let core = RunBorrowCore {
             context: &mut self.context,
@@ -1439,6 +4755,21 @@ where
         }
     }
 
+    pub fn forbid_mut(&mut self) -> &mut F {
+        &mut self.forbid
+    }
+
+    pub fn observe_only<R: Rng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<Observation, PropagateError> {
+        self.borrow_mut().observe_only(rng)
+    }
+
+    pub fn propagate_only<R: Rng>(&mut self, rng: &mut R) -> Result<(), PropagateError> {
+        self.borrow_mut().propagate_only(rng)
+    }
+
     pub fn step<R: Rng>(&mut self, rng: &mut R) -> Result<Observe, PropagateError> {
         self.borrow_mut().step(rng)
     }
@@ -1447,8 +4778,108 @@ where
         self.borrow_mut().collapse(rng)
     }
 
+    pub fn collapse_with_budget<R: Rng>(
+        &mut self,
+        budget: Budget,
+        rng: &mut R,
+    ) -> Result<BudgetOutcome, PropagateError> {
+        self.borrow_mut().collapse_with_budget(budget, rng)
+    }
+
+    pub fn collapse_cancellable<R: Rng>(
+        &mut self,
+        token: &CancellationToken,
+        rng: &mut R,
+    ) -> Result<(), CancellableError> {
+        self.borrow_mut().collapse_cancellable(token, rng)
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn collapse_async<R: Rng>(
+        &mut self,
+        steps_per_yield: NonZeroUsize,
+        rng: &mut R,
+    ) -> Result<(), PropagateError> {
+        self.borrow_mut().collapse_async(steps_per_yield, rng).await
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn collapse_async_with<R, Y, YF>(
+        &mut self,
+        steps_per_yield: NonZeroUsize,
+        yield_now: YF,
+        rng: &mut R,
+    ) -> Result<(), PropagateError>
+    where
+        R: Rng,
+        Y: Future<Output = ()>,
+        YF: FnMut() -> Y,
+    {
+        self.borrow_mut()
+            .collapse_async_with(steps_per_yield, yield_now, rng)
+            .await
+    }
+
+    pub fn step_with_listener<R: Rng, L: DebugListener>(
+        &mut self,
+        rng: &mut R,
+        listener: &mut L,
+    ) -> Result<Observe, PropagateError> {
+        self.borrow_mut().step_with_listener(rng, listener)
+    }
+
+    pub fn step_with<R: Rng, C: FnMut(Coord, PatternId)>(
+        &mut self,
+        rng: &mut R,
+        on_cell_decided: C,
+    ) -> Result<Observe, PropagateError> {
+        self.borrow_mut().step_with(rng, on_cell_decided)
+    }
+
+    pub fn collapse_with_listener<R: Rng, L: DebugListener>(
+        &mut self,
+        rng: &mut R,
+        listener: &mut L,
+    ) -> Result<(), PropagateError> {
+        self.borrow_mut().collapse_with_listener(rng, listener)
+    }
+
+    pub fn collapse_with_partial_restarts<R: Rng>(
+        &mut self,
+        radius: u32,
+        max_restarts: usize,
+        rng: &mut R,
+    ) -> Result<(), PropagateError> {
+        self.borrow_mut()
+            .collapse_with_partial_restarts(radius, max_restarts, rng)
+    }
+
+    pub fn wave(&self) -> &Wave {
+        &self.wave
+    }
+
+    /// See `Context::heap_len`
+    pub fn heap_len(&self) -> usize {
+        self.context.heap_len()
+    }
+
+    /// See `Context::stale_fraction`
+    pub fn stale_fraction(&self) -> f64 {
+        self.context.stale_fraction(&self.wave)
+    }
+
+    /// See `Context::compact`
+    pub fn compact(&mut self) {
+        self.context.compact(&self.wave);
+    }
+
+    /// See `Wave::candidate_count_grid`
+    pub fn candidate_count_grid(&self) -> Grid<u32> {
+        self.wave.candidate_count_grid()
+    }
+
     pub fn wave_cell_ref(&self, coord: Coord) -> WaveCellRef {
-        let wave_cell = self.wave.grid.get_checked(coord);
+        let wave_cell = self.wave.cells.get_checked(coord);
         WaveCellRef {
             wave_cell,
             global_stats: &self.global_stats,
@@ -1456,14 +4887,14 @@ where
     }
 
     pub fn wave_cell_ref_iter(&self) -> impl Iterator<Item = WaveCellRef> {
-        self.wave.grid.iter().map(move |wave_cell| WaveCellRef {
+        self.wave.cells.iter().map(move |wave_cell| WaveCellRef {
             wave_cell,
             global_stats: &self.global_stats,
         })
     }
 
     pub fn wave_cell_ref_enumerate(&self) -> impl Iterator<Item = (Coord, WaveCellRef)> {
-        self.wave.grid.enumerate().map(move |(coord, wave_cell)| {
+        self.wave.cells.enumerate().map(move |(coord, wave_cell)| {
             let wave_cell_ref = WaveCellRef {
                 wave_cell,
                 global_stats: &self.global_stats,
@@ -1483,4 +4914,1439 @@ where
     {
         retry.retry(self, rng)
     }
+
+    /// Like `collapse_retrying`, but `observer` is told when each attempt starts and how it
+    /// ends, e.g. to show progress
+    pub fn collapse_retrying_observed<R, RO, O>(
+        self,
+        mut retry: RO,
+        observer: &mut O,
+        rng: &mut R,
+    ) -> RO::Return
+    where
+        R: Rng,
+        RO: retry::RetryOwnAll,
+        O: RetryObserver,
+    {
+        retry.retry_observed(self, rng, observer)
+    }
+}
+
+/// Collects the configuration of a run, as an alternative to choosing between the `new`,
+/// `new_wrap`, `new_forbid` and `new_wrap_forbid` constructors of `RunOwn`, `RunOwnAll` and
+/// `RunBorrow`. Constraints added with `constraint` are applied after the one given to
+/// `forbid`, in the order they were added.
+#[derive(Clone)]
+pub struct RunBuilder<W: Wrap = WrapXY, F: ForbidPattern = ForbidNothing> {
+    wrap: W,
+    forbid: F,
+    noise: Option<Grid<u32>>,
+    priority: Option<Grid<f32>>,
+}
+
+impl RunBuilder {
+    pub fn new() -> Self {
+        Self {
+            wrap: WrapXY,
+            forbid: ForbidNothing,
+            noise: None,
+            priority: None,
+        }
+    }
+}
+
+impl Default for RunBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<W: Wrap, F: ForbidPattern> RunBuilder<W, F> {
+    pub fn wrap<W2: Wrap>(self, wrap: W2) -> RunBuilder<W2, F> {
+        RunBuilder {
+            wrap,
+            forbid: self.forbid,
+            noise: self.noise,
+            priority: self.priority,
+        }
+    }
+
+    /// Replaces the constraint, including any added with `constraint`
+    pub fn forbid<F2: ForbidPattern>(self, forbid: F2) -> RunBuilder<W, F2> {
+        RunBuilder {
+            wrap: self.wrap,
+            forbid,
+            noise: self.noise,
+            priority: self.priority,
+        }
+    }
+
+    /// Adds a constraint to be applied after those already configured
+    pub fn constraint<C: ForbidPattern>(self, constraint: C) -> RunBuilder<W, (F, C)> {
+        RunBuilder {
+            wrap: self.wrap,
+            forbid: (self.forbid, constraint),
+            noise: self.noise,
+            priority: self.priority,
+        }
+    }
+
+    /// Break ties between cells of equal entropy with `noise`. See `Wave::set_noise`.
+    pub fn noise(mut self, noise: Grid<u32>) -> Self {
+        self.noise = Some(noise);
+        self
+    }
+
+    /// Bias the order cells are observed in with `priority`. See `Wave::set_priority`.
+    pub fn priority(mut self, priority: Grid<f32>) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Uses `wave` and `context` for the run, resizing `wave` to `output_size` if necessary
+    pub fn build_borrowed<'a, R: Rng>(
+        self,
+        output_size: Size,
+        context: &'a mut Context,
+        wave: &'a mut Wave,
+        global_stats: &'a GlobalStats,
+        rng: &mut R,
+    ) -> RunBorrow<'a, W, F> {
+        wave.resize(output_size);
+        if let Some(noise) = self.noise {
+            wave.set_noise(noise);
+        }
+        if let Some(priority) = self.priority {
+            wave.set_priority(priority);
+        }
+        RunBorrow::new_wrap_forbid(
+            context,
+            wave,
+            global_stats,
+            self.wrap,
+            self.forbid,
+            rng,
+        )
+    }
+
+    pub fn build_owned<'a, R: Rng>(
+        self,
+        output_size: Size,
+        global_stats: &'a GlobalStats,
+        rng: &mut R,
+    ) -> RunOwn<'a, W, F>
+    where
+        F: Clone + Sync + Send,
+    {
+        let mut wave = Wave::new(output_size);
+        if let Some(noise) = self.noise {
+            wave.set_noise(noise);
+        }
+        if let Some(priority) = self.priority {
+            wave.set_priority(priority);
+        }
+        let mut run = RunOwn {
+            context: Context::with_capacity(output_size, global_stats),
+            wave,
+            global_stats,
+            output_wrap: PhantomData,
+            forbid: self.forbid,
+        };
+        run.borrow_mut().reset(rng);
+        run
+    }
+
+    pub fn build_owned_all<R: Rng>(
+        self,
+        output_size: Size,
+        global_stats: GlobalStats,
+        rng: &mut R,
+    ) -> RunOwnAll<W, F>
+    where
+        F: Clone + Sync + Send,
+    {
+        let mut wave = Wave::new(output_size);
+        if let Some(noise) = self.noise {
+            wave.set_noise(noise);
+        }
+        if let Some(priority) = self.priority {
+            wave.set_priority(priority);
+        }
+        let mut run = RunOwnAll {
+            context: Context::with_capacity(output_size, &global_stats),
+            wave,
+            global_stats,
+            output_wrap: PhantomData,
+            forbid: self.forbid,
+        };
+        run.borrow_mut().reset(rng);
+        run
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{
+        all_compatible, all_compatible_stats, checkerboard_stats, three_colour_stats,
+    };
+
+    #[test]
+    fn adjacency_matrix() {
+        let weight = NonZeroU32::new(1);
+        assert!(GlobalStats::from_adjacency_matrix(
+            vec![weight, weight],
+            vec![all_compatible(2), all_compatible(2)]
+        )
+        .is_ok());
+        let mut allowed = vec![all_compatible(2), all_compatible(2)];
+        allowed[1][CardinalDirection::West as usize] = vec![1];
+        assert_eq!(
+            GlobalStats::from_adjacency_matrix(vec![weight, weight], allowed).err(),
+            Some(AdjacencyMatrixError::Asymmetric {
+                pattern_id: 0,
+                direction: CardinalDirection::East,
+                neighbour_id: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let weight = NonZeroU32::new(1);
+        let global_stats = GlobalStats::new(PatternTable::from_vec(vec![
+            PatternDescription::new(
+                weight,
+                CardinalDirectionTable::new_array([vec![0], vec![0], vec![0], vec![]]),
+            ),
+            PatternDescription::new(
+                None,
+                CardinalDirectionTable::new_array([vec![1], vec![1], vec![1], vec![1]]),
+            ),
+        ]));
+        let lints = global_stats.validate();
+        assert_eq!(
+            lints,
+            vec![
+                AdjacencyLint::Asymmetric {
+                    pattern_id: 0,
+                    direction: CardinalDirection::East,
+                    neighbour_id: 0,
+                },
+                AdjacencyLint::NoAllowedNeighbour {
+                    pattern_id: 0,
+                    direction: CardinalDirection::West,
+                },
+                AdjacencyLint::Unweighted { pattern_id: 1 },
+            ]
+        );
+        assert_eq!(
+            lints[1].to_string(),
+            "pattern 0 has no allowed western neighbour"
+        );
+        assert!(lints[0].is_error());
+    }
+
+    #[test]
+    fn debug_listener() {
+        use rand::SeedableRng;
+
+        #[derive(Default)]
+        struct Counts {
+            observations: usize,
+            contradictions: usize,
+        }
+        impl DebugListener for Counts {
+            fn on_observe(&mut self, _coord: Coord, _pattern_id: PatternId) {
+                self.observations += 1;
+            }
+            fn on_contradiction(&mut self, _coord: Coord) {
+                self.contradictions += 1;
+            }
+        }
+        let global_stats = all_compatible_stats(&[1, 1]);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(Size::new(4, 4), &global_stats, &mut rng);
+        let mut counts = Counts::default();
+        run.collapse_with_listener(&mut rng, &mut counts).unwrap();
+        assert_eq!(counts.observations, 16);
+        assert_eq!(counts.contradictions, 0);
+    }
+
+    #[test]
+    fn reset_with_size() {
+        use rand::SeedableRng;
+        let global_stats = all_compatible_stats(&[1, 1]);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut context = Context::new();
+        let mut wave = Wave::new(Size::new(4, 4));
+        let mut run = RunBorrow::new(&mut context, &mut wave, &global_stats, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        run.reset_with_size(Size::new(3, 5), &mut rng);
+        run.collapse(&mut rng).unwrap();
+        assert_eq!(wave.grid().size(), Size::new(3, 5));
+        assert!(wave
+            .grid()
+            .iter()
+            .all(|cell| cell.chosen_pattern_id().is_ok()));
+        // the cells which still fit keep their per-pattern tables, in row-major order
+        let tables = |wave: &Wave| {
+            wave.grid()
+                .iter()
+                .map(|cell| cell.num_ways_to_become_each_pattern.table.as_ptr())
+                .collect::<Vec<_>>()
+        };
+        let before = tables(&wave);
+        let mut run = RunBorrow::new(&mut context, &mut wave, &global_stats, &mut rng);
+        run.reset_with_size(Size::new(2, 2), &mut rng);
+        assert_eq!(tables(&wave), before[..4]);
+    }
+
+    #[test]
+    fn noise() {
+        use rand::SeedableRng;
+        struct FirstObservation(Option<Coord>);
+        impl DebugListener for FirstObservation {
+            fn on_observe(&mut self, coord: Coord, _pattern_id: PatternId) {
+                self.0.get_or_insert(coord);
+            }
+        }
+        let global_stats = all_compatible_stats(&[1, 1]);
+        let size = Size::new(4, 4);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut context = Context::new();
+        let mut wave = Wave::new(size);
+        wave.set_noise(Grid::new_fn(size, |coord| {
+            if coord == Coord::new(2, 3) {
+                0
+            } else {
+                1 + coord.x as u32
+            }
+        }));
+        let mut run = RunBorrow::new(&mut context, &mut wave, &global_stats, &mut rng);
+        let mut first = FirstObservation(None);
+        run.collapse_with_listener(&mut rng, &mut first).unwrap();
+        assert_eq!(first.0, Some(Coord::new(2, 3)));
+    }
+
+    #[test]
+    fn priority() {
+        use rand::SeedableRng;
+        struct Observations(Vec<Coord>);
+        impl DebugListener for Observations {
+            fn on_observe(&mut self, coord: Coord, _pattern_id: PatternId) {
+                self.0.push(coord);
+            }
+        }
+        let global_stats = all_compatible_stats(&[1, 1]);
+        let size = Size::new(5, 5);
+        let focus = Coord::new(1, 3);
+        let distance = |coord: Coord| {
+            crate::wrap::distance_squared::<WrapXY>(focus, coord, size) as f32
+        };
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut context = Context::new();
+        let mut wave = Wave::new(size);
+        wave.set_priority(Grid::new_fn(size, distance));
+        let mut run = RunBorrow::new(&mut context, &mut wave, &global_stats, &mut rng);
+        let mut observations = Observations(Vec::new());
+        run.collapse_with_listener(&mut rng, &mut observations)
+            .unwrap();
+        assert_eq!(observations.0.len(), size.count());
+        assert_eq!(observations.0[0], focus);
+        assert!(observations
+            .0
+            .windows(2)
+            .all(|pair| distance(pair[0]) <= distance(pair[1])));
+    }
+
+    #[test]
+    fn resolve_unweighted_cells() {
+        use crate::wrap::WrapNone;
+        use rand::SeedableRng;
+        // patterns 0 and 1 are weighted and may only neighbour the unweighted patterns 2 and
+        // 3, so observing a cell leaves its neighbours with only unweighted patterns
+        let weight = NonZeroU32::new(1);
+        let weighted = || [vec![2, 3], vec![2, 3], vec![2, 3], vec![2, 3]];
+        let unweighted = || all_compatible(4);
+        let global_stats = GlobalStats::from_adjacency_matrix(
+            vec![weight, weight, None, None],
+            vec![weighted(), weighted(), unweighted(), unweighted()],
+        )
+        .unwrap();
+        let collapse = |resolve: bool| {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+            let mut context = Context::new();
+            let mut wave = Wave::new(Size::new(5, 5));
+            wave.set_resolve_unweighted_cells(resolve);
+            let mut run = RunBorrow::new_wrap(
+                &mut context,
+                &mut wave,
+                &global_stats,
+                WrapNone,
+                &mut rng,
+            );
+            run.collapse(&mut rng).unwrap();
+            wave.grid()
+                .iter()
+                .all(|cell| cell.chosen_pattern_id().is_ok())
+        };
+        assert!(!collapse(false));
+        assert!(collapse(true));
+    }
+
+    #[test]
+    fn cancellation() {
+        use rand::SeedableRng;
+        let global_stats = all_compatible_stats(&[1, 1]);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let token = CancellationToken::new();
+        let run = RunOwn::new(Size::new(4, 4), &global_stats, &mut rng);
+        assert!(run
+            .clone()
+            .collapse_retrying(retry::ForeverCancellable(token.clone()), &mut rng)
+            .is_ok());
+        token.cancel();
+        assert_eq!(
+            run.collapse_retrying(retry::ForeverCancellable(token), &mut rng)
+                .err(),
+            Some(Cancelled)
+        );
+    }
+
+    #[test]
+    fn partial_restarts() {
+        use rand::SeedableRng;
+        let global_stats = three_colour_stats();
+        let size = Size::new(20, 20);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let wave = RunOwn::new(size, &global_stats, &mut rng)
+            .collapse_retrying(
+                retry::PartialRestart {
+                    radius: 2,
+                    max_restarts: 1000,
+                },
+                &mut rng,
+            )
+            .unwrap();
+        let id_grid =
+            Grid::new_grid_map_ref(wave.grid(), |cell| cell.chosen_pattern_id().unwrap());
+        for (coord, pattern_id) in id_grid.enumerate() {
+            for direction in CardinalDirections {
+                let neighbour = WrapXY::normalize_coord(coord + direction.coord(), size);
+                assert_ne!(id_grid.get_checked(neighbour.unwrap()), pattern_id);
+            }
+        }
+    }
+
+    #[test]
+    fn partial_restarts_unweighted() {
+        use rand::SeedableRng;
+        // forbidding the only weighted pattern anywhere removes it everywhere, leaving every
+        // cell to be resolved by `observe_unweighted`, and the second observation is
+        // contradicted, so the first is replayed into a cell which was never counted as
+        // undecided
+        struct ForbidWeighted {
+            num_observations: usize,
+        }
+        impl ForbidPattern for ForbidWeighted {
+            fn forbid<W: Wrap, R: Rng>(
+                &mut self,
+                fi: &mut ForbidInterface<W>,
+                rng: &mut R,
+            ) {
+                fi.forbid_pattern(Coord::new(0, 0), 0, rng).unwrap();
+            }
+            fn observed<W: Wrap>(
+                &mut self,
+                coord: Coord,
+                pattern_id: PatternId,
+                oi: &mut ObserveInterface<W>,
+            ) {
+                self.num_observations += 1;
+                if self.num_observations == 2 {
+                    let _ = oi.forbid_pattern(coord, pattern_id);
+                }
+            }
+        }
+        let weight = NonZeroU32::new(1);
+        let same = |ids: Vec<PatternId>| [ids.clone(), ids.clone(), ids.clone(), ids];
+        let global_stats = GlobalStats::from_adjacency_matrix(
+            vec![weight, None, None],
+            vec![same(vec![0]), same(vec![1, 2]), same(vec![1, 2])],
+        )
+        .unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut context = Context::new();
+        let mut wave = Wave::new(Size::new(10, 10));
+        wave.set_resolve_unweighted_cells(true);
+        let forbid = ForbidWeighted {
+            num_observations: 0,
+        };
+        let mut run = RunBorrow::new_forbid(
+            &mut context,
+            &mut wave,
+            &global_stats,
+            forbid,
+            &mut rng,
+        );
+        run.collapse_with_partial_restarts(0, 1, &mut rng).unwrap();
+        assert!(run.forbid_mut().num_observations > 100);
+        for (_, cell) in run.wave_cell_ref_enumerate() {
+            assert_ne!(cell.chosen_pattern_id().ok(), Some(0));
+        }
+    }
+
+    #[test]
+    fn golden() {
+        use rand::SeedableRng;
+        let weight = NonZeroU32::new(1);
+        let global_stats = checkerboard_stats();
+        let size = Size::new(2, 2);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(size, &global_stats, &mut rng);
+        assert_eq!(run.wave().to_debug_string(&global_stats), "? ?\n? ?\n");
+        run.collapse(&mut rng).unwrap();
+        let debug_string = run.into_wave().to_debug_string(&global_stats);
+        assert!(debug_string == "0 1\n1 0\n" || debug_string == "1 0\n0 1\n");
+        assert_eq!(global_stats.fingerprint(), 0xa4495fb4f9f288e7);
+        let everything =
+            |ids: Vec<PatternId>| [ids.clone(), ids.clone(), ids.clone(), ids];
+        let fingerprint = |ids: Vec<PatternId>| {
+            GlobalStats::from_adjacency_matrix(
+                vec![weight, weight],
+                vec![everything(ids.clone()), everything(ids)],
+            )
+            .unwrap()
+            .fingerprint()
+        };
+        assert_eq!(fingerprint(vec![0, 1]), fingerprint(vec![1, 0]));
+        assert_ne!(fingerprint(vec![0, 1]), global_stats.fingerprint());
+    }
+
+    #[test]
+    fn wave_cell_ref_compatibility() {
+        use rand::SeedableRng;
+        // each pattern may only have the next pattern (cyclically) to its east
+        let weight = NonZeroU32::new(1);
+        let all = vec![0, 1, 2];
+        let global_stats = GlobalStats::from_adjacency_matrix(
+            vec![weight, weight, weight],
+            (0..3)
+                .map(|id| {
+                    [
+                        all.clone(),
+                        vec![(id + 1) % 3],
+                        all.clone(),
+                        vec![(id + 2) % 3],
+                    ]
+                })
+                .collect(),
+        )
+        .unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(Size::new(3, 3), &global_stats, &mut rng);
+        let cell = run.wave_cell_ref(Coord::new(0, 0));
+        assert!(cell.is_pattern_compatible(0));
+        assert_eq!(
+            cell.compatible_pattern_ids().collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        assert_eq!(
+            cell.compatible_in_direction(CardinalDirection::East),
+            vec![0, 1, 2]
+        );
+        run.collapse(&mut rng).unwrap();
+        let cell = run.wave_cell_ref(Coord::new(1, 1));
+        let pattern_id = cell.chosen_pattern_id().unwrap();
+        assert!(cell.is_pattern_compatible(pattern_id));
+        assert!(!cell.is_pattern_compatible((pattern_id + 1) % 3));
+        assert!(!cell.is_pattern_compatible(3));
+        assert_eq!(
+            cell.compatible_in_direction(CardinalDirection::West),
+            vec![(pattern_id + 2) % 3]
+        );
+    }
+
+    #[test]
+    fn temperature() {
+        use rand::SeedableRng;
+        let mut global_stats = all_compatible_stats(&[1, 5, 2]);
+        let fingerprint = global_stats.fingerprint();
+        let size = Size::new(8, 8);
+        let count_heaviest = |global_stats: &GlobalStats| {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+            let mut run = RunOwn::new(size, global_stats, &mut rng);
+            run.collapse(&mut rng).unwrap();
+            run.wave_cell_ref_iter()
+                .filter(|cell| cell.chosen_pattern_id().ok() == Some(1))
+                .count()
+        };
+        let default = count_heaviest(&global_stats);
+        global_stats.set_temperature(0.0);
+        assert_ne!(global_stats.fingerprint(), fingerprint);
+        assert_eq!(count_heaviest(&global_stats), 64);
+        global_stats.set_temperature(0.5);
+        assert!(count_heaviest(&global_stats) > default);
+        global_stats.set_temperature(100.0);
+        assert!(count_heaviest(&global_stats) < default);
+    }
+
+    #[test]
+    fn compact_heap() {
+        use rand::SeedableRng;
+        let weight = NonZeroU32::new(1);
+        // 0 and 1 can't be next to each other, and 2 can be next to anything, so collapsing
+        // never contradicts
+        let all = |ids: Vec<PatternId>| [ids.clone(), ids.clone(), ids.clone(), ids];
+        let global_stats = GlobalStats::from_adjacency_matrix(
+            vec![weight, weight, weight],
+            vec![all(vec![0, 2]), all(vec![1, 2]), all(vec![0, 1, 2])],
+        )
+        .unwrap();
+        let size = Size::new(8, 8);
+        let collapse = |compact: bool| {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+            let mut run = RunOwn::new(size, &global_stats, &mut rng);
+            assert_eq!(run.heap_len(), 64);
+            assert_eq!(run.stale_fraction(), 0.);
+            for _ in 0..8 {
+                run.step(&mut rng).unwrap();
+            }
+            assert!(run.stale_fraction() > 0.);
+            if compact {
+                let heap_len = run.heap_len();
+                run.compact();
+                assert!(run.heap_len() < heap_len);
+                assert_eq!(run.stale_fraction(), 0.);
+            }
+            run.collapse(&mut rng).unwrap();
+            Grid::new_grid_map_ref(run.wave().grid(), |cell| {
+                cell.chosen_pattern_id().unwrap()
+            })
+        };
+        assert_eq!(collapse(true), collapse(false));
+    }
+
+    #[test]
+    fn trouble_spots() {
+        use rand::SeedableRng;
+        let global_stats = checkerboard_stats();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        // observing any cell decides every other cell by propagation
+        let mut trouble_spots = TroubleSpots::new(1);
+        let mut run = RunOwn::new(Size::new(2, 2), &global_stats, &mut rng);
+        run.collapse_with_listener(&mut rng, &mut trouble_spots)
+            .unwrap();
+        let worst = trouble_spots.worst(4);
+        assert_eq!(worst.len(), 3);
+        assert!(worst.iter().all(|&(_, count)| count == 1));
+        // a wrapping checkerboard of odd size always contradicts
+        let mut trouble_spots = TroubleSpots::new(0);
+        let mut run = RunOwn::new(Size::new(3, 3), &global_stats, &mut rng);
+        for _ in 0..4 {
+            assert!(run
+                .collapse_with_listener(&mut rng, &mut trouble_spots)
+                .is_err());
+        }
+        let total = trouble_spots
+            .worst(9)
+            .iter()
+            .map(|&(_, count)| count)
+            .sum::<u32>();
+        assert_eq!(total, 4);
+        let (worst_coord, worst_count) = trouble_spots.worst(1)[0];
+        assert_eq!(trouble_spots.count(worst_coord), worst_count);
+        trouble_spots.clear();
+        assert!(trouble_spots.worst(1).is_empty());
+    }
+
+    #[test]
+    fn pattern_histogram() {
+        use rand::SeedableRng;
+        let global_stats = checkerboard_stats();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(Size::new(4, 2), &global_stats, &mut rng);
+        assert_eq!(run.wave().pattern_histogram().iter().sum::<u32>(), 0);
+        run.collapse(&mut rng).unwrap();
+        let histogram = run.wave().pattern_histogram();
+        assert_eq!(histogram.len(), 2);
+        assert_eq!((histogram[0], histogram[1]), (4, 4));
+    }
+
+    #[test]
+    fn context_with_capacity() {
+        use rand::SeedableRng;
+        let global_stats = all_compatible_stats(&[1, 1, 1]);
+        let size = Size::new(6, 4);
+        let collapse = |context: &mut Context| {
+            let mut wave = Wave::new(size);
+            let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+            RunBorrow::new(context, &mut wave, &global_stats, &mut rng)
+                .collapse(&mut rng)
+                .unwrap();
+            wave.to_debug_string(&global_stats)
+        };
+        let mut context = Context::with_capacity(size, &global_stats);
+        let capacity = context.observations.capacity();
+        assert!(capacity >= size.count());
+        assert_eq!(collapse(&mut context), collapse(&mut Context::new()));
+        assert_eq!(context.observations.capacity(), capacity);
+    }
+
+    #[test]
+    fn soft_constraints() {
+        use rand::SeedableRng;
+        let global_stats = all_compatible_stats(&[1, 1]);
+        let size = Size::new(4, 2);
+        let mut soft_constraints = SoftConstraints::new();
+        for coord in size.coord_iter_row_major() {
+            // discourage 1 on the left and 0 on the right
+            let discouraged = if coord.x < 2 { 1 } else { 0 };
+            soft_constraints.set(coord, discouraged, 1e-9);
+        }
+        assert_eq!(soft_constraints.get(Coord::new(0, 0), 1), 1e-9);
+        assert_eq!(soft_constraints.get(Coord::new(0, 0), 0), 1.0);
+        let mut context = Context::new();
+        let mut wave = Wave::new(size);
+        wave.set_soft_constraints(soft_constraints.clone());
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        RunBorrow::new(&mut context, &mut wave, &global_stats, &mut rng)
+            .collapse(&mut rng)
+            .unwrap();
+        assert_eq!(wave.to_debug_string(&global_stats), "0 0 1 1\n0 0 1 1\n");
+        // a discouraged pattern is still chosen when nothing else is compatible
+        let global_stats = checkerboard_stats();
+        RunBorrow::new(&mut context, &mut wave, &global_stats, &mut rng)
+            .collapse(&mut rng)
+            .unwrap();
+        let debug_string = wave.to_debug_string(&global_stats);
+        assert!(debug_string.contains('0') && debug_string.contains('1'));
+    }
+
+    #[test]
+    fn candidate_count_grid() {
+        use rand::SeedableRng;
+        let global_stats = all_compatible_stats(&[1, 1, 1]);
+        let size = Size::new(3, 2);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(size, &global_stats, &mut rng);
+        assert!(run.candidate_count_grid().iter().all(|&count| count == 3));
+        run.step(&mut rng).unwrap();
+        let counts = run.candidate_count_grid();
+        assert_eq!(counts.iter().filter(|&&count| count == 1).count(), 1);
+        assert_eq!(counts.iter().filter(|&&count| count == 3).count(), 5);
+    }
+
+    #[test]
+    fn observe_only() {
+        use rand::SeedableRng;
+        let global_stats = checkerboard_stats();
+        let size = Size::new(4, 4);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(size, &global_stats, &mut rng);
+        let coord = match run.observe_only(&mut rng).unwrap() {
+            Observation::Observed { coord, .. } => coord,
+            Observation::Complete => panic!("nothing was observed"),
+        };
+        let num_decided = |run: &RunOwn| {
+            run.wave_cell_ref_iter()
+                .filter(|cell| cell.chosen_pattern_id().is_ok())
+                .count()
+        };
+        // only the observed cell is decided until the observation is propagated
+        assert_eq!(num_decided(&run), 1);
+        assert!(run.wave_cell_ref(coord).chosen_pattern_id().is_ok());
+        run.propagate_only(&mut rng).unwrap();
+        assert_eq!(num_decided(&run), 16);
+        assert_eq!(run.observe_only(&mut rng).ok(), Some(Observation::Complete));
+    }
+
+    #[test]
+    fn try_reset() {
+        use rand::SeedableRng;
+        #[derive(Clone)]
+        struct ForceAll(Vec<(Coord, PatternId)>);
+        impl ForbidPattern for ForceAll {
+            fn forbid<W: Wrap, R: Rng>(
+                &mut self,
+                fi: &mut ForbidInterface<W>,
+                rng: &mut R,
+            ) {
+                for &(coord, pattern_id) in self.0.iter() {
+                    if fi
+                        .forbid_all_patterns_except(coord, pattern_id, rng)
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        }
+        let global_stats = checkerboard_stats();
+        let size = Size::new(4, 4);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let satisfiable = ForceAll(vec![(Coord::new(0, 0), 0), (Coord::new(1, 0), 1)]);
+        let mut run = RunOwn::new_forbid(size, &global_stats, satisfiable, &mut rng);
+        assert_eq!(run.borrow_mut().try_reset(3, &mut rng), Ok(()));
+        run.collapse(&mut rng).unwrap();
+        assert_eq!(
+            run.into_wave().to_debug_string(&global_stats),
+            "0 1 0 1\n1 0 1 0\n0 1 0 1\n1 0 1 0\n"
+        );
+        // neighbours can't be the same pattern
+        let unsatisfiable = ForceAll(vec![(Coord::new(0, 0), 0), (Coord::new(1, 0), 0)]);
+        let mut run = RunOwn::new_forbid(size, &global_stats, unsatisfiable, &mut rng);
+        assert_eq!(
+            run.borrow_mut().try_reset(3, &mut rng),
+            Err(SetupError::UnsatisfiableForbid)
+        );
+    }
+
+    #[test]
+    fn cumulative_weights() {
+        let weights = [3, 0, 1, 4, 1, 5, 9, 2, 6];
+        let mut cumulative_weights = CumulativeWeights::default();
+        cumulative_weights.init(weights.iter().cloned());
+        let linear_scan = |weights: &[u32], mut remaining: u32| {
+            for (index, &weight) in weights.iter().enumerate() {
+                if remaining < weight {
+                    return index;
+                }
+                remaining -= weight;
+            }
+            unreachable!()
+        };
+        let mut weights = weights.to_vec();
+        for index in [5, 0, 8] {
+            assert_eq!(cumulative_weights.total(), weights.iter().sum::<u32>());
+            for remaining in 0..cumulative_weights.total() {
+                assert_eq!(
+                    cumulative_weights.find(remaining),
+                    linear_scan(&weights, remaining)
+                );
+            }
+            cumulative_weights.remove(index, weights[index]);
+            weights[index] = 0;
+        }
+    }
+
+    #[test]
+    fn wave_memory_cap() {
+        use rand::SeedableRng;
+        let global_stats = checkerboard_stats();
+        let size = Size::new(10, 10);
+        let estimated_bytes = global_stats.estimated_wave_bytes(size);
+        assert!(estimated_bytes > 0);
+        assert_eq!(
+            global_stats.estimated_wave_bytes(Size::new(20, 10)),
+            estimated_bytes * 2
+        );
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let too_small = RunOwn::try_new_wrap_forbid(
+            size,
+            &global_stats,
+            WrapXY,
+            ForbidNothing,
+            estimated_bytes - 1,
+            &mut rng,
+        );
+        assert_eq!(
+            too_small.err(),
+            Some(WaveTooLarge {
+                estimated_bytes,
+                max_bytes: estimated_bytes - 1,
+            })
+        );
+        let mut run = RunOwn::try_new_wrap_forbid(
+            size,
+            &global_stats,
+            WrapXY,
+            ForbidNothing,
+            estimated_bytes,
+            &mut rng,
+        )
+        .unwrap();
+        assert!(run.collapse(&mut rng).is_ok());
+    }
+
+    #[test]
+    fn run_builder() {
+        use crate::wrap::WrapNone;
+        use rand::SeedableRng;
+        let global_stats = three_colour_stats();
+        let size = Size::new(6, 6);
+        let noise = Grid::new_fn(size, |Coord { x, y }| (x * 7 + y * 13) as u32 % 17);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunBuilder::new()
+            .wrap(WrapNone)
+            .noise(noise.clone())
+            .build_owned(size, &global_stats, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let built = run.into_wave().to_debug_string(&global_stats);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut context = Context::new();
+        let mut wave = Wave::new(size);
+        wave.set_noise(noise);
+        let mut run = RunBorrow::new_wrap(
+            &mut context,
+            &mut wave,
+            &global_stats,
+            WrapNone,
+            &mut rng,
+        );
+        run.collapse(&mut rng).unwrap();
+        assert_eq!(wave.to_debug_string(&global_stats), built);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn collapse_async() {
+        use rand::SeedableRng;
+        use std::task::{RawWaker, RawWakerVTable, Waker};
+        fn raw_waker() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker {
+                raw_waker()
+            }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+        let global_stats = all_compatible_stats(&[1, 1]);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(Size::new(4, 4), &global_stats, &mut rng);
+        let steps_per_yield = NonZeroUsize::new(4).unwrap();
+        let mut future = Box::pin(run.collapse_async(steps_per_yield, &mut rng));
+        let waker = unsafe { Waker::from_raw(raw_waker()) };
+        let mut cx = TaskContext::from_waker(&waker);
+        let mut num_yields = 0;
+        while future.as_mut().poll(&mut cx).is_pending() {
+            num_yields += 1;
+        }
+        drop(future);
+        // 16 cells are decided 4 at a time
+        assert_eq!(num_yields, 4);
+        assert!(run
+            .wave()
+            .grid()
+            .iter()
+            .all(|cell| cell.chosen_pattern_id().is_ok()));
+    }
+
+    #[test]
+    fn offset_adjacency() {
+        use rand::SeedableRng;
+        let mut global_stats = all_compatible_stats(&[1, 1]);
+        // each pattern must be repeated along the diagonal
+        global_stats
+            .add_offset_adjacency(Coord::new(1, 1), vec![vec![0], vec![1]])
+            .unwrap();
+        let size = Size::new(6, 6);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(size, &global_stats, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let wave = run.into_wave();
+        for (coord, cell) in wave.grid().enumerate() {
+            let diagonal = wave.grid().get_tiled(coord + Coord::new(1, 1));
+            assert_eq!(
+                cell.chosen_pattern_id().unwrap(),
+                diagonal.chosen_pattern_id().unwrap()
+            );
+        }
+        assert_eq!(
+            global_stats.add_offset_adjacency(Coord::new(0, 0), vec![vec![], vec![]]),
+            Err(OffsetAdjacencyError::ZeroOffset)
+        );
+    }
+
+    #[test]
+    fn offset_adjacency_excludes_patterns() {
+        use rand::SeedableRng;
+        let weight = NonZeroU32::new(1);
+        let all = vec![0, 1, 2];
+        // pattern 1 must have pattern 2 on both sides
+        let mut global_stats = GlobalStats::from_adjacency_matrix(
+            vec![weight, weight, weight],
+            vec![
+                [all.clone(), vec![0, 2], all.clone(), vec![0, 2]],
+                [all.clone(), vec![2], all.clone(), vec![2]],
+                [all.clone(), all.clone(), all.clone(), all.clone()],
+            ],
+        )
+        .unwrap();
+        // nothing allows pattern 2, and the repeated 0 is only counted once
+        global_stats
+            .add_offset_adjacency(
+                Coord::new(0, 2),
+                vec![vec![0, 0, 1], vec![0, 1], vec![0, 1]],
+            )
+            .unwrap();
+        let size = Size::new(4, 4);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(size, &global_stats, &mut rng);
+        assert!(run.candidate_count_grid().iter().all(|&count| count == 2));
+        // removing pattern 2 leaves nothing next to which pattern 1 can go
+        run.collapse(&mut rng).unwrap();
+        let wave = run.into_wave();
+        for cell in wave.grid().iter() {
+            assert_eq!(cell.chosen_pattern_id().unwrap(), 0);
+        }
+        let num_rules = global_stats.offset_rules.len();
+        assert_eq!(
+            global_stats
+                .add_offset_adjacency(Coord::new(1, 0), vec![vec![2], vec![2], vec![2]]),
+            Err(OffsetAdjacencyError::Unsatisfiable)
+        );
+        assert_eq!(global_stats.offset_rules.len(), num_rules);
+    }
+
+    #[test]
+    fn seed_scan() {
+        use rand::SeedableRng;
+        let global_stats = three_colour_stats();
+        let size = Size::new(6, 6);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let (wave, seed) = RunOwn::new(size, &global_stats, &mut rng)
+            .collapse_retrying(
+                retry::SeedScan {
+                    attempts: 100,
+                    base_seed: 1234,
+                },
+                &mut rng,
+            )
+            .unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        let mut run = RunOwn::new(size, &global_stats, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let ids = |wave: &Wave| {
+            Grid::new_grid_map_ref(wave.grid(), |cell| cell.chosen_pattern_id().unwrap())
+        };
+        assert_eq!(ids(&wave), ids(&run.into_wave()));
+    }
+
+    #[test]
+    fn collapse_many() {
+        use rand::{rngs::StdRng, SeedableRng};
+        let global_stats = all_compatible_stats(&[1, 1, 1]);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(Size::new(6, 6), &global_stats, &mut rng);
+        let ids = |wave: &Wave| {
+            Grid::new_grid_map_ref(wave.grid(), |cell| cell.chosen_pattern_id().unwrap())
+        };
+        let outputs =
+            run.collapse_many(3, retry::NumTimes(0), &mut rng, |result, wave| {
+                result.map(|()| ids(wave))
+            });
+        // the last output is left in the run
+        assert_eq!(outputs[2].as_ref().unwrap(), &ids(run.wave()));
+        assert_ne!(outputs[0].as_ref().unwrap(), outputs[1].as_ref().unwrap());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn par_collapse_many() {
+        use rand::{rngs::StdRng, SeedableRng};
+        use retry::RetryOwn;
+        let global_stats = three_colour_stats();
+        let mut rng = StdRng::seed_from_u64(0);
+        let run = RunOwn::new(Size::new(6, 6), &global_stats, &mut rng);
+        let ids = |wave: &Wave| {
+            Grid::new_grid_map_ref(wave.grid(), |cell| cell.chosen_pattern_id().unwrap())
+        };
+        let mut seed_rng = StdRng::seed_from_u64(1);
+        let waves = run.par_collapse_many::<StdRng, _, _, _, _>(
+            4,
+            retry::NumTimes(10),
+            &mut seed_rng,
+            |result, wave| result.map(|()| ids(wave)),
+        );
+        // each output is collapsed with a `StdRng` seeded from the caller's rng, after
+        // resetting the run with it
+        let mut seed_rng = StdRng::seed_from_u64(1);
+        for wave in waves {
+            let mut rng = StdRng::seed_from_u64(seed_rng.gen());
+            let mut expected = run.clone();
+            expected.borrow_mut().reset(&mut rng);
+            let expected = retry::NumTimes(10).retry(expected, &mut rng).unwrap();
+            assert_eq!(wave.unwrap(), ids(&expected));
+        }
+    }
+
+    #[cfg(feature = "debug-invariants")]
+    #[test]
+    fn check_consistency() {
+        use rand::SeedableRng;
+        let weight = NonZeroU32::new(1);
+        let global_stats = GlobalStats::from_adjacency_matrix(
+            vec![weight, NonZeroU32::new(3), None],
+            vec![
+                [vec![0, 1], vec![1, 2], vec![0, 1], vec![1, 2]],
+                [vec![0, 1, 2], vec![0, 1, 2], vec![0, 1, 2], vec![0, 1, 2]],
+                [vec![1, 2], vec![0, 1], vec![1, 2], vec![0, 1]],
+            ],
+        )
+        .unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(Size::new(6, 6), &global_stats, &mut rng);
+        loop {
+            let result = run.step(&mut rng);
+            assert_eq!(
+                run.clone().into_wave().check_consistency(&global_stats),
+                Ok(())
+            );
+            if let Ok(Observe::Complete) = result {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn unsatisfiable_patterns() {
+        use crate::wrap::WrapNone;
+        let weight = NonZeroU32::new(1);
+        let global_stats = GlobalStats::from_adjacency_matrix(
+            vec![weight, weight],
+            vec![
+                [vec![0, 1], vec![], vec![0, 1], vec![1]],
+                [vec![0, 1], vec![0, 1], vec![0, 1], vec![1]],
+            ],
+        )
+        .unwrap();
+        let size = Size::new(4, 4);
+        assert_eq!(global_stats.unsatisfiable_patterns(size, WrapXY), vec![0]);
+        assert!(global_stats
+            .unsatisfiable_patterns(size, WrapNone)
+            .is_empty());
+    }
+
+    #[test]
+    fn adjacency_weights() {
+        use rand::SeedableRng;
+        let weight = NonZeroU32::new(1);
+        // each pattern prefers itself to the east and west and the other pattern to the north
+        // and south, so outputs tend towards horizontal stripes
+        let weighted = |same: PatternId, other: PatternId| {
+            let all = |preferred: PatternId| vec![(preferred, 1e6), (preferred ^ 1, 1.0)];
+            [all(other), all(same), all(other), all(same)]
+        };
+        let global_stats = GlobalStats::from_weighted_adjacency_matrix(
+            vec![weight, weight],
+            vec![weighted(0, 1), weighted(1, 0)],
+        )
+        .unwrap();
+        let size = Size::new(12, 12);
+        let mut context = Context::new();
+        let mut wave = Wave::new(size);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        RunBorrow::new(&mut context, &mut wave, &global_stats, &mut rng)
+            .collapse(&mut rng)
+            .unwrap();
+        let id = |coord| wave.grid().get_checked(coord).chosen_pattern_id().unwrap();
+        let (mut same_horizontal, mut same_vertical) = (0, 0);
+        for coord in size.coord_iter_row_major() {
+            let east = Coord::new((coord.x + 1) % 12, coord.y);
+            let south = Coord::new(coord.x, (coord.y + 1) % 12);
+            same_horizontal += (id(coord) == id(east)) as u32;
+            same_vertical += (id(coord) == id(south)) as u32;
+        }
+        assert!(same_horizontal > 100, "{}", same_horizontal);
+        assert!(same_vertical < 24, "{}", same_vertical);
+        let mut invalid = weighted(0, 1);
+        invalid[1][0].1 = 0.0;
+        assert!(matches!(
+            GlobalStats::from_weighted_adjacency_matrix(
+                vec![weight, weight],
+                vec![invalid, weighted(1, 0)],
+            ),
+            Err(AdjacencyMatrixError::InvalidWeight {
+                pattern_id: 0,
+                direction: CardinalDirection::East,
+                neighbour_id: 0,
+            })
+        ));
+        let mut global_stats = global_stats;
+        assert_eq!(
+            global_stats.set_adjacency_weights(PatternTable::from_vec(Vec::new())),
+            Err(AdjacencyMatrixError::LengthMismatch {
+                num_weights: 2,
+                num_allowed: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn forbid_contradictions() {
+        use rand::SeedableRng;
+        #[derive(Clone)]
+        struct Forbid;
+        impl ForbidPattern for Forbid {
+            fn forbid<W: Wrap, R: Rng>(
+                &mut self,
+                fi: &mut ForbidInterface<W>,
+                rng: &mut R,
+            ) {
+                // adjacent cells can't both be 0 on a checkerboard
+                let _ = fi.forbid_all_patterns_except(Coord::new(0, 0), 0, rng);
+                let _ = fi.forbid_all_patterns_except(Coord::new(1, 0), 0, rng);
+                let _ = fi.forbid_pattern(Coord::new(2, 0), 1, rng);
+            }
+        }
+        let global_stats = checkerboard_stats();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let contradictions = match RunOwn::new_wrap_forbid_checked(
+            Size::new(4, 4),
+            &global_stats,
+            WrapXY,
+            Forbid,
+            &mut rng,
+        ) {
+            Ok(_) => panic!("expected a contradiction"),
+            Err(contradictions) => contradictions,
+        };
+        assert_eq!(
+            contradictions.iter().cloned().collect::<Vec<_>>(),
+            vec![ForbidContradiction {
+                call: ForbidCall::ForbidAllPatternsExcept,
+                coord: Coord::new(1, 0),
+                pattern_id: 0,
+            }]
+        );
+        let mut context = Context::new();
+        let mut wave = Wave::new(Size::new(4, 4));
+        assert!(RunBorrow::new_wrap_forbid_checked(
+            &mut context,
+            &mut wave,
+            &global_stats,
+            WrapXY,
+            ForbidNothing,
+            &mut rng,
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn forbid_retry() {
+        use rand::SeedableRng;
+        #[derive(Clone)]
+        struct Forbid {
+            num_failures: usize,
+        }
+        impl ForbidPattern for Forbid {
+            fn forbid<W: Wrap, R: Rng>(
+                &mut self,
+                fi: &mut ForbidInterface<W>,
+                rng: &mut R,
+            ) {
+                let mut attempt = 0;
+                let _ = fi.retry(3, rng, |fi, rng| {
+                    attempt += 1;
+                    // adjacent cells can't both be 0 on a checkerboard
+                    let neighbour_id = if attempt > self.num_failures { 1 } else { 0 };
+                    fi.forbid_all_patterns_except(Coord::new(0, 0), 0, rng)?;
+                    fi.forbid_all_patterns_except(Coord::new(1, 0), neighbour_id, rng)
+                });
+            }
+        }
+        let global_stats = checkerboard_stats();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let new_run = |num_failures, rng: &mut rand::rngs::StdRng| {
+            RunOwn::new_wrap_forbid_checked(
+                Size::new(4, 4),
+                &global_stats,
+                WrapXY,
+                Forbid { num_failures },
+                rng,
+            )
+        };
+        // contradictions the forbid recovered from aren't reported
+        assert!(new_run(2, &mut rng).is_ok());
+        let contradictions = match new_run(3, &mut rng) {
+            Ok(_) => panic!("expected a contradiction"),
+            Err(contradictions) => contradictions,
+        };
+        assert_eq!(contradictions.iter().len(), 3);
+    }
+
+    #[test]
+    fn validate_against() {
+        use rand::SeedableRng;
+        let checkerboard_stats = checkerboard_stats();
+        let all_stats = all_compatible_stats(&[1, 1]);
+        let mut context = Context::new();
+        let mut wave = Wave::new(Size::new(4, 4));
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        RunBorrow::new(&mut context, &mut wave, &checkerboard_stats, &mut rng)
+            .collapse(&mut rng)
+            .unwrap();
+        assert!(wave
+            .validate_against(&checkerboard_stats, WrapXY)
+            .is_empty());
+        // diagonal neighbours on a checkerboard are the same pattern
+        let mut diagonal_stats = all_stats.clone();
+        diagonal_stats
+            .add_offset_adjacency(Coord::new(1, 1), vec![vec![1], vec![0]])
+            .unwrap();
+        let violations = wave.validate_against(&diagonal_stats, WrapXY);
+        assert_eq!(violations.len(), 16);
+        assert!(violations
+            .iter()
+            .all(|violation| violation.offset == Coord::new(1, 1)));
+        RunBorrow::new(&mut context, &mut wave, &all_stats, &mut rng)
+            .collapse(&mut rng)
+            .unwrap();
+        let violations = wave.validate_against(&checkerboard_stats, WrapXY);
+        assert!(!violations.is_empty());
+        for violation in violations {
+            assert_eq!(violation.pattern_id, violation.neighbour_id);
+            assert_eq!(
+                WrapXY::normalize_coord(
+                    violation.coord + violation.offset,
+                    wave.grid().size()
+                ),
+                Some(violation.neighbour_coord)
+            );
+        }
+    }
+
+    #[test]
+    fn unconstrained_fast_path() {
+        use rand::SeedableRng;
+        let global_stats = all_compatible_stats(&[1, 2, 3]);
+        assert!(global_stats.is_unconstrained());
+        let mut context = Context::new();
+        let mut wave = Wave::new(Size::new(8, 8));
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunBorrow::new(&mut context, &mut wave, &global_stats, &mut rng);
+        run.forbid_with(&mut rng, |_, forbid_interface, rng| {
+            forbid_interface
+                .forbid_pattern(Coord::new(0, 0), 2, rng)
+                .unwrap();
+        })
+        .unwrap();
+        run.collapse(&mut rng).unwrap();
+        assert!(context.unconstrained_index.is_some());
+        let chosen = wave
+            .grid()
+            .get_checked(Coord::new(0, 0))
+            .chosen_pattern_id();
+        assert!(matches!(chosen, Ok(0) | Ok(1)));
+        let histogram = wave.pattern_histogram();
+        assert_eq!(histogram.iter().sum::<u32>(), 64);
+        assert!(histogram.iter().all(|&count| count > 0));
+        assert!(wave.validate_against(&global_stats, WrapXY).is_empty());
+    }
+
+    #[test]
+    fn resum_weight_log_weight() {
+        let num_patterns = 100;
+        let global_stats = all_compatible_stats(&(1..=num_patterns).collect::<Vec<_>>());
+        let mut wave_cell = WaveCell::default();
+        wave_cell.init(0, 0.0, &global_stats, &[]);
+        for pattern_id in 0..num_patterns - 1 {
+            wave_cell.remove_pattern(pattern_id, &global_stats);
+        }
+        // the sum was recomputed when the last pattern but one was removed, so it has no
+        // rounding error left over from the other removals
+        let last = global_stats.pattern_stats(num_patterns - 1).unwrap();
+        assert_eq!(
+            wave_cell.stats.sum_compatible_pattern_weight_log_weight,
+            last.weight_log_weight
+        );
+        assert_eq!(wave_cell.entropy(), Some(0.));
+        // likewise when patterns are forbidden
+        let mut wave = Wave::new(Size::new(1, 1));
+        wave.init(&global_stats, &mut rand::rngs::mock::StepRng::new(0, 1));
+        let mut propagator = Propagator::default();
+        for pattern_id in 0..num_patterns - 1 {
+            WaveCellHandle::new(
+                &mut wave,
+                Coord::new(0, 0),
+                &mut propagator,
+                &global_stats,
+            )
+            .forbid_pattern(pattern_id);
+        }
+        let wave_cell = wave.cells.get_checked(Coord::new(0, 0));
+        assert_eq!(
+            wave_cell.stats.sum_compatible_pattern_weight_log_weight,
+            last.weight_log_weight
+        );
+    }
+
+    #[test]
+    fn wave_accessors() {
+        use rand::SeedableRng;
+        let global_stats = checkerboard_stats();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(Size::new(4, 2), &global_stats, &mut rng);
+        run.collapse(&mut rng).unwrap();
+        let wave = run.into_wave();
+        assert_eq!(wave.size(), Size::new(4, 2));
+        assert!(wave.get(Coord::new(4, 0)).is_none());
+        assert!(wave.get(Coord::new(-1, 0)).is_none());
+        for coord in wave.size().coord_iter_row_major() {
+            assert_eq!(
+                wave.get(coord).unwrap().chosen_pattern_id().ok(),
+                wave.grid().get_checked(coord).chosen_pattern_id().ok()
+            );
+            assert_eq!(
+                wave[coord].chosen_pattern_id().ok(),
+                wave.grid().get_checked(coord).chosen_pattern_id().ok()
+            );
+        }
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn suspend_and_resume() {
+        use rand::SeedableRng;
+        let global_stats = GlobalStats::from_adjacency_matrix(
+            vec![NonZeroU32::new(1), NonZeroU32::new(2), NonZeroU32::new(3)],
+            vec![
+                [vec![0, 1], vec![0, 1], vec![0, 1], vec![0, 1]],
+                [vec![0, 1, 2], vec![0, 1, 2], vec![0, 1, 2], vec![0, 1, 2]],
+                [vec![1, 2], vec![1, 2], vec![1, 2], vec![1, 2]],
+            ],
+        )
+        .unwrap();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut run = RunOwn::new(Size::new(8, 8), &global_stats, &mut rng);
+        for _ in 0..10 {
+            run.step(&mut rng).unwrap();
+        }
+        let mut saved = Vec::new();
+        run.save(&mut saved).unwrap();
+        let mut resumed_rng = rng.clone();
+        run.collapse(&mut rng).unwrap();
+        let mut resumed = RunOwn::<WrapXY, ForbidNothing>::load(
+            saved.as_slice(),
+            &global_stats,
+            WrapXY,
+        )
+        .unwrap();
+        resumed.collapse(&mut resumed_rng).unwrap();
+        let chosen = |wave: Wave| {
+            wave.grid()
+                .iter()
+                .map(|cell| cell.chosen_pattern_id().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(chosen(run.into_wave()), chosen(resumed.into_wave()));
+        let other_stats = GlobalStats::from_adjacency_matrix(
+            vec![NonZeroU32::new(1)],
+            vec![[vec![0], vec![0], vec![0], vec![0]]],
+        )
+        .unwrap();
+        let error =
+            RunOwn::<WrapXY, ForbidNothing>::load(saved.as_slice(), &other_stats, WrapXY)
+                .err()
+                .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let suspended: SuspendedRun<ForbidNothing> =
+            bincode::deserialize(&saved).unwrap();
+        assert_eq!(
+            suspended.resume(&global_stats, crate::wrap::WrapNone).err(),
+            Some(ResumeError::WrapMismatch)
+        );
+    }
 }
@@ -1,8 +1,13 @@
 use coord_2d::{Coord, Size};
+use std::cmp;
 
 pub trait Wrap: Copy + Send + Sync + private::Sealed {
     #[doc(hidden)]
     fn normalize_coord(coord: Coord, size: Size) -> Option<Coord>;
+    // True if a cell is never the neighbour of more than one cell in the same direction, as
+    // is the case for wrapping but not for clamping or mirroring
+    #[doc(hidden)]
+    const ONE_TO_ONE: bool = true;
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -68,6 +73,181 @@ impl Wrap for WrapY {
     }
 }
 
+/// How coordinates beyond one edge of the output are treated, for use with `WrapAxes`
+pub trait AxisWrap: Copy + Send + Sync + private::Sealed {
+    #[doc(hidden)]
+    fn normalize_axis(value: i32, size: u32) -> Option<i32>;
+    #[doc(hidden)]
+    const ONE_TO_ONE: bool;
+}
+
+/// Cells at the edge have no neighbours beyond it
+#[derive(Clone, Copy, Debug)]
+pub struct NoWrap;
+
+/// The edge is adjacent to the opposite edge
+#[derive(Clone, Copy, Debug)]
+pub struct Repeat;
+
+/// Cells at the edge are their own neighbours beyond it, so patterns at the edge must be
+/// compatible with themselves in that direction
+#[derive(Clone, Copy, Debug)]
+pub struct Clamp;
+
+/// The output is reflected at the edge, so the neighbour beyond the edge of a cell at the
+/// edge is the cell next to it on the other side
+#[derive(Clone, Copy, Debug)]
+pub struct Mirror;
+
+impl AxisWrap for NoWrap {
+    const ONE_TO_ONE: bool = true;
+    fn normalize_axis(value: i32, size: u32) -> Option<i32> {
+        if value_is_valid(value, size) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+impl AxisWrap for Repeat {
+    const ONE_TO_ONE: bool = true;
+    fn normalize_axis(value: i32, size: u32) -> Option<i32> {
+        Some(normalize_value(value, size))
+    }
+}
+
+impl AxisWrap for Clamp {
+    const ONE_TO_ONE: bool = false;
+    fn normalize_axis(value: i32, size: u32) -> Option<i32> {
+        Some(value.clamp(0, size as i32 - 1))
+    }
+}
+
+impl AxisWrap for Mirror {
+    const ONE_TO_ONE: bool = false;
+    fn normalize_axis(value: i32, size: u32) -> Option<i32> {
+        if size == 1 {
+            return Some(0);
+        }
+        let period = 2 * (size as i32 - 1);
+        let value = value.rem_euclid(period);
+        Some(if value >= size as i32 {
+            period - value
+        } else {
+            value
+        })
+    }
+}
+
+/// Chooses how each axis of the output wraps independently, e.g.
+/// `WrapAxes { x: Mirror, y: Repeat }`. `WrapAxes { x: Repeat, y: NoWrap }` behaves the same
+/// as `WrapX`.
+#[derive(Clone, Copy, Debug)]
+pub struct WrapAxes<X: AxisWrap, Y: AxisWrap> {
+    pub x: X,
+    pub y: Y,
+}
+
+impl<X: AxisWrap, Y: AxisWrap> Wrap for WrapAxes<X, Y> {
+    const ONE_TO_ONE: bool = X::ONE_TO_ONE && Y::ONE_TO_ONE;
+    fn normalize_coord(coord: Coord, size: Size) -> Option<Coord> {
+        let x = X::normalize_axis(coord.x, size.x())?;
+        let y = Y::normalize_axis(coord.y, size.y())?;
+        Some(Coord::new(x, y))
+    }
+}
+
+// Whether each axis repeats, so that the shortest way between two cells may go across the
+// edge. Axes which clamp or mirror don't repeat, as there's no way across their edges.
+fn repeating_axes<W: Wrap>(size: Size) -> (bool, bool) {
+    let repeats = |coord, expected| W::normalize_coord(coord, size) == Some(expected);
+    (
+        size.height() > 0
+            && repeats(Coord::new(-1, 0), Coord::new(size.width() as i32 - 1, 0)),
+        size.width() > 0
+            && repeats(Coord::new(0, -1), Coord::new(0, size.height() as i32 - 1)),
+    )
+}
+
+// The shorter of the two ways from `from` to `to` along an axis of length `size`
+fn shortest_axis_offset(from: i32, to: i32, size: u32, repeats: bool) -> i32 {
+    let offset = to - from;
+    if repeats {
+        let offset = offset.rem_euclid(size as i32);
+        if offset > size as i32 / 2 {
+            offset - size as i32
+        } else {
+            offset
+        }
+    } else {
+        offset
+    }
+}
+
+/// The offset from `from` to `to`, in an output of `size` wrapped by `W`, going the shortest
+/// way around along each axis which repeats
+pub fn shortest_offset<W: Wrap>(from: Coord, to: Coord, size: Size) -> Coord {
+    let (repeats_x, repeats_y) = repeating_axes::<W>(size);
+    Coord::new(
+        shortest_axis_offset(from.x, to.x, size.width(), repeats_x),
+        shortest_axis_offset(from.y, to.y, size.height(), repeats_y),
+    )
+}
+
+/// The square of the euclidean distance between `from` and `to`, in an output of `size`
+/// wrapped by `W`. Along axes which repeat (e.g. both axes of `WrapXY`) this is the toroidal
+/// distance, so cells on opposite edges are close together.
+pub fn distance_squared<W: Wrap>(from: Coord, to: Coord, size: Size) -> u64 {
+    let Coord { x, y } = shortest_offset::<W>(from, to, size);
+    (x as i64 * x as i64 + y as i64 * y as i64) as u64
+}
+
+/// Every cell of an output of `size` wrapped by `W` whose euclidean distance from `centre`
+/// (measured as by `distance_squared`) is at most `distance`, each listed once even if the
+/// circle is wide enough to reach it both ways around
+pub fn coords_within_distance<W: Wrap>(
+    centre: Coord,
+    distance: u32,
+    size: Size,
+) -> impl Iterator<Item = Coord> {
+    let (repeats_x, repeats_y) = repeating_axes::<W>(size);
+    let distance = cmp::min(distance, i32::MAX as u32) as i32;
+    // The offsets along an axis which reach a cell inside the output. Along a repeating axis,
+    // each cell is reached by exactly one offset.
+    let range = move |centre: i32, length: u32, repeats: bool| {
+        if repeats {
+            cmp::max(-distance, -((length as i32 - 1) / 2))
+                ..=cmp::min(distance, length as i32 / 2)
+        } else {
+            cmp::max(-distance, -centre)..=cmp::min(distance, length as i32 - 1 - centre)
+        }
+    };
+    let distance_squared = distance as i64 * distance as i64;
+    range(centre.y, size.height(), repeats_y)
+        .flat_map(move |y| {
+            range(centre.x, size.width(), repeats_x).map(move |x| Coord::new(x, y))
+        })
+        .filter(move |&Coord { x, y }| {
+            x as i64 * x as i64 + y as i64 * y as i64 <= distance_squared
+        })
+        .map(move |offset| {
+            let Coord { x, y } = centre + offset;
+            Coord::new(
+                if repeats_x {
+                    x.rem_euclid(size.width() as i32)
+                } else {
+                    x
+                },
+                if repeats_y {
+                    y.rem_euclid(size.height() as i32)
+                } else {
+                    y
+                },
+            )
+        })
+}
+
 mod private {
     use super::*;
 
@@ -77,6 +257,12 @@ mod private {
     impl Sealed for WrapY {}
     impl Sealed for WrapXY {}
     impl Sealed for WrapNone {}
+    impl<X: AxisWrap, Y: AxisWrap> Sealed for WrapAxes<X, Y> {}
+
+    impl Sealed for NoWrap {}
+    impl Sealed for Repeat {}
+    impl Sealed for Clamp {}
+    impl Sealed for Mirror {}
 }
 
 #[cfg(test)]
@@ -110,4 +296,72 @@ mod test {
             Some(Coord::new(2, 1)),
         };
     }
+    #[test]
+    fn wrap_axes() {
+        let size = Size::new(4, 5);
+        assert_eq! {
+            WrapAxes::<Mirror, Clamp>::normalize_coord(Coord::new(-1, 5), size),
+            Some(Coord::new(1, 4)),
+        };
+        assert_eq! {
+            WrapAxes::<Mirror, Clamp>::normalize_coord(Coord::new(4, -1), size),
+            Some(Coord::new(2, 0)),
+        };
+        assert_eq! {
+            WrapAxes::<Repeat, NoWrap>::normalize_coord(Coord::new(4, 3), size),
+            WrapX::normalize_coord(Coord::new(4, 3), size),
+        };
+        assert_eq! {
+            WrapAxes::<Repeat, NoWrap>::normalize_coord(Coord::new(2, 5), size),
+            None,
+        };
+    }
+
+    #[test]
+    fn distance() {
+        let size = Size::new(10, 6);
+        assert_eq!(
+            shortest_offset::<WrapXY>(Coord::new(1, 1), Coord::new(9, 5), size),
+            Coord::new(-2, -2)
+        );
+        assert_eq!(
+            shortest_offset::<WrapX>(Coord::new(1, 1), Coord::new(9, 5), size),
+            Coord::new(-2, 4)
+        );
+        assert_eq!(
+            distance_squared::<WrapNone>(Coord::new(1, 1), Coord::new(9, 1), size),
+            64
+        );
+        // mirroring doesn't connect opposite edges
+        assert_eq!(
+            distance_squared::<WrapAxes<Mirror, Clamp>>(
+                Coord::new(1, 1),
+                Coord::new(9, 1),
+                size
+            ),
+            64
+        );
+        let mut within = coords_within_distance::<WrapXY>(Coord::new(0, 0), 1, size)
+            .collect::<Vec<_>>();
+        within.sort_by_key(|coord| (coord.y, coord.x));
+        assert_eq!(
+            within,
+            vec![
+                Coord::new(0, 0),
+                Coord::new(1, 0),
+                Coord::new(9, 0),
+                Coord::new(0, 1),
+                Coord::new(0, 5),
+            ]
+        );
+        assert_eq!(
+            coords_within_distance::<WrapNone>(Coord::new(0, 0), 1, size).count(),
+            3
+        );
+        // a circle wider than the output reaches every cell exactly once
+        assert_eq!(
+            coords_within_distance::<WrapXY>(Coord::new(3, 3), 100, size).count(),
+            size.count()
+        );
+    }
 }